@@ -0,0 +1,25 @@
+use std::time::Duration;
+
+/// Estimate the concurrency needed to sustain a TPS, given the expected latency of a Scenario.
+///
+/// By Little's Law, the average number of in-flight Transactions is the TPS multiplied by the
+/// latency. The estimate is rounded up, and is at least 1.
+pub fn estimate_concurrency(target_tps: u32, expected_latency: Duration) -> usize {
+    let concurrency = (target_tps as f64 * expected_latency.as_secs_f64()).ceil();
+    // NOTE: `as` saturates to the range of `usize`.
+    (concurrency as usize).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_concurrency() {
+        assert_eq!(estimate_concurrency(1_000, Duration::from_millis(200)), 200);
+        assert_eq!(estimate_concurrency(50_000, Duration::from_millis(10)), 500);
+        assert_eq!(estimate_concurrency(3, Duration::from_millis(500)), 2);
+        assert_eq!(estimate_concurrency(1_000, Duration::ZERO), 1);
+        assert_eq!(estimate_concurrency(0, Duration::from_secs(1)), 1);
+    }
+}
@@ -1,10 +1,12 @@
-use crate::BASE_TPS;
+use crate::{BASE_TPS, DEFAULT_METRICS_PREFIX};
 #[cfg(feature = "rt")]
 use serde::{Deserialize, Serialize};
 #[allow(unused_imports)]
 #[cfg(feature = "rt")]
 use serde_with::{serde_as, DurationSecondsWithFrac};
+use std::collections::BTreeMap;
 use std::num::NonZeroU32;
+use std::path::PathBuf;
 use std::time::Duration;
 
 // TODO: Have a separate builder
@@ -21,6 +23,110 @@ pub struct ScenarioConfig {
     pub latency: Option<LatencyConfig>,
     #[cfg_attr(feature = "rt", serde(default))]
     pub hints: HintConfig,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub base_url: Option<String>,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub fast_start: bool,
+    #[cfg_attr(feature = "rt", serde_as(as = "Option<DurationSecondsWithFrac>"))]
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub max_duration: Option<Duration>,
+    #[cfg_attr(feature = "rt", serde_as(as = "Option<DurationSecondsWithFrac>"))]
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub cooldown: Option<Duration>,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub peer_tags: Vec<String>,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub tps_smoothing: Option<f64>,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub metrics_prefix: Option<String>,
+    /// Replace the characters of the metric names which statsd can't handle.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub sanitize_metric_names: bool,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub rate_limit_backoff: bool,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub required_tps_fraction: Option<f64>,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub collect_raw_samples: bool,
+    #[cfg_attr(feature = "rt", serde_as(as = "Option<DurationSecondsWithFrac>"))]
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub max_sample_interval: Option<Duration>,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub adaptive_concurrency_shedding: bool,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub tps_tolerance: Option<f64>,
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub state_drift_detection: Option<bool>,
+    /// Cap on the total number of Transactions run, across all peers.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub max_transactions: Option<u64>,
+    /// Independent TPS limits for Transactions run within `balter::with_host()`, by host.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub tps_per_host: BTreeMap<String, NonZeroU32>,
+    /// Emit metrics at this fixed cadence, rather than once per (adaptive) sampling window.
+    #[cfg_attr(feature = "rt", serde_as(as = "Option<DurationSecondsWithFrac>"))]
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub metrics_interval: Option<Duration>,
+    /// Fraction of Transactions whose latency is recorded, within (0, 1].
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub latency_sample_rate: Option<f64>,
+    /// Start the error rate search at this TPS, and only ever decrease from it.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub descend_from: Option<NonZeroU32>,
+    /// Header the target must respond with before any load is sent.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub required_header: Option<RequiredHeader>,
+    /// File the decisions of the controllers are recorded to.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub trace_path: Option<PathBuf>,
+    /// What to do with controller trace rows which are written faster than the file can keep up.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub trace_overflow: OverflowPolicy,
+    /// Run at this fixed concurrency with no TPS limit, instead of the controllers.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub unbounded_concurrency: Option<usize>,
+    /// How the windows of a sample set are weighed when calculating the mean TPS.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub sample_weighting: Weighting,
+    /// Ramp the concurrency over time with no TPS limit, instead of the controllers.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub concurrency_ramp: Option<ConcurrencyRamp>,
+    /// Gain in TPS from added concurrency, relative to the TPS per task, below which the
+    /// Scenario is considered TPS limited, within (0, 1].
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub underpowered_slope_threshold: Option<f64>,
+    /// Whether sampling windows span a fixed time or a fixed number of Transactions.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub window_mode: WindowMode,
+    /// Seed of the RNGs of the Scenario's tasks. Generated when the Scenario starts if not set.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub seed: Option<u64>,
+    /// Lowest goal TPS the error rate controller decreases to.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub tps_floor: Option<NonZeroU32>,
+    /// Whether each task of the Scenario runs it once and is replaced by a new task, rather than
+    /// looping over it.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub run_once_per_task: bool,
+    /// Concurrency the Scenario is pinned at, with only its TPS adjusted.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub fixed_concurrency: Option<usize>,
+    /// Number of tasks the Scenario never exceeds, as a safety net against runaway scaling.
+    /// `MAX_CONCURRENCY` if not set.
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub max_concurrency: Option<usize>,
+    /// Render a live progress bar for the Scenario. Only shown on this server, so not sent to
+    /// other servers.
+    #[cfg_attr(feature = "rt", serde(skip))]
+    pub progress_bar: bool,
+    /// Delay added before each Transaction by the distributed runtime of this server, to
+    /// simulate the network latency of another region. Not sent to other servers.
+    #[cfg_attr(feature = "rt", serde(skip))]
+    pub added_latency: Option<Duration>,
+    /// InfluxDB write URL the results of the Scenario are persisted to once it completes. Only
+    /// written by this server, so not sent to other servers.
+    #[cfg_attr(feature = "rt", serde(skip))]
+    pub results_db: Option<String>,
 }
 
 impl ScenarioConfig {
@@ -32,6 +138,42 @@ impl ScenarioConfig {
             error_rate: None,
             latency: None,
             hints: HintConfig::default(),
+            base_url: None,
+            fast_start: false,
+            max_duration: None,
+            cooldown: None,
+            peer_tags: vec![],
+            tps_smoothing: None,
+            metrics_prefix: None,
+            sanitize_metric_names: false,
+            rate_limit_backoff: false,
+            required_tps_fraction: None,
+            collect_raw_samples: false,
+            max_sample_interval: None,
+            adaptive_concurrency_shedding: false,
+            tps_tolerance: None,
+            state_drift_detection: None,
+            max_transactions: None,
+            tps_per_host: BTreeMap::new(),
+            metrics_interval: None,
+            latency_sample_rate: None,
+            descend_from: None,
+            required_header: None,
+            trace_path: None,
+            trace_overflow: OverflowPolicy::Block,
+            unbounded_concurrency: None,
+            sample_weighting: Weighting::Uniform,
+            concurrency_ramp: None,
+            underpowered_slope_threshold: None,
+            window_mode: WindowMode::Time,
+            seed: None,
+            tps_floor: None,
+            run_once_per_task: false,
+            fixed_concurrency: None,
+            max_concurrency: None,
+            progress_bar: false,
+            added_latency: None,
+            results_db: None,
         }
     }
 
@@ -40,7 +182,9 @@ impl ScenarioConfig {
         // but its realistically an edge-case.
         #[allow(clippy::match_like_matches_macro)]
         match (self.max_tps, self.error_rate, self.latency) {
-            (None, None, None) => true,
+            (None, None, None) => {
+                self.unbounded_concurrency.is_none() && self.concurrency_ramp.is_none()
+            }
             _ => false,
         }
     }
@@ -49,9 +193,17 @@ impl ScenarioConfig {
         match self {
             ScenarioConfig {
                 error_rate: Some(_),
+                descend_from: Some(tps),
                 ..
-            }
-            | ScenarioConfig {
+            } => Some(*tps),
+
+            ScenarioConfig {
+                error_rate: Some(_),
+                tps_floor,
+                ..
+            } => Some(tps_floor.map_or(BASE_TPS, |floor| floor.max(BASE_TPS))),
+
+            ScenarioConfig {
                 latency: Some(_), ..
             } => Some(BASE_TPS),
 
@@ -63,10 +215,41 @@ impl ScenarioConfig {
         }
     }
 
+    /// Prefix for this Scenario's metric names, e.g. `balter_{name}`. With
+    /// `sanitize_metric_names`, any characters statsd can't handle are replaced (see
+    /// [crate::sanitize_metric_name]).
+    pub fn metrics_base_label(&self) -> String {
+        let prefix = self
+            .metrics_prefix
+            .as_deref()
+            .unwrap_or(DEFAULT_METRICS_PREFIX);
+        let label = format!("{prefix}_{}", self.name);
+        if self.sanitize_metric_names {
+            crate::sanitize_metric_name(&label)
+        } else {
+            label
+        }
+    }
+
     pub fn concurrency(&self) -> usize {
         self.hints.concurrency
     }
 
+    /// Concurrency to start at for the given TPS. With an expected latency hint, the concurrency
+    /// hint is raised to the estimate from Little's Law (see [crate::estimate_concurrency]). A
+    /// fixed concurrency overrides both.
+    pub fn starting_concurrency(&self, tps: NonZeroU32) -> usize {
+        if let Some(concurrency) = self.fixed_concurrency {
+            return concurrency;
+        }
+        match self.hints.expected_latency {
+            Some(latency) => self
+                .concurrency()
+                .max(crate::estimate_concurrency(tps.get(), latency)),
+            None => self.concurrency(),
+        }
+    }
+
     #[allow(unused)]
     pub fn set_max_tps(&mut self, max_tps: NonZeroU32) {
         self.max_tps = Some(max_tps);
@@ -89,18 +272,126 @@ impl LatencyConfig {
     }
 }
 
+#[doc(hidden)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "rt", cfg_eval::cfg_eval, serde_as)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
+pub struct ConcurrencyRamp {
+    pub start: usize,
+    pub end: usize,
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
+    pub duration: Duration,
+}
+
+impl ConcurrencyRamp {
+    pub fn new(start: usize, end: usize, duration: Duration) -> Self {
+        Self {
+            start,
+            end,
+            duration,
+        }
+    }
+
+    /// Concurrency to run at, `elapsed` into the ramp. Held at `end` once the ramp is over.
+    pub fn concurrency_at(&self, elapsed: Duration) -> usize {
+        let progress = (elapsed.as_secs_f64() / self.duration.as_secs_f64()).min(1.);
+        let delta = (self.end as f64 - self.start as f64) * progress;
+        (self.start as f64 + delta).round() as usize
+    }
+}
+
+#[doc(hidden)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
+pub struct RequiredHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl RequiredHeader {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// How the sampling windows of a sample set are weighed against each other when calculating the
+/// mean TPS the controllers act on.
+///
+/// Weighing recent windows more heavily tracks a target whose capacity is changing more closely,
+/// at the cost of a noisier mean (and so a less stable controller) for a target which isn't.
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "rt", serde(rename_all = "snake_case"))]
+pub enum Weighting {
+    /// Every window is weighed equally.
+    #[default]
+    Uniform,
+    /// Windows are weighed by their position in the sample set, with the latest window weighing
+    /// the most.
+    Linear,
+    /// Each window is weighed twice as heavily as the one before it.
+    Exponential,
+}
+
+impl Weighting {
+    /// Weight of the window at `index` (oldest first) within a sample set.
+    pub fn weight(&self, index: usize) -> f64 {
+        match self {
+            Weighting::Uniform => 1.,
+            Weighting::Linear => (index + 1) as f64,
+            Weighting::Exponential => 2f64.powi(index as i32),
+        }
+    }
+}
+
+/// What a Scenario does when a sink of per-window events (e.g. the controller trace of
+/// `trace_to()`) can't keep up, and its buffer is full.
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "rt", serde(rename_all = "snake_case"))]
+pub enum OverflowPolicy {
+    /// Wait for room in the buffer, so no event is lost. This stalls the sampling loop while the
+    /// sink catches up, which distorts the measurements.
+    #[default]
+    Block,
+    /// Drop the oldest buffered event to make room for the new one.
+    DropOldest,
+    /// Drop the new event.
+    DropNewest,
+}
+
+/// What closes a sampling window, after which its measurements are handed to the controllers.
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "rt", serde(rename_all = "snake_case"))]
+pub enum WindowMode {
+    /// Windows span a fixed time (see `BASE_INTERVAL`), and hold however many Transactions
+    /// completed within it.
+    #[default]
+    Time,
+    /// Windows hold exactly this many completed Transactions, and span however long they took.
+    Count(usize),
+}
+
 #[doc(hidden)]
 #[derive(Clone, Debug, Copy)]
 #[cfg_attr(feature = "rt", cfg_eval::cfg_eval, serde_as)]
 #[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
 pub struct HintConfig {
     pub concurrency: usize,
+    #[cfg_attr(feature = "rt", serde_as(as = "Option<DurationSecondsWithFrac>"))]
+    #[cfg_attr(feature = "rt", serde(default))]
+    pub expected_latency: Option<Duration>,
 }
 
 impl Default for HintConfig {
     fn default() -> Self {
         Self {
             concurrency: crate::BASE_CONCURRENCY,
+            expected_latency: None,
         }
     }
 }
@@ -109,6 +400,8 @@ impl Default for HintConfig {
 mod tests {
     use super::*;
 
+    // NOTE: Serialization is only derived with the `rt` feature.
+    #[cfg(feature = "rt")]
     #[test]
     fn test_scenario_config_serialization() {
         insta::assert_json_snapshot!(ScenarioConfig {
@@ -118,6 +411,74 @@ mod tests {
             error_rate: Some(0.03),
             latency: Some(LatencyConfig::new(Duration::from_millis(20), 0.99)),
             hints: HintConfig::default(),
+            base_url: Some("http://localhost:3002".to_string()),
+            fast_start: false,
+            max_duration: Some(Duration::from_secs(600)),
+            cooldown: Some(Duration::from_secs(30)),
+            peer_tags: vec!["gpu".to_string()],
+            tps_smoothing: Some(0.5),
+            metrics_prefix: Some("loadtest".to_string()),
+            sanitize_metric_names: true,
+            rate_limit_backoff: true,
+            required_tps_fraction: Some(0.95),
+            collect_raw_samples: true,
+            max_sample_interval: Some(Duration::from_secs(120)),
+            adaptive_concurrency_shedding: true,
+            tps_tolerance: Some(0.01),
+            state_drift_detection: Some(false),
+            max_transactions: Some(1_000_000),
+            tps_per_host: BTreeMap::from([(
+                "api.example.com".to_string(),
+                NonZeroU32::new(200).unwrap()
+            )]),
+            metrics_interval: Some(Duration::from_secs(1)),
+            latency_sample_rate: Some(0.1),
+            descend_from: Some(NonZeroU32::new(5_000).unwrap()),
+            required_header: Some(RequiredHeader::new("X-Environment", "staging")),
+            trace_path: Some(PathBuf::from("controller-trace.csv")),
+            trace_overflow: OverflowPolicy::DropOldest,
+            unbounded_concurrency: Some(50),
+            sample_weighting: Weighting::Linear,
+            concurrency_ramp: Some(ConcurrencyRamp::new(10, 200, Duration::from_secs(600))),
+            underpowered_slope_threshold: Some(0.2),
+            window_mode: WindowMode::Count(500),
+            seed: Some(0xba17e5),
+            tps_floor: Some(NonZeroU32::new(50).unwrap()),
+            run_once_per_task: true,
+            fixed_concurrency: Some(32),
+            max_concurrency: Some(50_000),
+            progress_bar: true,
+            added_latency: None,
+            results_db: Some("http://localhost:8086/write?db=balter".to_string()),
         });
     }
+
+    #[test]
+    fn test_concurrency_ramp() {
+        let ramp = ConcurrencyRamp::new(10, 50, Duration::from_secs(20));
+        assert_eq!(ramp.concurrency_at(Duration::ZERO), 10);
+        assert_eq!(ramp.concurrency_at(Duration::from_secs(5)), 20);
+        assert_eq!(ramp.concurrency_at(Duration::from_secs(20)), 50);
+        assert_eq!(ramp.concurrency_at(Duration::from_secs(60)), 50);
+
+        let down = ConcurrencyRamp::new(50, 10, Duration::from_secs(20));
+        assert_eq!(down.concurrency_at(Duration::from_secs(5)), 40);
+    }
+
+    #[test]
+    fn test_metrics_base_label() {
+        let mut config = ScenarioConfig::new("checkout");
+        assert_eq!(config.metrics_base_label(), "balter_checkout");
+
+        config.metrics_prefix = Some("loadtest".to_string());
+        assert_eq!(config.metrics_base_label(), "loadtest_checkout");
+
+        // NOTE: Names are kept as-is unless sanitizing, so existing metrics aren't renamed.
+        config.name = "checkout (EU)".to_string();
+        config.metrics_prefix = Some("team:payments".to_string());
+        assert_eq!(config.metrics_base_label(), "team:payments_checkout (EU)");
+
+        config.sanitize_metric_names = true;
+        assert_eq!(config.metrics_base_label(), "team_payments_checkout__EU_");
+    }
 }
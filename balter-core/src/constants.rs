@@ -1,7 +1,12 @@
 use std::num::NonZeroU32;
 use std::time::Duration;
 
+pub const DEFAULT_METRICS_PREFIX: &str = "balter";
 pub const BASE_TPS: NonZeroU32 = unsafe { NonZeroU32::new_unchecked(512) };
 pub const BASE_CONCURRENCY: usize = 10;
+pub const MAX_CONCURRENCY: usize = 100_000;
+pub const FAST_START_PROBE_TPS: NonZeroU32 = unsafe { NonZeroU32::new_unchecked(1_000_000) };
+pub const COOLDOWN_FLOOR_TPS: NonZeroU32 = unsafe { NonZeroU32::new_unchecked(10) };
 pub const BASE_INTERVAL: Duration = Duration::from_millis(1000);
 pub const BASE_INTERVAL_SLOW: Duration = Duration::from_millis(5000);
+pub const MAX_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
@@ -0,0 +1,334 @@
+use crate::stats::{RunStatistics, SloStatistics};
+use std::fmt::Write;
+
+/// JUnit XML report of the results of one or more Scenarios, for CI systems which ingest test
+/// results in that format (e.g. Jenkins or GitLab).
+///
+/// Each Scenario is a test suite, and each of its goals is a test case which passes or fails:
+/// `tps_goal` (see `require_tps()`), `error_rate` and `latency` (if set via `error_rate()` or
+/// `latency()`), one `slo:<transaction>` per latency SLO, and `completed`, which fails if a task
+/// of the Scenario panicked. The goal and the achieved value are properties of each test case.
+/// Every test case of a Scenario which was skipped by its `precondition()` is skipped.
+///
+/// # Example
+/// ```no_run
+/// use balter_core::{JunitReport, RunStatistics};
+///
+/// let checkout = RunStatistics::default();
+/// let search = RunStatistics::default();
+///
+/// let mut report = JunitReport::new("load-tests");
+/// report.add("checkout", &checkout);
+/// report.add("search", &search);
+/// std::fs::write("junit.xml", report.to_xml()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct JunitReport {
+    name: String,
+    suites: Vec<(String, RunStatistics)>,
+}
+
+impl JunitReport {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            suites: vec![],
+        }
+    }
+
+    /// Add the results of a Scenario as a test suite named `test_name`.
+    pub fn add(&mut self, test_name: &str, stats: &RunStatistics) -> &mut Self {
+        self.suites.push((test_name.to_string(), stats.clone()));
+        self
+    }
+
+    pub fn to_xml(&self) -> String {
+        let suites: Vec<_> = self
+            .suites
+            .iter()
+            .map(|(name, stats)| (name, stats, test_cases(stats)))
+            .collect();
+
+        let tests: usize = suites.iter().map(|(_, _, cases)| cases.len()).sum();
+        let (failures, skipped) = suites
+            .iter()
+            .map(|(_, stats, cases)| counts(stats, cases))
+            .fold((0, 0), |(f, s), (failures, skipped)| {
+                (f + failures, s + skipped)
+            });
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuites name=\"{}\" tests=\"{tests}\" failures=\"{failures}\" skipped=\"{skipped}\">",
+            escape(&self.name)
+        );
+        for (name, stats, cases) in &suites {
+            write_suite(&mut xml, name, stats, cases);
+        }
+        xml.push_str("</testsuites>\n");
+        xml
+    }
+}
+
+impl RunStatistics {
+    /// The results of the Scenario as a JUnit XML report with a single test suite named
+    /// `test_name`, see [`JunitReport`] for the test cases.
+    pub fn to_junit_xml(&self, test_name: &str) -> String {
+        let mut report = JunitReport::new(test_name);
+        report.add(test_name, self);
+        report.to_xml()
+    }
+}
+
+struct TestCase {
+    name: String,
+    properties: Vec<(&'static str, String)>,
+    failure: Option<String>,
+}
+
+fn test_cases(stats: &RunStatistics) -> Vec<TestCase> {
+    let mut cases = vec![TestCase {
+        name: "tps_goal".to_string(),
+        properties: vec![
+            ("goal_tps", stats.goal_tps.to_string()),
+            ("actual_tps", stats.actual_tps.to_string()),
+        ],
+        failure: (!stats.tps_goal_met).then(|| {
+            format!(
+                "achieved {:.2} TPS, short of the goal of {} TPS",
+                stats.actual_tps, stats.goal_tps
+            )
+        }),
+    }];
+
+    if let Some(goal) = stats.error_rate_goal {
+        cases.push(TestCase {
+            name: "error_rate".to_string(),
+            properties: vec![
+                ("goal_error_rate", goal.to_string()),
+                ("error_rate", stats.error_rate.to_string()),
+            ],
+            failure: (stats.error_rate > goal).then(|| {
+                format!(
+                    "error rate of {:.2}% exceeds the goal of {:.2}%",
+                    stats.error_rate * 100.,
+                    goal * 100.
+                )
+            }),
+        });
+    }
+
+    if let Some(goal) = &stats.latency_goal {
+        cases.push(latency_case("latency".to_string(), goal));
+    }
+
+    let mut slos: Vec<_> = stats.slos.iter().collect();
+    slos.sort_by_key(|(name, _)| *name);
+    for (name, slo) in slos {
+        cases.push(latency_case(format!("slo:{name}"), slo));
+    }
+
+    cases.push(TestCase {
+        name: "completed".to_string(),
+        properties: vec![("panicked_tasks", stats.panicked_tasks.to_string())],
+        failure: stats.failed.then(|| {
+            format!(
+                "terminated early after {} Scenario tasks panicked",
+                stats.panicked_tasks
+            )
+        }),
+    });
+
+    cases
+}
+
+fn latency_case(name: String, latency: &SloStatistics) -> TestCase {
+    let percentile = latency.quantile * 100.;
+    TestCase {
+        name,
+        properties: vec![
+            ("goal_latency", latency.target.as_secs_f64().to_string()),
+            ("quantile", latency.quantile.to_string()),
+            ("latency", latency.achieved.as_secs_f64().to_string()),
+        ],
+        failure: (!latency.is_met()).then(|| {
+            format!(
+                "p{percentile} latency of {:.2}ms exceeds the goal of {:.2}ms",
+                latency.achieved.as_secs_f64() * 1_000.,
+                latency.target.as_secs_f64() * 1_000.
+            )
+        }),
+    }
+}
+
+/// Number of failed and skipped test cases of a suite.
+fn counts(stats: &RunStatistics, cases: &[TestCase]) -> (usize, usize) {
+    if stats.skipped {
+        (0, cases.len())
+    } else {
+        let failures = cases.iter().filter(|case| case.failure.is_some()).count();
+        (failures, 0)
+    }
+}
+
+fn write_suite(xml: &mut String, name: &str, stats: &RunStatistics, cases: &[TestCase]) {
+    let name = escape(name);
+    let (failures, skipped) = counts(stats, cases);
+
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{name}\" tests=\"{}\" failures=\"{failures}\" skipped=\"{skipped}\">",
+        cases.len()
+    );
+    xml.push_str("    <properties>\n");
+    let properties = [
+        ("concurrency", stats.concurrency.to_string()),
+        ("tps_limited", stats.tps_limited.to_string()),
+        ("latency_p50", stats.latency_p50.as_secs_f64().to_string()),
+        ("latency_p90", stats.latency_p90.as_secs_f64().to_string()),
+        ("latency_p95", stats.latency_p95.as_secs_f64().to_string()),
+        ("latency_p99", stats.latency_p99.as_secs_f64().to_string()),
+        ("capped", stats.capped.to_string()),
+        ("generator_saturated", stats.generator_saturated.to_string()),
+        (
+            "generator_resource_exhausted",
+            stats.generator_resource_exhausted.to_string(),
+        ),
+        ("concurrency_capped", stats.concurrency_capped.to_string()),
+    ];
+    for (key, value) in properties {
+        write_property(xml, "      ", key, &value);
+    }
+    xml.push_str("    </properties>\n");
+
+    for case in cases {
+        let _ = writeln!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{name}\">",
+            escape(&case.name)
+        );
+        xml.push_str("      <properties>\n");
+        for (key, value) in &case.properties {
+            write_property(xml, "        ", key, value);
+        }
+        xml.push_str("      </properties>\n");
+
+        if stats.skipped {
+            xml.push_str("      <skipped message=\"precondition did not hold\"/>\n");
+        } else if let Some(failure) = &case.failure {
+            let _ = writeln!(xml, "      <failure message=\"{}\"/>", escape(failure));
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n");
+}
+
+fn write_property(xml: &mut String, indent: &str, key: &str, value: &str) {
+    let _ = writeln!(
+        xml,
+        "{indent}<property name=\"{key}\" value=\"{}\"/>",
+        escape(value)
+    );
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn stats() -> RunStatistics {
+        RunStatistics {
+            concurrency: 20,
+            goal_tps: 500,
+            actual_tps: 498.5,
+            latency_p99: Duration::from_millis(12),
+            error_rate: 0.07,
+            tps_goal_met: true,
+            error_rate_goal: Some(0.05),
+            latency_goal: Some(SloStatistics {
+                target: Duration::from_millis(20),
+                quantile: 0.99,
+                achieved: Duration::from_millis(12),
+            }),
+            slos: HashMap::from([(
+                "search".to_string(),
+                SloStatistics {
+                    target: Duration::from_millis(200),
+                    quantile: 0.99,
+                    achieved: Duration::from_millis(250),
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let xml = stats().to_junit_xml("checkout <eu>");
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        for tag in ["testsuites", "testsuite", "testcase", "properties"] {
+            let opened =
+                xml.matches(&format!("<{tag}>")).count() + xml.matches(&format!("<{tag} ")).count();
+            let closed = xml.matches(&format!("</{tag}>")).count();
+            assert_eq!(opened, closed, "unbalanced <{tag}>");
+        }
+        assert_eq!(xml.matches("<testcase ").count(), 5);
+        assert_eq!(xml.matches("<failure ").count(), 2);
+
+        insta::assert_snapshot!(xml);
+    }
+
+    #[test]
+    fn test_junit_report() {
+        let skipped = RunStatistics {
+            skipped: true,
+            ..Default::default()
+        };
+
+        let mut report = JunitReport::new("load-tests");
+        report.add("checkout", &stats()).add("search", &skipped);
+        let xml = report.to_xml();
+
+        assert!(xml
+            .contains("<testsuites name=\"load-tests\" tests=\"7\" failures=\"2\" skipped=\"2\">"));
+        assert!(
+            xml.contains("<testsuite name=\"search\" tests=\"2\" failures=\"0\" skipped=\"2\">")
+        );
+        assert_eq!(xml.matches("<skipped ").count(), 2);
+    }
+
+    #[test]
+    fn test_junit_default_goals() {
+        // NOTE: An unconfigured run (and a skipped one) has no TPS requirement to fall short of.
+        let unconfigured = RunStatistics::default();
+        let xml = unconfigured.to_junit_xml("unconfigured");
+        assert!(xml.contains("failures=\"0\""));
+        assert_eq!(xml.matches("<failure ").count(), 0);
+
+        let skipped = RunStatistics {
+            skipped: true,
+            ..Default::default()
+        };
+        let tps_goal = test_cases(&skipped).remove(0);
+        assert_eq!(tps_goal.name, "tps_goal");
+        assert!(tps_goal.failure.is_none());
+    }
+}
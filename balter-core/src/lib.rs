@@ -1,9 +1,13 @@
+mod concurrency;
 mod config;
 mod constants;
+mod junit;
 mod metrics;
 mod stats;
 
+pub use concurrency::*;
 pub use config::*;
 pub use constants::*;
+pub use junit::*;
 pub use metrics::*;
 pub use stats::*;
@@ -1,8 +1,19 @@
+use std::time::Duration;
+
 #[derive(Copy, Clone)]
 pub struct TransactionLabels {
     pub success: &'static str,
     pub error: &'static str,
     pub latency: &'static str,
+    pub slo: Option<LatencySlo>,
+}
+
+/// Per-Transaction latency target, set via `#[transaction(latency_slo = "200ms@p99")]`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LatencySlo {
+    pub name: &'static str,
+    pub latency: Duration,
+    pub quantile: f64,
 }
 
 #[macro_export]
@@ -12,6 +23,67 @@ macro_rules! generate_labels {
             success: concat!(stringify!($base_name), "_success"),
             error: concat!(stringify!($base_name), "_error"),
             latency: concat!(stringify!($base_name), "_latency"),
+            slo: None,
+        }
+    };
+    ($base_name:expr, latency_nanos = $nanos:expr, quantile = $quantile:expr) => {
+        ::balter::core::TransactionLabels {
+            success: concat!(stringify!($base_name), "_success"),
+            error: concat!(stringify!($base_name), "_error"),
+            latency: concat!(stringify!($base_name), "_latency"),
+            slo: Some(::balter::core::LatencySlo {
+                name: stringify!($base_name),
+                latency: ::std::time::Duration::from_nanos($nanos),
+                quantile: $quantile,
+            }),
         }
     };
 }
+
+/// Replace the characters of a metric name which statsd (or DogStatsD) exporters would
+/// misinterpret, such as the `:`, `|`, `@` and `#` of its wire format, commas and whitespace,
+/// with `_`. Letters, digits, `_`, `.` and `-` are kept.
+///
+/// NOTE: Prometheus exporters replace `.` and `-` themselves, so sanitized names are valid for
+/// both.
+pub fn sanitize_metric_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether statsd would parse `name` as a metric name, rather than as part of the value,
+    /// type, sample rate or tags of the line.
+    fn is_statsd_name(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_graphic() && !matches!(c, ':' | '|' | '@' | '#' | ','))
+    }
+
+    #[test]
+    fn test_sanitize_metric_name() {
+        assert_eq!(sanitize_metric_name("balter_checkout"), "balter_checkout");
+        assert_eq!(sanitize_metric_name("loadtest.api-v2"), "loadtest.api-v2");
+        assert_eq!(
+            sanitize_metric_name("checkout: EU|prod@p99 #1,2"),
+            "checkout__EU_prod_p99__1_2"
+        );
+        assert_eq!(sanitize_metric_name("café"), "caf_");
+
+        for name in ["checkout: EU|prod@p99 #1,2", "api::get", "tab\tnew\nline"] {
+            assert!(!is_statsd_name(name), "{name}");
+            assert!(is_statsd_name(&sanitize_metric_name(name)), "{name}");
+        }
+    }
+}
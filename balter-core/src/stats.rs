@@ -1,15 +1,608 @@
+#[cfg(feature = "rt")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "rt")]
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use std::collections::HashMap;
+use std::fmt;
 use std::time::Duration;
 
 /// Run Statistics for a given Scenario
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "rt", cfg_eval::cfg_eval, serde_as)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
 pub struct RunStatistics {
     pub concurrency: usize,
     pub goal_tps: u32,
+    /// Successful Transactions per second over the final measurement window (the goodput), which
+    /// is what the goal TPS is measured against.
     pub actual_tps: f64,
+    /// Transactions per second over the final measurement window, including those which returned
+    /// an error. A `total_tps` of 1,000 at a 40% error rate is an `actual_tps` of 600.
+    pub total_tps: f64,
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
     pub latency_p50: Duration,
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
     pub latency_p90: Duration,
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
     pub latency_p95: Duration,
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
     pub latency_p99: Duration,
+    /// The latency distribution has two modes (e.g. cache hits and misses), so a single
+    /// percentile may be misleading.
+    pub latency_multimodal: bool,
+    /// Mean latency of each mode, if `latency_multimodal`.
+    #[cfg_attr(feature = "rt", serde_as(as = "Vec<DurationSecondsWithFrac>"))]
+    pub latency_modes: Vec<Duration>,
     pub error_rate: f64,
     pub tps_limited: bool,
+    /// Slopes of the TPS by concurrency which marked the run `tps_limited`, normalized by the TPS
+    /// per task: `1` is linear scaling and `0` is flat. Each was below the
+    /// `underpowered_slope_threshold()`. Empty if the run wasn't TPS limited, or was limited by
+    /// `at_concurrency()` rather than the target.
+    pub underpowered_evidence: Vec<f64>,
+    /// The final TPS was within the fraction of the goal set by `require_tps()` (always `true`
+    /// if no requirement was set).
+    pub tps_goal_met: bool,
+    /// Error rate goal set by `error_rate()`, if any. Met if `error_rate` is at most the goal.
+    pub error_rate_goal: Option<f64>,
+    /// Latency goal set by `latency()`, if any, against the latency achieved at its quantile
+    /// over the final measurement window.
+    pub latency_goal: Option<SloStatistics>,
+    /// Time-to-first-byte, if recorded via `balter::record_latency("ttfb", ..)`.
+    #[cfg_attr(feature = "rt", serde_as(as = "Option<DurationSecondsWithFrac>"))]
+    pub ttfb_p50: Option<Duration>,
+    #[cfg_attr(feature = "rt", serde_as(as = "Option<DurationSecondsWithFrac>"))]
+    pub ttfb_p99: Option<Duration>,
+    /// The load generator was CPU-bound while TPS limited (requires the `sysinfo` feature).
+    pub generator_saturated: bool,
+    /// Transactions failed as the load generator ran out of file descriptors (or other local
+    /// resources, see `Outcome::ResourceExhausted`), so its concurrency stopped being raised.
+    pub generator_resource_exhausted: bool,
+    /// The concurrency was clamped to the safety limit set by `max_concurrency()`, so the
+    /// Scenario ran fewer tasks than its controllers asked for.
+    pub concurrency_capped: bool,
+    /// Fraction of the TPS limit the rate limiter delivered, over the windows in which it (rather
+    /// than the target) was the bottleneck. `0` if there were none, e.g. for a TPS limited run.
+    pub rate_limiter_accuracy: f64,
+    /// The run was terminated by the `max_duration()` safety cap.
+    pub capped: bool,
+    /// The run was terminated early because a Scenario task panicked.
+    pub failed: bool,
+    /// Number of Scenario tasks which had panicked when the run was terminated. These are
+    /// excluded from `concurrency`.
+    pub panicked_tasks: usize,
+    /// The run was skipped because its `precondition()` didn't hold, so no load was sent.
+    pub skipped: bool,
+    /// The error rate goal was exceeded even at the `tps_floor()`, so the run was terminated
+    /// early.
+    pub error_target_unachievable: bool,
+    /// Seed of the RNGs of the Scenario's tasks, either set via `seed()` or generated for the
+    /// run. `None` if the Scenario didn't run.
+    pub seed: Option<u64>,
+    /// Custom measurements recorded via `balter::record()`, keyed by name.
+    ///
+    /// NOTE: These, `phases` and `error_categories` cover the run (or phase, or sweep step) after
+    /// the Scenario stabilized, excluding its warmup.
+    pub measurements: HashMap<String, MeasurementStatistics>,
+    /// Time spent in the phases of Transactions timed via `balter::phase()`, in seconds, keyed
+    /// by phase name (requires the `phase-timing` feature).
+    pub phases: HashMap<String, MeasurementStatistics>,
+    /// Number of errors by category, for Transactions with `#[transaction(categorize = ...)]`.
+    pub error_categories: HashMap<String, u64>,
+    /// Latency SLOs set via `#[transaction(latency_slo = ...)]`, keyed by Transaction name.
+    pub slos: HashMap<String, SloStatistics>,
+    /// Raw measurement windows of the final sample set, if enabled via `collect_raw_samples()`.
+    pub raw_samples: Option<Vec<SampleData>>,
+    /// Transactions run within `balter::variant()`, keyed by variant name.
+    pub variants: HashMap<String, VariantStatistics>,
+    /// Concurrency the Scenario settled on at each goal TPS it stabilized at, by ascending TPS.
+    pub tps_concurrency_map: Vec<(u32, usize)>,
+    /// First concurrency of an unbounded run (e.g. `concurrency_ramp()`) at which the error rate
+    /// or latency exceeded the limit set by `error_rate()` or `latency()`.
+    pub breaking_concurrency: Option<usize>,
+}
+
+// NOTE: Not derived, as a run without a TPS requirement (or which didn't run) met its goal.
+impl Default for RunStatistics {
+    fn default() -> Self {
+        Self {
+            concurrency: Default::default(),
+            goal_tps: Default::default(),
+            actual_tps: Default::default(),
+            total_tps: Default::default(),
+            latency_p50: Default::default(),
+            latency_p90: Default::default(),
+            latency_p95: Default::default(),
+            latency_p99: Default::default(),
+            latency_multimodal: Default::default(),
+            latency_modes: Default::default(),
+            error_rate: Default::default(),
+            tps_limited: Default::default(),
+            underpowered_evidence: Default::default(),
+            tps_goal_met: true,
+            error_rate_goal: Default::default(),
+            latency_goal: Default::default(),
+            ttfb_p50: Default::default(),
+            ttfb_p99: Default::default(),
+            generator_saturated: Default::default(),
+            generator_resource_exhausted: Default::default(),
+            concurrency_capped: Default::default(),
+            rate_limiter_accuracy: Default::default(),
+            capped: Default::default(),
+            failed: Default::default(),
+            panicked_tasks: Default::default(),
+            skipped: Default::default(),
+            error_target_unachievable: Default::default(),
+            seed: Default::default(),
+            measurements: Default::default(),
+            phases: Default::default(),
+            error_categories: Default::default(),
+            slos: Default::default(),
+            raw_samples: Default::default(),
+            variants: Default::default(),
+            tps_concurrency_map: Default::default(),
+            breaking_concurrency: Default::default(),
+        }
+    }
+}
+
+impl fmt::Display for RunStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn ms(latency: Duration) -> String {
+            format!("{:.2}ms", latency.as_secs_f64() * 1_000.)
+        }
+
+        fn yes_no(val: bool) -> &'static str {
+            if val {
+                "yes"
+            } else {
+                "no"
+            }
+        }
+
+        writeln!(f, "{:<16}{}", "Goal TPS:", self.goal_tps)?;
+        writeln!(f, "{:<16}{:.2}", "Actual TPS:", self.actual_tps)?;
+        writeln!(f, "{:<16}{:.2}%", "Error Rate:", self.error_rate * 100.)?;
+        writeln!(f, "{:<16}{}", "Latency p50:", ms(self.latency_p50))?;
+        writeln!(f, "{:<16}{}", "Latency p90:", ms(self.latency_p90))?;
+        writeln!(f, "{:<16}{}", "Latency p95:", ms(self.latency_p95))?;
+        writeln!(f, "{:<16}{}", "Latency p99:", ms(self.latency_p99))?;
+        if self.latency_multimodal {
+            let modes: Vec<_> = self.latency_modes.iter().map(|mode| ms(*mode)).collect();
+            writeln!(f, "{:<16}{}", "Latency Modes:", modes.join(", "))?;
+        }
+        if let Some(ttfb_p50) = self.ttfb_p50 {
+            writeln!(f, "{:<16}{}", "TTFB p50:", ms(ttfb_p50))?;
+        }
+        if let Some(ttfb_p99) = self.ttfb_p99 {
+            writeln!(f, "{:<16}{}", "TTFB p99:", ms(ttfb_p99))?;
+        }
+        writeln!(f, "{:<16}{}", "Concurrency:", self.concurrency)?;
+        write!(f, "{:<16}{}", "TPS Limited:", yes_no(self.tps_limited))?;
+
+        if !self.underpowered_evidence.is_empty() {
+            let slopes: Vec<_> = self
+                .underpowered_evidence
+                .iter()
+                .map(|slope| format!("{slope:.3}"))
+                .collect();
+            write!(f, "\n{:<16}{}", "Slopes:", slopes.join(", "))?;
+        }
+
+        if self.generator_saturated {
+            write!(
+                f,
+                "\n{:<16}{}",
+                "CPU Saturated:",
+                yes_no(self.generator_saturated)
+            )?;
+        }
+
+        if self.generator_resource_exhausted {
+            write!(
+                f,
+                "\n{:<16}{}",
+                "FDs Exhausted:",
+                yes_no(self.generator_resource_exhausted)
+            )?;
+        }
+
+        if self.concurrency_capped {
+            write!(
+                f,
+                "\n{:<16}{}",
+                "Tasks Capped:",
+                yes_no(self.concurrency_capped)
+            )?;
+        }
+
+        if self.rate_limiter_accuracy > 0. {
+            write!(
+                f,
+                "\n{:<16}{:.2}%",
+                "Limiter:",
+                self.rate_limiter_accuracy * 100.
+            )?;
+        }
+
+        if let Some(concurrency) = self.breaking_concurrency {
+            write!(f, "\n{:<16}{}", "Breaking at:", concurrency)?;
+        }
+
+        if self.capped {
+            write!(f, "\n{:<16}{}", "Capped:", yes_no(self.capped))?;
+        }
+
+        if self.failed {
+            write!(f, "\n{:<16}{}", "Failed:", yes_no(self.failed))?;
+            write!(f, "\n{:<16}{}", "Panicked Tasks:", self.panicked_tasks)?;
+        }
+
+        if self.skipped {
+            write!(f, "\n{:<16}{}", "Skipped:", yes_no(self.skipped))?;
+        }
+
+        if !self.tps_concurrency_map.is_empty() {
+            write!(f, "\nConcurrency by TPS:")?;
+            for (tps, concurrency) in &self.tps_concurrency_map {
+                write!(f, "\n  {tps} TPS: {concurrency}")?;
+            }
+        }
+
+        if !self.measurements.is_empty() {
+            write!(f, "\nMeasurements:")?;
+
+            let mut names: Vec<_> = self.measurements.keys().collect();
+            names.sort();
+            for name in names {
+                let m = &self.measurements[name];
+                write!(
+                    f,
+                    "\n  {name}: count={}, mean={:.2}, min={:.2}, max={:.2}, p50={:.2}, p90={:.2}, p99={:.2}",
+                    m.count, m.mean, m.min, m.max, m.p50, m.p90, m.p99
+                )?;
+            }
+        }
+
+        if !self.phases.is_empty() {
+            write!(f, "\nPhases:")?;
+
+            let mut names: Vec<_> = self.phases.keys().collect();
+            names.sort();
+            for name in names {
+                let p = &self.phases[name];
+                write!(
+                    f,
+                    "\n  {name}: count={}, mean={}, p99={}",
+                    p.count,
+                    ms(Duration::from_secs_f64(p.mean)),
+                    ms(Duration::from_secs_f64(p.p99))
+                )?;
+            }
+        }
+
+        if !self.error_categories.is_empty() {
+            write!(f, "\nError Categories:")?;
+
+            let total: u64 = self.error_categories.values().sum();
+            let mut categories: Vec<_> = self.error_categories.iter().collect();
+            // NOTE: Most common first, so that the main cause of errors stands out.
+            categories.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+            for (category, count) in categories {
+                write!(
+                    f,
+                    "\n  {category}: {count} ({:.2}%)",
+                    *count as f64 / total as f64 * 100.
+                )?;
+            }
+        }
+
+        if !self.slos.is_empty() {
+            write!(f, "\nLatency SLOs:")?;
+
+            let mut names: Vec<_> = self.slos.keys().collect();
+            names.sort();
+            for name in names {
+                let slo = &self.slos[name];
+                write!(
+                    f,
+                    "\n  {name}: p{}={} (target {}, {})",
+                    slo.quantile * 100.,
+                    ms(slo.achieved),
+                    ms(slo.target),
+                    if slo.is_met() { "met" } else { "violated" }
+                )?;
+            }
+        }
+
+        if !self.variants.is_empty() {
+            write!(f, "\nVariants:")?;
+
+            let mut names: Vec<_> = self.variants.keys().collect();
+            names.sort();
+            for name in names {
+                let v = &self.variants[name];
+                write!(
+                    f,
+                    "\n  {name}: tps={:.2}, error_rate={:.2}%, p50={}, p90={}, p99={}",
+                    v.actual_tps,
+                    v.error_rate * 100.,
+                    ms(v.latency_p50),
+                    ms(v.latency_p90),
+                    ms(v.latency_p99)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RunStatistics {
+    /// Total time spent in each phase (see [`phases`](Self::phases)) in the folded stack format,
+    /// one `phase microseconds` line per phase, which flamegraph renderers (e.g. `inferno` or
+    /// `flamegraph.pl`) draw as a breakdown of where time went within the Transactions.
+    pub fn folded_phases(&self) -> String {
+        let mut names: Vec<_> = self.phases.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let p = &self.phases[name];
+                let total_us = (p.mean * p.count as f64 * 1_000_000.).round() as u64;
+                format!("{name} {total_us}\n")
+            })
+            .collect()
+    }
+}
+
+/// Achieved latency of a Transaction against its latency SLO.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "rt", cfg_eval::cfg_eval, serde_as)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
+pub struct SloStatistics {
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
+    pub target: Duration,
+    pub quantile: f64,
+    /// Latency at `quantile` over the final measurement window.
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
+    pub achieved: Duration,
+}
+
+impl SloStatistics {
+    pub fn is_met(&self) -> bool {
+        self.achieved <= self.target
+    }
+}
+
+/// TPS, error rate and latency of the Transactions run within a single variant, over the final
+/// measurement window.
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "rt", cfg_eval::cfg_eval, serde_as)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
+pub struct VariantStatistics {
+    pub actual_tps: f64,
+    pub error_rate: f64,
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
+    pub latency_p50: Duration,
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
+    pub latency_p90: Duration,
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
+    pub latency_p99: Duration,
+}
+
+/// Raw counts of a single measurement window, for offline analysis.
+///
+/// The counts are of that window alone, not cumulative over the run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "rt", cfg_eval::cfg_eval, serde_as)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
+pub struct SampleData {
+    pub success: u64,
+    pub error: u64,
+    #[cfg_attr(feature = "rt", serde_as(as = "DurationSecondsWithFrac"))]
+    pub elapsed: Duration,
+}
+
+impl SampleData {
+    /// Successful Transactions per second over the window.
+    pub fn goodput(&self) -> f64 {
+        per_sec(self.success, self.elapsed)
+    }
+
+    /// Transactions per second over the window, including those which returned an error.
+    pub fn tps(&self) -> f64 {
+        per_sec(self.success + self.error, self.elapsed)
+    }
+}
+
+/// Rate of `count` over `elapsed`, or zero for an empty window.
+pub fn per_sec(count: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0. {
+        count as f64 / secs
+    } else {
+        0.
+    }
+}
+
+/// Summary of a custom measurement over the whole run.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "rt", derive(Serialize, Deserialize))]
+pub struct MeasurementStatistics {
+    pub count: u64,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_statistics_display() {
+        let stats = RunStatistics {
+            concurrency: 20,
+            goal_tps: 10_000,
+            actual_tps: 9_985.214,
+            latency_p50: Duration::from_micros(1_230),
+            latency_p90: Duration::from_micros(2_500),
+            latency_p95: Duration::from_millis(3),
+            latency_p99: Duration::from_micros(10_125),
+            latency_multimodal: true,
+            latency_modes: vec![Duration::from_micros(1_100), Duration::from_micros(9_800)],
+            error_rate: 0.0052,
+            tps_limited: true,
+            underpowered_evidence: vec![0.021, 0.0294],
+            rate_limiter_accuracy: 0.9952,
+            measurements: HashMap::from([(
+                "cart_size".to_string(),
+                MeasurementStatistics {
+                    count: 100,
+                    mean: 3.5,
+                    min: 1.,
+                    max: 8.,
+                    p50: 3.,
+                    p90: 6.,
+                    p99: 8.,
+                },
+            )]),
+            error_categories: HashMap::from([
+                ("timeout".to_string(), 30),
+                ("server_error".to_string(), 15),
+                ("connection_refused".to_string(), 5),
+            ]),
+            slos: HashMap::from([(
+                "search".to_string(),
+                SloStatistics {
+                    target: Duration::from_millis(200),
+                    quantile: 0.99,
+                    achieved: Duration::from_micros(185_500),
+                },
+            )]),
+            variants: HashMap::from([
+                (
+                    "A".to_string(),
+                    VariantStatistics {
+                        actual_tps: 4_990.5,
+                        error_rate: 0.001,
+                        latency_p50: Duration::from_micros(1_100),
+                        latency_p90: Duration::from_micros(2_200),
+                        latency_p99: Duration::from_micros(8_000),
+                    },
+                ),
+                (
+                    "B".to_string(),
+                    VariantStatistics {
+                        actual_tps: 4_994.714,
+                        error_rate: 0.0094,
+                        latency_p50: Duration::from_micros(1_400),
+                        latency_p90: Duration::from_micros(2_900),
+                        latency_p99: Duration::from_micros(12_250),
+                    },
+                ),
+            ]),
+            tps_concurrency_map: vec![(2_500, 6), (5_000, 11), (10_000, 20)],
+            phases: HashMap::from([
+                ("serialize".to_string(), phase(100, 0.000_150, 0.000_400)),
+                ("acquire_connection".to_string(), phase(100, 0.002, 0.012)),
+            ]),
+            ..Default::default()
+        };
+
+        insta::assert_snapshot!(stats.to_string());
+    }
+
+    #[test]
+    fn test_sample_data_goodput() {
+        let sample = SampleData {
+            success: 1_200,
+            error: 800,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(sample.tps(), 1_000.);
+        assert_eq!(sample.goodput(), 600.);
+
+        let empty = SampleData {
+            success: 0,
+            error: 0,
+            elapsed: Duration::ZERO,
+        };
+        assert_eq!(empty.goodput(), 0.);
+    }
+
+    fn phase(count: u64, mean: f64, p99: f64) -> MeasurementStatistics {
+        MeasurementStatistics {
+            count,
+            mean,
+            p99,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_folded_phases() {
+        let stats = RunStatistics {
+            phases: HashMap::from([
+                ("serialize".to_string(), phase(100, 0.000_150, 0.000_400)),
+                ("acquire_connection".to_string(), phase(100, 0.002, 0.012)),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            stats.folded_phases(),
+            "acquire_connection 200000\nserialize 15000\n"
+        );
+        assert_eq!(RunStatistics::default().folded_phases(), "");
+    }
+
+    // NOTE: Serialization is only derived with the `rt` feature.
+    #[cfg(feature = "rt")]
+    #[test]
+    fn test_run_statistics_serialization() {
+        insta::assert_json_snapshot!(RunStatistics {
+            concurrency: 20,
+            goal_tps: 10_000,
+            actual_tps: 9_985.25,
+            latency_p50: Duration::from_micros(1_250),
+            latency_p90: Duration::from_micros(2_500),
+            latency_p95: Duration::from_millis(3),
+            latency_p99: Duration::from_millis(10),
+            error_rate: 0.005,
+            tps_goal_met: true,
+            ttfb_p50: Some(Duration::from_micros(500)),
+            slos: HashMap::from([(
+                "search".to_string(),
+                SloStatistics {
+                    target: Duration::from_millis(200),
+                    quantile: 0.99,
+                    achieved: Duration::from_millis(185),
+                },
+            )]),
+            tps_concurrency_map: vec![(5_000, 11), (10_000, 20)],
+            ..Default::default()
+        });
+    }
+
+    #[cfg(feature = "rt")]
+    #[test]
+    fn test_sample_data_serialization() {
+        insta::assert_json_snapshot!(vec![
+            SampleData {
+                success: 998,
+                error: 2,
+                elapsed: Duration::from_millis(1_000),
+            },
+            SampleData {
+                success: 1_003,
+                error: 0,
+                elapsed: Duration::from_millis(1_002),
+            },
+        ]);
+    }
 }
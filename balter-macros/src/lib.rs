@@ -1,11 +1,13 @@
 use proc_macro::TokenStream;
 use proc_macro2::{Span, TokenStream as TokenStream2};
 use quote::quote;
-use syn::{Ident, ItemFn};
+use syn::meta::ParseNestedMeta;
+use syn::parse::Parser;
+use syn::{Ident, ItemFn, LitInt, LitStr, Path};
 
 /// Proc macro to denote a Transaction
 ///
-/// NOTE: Currently this macro only works on functions with a `Result<T, E>` return value. This is a
+/// NOTE: Currently this macro only works on async functions with a `Result<T, E>` return value. This is a
 /// restriction which will be lifted soon.
 ///
 /// # Example
@@ -13,7 +15,96 @@ use syn::{Ident, ItemFn};
 /// use balter::prelude::*;
 ///
 /// #[transaction]
-/// fn my_transaction(arg_1: u32, arg_2: &str) -> Result<String, MyError> {
+/// async fn my_transaction(arg_1: u32, arg_2: &str) -> Result<String, MyError> {
+///     ...
+/// }
+/// ```
+///
+/// # Arguments
+/// - `record = path::to::fn`: Call `fn(&T) -> f64` on every successful return value and record
+///   the result as a custom measurement named `<transaction>_record`. See `balter::record()`.
+///
+/// - `latency_slo = "<latency>@p<quantile>"`: A latency target for this Transaction (e.g.
+///   `"200ms@p99"`, units are `ns`, `us`, `ms` or `s`). TPS is scaled down if any Transaction's
+///   SLO is violated, and the achieved latency is reported in `RunStatistics::slos`.
+///
+/// - `categorize = path::to::fn`: Call `fn(&E) -> &'static str` on every error and count it
+///   under the returned category (e.g. `"timeout"` or `"server_error"`), for a breakdown of the
+///   errors in `RunStatistics::error_categories`. Results which `classify()` doesn't count as
+///   an error are skipped.
+///
+/// - `streaming`: For streaming calls (such as gRPC server-streaming) the body returns a
+///   `Stream` of `Result<T, E>` rather than a single `Result<T, E>`, and each item of the stream
+///   is measured as its own Transaction. TPS is then messages per second rather than calls per
+///   second, and latency is the time between messages. Not supported with `record` or
+///   `categorize`.
+///
+/// - `nested`: For Transactions called from within another Transaction (e.g. the steps of a
+///   composite operation). By default every Transaction counts towards the Scenario's TPS,
+///   latency and error rate and is subject to the TPS limit, including those called from within
+///   another Transaction, so a composite operation of three steps counts as four Transactions.
+///   A `nested` Transaction called from within another Transaction is only recorded under its
+///   own metrics (and latency SLO), and only the enclosing Transaction counts. Called directly
+///   from a Scenario, it is a regular Transaction. Not supported with `streaming`.
+///
+/// - `retries = <n>`: Retry the body up to `n` times while its result counts as an error (see
+///   `classify()`), like a client with retries would. Only the final result is recorded, so a
+///   call which succeeds on a retry counts as a single success, and its latency is the total
+///   over every attempt, including the backoff between them. The body is run once per attempt,
+///   so it can only borrow the Transaction's arguments. Not supported with `streaming`.
+///
+/// - `backoff = "<duration>"`: Wait before the first retry (e.g. `"50ms"`, units are `ns`,
+///   `us`, `ms` or `s`), doubling the wait for each retry after it. No wait by default. Requires
+///   `retries`.
+///
+/// ```ignore
+/// use balter::prelude::*;
+///
+/// fn body_len(body: &String) -> f64 {
+///     body.len() as f64
+/// }
+///
+/// #[transaction(record = body_len)]
+/// async fn get_body() -> Result<String, MyError> {
+///     ...
+/// }
+///
+/// fn error_category(err: &MyError) -> &'static str {
+///     match err {
+///         MyError::Timeout => "timeout",
+///         MyError::Status(status) if *status >= 500 => "server_error",
+///         _ => "other",
+///     }
+/// }
+///
+/// #[transaction(categorize = error_category)]
+/// async fn get_user() -> Result<User, MyError> {
+///     ...
+/// }
+///
+/// #[transaction(latency_slo = "200ms@p99")]
+/// async fn search() -> Result<String, MyError> {
+///     ...
+/// }
+///
+/// #[transaction(retries = 3, backoff = "50ms")]
+/// async fn get_order() -> Result<Order, MyError> {
+///     ...
+/// }
+///
+/// #[transaction(streaming)]
+/// async fn subscribe() -> impl Stream<Item = Result<Event, MyError>> {
+///     ...
+/// }
+///
+/// #[transaction]
+/// async fn checkout() -> Result<(), MyError> {
+///     add_to_cart().await?;
+///     pay().await
+/// }
+///
+/// #[transaction(nested)]
+/// async fn add_to_cart() -> Result<(), MyError> {
 ///     ...
 /// }
 /// ```
@@ -22,7 +113,109 @@ pub fn transaction(attr: TokenStream, item: TokenStream) -> TokenStream {
     transaction_internal(attr, item).into()
 }
 
-fn transaction_internal(_attr: TokenStream, item: TokenStream) -> TokenStream2 {
+#[derive(Default)]
+struct TransactionArgs {
+    record: Option<Path>,
+    categorize: Option<Path>,
+    latency_slo: Option<(u64, f64)>,
+    streaming: bool,
+    nested: bool,
+    retries: Option<u32>,
+    backoff: Option<u64>,
+}
+
+impl TransactionArgs {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("record") {
+            self.record = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("categorize") {
+            self.categorize = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("latency_slo") {
+            let slo: LitStr = meta.value()?.parse()?;
+            let parsed =
+                parse_latency_slo(&slo.value()).map_err(|err| syn::Error::new(slo.span(), err))?;
+            self.latency_slo = Some(parsed);
+            Ok(())
+        } else if meta.path.is_ident("streaming") {
+            self.streaming = true;
+            Ok(())
+        } else if meta.path.is_ident("nested") {
+            self.nested = true;
+            Ok(())
+        } else if meta.path.is_ident("retries") {
+            let retries: LitInt = meta.value()?.parse()?;
+            self.retries = Some(retries.base10_parse()?);
+            Ok(())
+        } else if meta.path.is_ident("backoff") {
+            let backoff: LitStr = meta.value()?.parse()?;
+            let parsed = parse_nanos(&backoff.value()).ok_or_else(|| {
+                syn::Error::new(
+                    backoff.span(),
+                    format!(
+                        "invalid backoff `{}`, expected e.g. \"50ms\"",
+                        backoff.value()
+                    ),
+                )
+            })?;
+            self.backoff = Some(parsed);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported transaction attribute"))
+        }
+    }
+}
+
+/// Parse a latency SLO of the form `200ms@p99` into nanoseconds and a quantile.
+fn parse_latency_slo(slo: &str) -> Result<(u64, f64), String> {
+    let invalid = || format!("invalid latency_slo `{slo}`, expected e.g. \"200ms@p99\"");
+
+    let (latency, quantile) = slo.split_once('@').ok_or_else(invalid)?;
+    let nanos = parse_nanos(latency).ok_or_else(invalid)?;
+
+    let percentile: f64 = quantile
+        .trim()
+        .strip_prefix('p')
+        .ok_or_else(invalid)?
+        .parse()
+        .map_err(|_| invalid())?;
+    let quantile = percentile / 100.;
+
+    if !(nanos > 0 && quantile > 0. && quantile < 1.) {
+        return Err(invalid());
+    }
+
+    Ok((nanos, quantile))
+}
+
+/// Parse a duration such as `200ms` into nanoseconds.
+fn parse_nanos(duration: &str) -> Option<u64> {
+    let duration = duration.trim();
+    let unit_idx = duration.find(|c: char| c.is_ascii_alphabetic())?;
+    let (value, unit) = duration.split_at(unit_idx);
+    let value: f64 = value.trim().parse().ok()?;
+    let nanos_per_unit = match unit {
+        "ns" => 1.,
+        "us" => 1e3,
+        "ms" => 1e6,
+        "s" => 1e9,
+        _ => return None,
+    };
+
+    if !(value >= 0. && value.is_finite()) {
+        return None;
+    }
+    Some((value * nanos_per_unit).round() as u64)
+}
+
+fn transaction_internal(attr: TokenStream, item: TokenStream) -> TokenStream2 {
+    let mut args = TransactionArgs::default();
+    let parser = syn::meta::parser(|meta| args.parse(meta));
+    if let Err(err) = parser.parse(attr) {
+        return err.to_compile_error();
+    }
+
     let input = syn::parse::<ItemFn>(item).unwrap();
 
     let ItemFn {
@@ -33,19 +226,127 @@ fn transaction_internal(_attr: TokenStream, item: TokenStream) -> TokenStream2 {
     } = input;
     let stmts = &block.stmts;
 
+    // NOTE: The body is wrapped in an `async move` block and awaited, which would otherwise fail
+    // with an error about `.await` outside of an async context.
+    if sig.asyncness.is_none() {
+        return syn::Error::new_spanned(sig.fn_token, "#[transaction] requires an async fn")
+            .to_compile_error();
+    }
+
     let ident = &sig.ident;
+    let labels = match args.latency_slo {
+        Some((nanos, quantile)) => quote! {
+            ::balter::core::generate_labels!(#ident, latency_nanos = #nanos, quantile = #quantile)
+        },
+        None => quote! { ::balter::core::generate_labels!(#ident) },
+    };
+
+    if args.backoff.is_some() && args.retries.is_none() {
+        return syn::Error::new(Span::call_site(), "backoff requires retries").to_compile_error();
+    }
+
+    if args.streaming {
+        if args.retries.is_some() {
+            return syn::Error::new(
+                Span::call_site(),
+                "retries is not supported for streaming transactions",
+            )
+            .to_compile_error();
+        }
+
+        if args.nested {
+            return syn::Error::new(
+                Span::call_site(),
+                "nested is not supported for streaming transactions",
+            )
+            .to_compile_error();
+        }
+
+        if let Some(record) = args.record {
+            return syn::Error::new_spanned(
+                record,
+                "record is not supported for streaming transactions",
+            )
+            .to_compile_error();
+        }
+
+        if let Some(categorize) = args.categorize {
+            return syn::Error::new_spanned(
+                categorize,
+                "categorize is not supported for streaming transactions",
+            )
+            .to_compile_error();
+        }
+
+        return quote! {
+            #(#attrs)* #vis #sig {
+                ::balter::transaction::transaction_stream_hook(#labels, async move {
+                    #(#stmts)*
+                }).await
+            }
+        };
+    }
+
+    let hook = if args.nested {
+        quote! { ::balter::transaction::nested_transaction_hook }
+    } else {
+        quote! { ::balter::transaction::transaction_hook }
+    };
+
+    // NOTE: With retries, the body is run once per attempt, so it can't move the arguments.
+    let body = match args.retries {
+        Some(retries) => {
+            let backoff = args.backoff.unwrap_or(0);
+            quote! {
+                ::balter::transaction::retry_hook(#retries, #backoff, || async {
+                    #(#stmts)*
+                })
+            }
+        }
+        None => quote! {
+            async move {
+                #(#stmts)*
+            }
+        },
+    };
+
+    if args.record.is_none() && args.categorize.is_none() {
+        return quote! {
+            #(#attrs)* #vis #sig {
+                #hook(#labels, #body).await
+            }
+        };
+    }
+
+    let record = args.record.map(|record| {
+        quote! {
+            if let Ok(value) = &res {
+                ::balter::record(concat!(stringify!(#ident), "_record"), #record(value));
+            }
+        }
+    });
+    let nested = args.nested;
+    let categorize = args.categorize.map(|categorize| {
+        quote! {
+            ::balter::transaction::categorize_error(#nested, &res, #categorize);
+        }
+    });
+
     quote! {
         #(#attrs)* #vis #sig {
-            ::balter::transaction::transaction_hook(::balter::core::generate_labels!(#ident), async move {
-                #(#stmts)*
-            }).await
+            let res = #hook(#labels, #body).await;
+
+            #record
+            #categorize
+
+            res
         }
     }
 }
 
 /// Proc macro to denote a Scenario
 ///
-/// NOTE: Currently this macro only works on functions which take no arguments and with no return value.
+/// NOTE: Currently this macro only works on async functions which take no arguments and with no return value.
 /// (void functions). This is a restriction which will be lifted soon.
 ///
 /// See the `Scenario` struct for more information on the methods this macro provides on functions.
@@ -55,7 +356,7 @@ fn transaction_internal(_attr: TokenStream, item: TokenStream) -> TokenStream2 {
 /// use balter::prelude::*;
 ///
 /// #[scenario]
-/// fn my_scenario() {
+/// async fn my_scenario() {
 /// }
 /// ```
 #[proc_macro_attribute]
@@ -65,7 +366,7 @@ pub fn scenario(attr: TokenStream, item: TokenStream) -> TokenStream {
 
 /// Proc macro to denote a Scenario
 ///
-/// NOTE: Currently this macro only works on functions which take no arguments and with no return value.
+/// NOTE: Currently this macro only works on async functions which take no arguments and with no return value.
 /// (void functions). This is a restriction which will be lifted soon.
 ///
 /// See the `Scenario` struct for more information on the methods this macro provides on functions.
@@ -75,7 +376,7 @@ pub fn scenario(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// use balter::prelude::*;
 ///
 /// #[scenario]
-/// fn my_scenario() {
+/// async fn my_scenario() {
 /// }
 /// ```
 #[proc_macro_attribute]
@@ -94,6 +395,11 @@ fn scenario_internal(_attr: TokenStream, item: TokenStream, linkme: bool) -> Tok
     } = input;
     let stmts = &block.stmts;
 
+    if sig.asyncness.is_none() {
+        return syn::Error::new_spanned(sig.fn_token, "#[scenario] requires an async fn")
+            .to_compile_error();
+    }
+
     let new_name = Ident::new(&format!("__balter_{}", sig.ident), Span::call_site());
     let mut new_sig = sig.clone();
     new_sig.ident = new_name.clone();
@@ -153,3 +459,64 @@ fn scenario_internal(_attr: TokenStream, item: TokenStream, linkme: bool) -> Tok
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nanos_units() {
+        assert_eq!(parse_nanos("150ns"), Some(150));
+        assert_eq!(parse_nanos("20us"), Some(20_000));
+        assert_eq!(parse_nanos("50ms"), Some(50_000_000));
+        assert_eq!(parse_nanos("2s"), Some(2_000_000_000));
+        assert_eq!(parse_nanos("1.5ms"), Some(1_500_000));
+        assert_eq!(parse_nanos(" 10 ms "), Some(10_000_000));
+    }
+
+    #[test]
+    fn test_parse_nanos_invalid() {
+        assert_eq!(parse_nanos("50"), None);
+        assert_eq!(parse_nanos("50m"), None);
+        assert_eq!(parse_nanos("50min"), None);
+        assert_eq!(parse_nanos("ms"), None);
+        assert_eq!(parse_nanos("-5ms"), None);
+        assert_eq!(parse_nanos("infms"), None);
+        assert_eq!(parse_nanos(""), None);
+    }
+
+    #[test]
+    fn test_parse_latency_slo() {
+        assert_eq!(parse_latency_slo("200ms@p99"), Ok((200_000_000, 0.99)));
+        assert_eq!(parse_latency_slo("1s@p50"), Ok((1_000_000_000, 0.5)));
+
+        let (nanos, quantile) = parse_latency_slo("500us @ p99.9").unwrap();
+        assert_eq!(nanos, 500_000);
+        assert!((quantile - 0.999).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_parse_latency_slo_quantile_range() {
+        assert!(parse_latency_slo("200ms@p0").is_err());
+        assert!(parse_latency_slo("200ms@p100").is_err());
+        assert!(parse_latency_slo("200ms@p150").is_err());
+        assert!(parse_latency_slo("200ms@p-10").is_err());
+        assert!(parse_latency_slo("0ms@p99").is_err());
+    }
+
+    #[test]
+    fn test_parse_latency_slo_malformed() {
+        assert!(parse_latency_slo("200ms").is_err());
+        assert!(parse_latency_slo("200ms@99").is_err());
+        assert!(parse_latency_slo("200ms@pxx").is_err());
+        assert!(parse_latency_slo("200@p99").is_err());
+        assert!(parse_latency_slo("200h@p99").is_err());
+        assert!(parse_latency_slo("@p99").is_err());
+        assert!(parse_latency_slo("").is_err());
+
+        assert_eq!(
+            parse_latency_slo("200@p99"),
+            Err("invalid latency_slo `200@p99`, expected e.g. \"200ms@p99\"".to_string())
+        );
+    }
+}
@@ -0,0 +1,8 @@
+use balter_macros::transaction;
+
+#[transaction(latency_slo = "200ms@p100")]
+async fn my_transaction() -> Result<(), String> {
+    Ok(())
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use balter_macros::transaction;
+
+#[transaction(record = "body_len")]
+async fn my_transaction() -> Result<String, String> {
+    Ok(String::new())
+}
+
+fn main() {}
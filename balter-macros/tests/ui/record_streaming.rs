@@ -0,0 +1,12 @@
+use balter_macros::transaction;
+
+fn body_len(body: &String) -> f64 {
+    body.len() as f64
+}
+
+#[transaction(streaming, record = body_len)]
+async fn my_transaction() -> Result<String, String> {
+    Ok(String::new())
+}
+
+fn main() {}
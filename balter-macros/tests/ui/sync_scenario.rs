@@ -0,0 +1,6 @@
+use balter_macros::scenario;
+
+#[scenario]
+fn my_scenario() {}
+
+fn main() {}
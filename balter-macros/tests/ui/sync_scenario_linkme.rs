@@ -0,0 +1,6 @@
+use balter_macros::scenario_linkme;
+
+#[scenario_linkme]
+fn my_scenario() {}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use balter_macros::transaction;
+
+#[transaction]
+fn my_transaction() -> Result<(), String> {
+    Ok(())
+}
+
+fn main() {}
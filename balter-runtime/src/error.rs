@@ -6,6 +6,12 @@ pub(crate) enum RuntimeError {
     #[error("No scenario found")]
     NoScenario,
 
+    #[error("Scenario {name} has a goal of {goal} TPS, which exceeds the max global TPS of {cap}")]
+    ExceedsMaxGlobalTps { name: String, goal: u32, cap: u32 },
+
+    #[error("Server is draining, and no longer accepts new Scenarios")]
+    Draining,
+
     #[error("Helper task channel closed unexpectedly.")]
     ChannelClosed,
 
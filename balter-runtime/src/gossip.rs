@@ -1,50 +1,132 @@
 use crate::error::RuntimeError;
-use balter_core::ScenarioConfig;
+use crate::runtime::cap;
+use balter_core::{RunStatistics, ScenarioConfig};
 use interchange::GossipStream;
 use message::{Handshake, Message};
+use std::collections::BTreeSet;
 use std::net::SocketAddr;
+use std::num::NonZeroU32;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::Instant;
 use tokio_tungstenite::connect_async;
-use tracing::debug;
+use tracing::{debug, info, warn};
 use url::Url;
 use uuid::Uuid;
 
+mod backoff;
 mod data;
 mod error;
 mod interchange;
 pub(crate) mod message;
 mod protocol;
 
+use backoff::PeerBackoff;
 pub(crate) use data::{GossipData, PeerInfo};
 pub(crate) use error::GossipError;
 
+// TODO: This gossip interval rate is arbitrary at this point. It would be nice to either
+// ground this in a value that has some meaning, make it auto-adjusting, or even just have it
+// as a parameter.
+const GOSSIP_INTERVAL: Duration = Duration::from_secs(5);
+
 pub(crate) async fn gossip_task(gossip: Gossip) -> Result<(), GossipError> {
-    // TODO: This gossip interval rate is arbitrary at this point. It would be nice to either
-    // ground this in a value that has some meaning, make it auto-adjusting, or even just have it
-    // as a parameter.
-    let mut interval = tokio::time::interval(Duration::from_millis(5000));
+    let mut backoff = PeerBackoff::new();
+    let mut next_gossip = Instant::now();
 
     loop {
-        interval.tick().await;
+        // NOTE: Peers which are backing off are retried as soon as their back-off expires,
+        // rather than waiting to be randomly selected, so that a recovering peer is reconnected
+        // promptly.
+        let wake = backoff
+            .next_retry()
+            .map_or(next_gossip, |retry| retry.min(next_gossip));
+        tokio::time::sleep_until(wake).await;
 
-        let peer = { gossip.data.lock()?.select_random_peer() };
-        if let Some(peer) = peer {
-            let mut stream = peer_stream(&peer).await?;
-            gossip.request_sync(&mut stream, peer.addr).await?;
+        let now = Instant::now();
+        let peer = if let Some(server_id) = backoff.due(now) {
+            let peer = gossip.data.lock()?.peer(&server_id);
+            if peer.is_none() {
+                backoff.forget(&server_id);
+            }
+            peer
+        } else if now >= next_gossip {
+            next_gossip = now + GOSSIP_INTERVAL;
+            let peer = gossip
+                .data
+                .lock()?
+                .select_random_peer(|server_id| backoff.is_ready(server_id, now));
+            if peer.is_none() {
+                debug!("No peers to gossip with.");
+            }
+            peer
         } else {
-            debug!("No peers to gossip with.");
+            None
+        };
+
+        if let Some(peer) = peer {
+            let (tx_count, tx_finished) = cap::local_transactions();
+            gossip.data.lock()?.set_tx_count(tx_count, tx_finished);
+            gossip_with_peer(&gossip, &mut backoff, &peer).await;
+            let data = gossip.data.lock()?;
+            cap::set_peer_transactions(data.peer_tx_count(), data.peer_tx_running());
         }
     }
 }
 
-type SpawnHook = fn(ScenarioConfig) -> Result<(), RuntimeError>;
+/// Gossip with a single peer, backing off from it on failure. Returns the delay until the peer is
+/// retried if it failed.
+async fn gossip_with_peer(
+    gossip: &Gossip,
+    backoff: &mut PeerBackoff,
+    peer: &PeerInfo,
+) -> Option<Duration> {
+    let res = async {
+        let mut stream = peer_stream(peer).await?;
+        gossip.request_sync(&mut stream, peer.addr).await
+    }
+    .await;
+
+    match res {
+        Ok(()) => {
+            if backoff.success(peer.server_id) {
+                info!("Reconnected to peer {}", peer.addr);
+            }
+            None
+        }
+        Err(err) => {
+            let delay = backoff.failure(peer.server_id, Instant::now());
+            warn!(
+                "Unable to gossip with peer {}: {err}. Retrying in {delay:?}.",
+                peer.addr
+            );
+
+            // NOTE: Otherwise a crashed peer keeps its last gossiped state (e.g. free) forever,
+            // and keeps being asked for help.
+            if backoff.is_unreachable(&peer.server_id) {
+                if let Ok(mut data) = gossip.data.lock() {
+                    if data.mark_unreachable(&peer.server_id) {
+                        warn!("Peer {} is unreachable.", peer.addr);
+                    }
+                }
+            }
+            Some(delay)
+        }
+    }
+}
+
+type SpawnHook = fn(ScenarioConfig) -> Result<JoinHandle<RunStatistics>, RuntimeError>;
 
 #[derive(Clone)]
 pub(crate) struct Gossip {
     server_id: Uuid,
     pub data: Arc<Mutex<GossipData>>,
     scenario_spawn_hook: SpawnHook,
+    max_global_tps: Option<NonZeroU32>,
+    added_latency: Option<Duration>,
+    results_db: Option<String>,
+    running: Arc<Mutex<Vec<AbortHandle>>>,
 }
 
 impl Gossip {
@@ -53,7 +135,84 @@ impl Gossip {
             data: Arc::new(Mutex::new(GossipData::new(server_id, port))),
             server_id,
             scenario_spawn_hook,
+            max_global_tps: None,
+            added_latency: None,
+            results_db: None,
+            running: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Tags advertised to peers, which Scenarios can require in order to delegate work here.
+    pub fn with_tags(self, tags: BTreeSet<String>) -> Self {
+        if let Ok(mut data) = self.data.lock() {
+            data.set_tags(tags);
+        }
+        self
+    }
+
+    /// Safety limit on the goal TPS of any Scenario run on this server, see
+    /// [`BalterRuntime::max_global_tps()`](crate::runtime::BalterRuntime::max_global_tps).
+    pub fn with_max_global_tps(mut self, max_global_tps: Option<NonZeroU32>) -> Self {
+        self.max_global_tps = max_global_tps;
+        self
+    }
+
+    pub fn max_global_tps(&self) -> Option<NonZeroU32> {
+        self.max_global_tps
+    }
+
+    /// Latency added to each Transaction run on this server, see
+    /// [`BalterRuntime::added_latency()`](crate::runtime::BalterRuntime::added_latency).
+    pub fn with_added_latency(mut self, added_latency: Option<Duration>) -> Self {
+        self.added_latency = added_latency;
+        self
+    }
+
+    /// Database the results of Scenarios started via `/run` are persisted to, see
+    /// `BalterRuntime::with_results_db()` (requires the `influxdb` feature).
+    pub fn with_results_db(mut self, results_db: Option<String>) -> Self {
+        self.results_db = results_db;
+        self
+    }
+
+    pub fn results_db(&self) -> Option<String> {
+        self.results_db.clone()
+    }
+
+    /// Run a Scenario on this server, unless it is draining.
+    pub fn spawn_scenario(
+        &self,
+        mut config: ScenarioConfig,
+    ) -> Result<JoinHandle<RunStatistics>, RuntimeError> {
+        if self.data.lock()?.is_draining() {
+            return Err(RuntimeError::Draining);
         }
+
+        // NOTE: Always overwritten, as the added latency is this server's, not the sender's.
+        config.added_latency = self.added_latency;
+        let handle = (self.scenario_spawn_hook)(config)?;
+        self.running.lock()?.push(handle.abort_handle());
+        Ok(handle)
+    }
+
+    /// Number of Scenarios started via [`spawn_scenario()`](Self::spawn_scenario) which are
+    /// still running.
+    pub fn running_scenarios(&self) -> Result<usize, RuntimeError> {
+        let mut running = self.running.lock()?;
+        running.retain(|handle| !handle.is_finished());
+        Ok(running.len())
+    }
+
+    /// Stop accepting new Scenarios, both via `/run` and from peers, and mark this server as
+    /// draining so that peers no longer delegate to it. Returns the number of Scenarios still
+    /// running.
+    pub fn drain(&self) -> Result<usize, RuntimeError> {
+        self.data.lock()?.set_state_draining();
+        self.running_scenarios()
+    }
+
+    pub fn is_draining(&self) -> Result<bool, RuntimeError> {
+        Ok(self.data.lock()?.is_draining())
     }
 
     pub async fn receive_request(
@@ -65,12 +224,35 @@ impl Gossip {
         match msg.inner() {
             Handshake::Sync => self.receive_sync_request(stream, peer_addr).await,
             Handshake::Help => self.receive_help_request(stream, peer_addr).await,
+            Handshake::Ping => self.receive_ping_request(stream, peer_addr).await,
         }
     }
 }
 
+/// Sync with every known peer, so that they learn about a change of state (e.g. draining)
+/// without waiting for the next round of gossip.
+pub(crate) async fn announce(gossip: &Gossip) -> Result<(), GossipError> {
+    let peers = gossip.data.lock()?.other_peers();
+    for peer in peers {
+        let res = async {
+            let mut stream = peer_stream(&peer).await?;
+            gossip.request_sync(&mut stream, peer.addr).await
+        }
+        .await;
+
+        if let Err(err) = res {
+            warn!("Unable to announce to peer {}: {err}", peer.addr);
+        }
+    }
+    Ok(())
+}
+
 pub async fn peer_stream(peer: &PeerInfo) -> Result<impl GossipStream, GossipError> {
-    let url = Url::parse(&format!("ws://{}/ws", peer.addr))?;
+    addr_stream(peer.addr).await
+}
+
+pub async fn addr_stream(addr: SocketAddr) -> Result<impl GossipStream, GossipError> {
+    let url = Url::parse(&format!("ws://{addr}/ws"))?;
     let (stream, _) = connect_async(url).await?;
     Ok(stream)
 }
@@ -80,6 +262,8 @@ mod tests {
     use super::*;
     use crate::runtime::spawn_scenario;
     use axum::{extract::ws::WebSocketUpgrade, routing::get, Router};
+    use data::PeerState;
+    use std::collections::BTreeMap;
     use tokio::sync::mpsc::{channel, Receiver, Sender};
     use tokio_tungstenite::connect_async;
     use url::Url;
@@ -151,4 +335,169 @@ mod tests {
         let peer_count = gossip.data.lock().unwrap().peers.len();
         assert_eq!(peer_count, 2);
     }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn tx_count_test() {
+        let gossip_0 = Gossip::new(Uuid::new_v4(), 1234, spawn_scenario);
+        let gossip_1 = Gossip::new(Uuid::new_v4(), 4321, spawn_scenario);
+        let addr: SocketAddr = "0.0.0.0:1111".parse().unwrap();
+
+        async fn sync(from: &Gossip, to: &Gossip, addr: SocketAddr) {
+            let (mut stream_0, mut stream_1) = FakeStream::duplex();
+            let (res0, res1) = tokio::join! {
+                from.request_sync(&mut stream_0, addr),
+                to.receive_request(&mut stream_1, addr),
+            };
+            res0.unwrap();
+            res1.unwrap();
+        }
+
+        let tx_count = |count: u64| BTreeMap::from([("test_tx_count".to_string(), count)]);
+        let set_tx_count = |gossip: &Gossip, count: u64, finished: bool| {
+            let finished = if finished {
+                BTreeSet::from(["test_tx_count".to_string()])
+            } else {
+                BTreeSet::new()
+            };
+            gossip
+                .data
+                .lock()
+                .unwrap()
+                .set_tx_count(tx_count(count), finished);
+        };
+        let peer_tx_count =
+            |gossip: &Gossip| gossip.data.lock().unwrap().peer_tx_count()["test_tx_count"];
+        let peer_tx_running = |gossip: &Gossip| gossip.data.lock().unwrap().peer_tx_running();
+
+        // NOTE: Each server learns its own address on the first sync.
+        sync(&gossip_0, &gossip_1, addr).await;
+        set_tx_count(&gossip_0, 300, false);
+        set_tx_count(&gossip_1, 500, false);
+
+        sync(&gossip_0, &gossip_1, addr).await;
+        assert_eq!(peer_tx_count(&gossip_0), 500);
+        assert_eq!(peer_tx_count(&gossip_1), 300);
+
+        // A peer's stale count doesn't overwrite a server's own count.
+        set_tx_count(&gossip_0, 700, false);
+        sync(&gossip_1, &gossip_0, addr).await;
+        assert_eq!(peer_tx_count(&gossip_0), 500);
+        assert_eq!(peer_tx_count(&gossip_1), 700);
+
+        // The final count of a finished Scenario still counts while a peer is running it.
+        set_tx_count(&gossip_1, 600, true);
+        sync(&gossip_0, &gossip_1, addr).await;
+        assert_eq!(peer_tx_count(&gossip_0), 600);
+        assert!(peer_tx_running(&gossip_0).is_empty());
+        assert!(peer_tx_running(&gossip_1).contains("test_tx_count"));
+
+        // Once dropped, it no longer counts.
+        gossip_1
+            .data
+            .lock()
+            .unwrap()
+            .set_tx_count(BTreeMap::new(), BTreeSet::new());
+        sync(&gossip_0, &gossip_1, addr).await;
+        assert!(gossip_0.data.lock().unwrap().peer_tx_count().is_empty());
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn reconnect_with_backoff() {
+        // NOTE: Reserve a port for the peer, which is down to begin with.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let peer_id = Uuid::new_v4();
+        let gossip = Gossip::new(Uuid::new_v4(), 1234, spawn_scenario);
+        let mut peer_data = GossipData::new(peer_id, peer_addr.port());
+        peer_data.learn_address(peer_addr);
+        gossip.data.lock().unwrap().merge(peer_data);
+        let peer = gossip.data.lock().unwrap().peer(&peer_id).unwrap();
+
+        let mut backoff = PeerBackoff::new();
+        let mut max_backoff = Duration::ZERO;
+        for failures in 1..=4 {
+            let delay = gossip_with_peer(&gossip, &mut backoff, &peer)
+                .await
+                .unwrap();
+            // NOTE: With jitter each delay lies in [backoff / 2, backoff], so the lower bound
+            // doubles with each failure.
+            assert!(delay >= max_backoff, "{failures}: {delay:?}");
+            max_backoff = Duration::from_secs(1 << (failures - 1));
+            assert!(delay <= max_backoff, "{failures}: {delay:?}");
+            assert_eq!(backoff.failures(&peer_id), failures);
+            assert!(!backoff.is_ready(&peer_id, Instant::now()));
+        }
+        let state = |gossip: &Gossip| gossip.data.lock().unwrap().peer(&peer_id).unwrap().state;
+        assert_eq!(state(&gossip), PeerState::Unreachable);
+        assert!(logs_contain("is unreachable"));
+
+        let server = tokio::spawn(async move {
+            let app = Router::new().route(
+                "/ws",
+                get(move |ws: WebSocketUpgrade| async move {
+                    ws.on_upgrade(move |mut socket| async move {
+                        let gossip = Gossip::new(peer_id, peer_addr.port(), spawn_scenario);
+                        gossip
+                            .receive_request(&mut socket, peer_addr)
+                            .await
+                            .unwrap();
+                    })
+                }),
+            );
+
+            let listener = tokio::net::TcpListener::bind(peer_addr).await.unwrap();
+            axum::serve(listener, app).await.unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(gossip_with_peer(&gossip, &mut backoff, &peer).await, None);
+        assert_eq!(backoff.failures(&peer_id), 0);
+        assert!(logs_contain("Reconnected to peer"));
+        assert_eq!(state(&gossip), PeerState::Free);
+
+        // NOTE: Back-off starts over if the peer goes down again.
+        server.abort();
+        let _ = server.await;
+        let delay = gossip_with_peer(&gossip, &mut backoff, &peer)
+            .await
+            .unwrap();
+        assert!(delay <= Duration::from_secs(1), "{delay:?}");
+        assert_eq!(backoff.failures(&peer_id), 1);
+    }
+
+    #[tokio::test]
+    async fn spawn_scenario_uses_own_added_latency() {
+        // NOTE: Captures the config the Scenario was spawned with.
+        static SPAWNED: Mutex<Option<ScenarioConfig>> = Mutex::new(None);
+        fn capture_spawn_scenario(
+            config: ScenarioConfig,
+        ) -> Result<JoinHandle<RunStatistics>, RuntimeError> {
+            *SPAWNED.lock().unwrap() = Some(config);
+            Ok(tokio::spawn(async { RunStatistics::default() }))
+        }
+        fn spawned_latency() -> Option<Duration> {
+            SPAWNED.lock().unwrap().take().unwrap().added_latency
+        }
+
+        let mut config = ScenarioConfig::new("spawn_scenario_uses_own_added_latency");
+        config.added_latency = Some(Duration::from_millis(20));
+
+        let gossip = Gossip::new(Uuid::new_v4(), 1234, capture_spawn_scenario)
+            .with_added_latency(Some(Duration::from_millis(80)));
+        gossip
+            .spawn_scenario(config.clone())
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(spawned_latency(), Some(Duration::from_millis(80)));
+
+        // NOTE: A peer's added latency isn't inherited.
+        let gossip = Gossip::new(Uuid::new_v4(), 1234, capture_spawn_scenario);
+        gossip.spawn_scenario(config).unwrap().await.unwrap();
+        assert_eq!(spawned_latency(), None);
+    }
 }
@@ -0,0 +1,147 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Consecutive failures after which a peer is given up on as unreachable, rather than treated as
+/// briefly unavailable. It is still retried, and reachable again once gossiped with.
+const UNREACHABLE_FAILURES: u32 = 3;
+
+/// Per-peer exponential back-off for peers which could not be gossiped with.
+///
+/// Each consecutive failure doubles the delay before the peer is retried (up to
+/// `MAX_BACKOFF`), with jitter so that servers don't retry a recovering peer in lockstep. A
+/// successful gossip resets the peer's back-off.
+#[derive(Debug, Default)]
+pub(crate) struct PeerBackoff {
+    peers: HashMap<Uuid, State>,
+}
+
+#[derive(Debug)]
+struct State {
+    failures: u32,
+    retry_at: Instant,
+}
+
+impl PeerBackoff {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a failure to gossip with the peer, returning the delay until it is retried.
+    pub fn failure(&mut self, peer: Uuid, now: Instant) -> Duration {
+        let state = self.peers.entry(peer).or_insert(State {
+            failures: 0,
+            retry_at: now,
+        });
+        state.failures += 1;
+
+        let delay = backoff_delay(state.failures);
+        state.retry_at = now + delay;
+        delay
+    }
+
+    /// Record a successful gossip with the peer, returning `true` if it had been backing off.
+    pub fn success(&mut self, peer: Uuid) -> bool {
+        self.peers.remove(&peer).is_some()
+    }
+
+    /// Whether the peer has failed too many times in a row to be expected back soon, see
+    /// `PeerState::Unreachable`.
+    pub fn is_unreachable(&self, peer: &Uuid) -> bool {
+        self.peers
+            .get(peer)
+            .is_some_and(|state| state.failures >= UNREACHABLE_FAILURES)
+    }
+
+    /// Whether the peer can be gossiped with (i.e. is not waiting out a back-off).
+    pub fn is_ready(&self, peer: &Uuid, now: Instant) -> bool {
+        self.peers
+            .get(peer)
+            .is_none_or(|state| state.retry_at <= now)
+    }
+
+    /// A backed-off peer which is due to be retried, if any.
+    pub fn due(&self, now: Instant) -> Option<Uuid> {
+        self.peers
+            .iter()
+            .filter(|(_, state)| state.retry_at <= now)
+            .min_by_key(|(_, state)| state.retry_at)
+            .map(|(peer, _)| *peer)
+    }
+
+    /// Time of the earliest retry of a backed-off peer.
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.peers.values().map(|state| state.retry_at).min()
+    }
+
+    /// Stop tracking a peer which is no longer known.
+    pub fn forget(&mut self, peer: &Uuid) {
+        self.peers.remove(peer);
+    }
+
+    #[cfg(test)]
+    pub fn failures(&self, peer: &Uuid) -> u32 {
+        self.peers.get(peer).map_or(0, |state| state.failures)
+    }
+}
+
+/// Delay after `failures` consecutive failures: half of the exponential back-off, plus a random
+/// amount up to the other half.
+fn backoff_delay(failures: u32) -> Duration {
+    let backoff = BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF);
+    let half = backoff / 2;
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        for failures in 1..=10 {
+            let backoff = (BASE_BACKOFF * 2u32.pow(failures - 1)).min(MAX_BACKOFF);
+            let delay = backoff_delay(failures);
+            assert!(
+                delay >= backoff / 2 && delay <= backoff,
+                "{failures}: {delay:?}"
+            );
+        }
+        assert!(backoff_delay(u32::MAX) <= MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_peer_backoff() {
+        let now = Instant::now();
+        let (down, up) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut backoff = PeerBackoff::new();
+        assert!(backoff.is_ready(&down, now));
+        assert_eq!(backoff.next_retry(), None);
+
+        let delay = backoff.failure(down, now);
+        assert!(!backoff.is_ready(&down, now));
+        assert!(backoff.is_ready(&up, now));
+        assert_eq!(backoff.due(now), None);
+        assert_eq!(backoff.next_retry(), Some(now + delay));
+        assert_eq!(backoff.due(now + delay), Some(down));
+
+        for _ in 1..UNREACHABLE_FAILURES {
+            assert!(!backoff.is_unreachable(&down));
+            backoff.failure(down, now);
+        }
+        assert!(backoff.is_unreachable(&down));
+        assert!(!backoff.is_unreachable(&up));
+
+        assert!(backoff.success(down));
+        assert!(!backoff.success(up));
+        assert!(!backoff.is_unreachable(&down));
+        assert!(backoff.is_ready(&down, now));
+        assert_eq!(backoff.failures(&down), 0);
+    }
+}
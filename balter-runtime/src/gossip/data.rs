@@ -1,16 +1,26 @@
 use rand::seq::IteratorRandom;
 use serde::{Deserialize, Serialize};
-use std::collections::{hash_map::DefaultHasher, HashMap};
+use std::collections::{
+    hash_map::{DefaultHasher, Entry},
+    BTreeMap, BTreeSet, HashMap, HashSet,
+};
 use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use tracing::error;
 use uuid::Uuid;
 
+/// Fraction of the other peers which, once busy (or draining or unreachable), stops this server
+/// from asking for help. At that point the few free peers are likely to be asked by others too,
+/// and helpers which become TPS limited would only have busy peers ping each other for help.
+pub(crate) const HELP_BUSY_THRESHOLD: f64 = 0.75;
+
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct GossipData {
     pub peers: HashMap<Uuid, PeerInfoPartial>,
     pub server_id: Uuid,
     my_addr: MyAddress,
+    my_tags: BTreeSet<String>,
+    draining: bool,
 }
 
 impl GossipData {
@@ -20,9 +30,16 @@ impl GossipData {
             peers,
             server_id,
             my_addr: MyAddress::Unknown { port },
+            my_tags: BTreeSet::new(),
+            draining: false,
         }
     }
 
+    pub fn set_tags(&mut self, tags: BTreeSet<String>) {
+        self.update_own(|info| info.tags.clone_from(&tags));
+        self.my_tags = tags;
+    }
+
     pub fn hash(&self) -> u64 {
         let mut s = DefaultHasher::new();
         let peers: Vec<_> = self.peers.iter().collect();
@@ -31,7 +48,44 @@ impl GossipData {
     }
 
     pub fn merge(&mut self, mut other: GossipData) {
-        self.peers.extend(other.peers.drain());
+        // NOTE: This server is the source of truth for its own info, which a peer may only have
+        // a stale copy of.
+        other.peers.remove(&self.server_id);
+
+        // NOTE: Each server bumps the version of its own info whenever it changes, so the highest
+        // version is the latest no matter which peer relayed it or in which order copies arrive.
+        // Wall-clock time is never compared, so clock skew between servers has no effect. A
+        // server's own copy of its info also replaces an equal version, which undoes
+        // `mark_unreachable()` once it is gossiped with again.
+        let sender = other.server_id;
+        for (id, info) in other.peers.drain() {
+            match self.peers.entry(id) {
+                Entry::Occupied(mut entry) => {
+                    let version = entry.get().version;
+                    if info.version > version || (id == sender && info.version == version) {
+                        entry.insert(info);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(info);
+                }
+            }
+        }
+    }
+
+    /// Modify this server's own info, bumping its version if anything changed so that peers
+    /// replace their copy of it. Returns `false` if the address of this server isn't known yet.
+    fn update_own(&mut self, update: impl FnOnce(&mut PeerInfoPartial)) -> bool {
+        let Some(info) = self.peers.get_mut(&self.server_id) else {
+            return false;
+        };
+
+        let before = info.clone();
+        update(info);
+        if *info != before {
+            info.version += 1;
+        }
+        true
     }
 
     // NOTE: This ends up being an interesting problem: what _is_ the address of the
@@ -46,7 +100,14 @@ impl GossipData {
             self.peers.insert(
                 self.server_id,
                 PeerInfoPartial {
-                    state: PeerState::Free,
+                    state: if self.draining {
+                        PeerState::Draining
+                    } else {
+                        PeerState::Free
+                    },
+                    tags: self.my_tags.clone(),
+                    tx_count: BTreeMap::new(),
+                    tx_finished: BTreeSet::new(),
 
                     addr,
                     version: 1,
@@ -57,21 +118,32 @@ impl GossipData {
         }
     }
 
-    pub fn select_random_peer(&self) -> Option<PeerInfo> {
+    /// Select a random peer for which `is_ready` holds.
+    pub fn select_random_peer(&self, is_ready: impl Fn(&Uuid) -> bool) -> Option<PeerInfo> {
         let mut rng = rand::thread_rng();
         self.peers
             .iter()
-            .map(|(id, info)| PeerInfo::from_partial(*info, *id))
+            .filter(|(id, _)| is_ready(id))
+            .map(|(id, info)| PeerInfo::from_partial(info, *id))
             .choose(&mut rng)
     }
 
-    pub fn select_free_peer(&self) -> Option<PeerInfo> {
+    pub fn peer(&self, server_id: &Uuid) -> Option<PeerInfo> {
+        self.peers
+            .get(server_id)
+            .map(|info| PeerInfo::from_partial(info, *server_id))
+    }
+
+    /// Select a random free peer which has all of the `required_tags`.
+    pub fn select_free_peer(&self, required_tags: &[String]) -> Option<PeerInfo> {
         let mut rng = rand::thread_rng();
         self.peers
             .iter()
             .filter_map(|(id, info)| {
-                if matches!(info.state, PeerState::Free) {
-                    Some(PeerInfo::from_partial(*info, *id))
+                if matches!(info.state, PeerState::Free)
+                    && required_tags.iter().all(|tag| info.tags.contains(tag))
+                {
+                    Some(PeerInfo::from_partial(info, *id))
                 } else {
                     None
                 }
@@ -79,25 +151,121 @@ impl GossipData {
             .choose(&mut rng)
     }
 
+    /// Whether at least [`HELP_BUSY_THRESHOLD`] of the other peers are unable to help, going by
+    /// their last gossiped state. `false` if there are no other peers.
+    pub fn is_cluster_busy(&self) -> bool {
+        let others: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(id, _)| **id != self.server_id)
+            .map(|(_, info)| info.state)
+            .collect();
+        if others.is_empty() {
+            return false;
+        }
+
+        let busy = others
+            .iter()
+            .filter(|state| !matches!(state, PeerState::Free))
+            .count();
+        busy as f64 / others.len() as f64 >= HELP_BUSY_THRESHOLD
+    }
+
+    /// Mark a peer which this server has given up gossiping with (see `PeerBackoff`) as
+    /// unreachable, returning `false` if it already was. Only this server's copy changes, without
+    /// bumping its version, since the peer remains the source of truth for its own info.
+    pub fn mark_unreachable(&mut self, server_id: &Uuid) -> bool {
+        if *server_id == self.server_id {
+            return false;
+        }
+
+        match self.peers.get_mut(server_id) {
+            Some(info) if info.state != PeerState::Unreachable => {
+                info.state = PeerState::Unreachable;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Other peers, excluding this server.
+    pub fn other_peers(&self) -> Vec<PeerInfo> {
+        self.peers
+            .iter()
+            .filter(|(id, _)| **id != self.server_id)
+            .map(|(id, info)| PeerInfo::from_partial(info, *id))
+            .collect()
+    }
+
     pub fn set_state_free(&mut self) {
-        if let Some(info) = self.peers.get_mut(&self.server_id) {
-            info.state = PeerState::Free;
-        } else {
+        // NOTE: Draining is final, a server never goes back to accepting work.
+        if self.draining {
+            return;
+        }
+
+        if !self.update_own(|info| info.state = PeerState::Free) {
             error!("Unable to modify state.");
         }
     }
 
     pub fn set_state_busy(&mut self) {
-        if let Some(info) = self.peers.get_mut(&self.server_id) {
-            info.state = PeerState::Busy;
-        } else {
+        if self.draining {
+            return;
+        }
+
+        if !self.update_own(|info| info.state = PeerState::Busy) {
             error!("Unable to modify state.");
         }
     }
 
+    /// Stop accepting work, so that peers no longer delegate to this server. If the address of
+    /// this server isn't known yet, peers learn that it is draining along with the address.
+    pub fn set_state_draining(&mut self) {
+        self.draining = true;
+        self.update_own(|info| info.state = PeerState::Draining);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    /// Set the number of Transactions run so far by each capped Scenario on this server, and
+    /// which of them have finished.
+    pub fn set_tx_count(&mut self, tx_count: BTreeMap<String, u64>, finished: BTreeSet<String>) {
+        self.update_own(|info| {
+            info.tx_count = tx_count;
+            info.tx_finished = finished;
+        });
+    }
+
+    /// Total number of Transactions run so far by peers, for each capped Scenario.
+    pub fn peer_tx_count(&self) -> HashMap<String, u64> {
+        let mut totals = HashMap::new();
+        for (_, info) in self.peers.iter().filter(|(id, _)| **id != self.server_id) {
+            for (name, count) in &info.tx_count {
+                *totals.entry(name.clone()).or_default() += count;
+            }
+        }
+        totals
+    }
+
+    /// Capped Scenarios which are still running on a peer.
+    pub fn peer_tx_running(&self) -> HashSet<String> {
+        self.peers
+            .iter()
+            .filter(|(id, _)| **id != self.server_id)
+            .flat_map(|(_, info)| {
+                info.tx_count
+                    .keys()
+                    .filter(|name| !info.tx_finished.contains(*name))
+            })
+            .cloned()
+            .collect()
+    }
+
     pub fn is_busy(&self) -> Option<bool> {
         match self.peers.get(&self.server_id) {
-            Some(info) if info.state == PeerState::Busy => Some(true),
+            Some(info) if matches!(info.state, PeerState::Busy | PeerState::Draining) => Some(true),
             Some(_info) => Some(false),
             None => None,
         }
@@ -113,7 +281,7 @@ pub(crate) struct PeerInfo {
 }
 
 impl PeerInfo {
-    fn from_partial(partial: PeerInfoPartial, server_id: Uuid) -> PeerInfo {
+    fn from_partial(partial: &PeerInfoPartial, server_id: Uuid) -> PeerInfo {
         PeerInfo {
             server_id,
             version: partial.version,
@@ -123,11 +291,20 @@ impl PeerInfo {
     }
 }
 
-#[derive(Hash, PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+// NOTE: `tags` is a `BTreeSet` rather than a `HashSet` so that `GossipData` remains hashable.
+#[derive(Hash, PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct PeerInfoPartial {
     version: u64,
     addr: SocketAddr,
     state: PeerState,
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    /// Transactions run so far by each capped Scenario on the peer, see `max_transactions()`.
+    #[serde(default)]
+    tx_count: BTreeMap<String, u64>,
+    /// Capped Scenarios in `tx_count` which have finished on the peer.
+    #[serde(default)]
+    tx_finished: BTreeSet<String>,
 }
 
 #[derive(Hash, PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
@@ -135,6 +312,8 @@ pub(crate) enum PeerState {
     Busy,
     Free,
     Unreachable,
+    /// Finishing its running Scenarios before shutting down, see `/drain`.
+    Draining,
 }
 
 // TODO: Naming is hard
@@ -143,3 +322,168 @@ enum MyAddress {
     Known,
     Unknown { port: u16 },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(port: u16, tags: &[&str]) -> PeerInfoPartial {
+        PeerInfoPartial {
+            version: 1,
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            state: PeerState::Free,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            tx_count: BTreeMap::new(),
+            tx_finished: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_select_free_peer_with_tags() {
+        let mut data = GossipData::new(Uuid::new_v4(), 7621);
+        let gpu = Uuid::new_v4();
+        let cpu = Uuid::new_v4();
+        data.peers.insert(gpu, peer(7622, &["gpu", "large"]));
+        data.peers.insert(cpu, peer(7623, &["cpu"]));
+
+        for _ in 0..20 {
+            let peer = data.select_free_peer(&["gpu".to_string()]).unwrap();
+            assert_eq!(peer.server_id, gpu);
+
+            let peer = data.select_free_peer(&["cpu".to_string()]).unwrap();
+            assert_eq!(peer.server_id, cpu);
+        }
+
+        assert!(data
+            .select_free_peer(&["gpu".to_string(), "cpu".to_string()])
+            .is_none());
+        assert!(data.select_free_peer(&["tpu".to_string()]).is_none());
+        assert!(data.select_free_peer(&[]).is_some());
+    }
+
+    #[test]
+    fn test_draining_peer_not_selected() {
+        let mut data = GossipData::new(Uuid::new_v4(), 7621);
+        let peer_id = Uuid::new_v4();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 7622));
+        let mut peer_data = GossipData::new(peer_id, addr.port());
+        peer_data.learn_address(addr);
+        data.merge(peer_data.clone());
+        assert_eq!(data.select_free_peer(&[]).unwrap().server_id, peer_id);
+
+        peer_data.set_state_draining();
+        data.merge(peer_data.clone());
+        assert!(data.select_free_peer(&[]).is_none());
+        assert_eq!(peer_data.is_busy(), Some(true));
+
+        // NOTE: Finishing a Scenario doesn't make a draining server free again.
+        peer_data.set_state_free();
+        data.merge(peer_data);
+        assert_eq!(data.peer(&peer_id).unwrap().state, PeerState::Draining);
+        assert!(data.select_free_peer(&[]).is_none());
+    }
+
+    #[test]
+    fn test_cluster_busy_threshold() {
+        let mut data = GossipData::new(Uuid::new_v4(), 7621);
+        data.learn_address(SocketAddr::from(([127, 0, 0, 1], 7621)));
+        assert!(!data.is_cluster_busy());
+
+        let peers: Vec<_> = (0..4).map(|_| Uuid::new_v4()).collect();
+        for (port, id) in (7622..).zip(&peers) {
+            data.peers.insert(*id, peer(port, &[]));
+        }
+
+        // NOTE: This server being busy itself doesn't count towards the threshold.
+        data.set_state_busy();
+        assert!(!data.is_cluster_busy());
+
+        let states = [PeerState::Busy, PeerState::Draining, PeerState::Unreachable];
+        for (id, state) in peers.iter().zip(states) {
+            assert!(!data.is_cluster_busy());
+            data.peers.get_mut(id).unwrap().state = state;
+        }
+        // 3 of 4 peers
+        assert!(data.is_cluster_busy());
+    }
+
+    #[test]
+    fn test_unreachable_peers() {
+        let mut data = GossipData::new(Uuid::new_v4(), 7621);
+        let relay_id = Uuid::new_v4();
+        let mut relay = GossipData::new(relay_id, 7622);
+        relay.learn_address(SocketAddr::from(([127, 0, 0, 1], 7622)));
+
+        let peers: Vec<_> = (7623..7626)
+            .map(|port| {
+                let id = Uuid::new_v4();
+                let mut peer_data = GossipData::new(id, port);
+                peer_data.learn_address(SocketAddr::from(([127, 0, 0, 1], port)));
+                relay.merge(peer_data.clone());
+                (id, peer_data)
+            })
+            .collect();
+        data.merge(relay.clone());
+
+        // NOTE: The peers crashed while free, so their last gossiped state never changes.
+        for (id, _) in &peers {
+            assert!(!data.is_cluster_busy());
+            assert!(data.mark_unreachable(id));
+            assert!(!data.mark_unreachable(id));
+        }
+        assert!(data.is_cluster_busy());
+        assert_eq!(data.select_free_peer(&[]).unwrap().server_id, relay_id);
+
+        // A stale copy relayed by another peer doesn't make them reachable again...
+        data.merge(relay);
+        assert!(data.is_cluster_busy());
+
+        // ...but gossiping with the peer itself does.
+        let (id, peer_data) = &peers[0];
+        data.merge(peer_data.clone());
+        assert_eq!(data.peer(id).unwrap().state, PeerState::Free);
+        assert!(!data.is_cluster_busy());
+    }
+
+    #[test]
+    fn test_merge_ignores_relay_order() {
+        let peer_id = Uuid::new_v4();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 7622));
+        let mut peer_data = GossipData::new(peer_id, addr.port());
+        peer_data.learn_address(addr);
+
+        // NOTE: Two servers, each with a copy of the peer's info from a different point in time.
+        let stale = peer_data.clone();
+        peer_data.set_state_busy();
+        let fresh = peer_data.clone();
+        assert_eq!(fresh.peer(&peer_id).unwrap().version, 2);
+
+        // Whichever copy is relayed last, the latest state wins.
+        for copies in [[&stale, &fresh], [&fresh, &stale]] {
+            let mut data = GossipData::new(Uuid::new_v4(), 7621);
+            for copy in copies {
+                data.merge(copy.clone());
+            }
+
+            let peer = data.peer(&peer_id).unwrap();
+            assert_eq!(peer.state, PeerState::Busy);
+            assert_eq!(peer.version, 2);
+        }
+    }
+
+    #[test]
+    fn test_version_only_bumped_on_change() {
+        let server_id = Uuid::new_v4();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 7621));
+        let mut data = GossipData::new(server_id, addr.port());
+        data.learn_address(addr);
+
+        data.set_state_free();
+        data.set_tx_count(BTreeMap::new(), BTreeSet::new());
+        assert_eq!(data.peer(&server_id).unwrap().version, 1);
+
+        data.set_state_busy();
+        data.set_state_busy();
+        assert_eq!(data.peer(&server_id).unwrap().version, 2);
+    }
+}
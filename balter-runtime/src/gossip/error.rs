@@ -26,6 +26,9 @@ pub enum GossipError {
 
     #[error("Peer to share work with is busy. Retries not implemented yet.")]
     PeerBusy,
+
+    #[error("Peer rejected the work as it exceeds the max global TPS.")]
+    PeerRejected,
 }
 
 impl<T> From<PoisonError<T>> for GossipError {
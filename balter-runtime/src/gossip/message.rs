@@ -34,6 +34,7 @@ impl<M> Message<M> {
 pub(crate) enum Handshake {
     Sync,
     Help,
+    Ping,
 }
 
 impl Message<Handshake> {
@@ -49,4 +50,10 @@ impl Message<Handshake> {
             inner: Handshake::Help,
         }
     }
+
+    pub fn ping() -> Self {
+        Message {
+            inner: Handshake::Ping,
+        }
+    }
 }
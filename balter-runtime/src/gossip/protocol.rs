@@ -1,2 +1,3 @@
 pub(crate) mod help;
+pub(crate) mod ping;
 pub(crate) mod sync;
@@ -1,10 +1,39 @@
-use super::super::{message::Message, Gossip, GossipError, GossipStream};
+use super::super::{message::Message, Gossip, GossipError, GossipStream, PeerInfo};
+use crate::runtime::handoff::{self, HANDOFF_INTERVAL};
+use crate::runtime::limit_global_tps;
 use balter_core::ScenarioConfig;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
-use tracing::error;
+use tracing::{debug, error, warn};
 
 impl Gossip {
+    /// Select a free peer to help with the given config, marking this server as busy. Returns
+    /// `None` if no peer is free, or if most peers are busy themselves (see
+    /// [`HELP_BUSY_THRESHOLD`](super::super::data::HELP_BUSY_THRESHOLD)).
+    pub(crate) fn select_helper(
+        &self,
+        config: &ScenarioConfig,
+    ) -> Result<Option<PeerInfo>, GossipError> {
+        let mut data = self.data.lock()?;
+        data.set_state_busy();
+
+        if data.is_cluster_busy() {
+            warn!(
+                "Most peers are busy, not requesting help for {}.",
+                config.name
+            );
+            return Ok(None);
+        }
+
+        let peer = data.select_free_peer(&config.peer_tags);
+        if peer.is_none() {
+            error!("No Peers available to help.");
+            // TODO: Implement some form of retry/auto-scaling
+        }
+        Ok(peer)
+    }
+
+    /// Request a peer to run the given config, and relay the peer's progress until it finishes.
     #[allow(unused)]
     pub(crate) async fn request_help(
         &self,
@@ -12,17 +41,24 @@ impl Gossip {
         peer_addr: SocketAddr,
         config: ScenarioConfig,
     ) -> Result<(), GossipError> {
+        let name = config.name.clone();
         stream.send(Message::help()).await?;
 
         stream.send(Message::run_config(config)).await?;
 
         let status: Message<Status> = stream.recv().await?;
 
-        if matches!(status.inner(), Status::Busy) {
-            Err(GossipError::PeerBusy)
-        } else {
-            Ok(())
+        match status.inner() {
+            Status::Busy => return Err(GossipError::PeerBusy),
+            Status::Rejected => return Err(GossipError::PeerRejected),
+            Status::Accepted => {}
         }
+
+        // NOTE: If the helper disappears, we stop subtracting its progress so that this server
+        // picks the load back up.
+        let res = relay_progress(stream, &name).await;
+        handoff::set_delegated_tps(&name, None);
+        res
     }
 
     #[allow(unused)]
@@ -32,6 +68,13 @@ impl Gossip {
         peer_addr: SocketAddr,
     ) -> Result<(), GossipError> {
         let msg: Message<RunConfig> = stream.recv().await?;
+        let mut config = msg.config();
+
+        if let Err(err) = limit_global_tps(&mut config, self.max_global_tps()) {
+            error!("Rejecting help request: {err}");
+            stream.send(Message::new(Status::Rejected)).await?;
+            return Ok(());
+        }
 
         // TODO: Be far more clever about whether this server can accept work
         let is_busy = self.data.lock()?.is_busy();
@@ -42,8 +85,23 @@ impl Gossip {
             }
             Some(false) => {
                 stream.send(Message::new(Status::Accepted)).await?;
-                // TODO: Handle error
-                let _ = (self.scenario_spawn_hook)(msg.config());
+                let name = config.name.clone();
+                // NOTE: Clear any stale progress from a previous local run of this Scenario.
+                handoff::clear_local_tps(&name);
+                match self.spawn_scenario(config) {
+                    Ok(handle) => {
+                        let mut interval = tokio::time::interval(HANDOFF_INTERVAL);
+                        while !handle.is_finished() {
+                            interval.tick().await;
+                            if let Some(tps) = handoff::local_tps(&name) {
+                                stream.send(Message::new(Progress::Tps(tps))).await?;
+                            }
+                        }
+                        handoff::clear_local_tps(&name);
+                    }
+                    Err(err) => error!("Unable to spawn delegated scenario: {err:?}"),
+                }
+                stream.send(Message::new(Progress::Finished)).await?;
             }
             None => {
                 error!("Could not find own info.");
@@ -55,6 +113,19 @@ impl Gossip {
     }
 }
 
+async fn relay_progress(stream: &mut impl GossipStream, name: &str) -> Result<(), GossipError> {
+    loop {
+        let progress: Message<Progress> = stream.recv().await?;
+        match progress.inner() {
+            Progress::Tps(tps) => {
+                debug!("Helper for {name} at {tps:.2} TPS");
+                handoff::set_delegated_tps(name, Some(*tps));
+            }
+            Progress::Finished => return Ok(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct RunConfig {
     config: ScenarioConfig,
@@ -76,6 +147,15 @@ impl Message<RunConfig> {
 pub(crate) enum Status {
     Busy,
     Accepted,
+    /// The config exceeds the max global TPS of the peer.
+    Rejected,
+}
+
+/// Progress of delegated work, sent by the helper until the work is finished.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Progress {
+    Tps(f64),
+    Finished,
 }
 
 #[cfg(test)]
@@ -84,7 +164,10 @@ mod tests {
     use crate::error::RuntimeError;
     use crate::gossip::tests::FakeStream;
     use crate::gossip::Gossip;
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use balter_core::RunStatistics;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::task::JoinHandle;
     use uuid::Uuid;
 
     #[tokio::test]
@@ -116,10 +199,186 @@ mod tests {
         assert!(SPAWNED.load(Ordering::Relaxed));
     }
 
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn busy_cluster_test() {
+        let nodes: Vec<_> = (0..5)
+            .map(|i| Gossip::new(Uuid::new_v4(), 1234 + i, fake_spawn_scenario))
+            .collect();
+        let addr = "0.0.0.0:1111".parse().unwrap();
+
+        async fn sync_all(nodes: &[Gossip], addr: SocketAddr) {
+            for (i, from) in nodes.iter().enumerate() {
+                for to in &nodes[i + 1..] {
+                    let (mut stream_0, mut stream_1) = FakeStream::duplex();
+                    let (res0, res1) = tokio::join! {
+                        from.request_sync(&mut stream_0, addr),
+                        to.receive_request(&mut stream_1, addr),
+                    };
+                    res0.unwrap();
+                    res1.unwrap();
+                }
+            }
+        }
+
+        // NOTE: Each node becomes busy running its own Scenario, then learns that every other
+        // node is too.
+        sync_all(&nodes, addr).await;
+        for node in &nodes {
+            node.data.lock().unwrap().set_state_busy();
+        }
+        sync_all(&nodes, addr).await;
+
+        // Once TPS limited, no node asks for help, rather than pinging the others.
+        let config = ScenarioConfig::new("test_busy_cluster");
+        for node in &nodes {
+            assert!(node.data.lock().unwrap().is_cluster_busy());
+            assert!(node.select_helper(&config).unwrap().is_none());
+        }
+
+        // With enough peers free again, help is requested from one of them.
+        for node in &nodes[2..] {
+            node.data.lock().unwrap().set_state_free();
+        }
+        sync_all(&nodes, addr).await;
+        let helper = nodes[0].select_helper(&config).unwrap().unwrap();
+        assert!(nodes[2..]
+            .iter()
+            .any(|node| node.data.lock().unwrap().server_id == helper.server_id));
+    }
+
     static SPAWNED: AtomicBool = AtomicBool::new(false);
 
-    fn fake_spawn_scenario(_config: ScenarioConfig) -> Result<(), RuntimeError> {
+    fn fake_spawn_scenario(
+        _config: ScenarioConfig,
+    ) -> Result<JoinHandle<RunStatistics>, RuntimeError> {
         SPAWNED.store(true, Ordering::Relaxed);
-        Ok(())
+        Ok(tokio::spawn(async { RunStatistics::default() }))
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn draining_test() {
+        let gossip_0 = Gossip::new(Uuid::new_v4(), 1234, fake_spawn_scenario);
+        let gossip_1 = Gossip::new(Uuid::new_v4(), 4321, slow_spawn_scenario);
+        let addr = "0.0.0.0:1111".parse().unwrap();
+
+        let (mut stream_0, mut stream_1) = FakeStream::duplex();
+        let (res0, res1) = tokio::join! {
+            gossip_0.request_sync(&mut stream_0, addr),
+            gossip_1.receive_request(&mut stream_1, addr),
+        };
+        assert!(res0.is_ok());
+        assert!(res1.is_ok());
+
+        let existing = gossip_1
+            .spawn_scenario(ScenarioConfig::new("test_draining"))
+            .unwrap();
+        assert_eq!(gossip_1.drain().unwrap(), 1);
+
+        // NOTE: Peers learn that the server is draining, and stop selecting it. As when asking
+        // for help, the requesting server is busy itself.
+        gossip_0.data.lock().unwrap().set_state_busy();
+        let (res0, res1) = tokio::join! {
+            gossip_0.request_sync(&mut stream_0, addr),
+            gossip_1.receive_request(&mut stream_1, addr),
+        };
+        assert!(res0.is_ok());
+        assert!(res1.is_ok());
+        assert!(gossip_0
+            .data
+            .lock()
+            .unwrap()
+            .select_free_peer(&[])
+            .is_none());
+
+        // A help request sent regardless is turned down.
+        let config = ScenarioConfig::new("test_draining");
+        let (res0, res1) = tokio::join! {
+            gossip_0.request_help(&mut stream_0, addr, config),
+            gossip_1.receive_request(&mut stream_1, addr),
+        };
+        assert!(matches!(res0, Err(GossipError::PeerBusy)));
+        assert!(res1.is_ok());
+        assert_eq!(SLOW_SPAWNED.load(Ordering::Relaxed), 1);
+
+        // Existing work still finishes.
+        existing.await.unwrap();
+        assert_eq!(gossip_1.running_scenarios().unwrap(), 0);
+        assert!(gossip_1.is_draining().unwrap());
+    }
+
+    static SLOW_SPAWNED: AtomicUsize = AtomicUsize::new(0);
+
+    fn slow_spawn_scenario(
+        _config: ScenarioConfig,
+    ) -> Result<JoinHandle<RunStatistics>, RuntimeError> {
+        SLOW_SPAWNED.fetch_add(1, Ordering::Relaxed);
+        Ok(tokio::spawn(async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            RunStatistics::default()
+        }))
+    }
+
+    const HANDOFF_GOAL_TPS: f64 = 1_000.;
+    const HANDOFF_RAMP: [f64; 4] = [100., 200., 300., 400.];
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn handoff_test() {
+        let gossip_0 = Gossip::new(Uuid::new_v4(), 1234, fake_spawn_scenario);
+        let gossip_1 = Gossip::new(Uuid::new_v4(), 4321, ramping_spawn_scenario);
+
+        let (mut stream_0, mut stream_1) = FakeStream::duplex();
+
+        let (res0, res1) = tokio::join! {
+            gossip_0.request_sync(&mut stream_0, "0.0.0.0:1111".parse().unwrap()),
+            gossip_1.receive_request(&mut stream_1, "0.0.0.0:1111".parse().unwrap()),
+        };
+
+        assert!(res0.is_ok());
+        assert!(res1.is_ok());
+
+        // NOTE: The originating node runs at the goal TPS minus whatever the helper reports.
+        let originator = tokio::spawn(async {
+            let mut combined = vec![];
+            for _ in 0..HANDOFF_RAMP.len() * 2 - 1 {
+                tokio::time::sleep(HANDOFF_INTERVAL / 2).await;
+                let own = HANDOFF_GOAL_TPS - handoff::delegated_tps("test_handoff");
+                let helper = handoff::local_tps("test_handoff").unwrap_or(0.);
+                combined.push(own + helper);
+            }
+            combined
+        });
+
+        let config = ScenarioConfig::new("test_handoff");
+        let (res0, res1) = tokio::join! {
+            gossip_0.request_help(&mut stream_0, "0.0.0.0:1111".parse().unwrap(), config),
+            gossip_1.receive_request(&mut stream_1, "0.0.0.0:1111".parse().unwrap()),
+        };
+
+        assert!(res0.is_ok());
+        assert!(res1.is_ok());
+
+        // NOTE: Progress is only reported every interval, so allow for one ramp step of lag.
+        for tps in originator.await.unwrap() {
+            assert!(
+                (tps - HANDOFF_GOAL_TPS).abs() <= 100.,
+                "combined TPS: {tps}"
+            );
+        }
+        assert_eq!(handoff::delegated_tps("test_handoff"), 0.);
+    }
+
+    fn ramping_spawn_scenario(
+        config: ScenarioConfig,
+    ) -> Result<JoinHandle<RunStatistics>, RuntimeError> {
+        Ok(tokio::spawn(async move {
+            for tps in HANDOFF_RAMP {
+                crate::runtime::report_tps(&config.name, tps);
+                tokio::time::sleep(HANDOFF_INTERVAL).await;
+            }
+            RunStatistics::default()
+        }))
     }
 }
@@ -0,0 +1,74 @@
+use super::super::{message::Message, Gossip, GossipError, GossipStream};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+impl Gossip {
+    /// Minimal handshake which checks that a peer speaks the gossip protocol, without modifying
+    /// either server's gossip data.
+    pub(crate) async fn request_ping(
+        &self,
+        stream: &mut impl GossipStream,
+    ) -> Result<Pong, GossipError> {
+        stream.send(Message::ping()).await?;
+        let msg: Message<Pong> = stream.recv().await?;
+        Ok(msg.inner)
+    }
+
+    pub(crate) async fn receive_ping_request(
+        &self,
+        stream: &mut impl GossipStream,
+        peer_addr: SocketAddr,
+    ) -> Result<(), GossipError> {
+        stream.send(Message::pong(&self.server_id, peer_addr)).await
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Pong {
+    /// ID of the responding server.
+    pub server_id: Uuid,
+    /// Address of the requesting server, as seen by the responding server.
+    pub addr: SocketAddr,
+}
+
+impl Message<Pong> {
+    pub fn pong(server_id: &Uuid, addr: SocketAddr) -> Message<Pong> {
+        Message {
+            inner: Pong {
+                server_id: server_id.to_owned(),
+                addr,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gossip::tests::FakeStream;
+    use crate::runtime::spawn_scenario;
+
+    #[tokio::test]
+    async fn ping_test() {
+        let gossip_0 = Gossip::new(Uuid::new_v4(), 1234, spawn_scenario);
+        let server_id = Uuid::new_v4();
+        let gossip_1 = Gossip::new(server_id, 4321, spawn_scenario);
+
+        let (mut stream_0, mut stream_1) = FakeStream::duplex();
+
+        let (res0, res1) = tokio::join! {
+            gossip_0.request_ping(&mut stream_0),
+            gossip_1.receive_request(&mut stream_1, "10.0.0.1:1111".parse().unwrap()),
+        };
+
+        assert!(res1.is_ok());
+        let pong = res0.unwrap();
+        assert_eq!(pong.server_id, server_id);
+        assert_eq!(pong.addr, "10.0.0.1:1111".parse().unwrap());
+
+        // NOTE: Unlike a sync, neither server learns about the other.
+        assert!(gossip_0.data.lock().unwrap().peers.is_empty());
+        assert!(gossip_1.data.lock().unwrap().peers.is_empty());
+    }
+}
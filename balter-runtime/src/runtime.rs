@@ -4,7 +4,7 @@
 //! involves spinning up an API server and a gossip protocol task.
 use crate::{
     error::RuntimeError,
-    gossip::{gossip_task, peer_stream, Gossip},
+    gossip::{announce, gossip_task, peer_stream, Gossip},
     server::server_task,
     DistributedScenario,
 };
@@ -15,13 +15,25 @@ use lazy_static::lazy_static;
 #[doc(hidden)]
 pub use linkme::distributed_slice;
 use std::future::Future;
+use std::num::NonZeroU32;
 use std::pin::Pin;
-use std::{collections::HashMap, net::SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::SocketAddr,
+};
+use tokio::task::JoinHandle;
 #[allow(unused)]
 use tracing::{debug, error, info, instrument, Instrument};
 
+pub(crate) mod cap;
+mod doctor;
+pub(crate) mod handoff;
 mod message;
 
+pub use cap::{peer_transactions, report_transactions};
+pub use handoff::{delegated_tps, report_tps};
 pub use message::RuntimeMessage;
 
 // TODO: This doesn't need to be a global, and can be threaded into each Scenario via task_local.
@@ -31,6 +43,14 @@ lazy_static! {
         bounded(10);
 }
 
+/// Set once the runtime is draining [`BALTER_OUT`], see [`is_attached()`].
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the distributed runtime is running in this process, and so draining [`BALTER_OUT`].
+pub fn is_attached() -> bool {
+    ATTACHED.load(Ordering::Relaxed)
+}
+
 /// An array created at link-time which stores the names of each scenario and their respective
 /// function pointer.
 #[doc(hidden)]
@@ -42,6 +62,9 @@ pub static BALTER_SCENARIOS: [(
 
 const DEFAULT_PORT: u16 = 7621;
 
+/// How often a draining server checks whether its Scenarios have finished.
+const DRAIN_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Parser, Debug)]
 #[command(version = "0.1")]
 struct BalterCli {
@@ -50,6 +73,19 @@ struct BalterCli {
 
     #[arg(short('n'), long)]
     peers: Vec<SocketAddr>,
+
+    #[arg(short, long = "tag")]
+    tags: Vec<String>,
+
+    #[arg(long)]
+    doctor: bool,
+
+    #[arg(long)]
+    max_tps_global: Option<NonZeroU32>,
+
+    /// Milliseconds
+    #[arg(long)]
+    added_latency: Option<u64>,
 }
 
 /// Default Balter distributed runtime. (requires `rt` feature)
@@ -73,6 +109,11 @@ struct BalterCli {
 pub struct BalterRuntime {
     port: u16,
     peers: Vec<SocketAddr>,
+    tags: BTreeSet<String>,
+    doctor: bool,
+    max_global_tps: Option<NonZeroU32>,
+    added_latency: Option<Duration>,
+    results_db: Option<String>,
 }
 
 impl Default for BalterRuntime {
@@ -86,6 +127,11 @@ impl BalterRuntime {
         BalterRuntime {
             port: DEFAULT_PORT,
             peers: vec![],
+            tags: BTreeSet::new(),
+            doctor: false,
+            max_global_tps: None,
+            added_latency: None,
+            results_db: None,
         }
     }
 
@@ -95,15 +141,36 @@ impl BalterRuntime {
     ///
     /// `-n`, `--peers` to provide addresses to peer servers to enable gossiping.
     ///
+    /// `-t`, `--tag` to add a tag to this server (see [`tags()`](Self::tags)).
+    ///
+    /// `--doctor` to check connectivity to each peer and exit (see [`doctor()`](Self::doctor)).
+    ///
+    /// `--max-tps-global` to set a safety limit on the goal TPS (see
+    /// [`max_global_tps()`](Self::max_global_tps)).
+    ///
+    /// `--added-latency` to add a delay in milliseconds to each Transaction (see
+    /// [`added_latency()`](Self::added_latency)).
+    ///
     /// # Example
     /// ```ignore
     /// $ ./my_load_test -p 2742
     /// $ ./my_load_test -n 127.0.0.1:7621 -n 127.0.0.2:7621
+    /// $ ./my_load_test -n 127.0.0.1:7621 -t gpu
+    /// $ ./my_load_test -n 127.0.0.1:7621 -n 127.0.0.2:7621 --doctor
+    /// $ ./my_load_test -n 127.0.0.1:7621 --max-tps-global 50000
+    /// $ ./my_load_test -n 127.0.0.1:7621 --added-latency 80
     /// ```
     pub fn with_args(mut self) -> Self {
         let args = BalterCli::parse();
         self.port = args.port;
         self.peers = args.peers;
+        self.tags.extend(args.tags);
+        self.doctor = args.doctor;
+        self.max_global_tps = args.max_tps_global.or(self.max_global_tps);
+        self.added_latency = args
+            .added_latency
+            .map(Duration::from_millis)
+            .or(self.added_latency);
         self
     }
 
@@ -117,17 +184,123 @@ impl BalterRuntime {
         self
     }
 
+    /// Tags for this server, which are gossiped to peers.
+    ///
+    /// Scenarios using `require_peer_tag()` only delegate work to servers with matching tags,
+    /// which allows for pinning Scenarios to specialized nodes in a heterogeneous cluster.
+    pub fn tags(mut self, tags: &[&str]) -> Self {
+        self.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    /// Run self-diagnostics rather than the runtime.
+    ///
+    /// On [`run()`](Self::run), this server's ID is printed along with whether each peer is
+    /// reachable and completes a gossip handshake, and the address this server is known by. The
+    /// process then exits, with a non-zero exit code if any peer failed.
+    pub fn doctor(mut self) -> Self {
+        self.doctor = true;
+        self
+    }
+
+    /// Safety limit on the goal TPS of any Scenario run by this server.
+    ///
+    /// Configs received via `/run` or as a help request from a peer are rejected if their goal
+    /// TPS exceeds `tps` (with a `400 Bad Request` for `/run`). Configs without a goal TPS (e.g.
+    /// only an error rate) are capped at `tps` instead, so that the controllers can't scale past
+    /// it. Since delegated work only covers the remaining TPS of the original goal, setting the
+    /// same limit on every server limits the whole cluster.
+    ///
+    /// NOTE: Scenarios awaited directly (rather than started via the runtime) are not limited.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the provided TPS is zero
+    pub fn max_global_tps(mut self, tps: u32) -> Self {
+        self.max_global_tps = Some(
+            NonZeroU32::new(tps)
+                .unwrap_or_else(|| panic!("Max global TPS must be non-zero. Given: {tps}")),
+        );
+        self
+    }
+
+    /// Delay added before each Transaction run by this server, to simulate its network latency
+    /// to the target.
+    ///
+    /// Giving each server of a cluster a different added latency models geographically
+    /// distributed load (e.g. 80ms for a server standing in for another continent) without
+    /// deploying to actual regions. The delay is generator-side: it slows down each Transaction,
+    /// so more concurrency is needed for the same TPS, but it is excluded from the reported
+    /// latency, which remains the target's.
+    ///
+    /// NOTE: Applies to Scenarios started via the runtime (`/run` or as a help request from a
+    /// peer), not ones awaited directly. Each server uses its own added latency, regardless of
+    /// which server the Scenario was started on.
+    pub fn added_latency(mut self, latency: Duration) -> Self {
+        self.added_latency = Some(latency);
+        self
+    }
+
+    /// InfluxDB write URL the results of each Scenario started on this server are persisted to,
+    /// see `persist_to()` for the rows written.
+    ///
+    /// NOTE: Applies to Scenarios started via `/run`, so each run is written once by the server
+    /// it was started on. Help requests from peers aren't persisted, as their results only
+    /// cover a share of the peer's run.
+    #[cfg(feature = "influxdb")]
+    pub fn with_results_db(mut self, dsn: &str) -> Self {
+        self.results_db = Some(dsn.to_string());
+        self
+    }
+
     #[instrument(name="balter", skip_all, fields(port=self.port))]
     pub async fn run(self) {
-        let gossip = Gossip::new(uuid::Uuid::new_v4(), self.port, spawn_scenario);
+        if self.doctor {
+            let healthy =
+                doctor::doctor(uuid::Uuid::new_v4(), self.port, &self.peers, &self.tags).await;
+            std::process::exit(if healthy { 0 } else { 1 });
+        }
+
+        let gossip = Gossip::new(uuid::Uuid::new_v4(), self.port, spawn_scenario)
+            .with_tags(self.tags)
+            .with_max_global_tps(self.max_global_tps)
+            .with_added_latency(self.added_latency)
+            .with_results_db(self.results_db);
 
         spawn_or_halt(server_task(self.port, gossip.clone())).await;
         spawn_or_halt(gossip_task(gossip.clone())).await;
         spawn_or_halt(helper_task(gossip.clone())).await;
+        spawn_or_halt(drain_task(gossip.clone())).await;
     }
 }
 
-pub(crate) fn spawn_scenario(config: ScenarioConfig) -> Result<(), RuntimeError> {
+/// Check a config received from outside this process against the max global TPS (see
+/// [`BalterRuntime::max_global_tps()`]), capping its goal TPS if it has none.
+pub(crate) fn limit_global_tps(
+    config: &mut ScenarioConfig,
+    max_global_tps: Option<NonZeroU32>,
+) -> Result<(), RuntimeError> {
+    let Some(cap) = max_global_tps else {
+        return Ok(());
+    };
+
+    match config.max_tps {
+        Some(goal) if goal > cap => Err(RuntimeError::ExceedsMaxGlobalTps {
+            name: config.name.clone(),
+            goal: goal.get(),
+            cap: cap.get(),
+        }),
+        None if !config.is_unconfigured() => {
+            config.set_max_tps(cap);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn spawn_scenario(
+    config: ScenarioConfig,
+) -> Result<JoinHandle<RunStatistics>, RuntimeError> {
     // TODO: We probably don't want to rebuild this every time.
     let scenarios: HashMap<_, _> = BALTER_SCENARIOS
         .iter()
@@ -141,37 +314,32 @@ pub(crate) fn spawn_scenario(config: ScenarioConfig) -> Result<(), RuntimeError>
     info!("Running scenario {}.", &config.name);
     let scenario = BALTER_SCENARIOS[*idx];
     let fut = scenario.1().set_config(config);
-    tokio::spawn(
-        async move {
-            fut.await;
-        }
-        .in_current_span(),
-    );
-    Ok(())
+    Ok(tokio::spawn(fut.in_current_span()))
 }
 
 async fn helper_task(gossip: Gossip) -> Result<(), RuntimeError> {
     let (_, ref rx) = *BALTER_OUT;
     let rx = rx.clone();
+    ATTACHED.store(true, Ordering::Relaxed);
     loop {
         if let Ok(msg) = rx.recv().await {
             match msg {
                 RuntimeMessage::Help(config) => {
-                    // TODO: The internal `data` probably shouldn't be exposed like this.
-                    let peer = {
-                        let mut data = gossip.data.lock()?;
-                        data.set_state_busy();
-                        data.select_free_peer()
-                    };
-                    if let Some(peer) = peer {
+                    if let Some(peer) = gossip.select_helper(&config)? {
                         let mut stream = peer_stream(&peer).await?;
-                        let res = gossip.request_help(&mut stream, peer.addr, config).await;
-                        if let Err(error) = res {
-                            error!("Error in gossip protocol: {error:?}");
-                        }
-                    } else {
-                        error!("No Peers available to help.");
-                        // TODO: Implement some form of retry/auto-scaling
+                        let gossip = gossip.clone();
+                        // NOTE: The help request stays open for the duration of the delegated
+                        // work, relaying the helper's progress, so it can't block this loop.
+                        tokio::spawn(
+                            async move {
+                                let res =
+                                    gossip.request_help(&mut stream, peer.addr, *config).await;
+                                if let Err(error) = res {
+                                    error!("Error in gossip protocol: {error:?}");
+                                }
+                            }
+                            .in_current_span(),
+                        );
                     }
                 }
                 RuntimeMessage::Finished => {
@@ -184,6 +352,20 @@ async fn helper_task(gossip: Gossip) -> Result<(), RuntimeError> {
     }
 }
 
+/// Exit once this server is draining (see `/drain`) and the Scenarios it was running have
+/// finished, after letting its peers know that it is leaving.
+pub(crate) async fn drain_task(gossip: Gossip) -> Result<(), RuntimeError> {
+    let mut interval = tokio::time::interval(DRAIN_INTERVAL);
+    loop {
+        interval.tick().await;
+        if gossip.is_draining()? && gossip.running_scenarios()? == 0 {
+            announce(&gossip).await?;
+            info!("Drained, shutting down.");
+            std::process::exit(0);
+        }
+    }
+}
+
 async fn spawn_or_halt<F, R, E>(fut: F)
 where
     F: Future<Output = Result<R, E>> + Send + 'static,
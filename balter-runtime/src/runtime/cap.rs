@@ -0,0 +1,109 @@
+//! Bookkeeping for the cluster-wide cap on Transactions (see `max_transactions()`).
+//!
+//! Each server gossips the number of Transactions run by its capped Scenarios, and sums up the
+//! counts gossiped by its peers, so that each Scenario can stop once the cluster as a whole has
+//! reached its cap.
+//!
+//! The final count of a Scenario which has finished is still gossiped until none of the peers
+//! are running it, since their Scenarios would otherwise no longer count its Transactions.
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+use tracing::error;
+
+// TODO: Like `BALTER_OUT`, these are keyed by Scenario name and so assume a single instance of
+// each Scenario per server.
+lazy_static! {
+    /// Transactions run by capped Scenarios on this server, along with whether each has finished.
+    static ref LOCAL_TRANSACTIONS: Mutex<BTreeMap<String, (u64, bool)>> =
+        Mutex::new(BTreeMap::new());
+
+    /// Transactions run by peers, as of the last gossip.
+    static ref PEER_TRANSACTIONS: Mutex<HashMap<String, u64>> = Mutex::new(HashMap::new());
+}
+
+/// Report the number of Transactions run so far by a locally running Scenario, or `None` once
+/// it has finished (keeping its final count until no peer is running it).
+#[doc(hidden)]
+pub fn report_transactions(name: &str, transactions: Option<u64>) {
+    let Ok(mut local) = LOCAL_TRANSACTIONS.lock() else {
+        error!("Transaction counts are poisoned.");
+        return;
+    };
+
+    if let Some(transactions) = transactions {
+        local.insert(name.to_string(), (transactions, false));
+    } else if let Some((_, finished)) = local.get_mut(name) {
+        *finished = true;
+    }
+}
+
+/// Number of Transactions run by peers for the given Scenario.
+#[doc(hidden)]
+pub fn peer_transactions(name: &str) -> u64 {
+    match PEER_TRANSACTIONS.lock() {
+        Ok(peers) => peers.get(name).copied().unwrap_or(0),
+        Err(_) => {
+            error!("Transaction counts are poisoned.");
+            0
+        }
+    }
+}
+
+/// Transactions run by each capped Scenario on this server, along with those which have
+/// finished.
+pub(crate) fn local_transactions() -> (BTreeMap<String, u64>, BTreeSet<String>) {
+    let Ok(local) = LOCAL_TRANSACTIONS.lock() else {
+        error!("Transaction counts are poisoned.");
+        return (BTreeMap::new(), BTreeSet::new());
+    };
+
+    let counts = local
+        .iter()
+        .map(|(name, (transactions, _))| (name.clone(), *transactions))
+        .collect();
+    let finished = local
+        .iter()
+        .filter(|(_, (_, finished))| *finished)
+        .map(|(name, _)| name.clone())
+        .collect();
+    (counts, finished)
+}
+
+/// Set the Transactions run by peers, and drop the final counts of the finished Scenarios which
+/// none of them are `running` anymore.
+pub(crate) fn set_peer_transactions(transactions: HashMap<String, u64>, running: HashSet<String>) {
+    match PEER_TRANSACTIONS.lock() {
+        Ok(mut peers) => *peers = transactions,
+        Err(_) => error!("Transaction counts are poisoned."),
+    }
+
+    match LOCAL_TRANSACTIONS.lock() {
+        Ok(mut local) => local.retain(|name, (_, finished)| !*finished || running.contains(name)),
+        Err(_) => error!("Transaction counts are poisoned."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finished_count_kept_while_peers_run() {
+        let name = "test_finished_count_kept_while_peers_run";
+        let local = |name: &str| {
+            let (counts, finished) = local_transactions();
+            (counts.get(name).copied(), finished.contains(name))
+        };
+
+        report_transactions(name, Some(100));
+        report_transactions(name, None);
+        assert_eq!(local(name), (Some(100), true));
+
+        set_peer_transactions(HashMap::new(), HashSet::from([name.to_string()]));
+        assert_eq!(local(name), (Some(100), true));
+
+        set_peer_transactions(HashMap::new(), HashSet::new());
+        assert_eq!(local(name), (None, false));
+    }
+}
@@ -0,0 +1,70 @@
+//! Self-diagnostics for the distributed runtime (`--doctor`).
+//!
+//! Rather than digging through logs to figure out why gossip isn't working, this connects to
+//! each configured peer, performs a minimal handshake, and prints a pass/fail report.
+use crate::gossip::{addr_stream, Gossip, GossipError};
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+use uuid::Uuid;
+
+const DOCTOR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Print a connectivity report for this server, returning whether every peer passed.
+pub(crate) async fn doctor(
+    server_id: Uuid,
+    port: u16,
+    peers: &[SocketAddr],
+    tags: &BTreeSet<String>,
+) -> bool {
+    // NOTE: A throwaway Gossip instance; a ping doesn't touch its data or spawn any Scenarios.
+    let gossip = Gossip::new(server_id, port, super::spawn_scenario);
+
+    println!("Balter doctor");
+    println!("  Server ID:  {server_id}");
+    println!("  Port:       {port}");
+    if !tags.is_empty() {
+        let tags: Vec<_> = tags.iter().map(String::as_str).collect();
+        println!("  Tags:       {}", tags.join(", "));
+    }
+
+    if peers.is_empty() {
+        println!("  Peers:      none configured (use `-n` to add peers)");
+        return false;
+    }
+
+    println!("  Peers:");
+    let mut healthy = true;
+    let mut resolved_addr = None;
+    for peer in peers {
+        match tokio::time::timeout(DOCTOR_TIMEOUT, ping(&gossip, *peer)).await {
+            Ok(Ok((peer_id, mut addr))) => {
+                println!("    [PASS] {peer} (server {peer_id})");
+                // NOTE: Like `GossipData::learn_address()`, the WebSocket port != server port
+                addr.set_port(port);
+                resolved_addr.get_or_insert(addr);
+            }
+            Ok(Err(err)) => {
+                println!("    [FAIL] {peer}: {err}");
+                healthy = false;
+            }
+            Err(_) => {
+                println!("    [FAIL] {peer}: timed out after {DOCTOR_TIMEOUT:?}");
+                healthy = false;
+            }
+        }
+    }
+
+    match resolved_addr {
+        Some(addr) => println!("  Address:    {addr} (as seen by peers)"),
+        None => println!("  Address:    unknown (no reachable peers)"),
+    }
+
+    healthy
+}
+
+async fn ping(gossip: &Gossip, peer: SocketAddr) -> Result<(Uuid, SocketAddr), GossipError> {
+    let mut stream = addr_stream(peer).await?;
+    let pong = gossip.request_ping(&mut stream).await?;
+    Ok((pong.server_id, pong.addr))
+}
@@ -0,0 +1,70 @@
+//! Bookkeeping for warm handoff of load between peers.
+//!
+//! When a Scenario delegates part of its load to a peer, the peer ramps up from scratch. To keep
+//! the total load constant, the helper reports the TPS it has achieved back to the originating
+//! server, which subtracts it from its own goal TPS.
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::error;
+
+/// Interval at which a helper reports its progress back to the originating server.
+pub(crate) const HANDOFF_INTERVAL: Duration = Duration::from_secs(1);
+
+// TODO: Like `BALTER_OUT`, these are keyed by Scenario name and so assume a single instance of
+// each Scenario per server.
+lazy_static! {
+    /// TPS achieved by Scenarios running on this server.
+    static ref LOCAL_TPS: Mutex<HashMap<String, f64>> = Mutex::new(HashMap::new());
+
+    /// TPS achieved by peers on work delegated from this server.
+    static ref DELEGATED_TPS: Mutex<HashMap<String, f64>> = Mutex::new(HashMap::new());
+}
+
+/// Report the TPS achieved by a locally running Scenario.
+#[doc(hidden)]
+pub fn report_tps(name: &str, tps: f64) {
+    set(&LOCAL_TPS, name, Some(tps));
+}
+
+/// TPS achieved by peers on work delegated by the given Scenario.
+#[doc(hidden)]
+pub fn delegated_tps(name: &str) -> f64 {
+    get(&DELEGATED_TPS, name).unwrap_or(0.)
+}
+
+pub(crate) fn local_tps(name: &str) -> Option<f64> {
+    get(&LOCAL_TPS, name)
+}
+
+pub(crate) fn clear_local_tps(name: &str) {
+    set(&LOCAL_TPS, name, None);
+}
+
+pub(crate) fn set_delegated_tps(name: &str, tps: Option<f64>) {
+    set(&DELEGATED_TPS, name, tps);
+}
+
+fn get(map: &Mutex<HashMap<String, f64>>, name: &str) -> Option<f64> {
+    match map.lock() {
+        Ok(map) => map.get(name).copied(),
+        Err(_) => {
+            error!("Handoff data is poisoned.");
+            None
+        }
+    }
+}
+
+fn set(map: &Mutex<HashMap<String, f64>>, name: &str, tps: Option<f64>) {
+    let Ok(mut map) = map.lock() else {
+        error!("Handoff data is poisoned.");
+        return;
+    };
+
+    if let Some(tps) = tps {
+        map.insert(name.to_string(), tps);
+    } else {
+        map.remove(name);
+    }
+}
@@ -1,6 +1,6 @@
 use balter_core::ScenarioConfig;
 
 pub enum RuntimeMessage {
-    Help(ScenarioConfig),
+    Help(Box<ScenarioConfig>),
     Finished,
 }
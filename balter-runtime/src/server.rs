@@ -1,9 +1,9 @@
-use crate::{error::RuntimeError, gossip::Gossip, runtime::spawn_scenario};
+use crate::{error::RuntimeError, gossip::Gossip, runtime::limit_global_tps};
 use axum::{
     extract::{
         connect_info::ConnectInfo,
         ws::{WebSocket, WebSocketUpgrade},
-        Json, State,
+        Json, Path, Query, State,
     },
     http::StatusCode,
     response::{IntoResponse, Response},
@@ -11,11 +11,16 @@ use axum::{
     Router,
 };
 use balter_core::ScenarioConfig;
+use jobs::{Job, Jobs};
+use serde::{Deserialize, Serialize};
 use std::{net::SocketAddr, sync::Arc};
 use thiserror::Error;
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
-use tracing::{debug, error, instrument};
+use tracing::{debug, error, info, instrument};
+use uuid::Uuid;
+
+mod jobs;
 
 #[derive(Error, Debug)]
 pub(crate) enum ServerError {
@@ -27,10 +32,15 @@ pub(crate) enum ServerError {
 }
 
 pub(crate) async fn server_task(port: u16, gossip: Gossip) -> Result<(), ServerError> {
-    let state = ServerState { gossip };
+    let state = ServerState {
+        gossip,
+        jobs: Jobs::default(),
+    };
 
     let app = Router::new()
         .route("/run", post(run))
+        .route("/run/:id", get(job))
+        .route("/drain", post(drain))
         .route("/ws", get(ws))
         .with_state(Arc::new(state))
         .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()))
@@ -47,15 +57,19 @@ pub(crate) async fn server_task(port: u16, gossip: Gossip) -> Result<(), ServerE
 
 struct ServerState {
     gossip: Gossip,
+    jobs: Jobs,
 }
 
 #[derive(Error, Debug)]
 enum HandlerError {
     #[error("Channel send error (Balter runtime has likely fallen over): {0}")]
-    Send(#[from] async_channel::SendError<ScenarioConfig>),
+    Send(#[from] Box<async_channel::SendError<ScenarioConfig>>),
 
     #[error("Runtime error: {0}")]
     Runtime(#[from] RuntimeError),
+
+    #[error("No job found with ID {0}")]
+    NoJob(Uuid),
 }
 
 impl IntoResponse for HandlerError {
@@ -65,6 +79,13 @@ impl IntoResponse for HandlerError {
             Runtime(RuntimeError::NoScenario) => {
                 (StatusCode::NOT_FOUND, "Scenario not found".to_string())
             }
+            Runtime(err @ RuntimeError::ExceedsMaxGlobalTps { .. }) => {
+                (StatusCode::BAD_REQUEST, err.to_string())
+            }
+            Runtime(err @ RuntimeError::Draining) => {
+                (StatusCode::SERVICE_UNAVAILABLE, err.to_string())
+            }
+            err @ NoJob(_) => (StatusCode::NOT_FOUND, err.to_string()),
             Send(err) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Internal error: {err:?}"),
@@ -78,16 +99,59 @@ impl IntoResponse for HandlerError {
     }
 }
 
-#[instrument(skip(_state))]
+#[derive(Debug, Default, Deserialize)]
+struct RunParams {
+    /// Respond once the Scenario has finished, rather than as soon as it has started.
+    #[serde(default)]
+    wait: bool,
+}
+
+#[instrument(skip(state))]
 async fn run(
-    State(_state): State<Arc<ServerState>>,
-    Json(scenario): Json<ScenarioConfig>,
-) -> Result<String, HandlerError> {
-    let output = format!("Running scenario {}", &scenario.name);
+    State(state): State<Arc<ServerState>>,
+    Query(params): Query<RunParams>,
+    Json(mut scenario): Json<ScenarioConfig>,
+) -> Result<Json<Job>, HandlerError> {
+    limit_global_tps(&mut scenario, state.gossip.max_global_tps())?;
+    scenario.results_db = state.gossip.results_db();
 
-    spawn_scenario(scenario)?;
+    let name = scenario.name.clone();
+    let handle = state.gossip.spawn_scenario(scenario)?;
+    let mut job = state.jobs.track(name, handle);
 
-    Ok(output)
+    if params.wait {
+        // NOTE: Only fails if the job is dropped while running (e.g. on shutdown), in which case
+        // it is reported as still running.
+        let _ = job.wait_for(|job| !job.is_running()).await;
+    }
+
+    let job = job.borrow().clone();
+    Ok(Json(job))
+}
+
+#[instrument(skip(state))]
+async fn job(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Job>, HandlerError> {
+    state.jobs.get(id).map(Json).ok_or(HandlerError::NoJob(id))
+}
+
+#[derive(Debug, Serialize)]
+struct DrainStatus {
+    /// Scenarios which are still running, and which the server waits on before shutting down.
+    running: usize,
+}
+
+/// Stop accepting new Scenarios and shut down once the running ones finish, see
+/// [`drain_task()`](crate::runtime::drain_task).
+#[instrument(skip(state))]
+async fn drain(
+    State(state): State<Arc<ServerState>>,
+) -> Result<(StatusCode, Json<DrainStatus>), HandlerError> {
+    let running = state.gossip.drain()?;
+    info!("Draining, with {running} Scenarios still running.");
+    Ok((StatusCode::ACCEPTED, Json(DrainStatus { running })))
 }
 
 async fn ws(
@@ -104,3 +168,147 @@ async fn handle_ws(mut socket: WebSocket, state: Arc<ServerState>, addr: SocketA
         error!("Error in gossip protocol: {err:?}");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::spawn_scenario;
+    use balter_core::RunStatistics;
+    use jobs::JobStatus;
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+    use tokio::task::JoinHandle;
+
+    fn server_state(gossip: Gossip) -> Arc<ServerState> {
+        Arc::new(ServerState {
+            gossip,
+            jobs: Jobs::default(),
+        })
+    }
+
+    fn fake_spawn_scenario(
+        config: ScenarioConfig,
+    ) -> Result<JoinHandle<RunStatistics>, RuntimeError> {
+        Ok(tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            RunStatistics {
+                goal_tps: config.max_tps.map_or(0, |tps| tps.get()),
+                ..Default::default()
+            }
+        }))
+    }
+
+    fn goal_tps(job: &Job) -> Option<u32> {
+        match &job.status {
+            JobStatus::Finished { statistics } => Some(statistics.goal_tps),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn run_above_max_global_tps_is_rejected() {
+        let gossip = Gossip::new(Uuid::new_v4(), 7622, spawn_scenario)
+            .with_max_global_tps(NonZeroU32::new(1_000));
+        let state = server_state(gossip);
+
+        let mut config = ScenarioConfig::new("run_above_max_global_tps_is_rejected");
+        config.max_tps = NonZeroU32::new(1_000_000);
+
+        let err = run(State(state), Query(RunParams::default()), Json(config))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            HandlerError::Runtime(RuntimeError::ExceedsMaxGlobalTps {
+                goal: 1_000_000,
+                cap: 1_000,
+                ..
+            })
+        ));
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn run_and_wait() {
+        let state = server_state(Gossip::new(Uuid::new_v4(), 7623, fake_spawn_scenario));
+
+        let mut config = ScenarioConfig::new("run_and_wait");
+        config.max_tps = NonZeroU32::new(500);
+
+        let Json(job) = run(State(state), Query(RunParams { wait: true }), Json(config))
+            .await
+            .unwrap();
+        assert_eq!(job.name, "run_and_wait");
+        assert_eq!(goal_tps(&job), Some(500));
+    }
+
+    #[tokio::test]
+    async fn run_while_draining_is_rejected() {
+        let state = server_state(Gossip::new(Uuid::new_v4(), 7625, fake_spawn_scenario));
+
+        let mut config = ScenarioConfig::new("run_while_draining_is_rejected");
+        config.max_tps = NonZeroU32::new(500);
+
+        let Json(started) = run(
+            State(state.clone()),
+            Query(RunParams::default()),
+            Json(config.clone()),
+        )
+        .await
+        .unwrap();
+
+        let (status, Json(drain_status)) = drain(State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(drain_status.running, 1);
+
+        let err = run(
+            State(state.clone()),
+            Query(RunParams::default()),
+            Json(config),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // NOTE: The Scenario started before draining still runs to completion.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let Json(finished) = job(State(state.clone()), Path(started.id)).await.unwrap();
+        assert_eq!(goal_tps(&finished), Some(500));
+        assert_eq!(state.gossip.running_scenarios().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn run_and_poll() {
+        let state = server_state(Gossip::new(Uuid::new_v4(), 7624, fake_spawn_scenario));
+
+        let mut config = ScenarioConfig::new("run_and_poll");
+        config.max_tps = NonZeroU32::new(500);
+
+        let Json(started) = run(
+            State(state.clone()),
+            Query(RunParams::default()),
+            Json(config),
+        )
+        .await
+        .unwrap();
+        assert!(started.is_running());
+
+        let mut polled = started.clone();
+        for _ in 0..50 {
+            let Json(job) = job(State(state.clone()), Path(started.id)).await.unwrap();
+            polled = job;
+            if !polled.is_running() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(polled.id, started.id);
+        assert_eq!(goal_tps(&polled), Some(500));
+
+        let err = job(State(state), Path(Uuid::new_v4())).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,139 @@
+//! Registry of the Scenarios started via `/run`, so that their statistics can be retrieved once
+//! they finish.
+use balter_core::RunStatistics;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, Instrument};
+use uuid::Uuid;
+
+/// Number of jobs retained; beyond this, the oldest finished jobs are forgotten.
+const MAX_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Job {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(flatten)]
+    pub status: JobStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum JobStatus {
+    Running,
+    Finished { statistics: Box<RunStatistics> },
+    Failed { error: String },
+}
+
+impl Job {
+    pub fn is_running(&self) -> bool {
+        matches!(self.status, JobStatus::Running)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Jobs {
+    inner: Mutex<JobsInner>,
+}
+
+#[derive(Default)]
+struct JobsInner {
+    jobs: HashMap<Uuid, watch::Receiver<Job>>,
+    /// Job IDs, oldest first.
+    order: VecDeque<Uuid>,
+}
+
+impl Jobs {
+    /// Track a running Scenario until it finishes.
+    pub fn track(&self, name: String, handle: JoinHandle<RunStatistics>) -> watch::Receiver<Job> {
+        let id = Uuid::new_v4();
+        let (tx, rx) = watch::channel(Job {
+            id,
+            name,
+            status: JobStatus::Running,
+        });
+
+        tokio::spawn(
+            async move {
+                let status = match handle.await {
+                    Ok(statistics) => JobStatus::Finished {
+                        statistics: Box::new(statistics),
+                    },
+                    Err(err) => JobStatus::Failed {
+                        error: err.to_string(),
+                    },
+                };
+                tx.send_modify(|job| job.status = status);
+            }
+            .in_current_span(),
+        );
+
+        match self.inner.lock() {
+            Ok(mut inner) => {
+                inner.jobs.insert(id, rx.clone());
+                inner.order.push_back(id);
+                inner.evict();
+            }
+            Err(_) => error!("Job registry is poisoned."),
+        }
+
+        rx
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Job> {
+        match self.inner.lock() {
+            Ok(inner) => inner.jobs.get(&id).map(|job| job.borrow().clone()),
+            Err(_) => {
+                error!("Job registry is poisoned.");
+                None
+            }
+        }
+    }
+}
+
+impl JobsInner {
+    fn evict(&mut self) {
+        let mut excess = self.order.len().saturating_sub(MAX_JOBS);
+        let jobs = &mut self.jobs;
+        // NOTE: Running jobs are never evicted.
+        self.order.retain(|id| {
+            let finished = jobs.get(id).is_some_and(|job| !job.borrow().is_running());
+            if excess > 0 && finished {
+                excess -= 1;
+                jobs.remove(id);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn evicts_oldest_finished_jobs() {
+        let jobs = Jobs::default();
+        let running = jobs.track("running".to_string(), tokio::spawn(std::future::pending()));
+
+        let mut finished = vec![];
+        for _ in 1..MAX_JOBS {
+            let mut job = jobs.track(
+                "finished".to_string(),
+                tokio::spawn(async { RunStatistics::default() }),
+            );
+            job.wait_for(|job| !job.is_running()).await.unwrap();
+            finished.push(job.borrow().id);
+        }
+        jobs.track("new".to_string(), tokio::spawn(std::future::pending()));
+
+        assert!(jobs.get(running.borrow().id).is_some());
+        assert!(jobs.get(finished[0]).is_none());
+        assert!(jobs.get(finished[1]).is_some());
+    }
+}
@@ -0,0 +1,187 @@
+//! Auth tokens shared by the tasks of a Scenario, see
+//! [`with_token_provider()`](crate::scenario::ConfigurableScenario::with_token_provider).
+use arc_swap::ArcSwapOption;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Fraction of a token's TTL after which it is refreshed, so that a Transaction is never handed
+/// a token which is about to expire.
+const REFRESH_AT: f64 = 0.9;
+
+/// An auth token returned by a token provider, valid for `ttl` from when it was acquired.
+#[derive(Debug, Clone)]
+pub struct Token {
+    value: Arc<str>,
+    ttl: Duration,
+}
+
+impl Token {
+    pub fn new(value: impl Into<String>, ttl: Duration) -> Self {
+        Self {
+            value: Arc::from(value.into()),
+            ttl,
+        }
+    }
+}
+
+type TokenFuture = Pin<Box<dyn Future<Output = Result<Token, String>> + Send>>;
+pub(crate) type TokenProviderFn = dyn Fn() -> TokenFuture + Send + Sync;
+
+struct CachedToken {
+    value: Arc<str>,
+    refresh_at: Instant,
+}
+
+/// Caches the token of a provider, refreshing it once it is close to expiring.
+pub(crate) struct TokenCache {
+    provider: Box<TokenProviderFn>,
+    current: ArcSwapOption<CachedToken>,
+    /// Held while refreshing, so that only one task calls the provider at a time.
+    refresh: Mutex<()>,
+}
+
+impl TokenCache {
+    pub fn new<F, Fut, E>(provider: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Token, E>> + Send + 'static,
+        E: Display,
+    {
+        Self {
+            provider: Box::new(move || {
+                let fut = provider();
+                Box::pin(async move { fut.await.map_err(|err| err.to_string()) })
+            }),
+            current: ArcSwapOption::empty(),
+            refresh: Mutex::new(()),
+        }
+    }
+
+    /// The current token, refreshing it first if it is missing or close to expiring. Returns
+    /// `None` if the provider fails, in which case the next call tries again.
+    pub async fn token(&self) -> Option<Arc<str>> {
+        if let Some(token) = self.fresh() {
+            return Some(token);
+        }
+
+        let _guard = self.refresh.lock().await;
+        // NOTE: Another task may have refreshed the token while this one waited for the lock.
+        if let Some(token) = self.fresh() {
+            return Some(token);
+        }
+
+        let acquired = Instant::now();
+        match (self.provider)().await {
+            Ok(token) => {
+                debug!("Refreshed auth token, valid for {:?}", token.ttl);
+                self.current.store(Some(Arc::new(CachedToken {
+                    value: token.value.clone(),
+                    refresh_at: acquired + token.ttl.mul_f64(REFRESH_AT),
+                })));
+                Some(token.value)
+            }
+            Err(err) => {
+                error!("Token provider failed: {err}");
+                None
+            }
+        }
+    }
+
+    fn fresh(&self) -> Option<Arc<str>> {
+        self.current
+            .load()
+            .as_ref()
+            .filter(|token| Instant::now() < token.refresh_at)
+            .map(|token| token.value.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    const TTL: Duration = Duration::from_millis(100);
+
+    /// A provider of tokens named after the time they expire, counting how often it is called.
+    fn counting_cache(calls: Arc<AtomicU64>) -> TokenCache {
+        let start = Instant::now();
+        TokenCache::new(move || {
+            let calls = calls.clone();
+            async move {
+                calls.fetch_add(1, Ordering::Relaxed);
+                // NOTE: Slow enough for concurrent callers to pile up behind the refresh.
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                let expiry = (Instant::now() + TTL - start).as_millis();
+                Ok::<_, String>(Token::new(expiry.to_string(), TTL))
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_token_refresh() {
+        let calls = Arc::new(AtomicU64::new(0));
+        let start = Instant::now();
+        let cache = Arc::new(counting_cache(calls.clone()));
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    let mut tokens = vec![];
+                    while start.elapsed() < Duration::from_millis(500) {
+                        let token = cache.token().await.unwrap();
+                        let expiry: u128 = token.parse().unwrap();
+                        // NOTE: The token is never expired, or about to, when handed out.
+                        assert!(start.elapsed().as_millis() + 5 < expiry, "{token}");
+                        tokens.push(token);
+                        tokio::time::sleep(Duration::from_millis(3)).await;
+                    }
+                    tokens
+                })
+            })
+            .collect();
+
+        let mut distinct = vec![];
+        for task in tasks {
+            distinct.extend(task.await.unwrap());
+        }
+        distinct.sort();
+        distinct.dedup();
+
+        // Refreshed every ~90ms, with a single provider call per refresh despite 10 tasks.
+        let calls = calls.load(Ordering::Relaxed);
+        assert!((5..=7).contains(&calls), "{calls}");
+        assert_eq!(distinct.len() as u64, calls);
+    }
+
+    #[tokio::test]
+    async fn test_token_provider_failure() {
+        let calls = Arc::new(AtomicU64::new(0));
+        let cache = TokenCache::new({
+            let calls = calls.clone();
+            move || {
+                let attempt = calls.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    if attempt == 0 {
+                        Err("Unauthorized")
+                    } else {
+                        Ok(Token::new("token", TTL))
+                    }
+                }
+            }
+        });
+
+        assert_eq!(cache.token().await, None);
+        assert_eq!(cache.token().await.as_deref(), Some("token"));
+        assert_eq!(cache.token().await.as_deref(), Some("token"));
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}
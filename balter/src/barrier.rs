@@ -0,0 +1,236 @@
+//! Synchronizing the tasks of a Scenario, see [`barrier()`].
+use crate::transaction::TRANSACTION_HOOK;
+use std::sync::Mutex;
+use tokio::sync::watch;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Wait until every task of the currently running Scenario has reached the barrier, then
+/// release them all at once.
+///
+/// This models synchronized events such as a flash sale, where every user hits the target at
+/// the same moment rather than spread out over time. The barrier is reusable: once released,
+/// the next call of each task waits for all of them again. Returns immediately if called outside
+/// of a running Scenario.
+///
+/// NOTE: Barriers require a fixed concurrency, e.g. via `unbounded_concurrency()`. The barrier
+/// is sized to the Scenario's current concurrency, which the controllers otherwise change as
+/// they go: when the concurrency is lowered, the tasks already waiting may be released as a
+/// smaller group, and when it is raised, the group waits for the new tasks to arrive. Calls
+/// should be unconditional, since a task which skips the barrier holds up the rest until the
+/// next time it reaches it.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     flash_sale()
+///         .unbounded_concurrency(500)
+///         .duration(Duration::from_secs(60))
+///         .await;
+/// }
+///
+/// #[scenario]
+/// async fn flash_sale() {
+///     balter::barrier().await;
+///     let _ = buy_item().await;
+/// }
+///
+/// #[transaction]
+/// async fn buy_item() -> Result<(), String> {
+///     // Some request logic...
+///     Ok(())
+/// }
+/// ```
+pub async fn barrier() {
+    let Ok(barrier) = TRANSACTION_HOOK.try_with(|hook| hook.barrier.clone()) else {
+        return;
+    };
+    barrier.wait().await;
+}
+
+/// Reusable barrier over the tasks of a Scenario, which can be resized as its concurrency
+/// changes.
+///
+/// NOTE: `tokio::sync::Barrier` has a fixed size and can't release a waiter which is cancelled,
+/// so a change in concurrency would leave the tasks waiting on it stuck forever.
+pub(crate) struct ScenarioBarrier {
+    state: Mutex<BarrierState>,
+    released: watch::Sender<u64>,
+}
+
+struct BarrierState {
+    size: usize,
+    arrived: usize,
+    generation: u64,
+}
+
+impl ScenarioBarrier {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(BarrierState {
+                size: 0,
+                arrived: 0,
+                generation: 0,
+            }),
+            released: watch::channel(0).0,
+        }
+    }
+
+    /// Set the number of tasks the barrier waits for, releasing the waiting tasks if there are
+    /// already enough of them.
+    pub fn resize(&self, size: usize) {
+        let Ok(mut state) = self.state.lock() else {
+            error!("Barrier state is poisoned.");
+            return;
+        };
+        state.size = size;
+        if state.arrived > 0 && state.arrived >= size {
+            self.release(&mut state);
+        }
+    }
+
+    pub async fn wait(&self) {
+        let mut released = self.released.subscribe();
+        let generation = {
+            let Ok(mut state) = self.state.lock() else {
+                error!("Barrier state is poisoned.");
+                return;
+            };
+            state.arrived += 1;
+            if state.arrived >= state.size {
+                self.release(&mut state);
+                return;
+            }
+            state.generation
+        };
+
+        let mut waiting = Waiting {
+            barrier: self,
+            generation,
+            released: false,
+        };
+        let _ = released.wait_for(|released| *released > generation).await;
+        waiting.released = true;
+    }
+
+    fn release(&self, state: &mut BarrierState) {
+        state.arrived = 0;
+        state.generation += 1;
+        self.released.send_replace(state.generation);
+    }
+}
+
+/// A task waiting on the barrier, which no longer counts as arrived if it is cancelled (e.g.
+/// when its task is aborted as the concurrency is lowered).
+struct Waiting<'a> {
+    barrier: &'a ScenarioBarrier,
+    generation: u64,
+    released: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        if let Ok(mut state) = self.barrier.state.lock() {
+            if state.generation == self.generation {
+                state.arrived = state.arrived.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test]
+    async fn test_release_together() {
+        let barrier = Arc::new(ScenarioBarrier::new());
+        barrier.resize(5);
+
+        let start = Instant::now();
+        let tasks: Vec<_> = (0..5u64)
+            .map(|i| {
+                let barrier = barrier.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(20 * i)).await;
+                    barrier.wait().await;
+                    start.elapsed()
+                })
+            })
+            .collect();
+
+        let mut released = vec![];
+        for task in tasks {
+            released.push(task.await.unwrap());
+        }
+
+        let first = *released.iter().min().unwrap();
+        let last = *released.iter().max().unwrap();
+        // NOTE: Nobody is released before the last task (after 80ms) arrives.
+        assert!(first >= Duration::from_millis(80), "{first:?}");
+        assert!(last - first < Duration::from_millis(10), "{released:?}");
+    }
+
+    #[tokio::test]
+    async fn test_reusable() {
+        let barrier = Arc::new(ScenarioBarrier::new());
+        barrier.resize(2);
+
+        let other = barrier.clone();
+        let task = tokio::spawn(async move {
+            for _ in 0..3 {
+                other.wait().await;
+            }
+        });
+        for _ in 0..3 {
+            barrier.wait().await;
+        }
+
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resize() {
+        let barrier = Arc::new(ScenarioBarrier::new());
+        barrier.resize(3);
+
+        let waiter = barrier.clone();
+        let task = tokio::spawn(async move { waiter.wait().await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // NOTE: An aborted waiter no longer counts towards the barrier.
+        let aborted = barrier.clone();
+        let aborted = tokio::spawn(async move { aborted.wait().await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        aborted.abort();
+        let _ = aborted.await;
+        assert_eq!(barrier.state.lock().unwrap().arrived, 1);
+        assert!(!task.is_finished());
+
+        barrier.resize(1);
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_outside_scenario() {
+        tokio::time::timeout(Duration::from_secs(1), barrier())
+            .await
+            .unwrap();
+    }
+}
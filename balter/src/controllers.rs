@@ -1,26 +1,95 @@
 mod constant;
 mod error_rate;
 mod latency;
+mod rate_limit;
+mod slo;
 
 pub(crate) use constant::ConstantController;
 pub(crate) use error_rate::ErrorRateController;
 pub(crate) use latency::LatencyController;
+pub(crate) use rate_limit::RateLimitController;
+pub(crate) use slo::SloController;
 
 use crate::measurement::Measurement;
 use balter_core::{LatencyConfig, ScenarioConfig};
 use std::num::NonZeroU32;
 
-pub(crate) trait Controller: Send {
+/// Qualitative state of a controller's search for its goal TPS.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControllerState {
+    /// Increasing TPS to find the goal.
+    Searching,
+    /// At the goal TPS.
+    Stable,
+    /// Held at a lower TPS by another constraint (e.g. the `.tps()` ceiling).
+    Limited,
+    /// Decreasing TPS after overshooting the goal.
+    BackingOff,
+}
+
+/// Convert a computed TPS into a goal TPS, flooring at 1 TPS.
+///
+/// Controllers can squeeze the goal towards zero (e.g. halving a goal of 1 TPS against a severely
+/// overloaded target), which must not panic mid-test. Values above `u32::MAX` saturate, and NaN
+/// is treated as 0.
+pub(crate) fn clamp_tps(tps: f64) -> NonZeroU32 {
+    // NOTE: `as` saturates to the range of `u32` (with NaN as 0).
+    NonZeroU32::new(tps as u32).unwrap_or(NonZeroU32::MIN)
+}
+
+/// Algorithm which sets the goal TPS from the measurements of each sampling window.
+pub trait Controller: Send {
+    /// Goal TPS to start at, before any window has been measured.
     fn initial_tps(&self) -> NonZeroU32;
+
+    /// Take the measurement of the latest window into account, returning the new goal TPS.
+    ///
+    /// `stable` is whether the load generator actually reached the previous goal TPS within the
+    /// window. Until it has, controllers only ever lower the goal, since the window doesn't show
+    /// how the target copes with the goal.
     fn limit(&mut self, sample: &Measurement, stable: bool) -> NonZeroU32;
+
+    fn current_goal(&self) -> NonZeroU32;
+    fn state(&self) -> ControllerState;
+
+    /// Seed the controller with a single probe measurement taken before the run starts (see
+    /// `fast_start`). Controllers which do not search for a goal TPS can ignore this.
+    fn calibrate(&mut self, _probe: &Measurement) {}
+
+    /// Whether the error rate goal is exceeded even at the lowest goal TPS allowed (see
+    /// `tps_floor()`), so that searching any further is pointless.
+    fn error_target_unachievable(&self) -> bool {
+        false
+    }
 }
 
-pub(crate) struct CompositeController {
+/// The controllers of a Scenario, with the goal TPS set by the most restrictive one.
+///
+/// Scenarios drive these internally, but they can also be driven by hand with [`Measurement`]s
+/// of load generated outside of Balter, to reuse its control algorithms in a custom harness:
+///
+/// ```
+/// use balter::{CompositeController, Controller, Measurement, ScenarioConfig};
+/// use std::time::Duration;
+///
+/// let mut config = ScenarioConfig::new("external");
+/// config.error_rate = Some(0.03);
+/// let mut controllers = CompositeController::new(&config);
+///
+/// // NOTE: One second of load at the previous goal TPS, all of which succeeded.
+/// let goal = controllers.initial_tps();
+/// let window = Measurement::new(goal.get().into(), 0, Duration::from_secs(1));
+/// assert!(controllers.limit(&window, true) > goal);
+/// ```
+pub struct CompositeController {
     controllers: Vec<Box<dyn Controller>>,
 }
 
 impl CompositeController {
+    /// Controllers for the goals of `config`: its TPS ceiling, error rate, latency and rate
+    /// limit backoff, along with any per-Transaction SLOs.
     pub fn new(config: &ScenarioConfig) -> Self {
+        let base_label = config.metrics_base_label();
         let mut controllers = vec![];
 
         if let Some(tps) = config.max_tps {
@@ -28,17 +97,29 @@ impl CompositeController {
         }
 
         if let Some(error_rate) = config.error_rate {
-            controllers.push(Box::new(ErrorRateController::new(&config.name, error_rate)));
+            let controller = match config.descend_from {
+                Some(start_tps) => {
+                    ErrorRateController::descending(&base_label, error_rate, start_tps)
+                }
+                None => ErrorRateController::new(&base_label, error_rate),
+            };
+            controllers.push(Box::new(controller.with_floor(config.tps_floor)));
         }
 
         if let Some(LatencyConfig { latency, quantile }) = config.latency {
-            controllers.push(Box::new(LatencyController::new(
-                &config.name,
-                latency,
-                quantile,
-            )));
+            let controller = LatencyController::new(&base_label, latency, quantile);
+            controllers.push(Box::new(controller.with_tolerance(config.tps_tolerance)));
+        }
+
+        if config.rate_limit_backoff {
+            controllers.push(Box::new(RateLimitController::new(&base_label)));
         }
 
+        // NOTE: Per-Transaction SLOs are only known once the Transactions run, so this is always
+        // present (and unconstrained until an SLO is violated).
+        let controller = SloController::new(&base_label).with_tolerance(config.tps_tolerance);
+        controllers.push(Box::new(controller));
+
         Self { controllers }
     }
 }
@@ -59,4 +140,78 @@ impl Controller for CompositeController {
             .min()
             .expect("No controllers present.")
     }
+
+    fn calibrate(&mut self, probe: &Measurement) {
+        for controller in self.controllers.iter_mut() {
+            controller.calibrate(probe);
+        }
+    }
+
+    fn current_goal(&self) -> NonZeroU32 {
+        self.controllers
+            .iter()
+            .map(|c| c.current_goal())
+            .min()
+            .expect("No controllers present.")
+    }
+
+    fn error_target_unachievable(&self) -> bool {
+        self.controllers
+            .iter()
+            .any(|c| c.error_target_unachievable())
+    }
+
+    /// State of the controller which is setting the goal TPS, or `Limited` if it is holding back
+    /// another controller which would otherwise still be searching.
+    fn state(&self) -> ControllerState {
+        let binding = self
+            .controllers
+            .iter()
+            .min_by_key(|c| c.current_goal())
+            .expect("No controllers present.");
+
+        let held_back = self.controllers.iter().any(|c| {
+            c.current_goal() > binding.current_goal() && c.state() != ControllerState::Stable
+        });
+
+        match binding.state() {
+            ControllerState::Stable if held_back => ControllerState::Limited,
+            state => state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_clamp_tps() {
+        assert_eq!(clamp_tps(1_000.7).get(), 1_000);
+        assert_eq!(clamp_tps(0.5).get(), 1);
+        assert_eq!(clamp_tps(0.).get(), 1);
+        assert_eq!(clamp_tps(-20.).get(), 1);
+        assert_eq!(clamp_tps(f64::NAN).get(), 1);
+        assert_eq!(clamp_tps(1e12).get(), u32::MAX);
+    }
+
+    #[test]
+    fn test_composite_state() {
+        let mut config = ScenarioConfig::new("test_composite_state");
+        config.max_tps = NonZeroU32::new(1_000);
+        config.error_rate = Some(0.03);
+        let mut controllers = CompositeController::new(&config);
+
+        // NOTE: The error rate controller starts below the TPS ceiling, so it sets the goal.
+        assert_eq!(controllers.current_goal(), balter_core::BASE_TPS);
+        assert_eq!(controllers.state(), ControllerState::Searching);
+
+        // With no errors it doubles past the ceiling, which is now holding it back.
+        let sample = Measurement::new(512, 0, Duration::from_secs(1));
+        let goal = controllers.limit(&sample, true);
+        assert_eq!(goal.get(), 1_000);
+        assert_eq!(controllers.current_goal().get(), 1_000);
+        assert_eq!(controllers.state(), ControllerState::Limited);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::controllers::Controller;
+use crate::controllers::{Controller, ControllerState};
 use crate::measurement::Measurement;
 use std::num::NonZeroU32;
 
@@ -20,4 +20,12 @@ impl Controller for ConstantController {
     fn limit(&mut self, _sample: &Measurement, _stable: bool) -> NonZeroU32 {
         self.goal_tps
     }
+
+    fn current_goal(&self) -> NonZeroU32 {
+        self.goal_tps
+    }
+
+    fn state(&self) -> ControllerState {
+        ControllerState::Stable
+    }
 }
@@ -1,27 +1,79 @@
-use crate::controllers::Controller;
+use crate::controllers::{clamp_tps, Controller, ControllerState};
 use crate::measurement::Measurement;
 use balter_core::BASE_TPS;
 use std::num::NonZeroU32;
+use std::sync::Arc;
 #[allow(unused_imports)]
 use tracing::{debug, error, info, instrument, trace, warn, Instrument};
 
 const ERROR_RATE_TOLERANCE: f64 = 0.03;
 const DEFAULT_SMALL_STEP_SIZE: f64 = 0.5;
+/// Consecutive samples over the error rate at the TPS floor after which the error rate is
+/// unachievable, so that a single noisy sample doesn't end the run.
+const UNACHIEVABLE_SAMPLES: usize = 2;
 
 pub(crate) struct ErrorRateController {
-    base_label: String,
+    goal_tps_label: Arc<str>,
+    state_label: Arc<str>,
     goal_tps: NonZeroU32,
     error_rate: f64,
     state: State,
+    backing_off: bool,
+    /// Only ever decrease the goal TPS (see `descend_from()`).
+    descending: bool,
+    /// Lowest goal TPS (see `tps_floor()`).
+    floor: Option<NonZeroU32>,
+    /// Consecutive samples over the error rate while at the floor.
+    over_at_floor: usize,
 }
 
 impl ErrorRateController {
-    pub fn new(name: &str, error_rate: f64) -> Self {
+    pub fn new(base_label: &str, error_rate: f64) -> Self {
         Self {
-            base_label: format!("balter_{name}"),
+            goal_tps_label: Arc::from(format!("{base_label}_erc_goal_tps")),
+            state_label: Arc::from(format!("{base_label}_erc_state")),
             goal_tps: BASE_TPS,
             error_rate,
             state: State::BigStep,
+            backing_off: false,
+            descending: false,
+            floor: None,
+            over_at_floor: 0,
+        }
+    }
+
+    /// Never decrease the goal TPS below `floor`, if set.
+    pub fn with_floor(mut self, floor: Option<NonZeroU32>) -> Self {
+        self.floor = floor;
+        self.goal_tps = self.goal_tps.max(self.min_tps());
+        self
+    }
+
+    fn min_tps(&self) -> NonZeroU32 {
+        self.floor.unwrap_or(NonZeroU32::MIN)
+    }
+
+    /// Count the samples over the error rate in a row while at the floor.
+    fn check_floor(&mut self, bounds: &Bounds) {
+        let Some(floor) = self.floor else {
+            return;
+        };
+
+        if matches!(bounds, Bounds::Over) && self.goal_tps <= floor {
+            self.over_at_floor += 1;
+        } else {
+            self.over_at_floor = 0;
+        }
+    }
+
+    /// Start at `start_tps` and only ever decrease from there, until the error rate is within
+    /// bounds.
+    pub fn descending(base_label: &str, error_rate: f64, start_tps: NonZeroU32) -> Self {
+        Self {
+            goal_tps: start_tps,
+            state: State::SmallStep(DEFAULT_SMALL_STEP_SIZE),
+            descending: true,
+            ..Self::new(base_label, error_rate)
         }
     }
 
@@ -40,87 +92,126 @@ impl ErrorRateController {
             _ => Bounds::Under,
         }
     }
-}
 
-impl Controller for ErrorRateController {
-    fn initial_tps(&self) -> NonZeroU32 {
-        BASE_TPS
-    }
-
-    fn limit(&mut self, sample: &Measurement, stable: bool) -> NonZeroU32 {
-        // TODO: Remove panic; this can be a type-safe check
-        let sample_error_rate = sample.error_rate;
-
-        let (new_goal_tps, new_state) = match self.check_bounds(sample_error_rate) {
+    /// Search for the goal TPS in both directions: doubling until the error rate is exceeded,
+    /// then narrowing in with smaller steps.
+    fn search(&self, bounds: &Bounds, sample: &Measurement) -> (NonZeroU32, State) {
+        match bounds {
             Bounds::Under => match self.state {
                 s @ State::BigStep => {
                     trace!("Under bounds w/ BigStep");
-                    (NonZeroU32::new(self.goal_tps.get() * 2).unwrap(), s)
+                    (clamp_tps(self.goal_tps.get() as f64 * 2.), s)
                 }
                 s @ State::SmallStep(step_ratio) => {
                     trace!("Under bounds w/ SmallStep.");
-                    // TODO: Better handling of conversions
                     let step = (self.goal_tps.get() as f64 * step_ratio).max(1.);
+                    (clamp_tps(self.goal_tps.get() as f64 + step), s)
+                }
+                State::Stable => {
+                    trace!("Under bounds w/ Stable.");
+                    (self.goal_tps, State::SmallStep(DEFAULT_SMALL_STEP_SIZE))
+                }
+            },
+            Bounds::At => match self.state {
+                State::BigStep | State::SmallStep(_) => {
+                    trace!("At bounds w/ BigStep|SmallStep.");
+                    (clamp_tps(sample.tps), State::Stable)
+                }
+                s @ State::Stable => {
+                    trace!("At bounds w/ Stable.");
+                    (self.goal_tps, s)
+                }
+            },
+            Bounds::Over => match self.state {
+                State::BigStep => {
+                    trace!("Over bounds w/ BigStep.");
                     (
-                        NonZeroU32::new(self.goal_tps.get() + step as u32).unwrap(),
-                        s,
+                        clamp_tps(self.goal_tps.get() as f64 / 2.),
+                        State::SmallStep(DEFAULT_SMALL_STEP_SIZE),
                     )
                 }
+                State::SmallStep(step_ratio) => {
+                    trace!("Over bounds w/ SmallStep({step_ratio}).");
+
+                    let rev_goal = self.goal_tps.get() as f64 / (step_ratio + 1.);
+                    (clamp_tps(rev_goal), State::SmallStep(step_ratio / 2.))
+                }
                 State::Stable => {
-                    trace!("Under bounds w/ Stable.");
+                    trace!("Over bounds w/ Stable.");
                     (self.goal_tps, State::SmallStep(DEFAULT_SMALL_STEP_SIZE))
                 }
             },
+        }
+    }
+
+    /// Decrease the goal TPS until the error rate is within bounds, never increasing it.
+    fn descend(&self, bounds: &Bounds, sample: &Measurement) -> (NonZeroU32, State) {
+        match bounds {
+            Bounds::Under => {
+                trace!("Under bounds while descending.");
+                (self.goal_tps, State::Stable)
+            }
             Bounds::At => {
-                match self.state {
-                    State::BigStep | State::SmallStep(_) => {
-                        trace!("At bounds w/ BigStep|SmallStep.");
-                        // TODO: Remove unwraps
-                        (convert_to_nonzerou32(sample.tps).unwrap(), State::Stable)
-                    }
-                    s @ State::Stable => {
-                        trace!("At bounds w/ Stable.");
-                        (self.goal_tps, s)
-                    }
-                }
+                trace!("At bounds while descending.");
+                (clamp_tps(sample.tps).min(self.goal_tps), State::Stable)
             }
             Bounds::Over => {
-                match self.state {
-                    State::BigStep => {
-                        trace!("Over bounds w/ BigStep.");
-                        // TODO: Remove unwrap
-                        (
-                            NonZeroU32::new(self.goal_tps.get() / 2).unwrap(),
-                            State::SmallStep(DEFAULT_SMALL_STEP_SIZE),
-                        )
-                    }
-                    State::SmallStep(step_ratio) => {
-                        trace!("Over bounds w/ SmallStep({step_ratio}).");
-
-                        let rev_goal = (self.goal_tps.get() as f64 / (step_ratio + 1.)).max(1.);
-                        (
-                            NonZeroU32::new(rev_goal as u32).unwrap(),
-                            State::SmallStep(step_ratio / 2.),
-                        )
-                    }
-                    State::Stable => {
-                        trace!("Over bounds w/ Stable.");
-                        (self.goal_tps, State::SmallStep(DEFAULT_SMALL_STEP_SIZE))
-                    }
-                }
+                trace!("Over bounds while descending.");
+                let goal = self.goal_tps.get() as f64 / (DEFAULT_SMALL_STEP_SIZE + 1.);
+                (clamp_tps(goal), State::SmallStep(DEFAULT_SMALL_STEP_SIZE))
+            }
+        }
+    }
+}
+
+impl Controller for ErrorRateController {
+    fn initial_tps(&self) -> NonZeroU32 {
+        self.goal_tps
+    }
+
+    fn calibrate(&mut self, probe: &Measurement) {
+        if self.descending {
+            debug!("Descending from a fixed starting TPS; ignoring calibration.");
+            return;
+        }
+
+        match self.check_bounds(probe.effective_error_rate()) {
+            Bounds::Under | Bounds::At => {
+                // NOTE: The probe ran at a low concurrency, so the measured TPS is a lower-bound
+                // of what the service can handle. We continue the BigStep search from there.
+                self.goal_tps = clamp_tps(probe.tps).max(BASE_TPS).max(self.min_tps());
+                debug!("Calibrated starting TPS to {}", self.goal_tps);
+            }
+            Bounds::Over => {
+                debug!("Error rate exceeded during calibration; using default starting TPS.");
             }
+        }
+    }
+
+    fn limit(&mut self, sample: &Measurement, stable: bool) -> NonZeroU32 {
+        // TODO: Remove panic; this can be a type-safe check
+        let sample_error_rate = sample.effective_error_rate();
+
+        let bounds = self.check_bounds(sample_error_rate);
+        self.check_floor(&bounds);
+        let (new_goal_tps, new_state) = if self.descending {
+            self.descend(&bounds, sample)
+        } else {
+            self.search(&bounds, sample)
         };
+        let new_goal_tps = new_goal_tps.max(self.min_tps());
 
         if new_goal_tps < self.goal_tps || stable {
             self.goal_tps = new_goal_tps;
             self.state = new_state;
+            self.backing_off = matches!(bounds, Bounds::Over);
         } else {
             debug!("TPS not stabalized; holding off on increasing Goal TPS");
         }
 
         if cfg!(feature = "metrics") {
-            metrics::gauge!(format!("{}_erc_goal_tps", &self.base_label)).set(self.goal_tps.get());
-            metrics::gauge!(format!("{}_erc_state", &self.base_label)).set(match self.state {
+            metrics::gauge!(self.goal_tps_label.clone()).set(self.goal_tps.get());
+            metrics::gauge!(self.state_label.clone()).set(match self.state {
                 State::BigStep => 2,
                 State::SmallStep(_) => 1,
                 State::Stable => 0,
@@ -129,6 +220,22 @@ impl Controller for ErrorRateController {
 
         self.goal_tps
     }
+
+    fn current_goal(&self) -> NonZeroU32 {
+        self.goal_tps
+    }
+
+    fn error_target_unachievable(&self) -> bool {
+        self.over_at_floor >= UNACHIEVABLE_SAMPLES
+    }
+
+    fn state(&self) -> ControllerState {
+        match self.state {
+            State::Stable => ControllerState::Stable,
+            _ if self.backing_off => ControllerState::BackingOff,
+            State::BigStep | State::SmallStep(_) => ControllerState::Searching,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -144,7 +251,171 @@ enum Bounds {
     Over,
 }
 
-fn convert_to_nonzerou32(val: f64) -> Option<NonZeroU32> {
-    let val = val as u32;
-    NonZeroU32::new(val)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Synthetic service which errors on any transaction beyond its capacity.
+    fn measure(goal_tps: NonZeroU32, capacity: u64) -> Measurement {
+        let goal_tps = goal_tps.get() as u64;
+        let success = goal_tps.min(capacity);
+        let error = goal_tps.saturating_sub(capacity);
+        Measurement::new(success, error, Duration::from_secs(1))
+    }
+
+    fn windows_to_stable(controller: &mut ErrorRateController, capacity: u64) -> usize {
+        let mut goal_tps = controller.initial_tps();
+        for window in 1..100 {
+            goal_tps = controller.limit(&measure(goal_tps, capacity), true);
+            if matches!(controller.state, State::Stable) {
+                return window;
+            }
+        }
+        panic!("ErrorRateController never stabilized");
+    }
+
+    #[test]
+    fn test_fast_start_converges_faster() {
+        let capacity = 100_000;
+
+        let mut default = ErrorRateController::new("", 0.03);
+        let default_windows = windows_to_stable(&mut default, capacity);
+
+        let mut fast_start = ErrorRateController::new("", 0.03);
+        // Probe at low concurrency only reaches a fraction of the capacity.
+        fast_start.calibrate(&Measurement::new(40_000, 0, Duration::from_secs(1)));
+        let fast_start_windows = windows_to_stable(&mut fast_start, capacity);
+
+        assert!(
+            fast_start_windows < default_windows,
+            "fast_start took {fast_start_windows} windows, default took {default_windows}"
+        );
+    }
+
+    #[test]
+    fn test_state() {
+        let mut controller = ErrorRateController::new("", 0.03);
+        assert_eq!(controller.state(), ControllerState::Searching);
+
+        let capacity = 3_000;
+        let mut goal_tps = controller.initial_tps();
+        let mut states = vec![];
+        for _ in 0..100 {
+            goal_tps = controller.limit(&measure(goal_tps, capacity), true);
+            assert_eq!(controller.current_goal(), goal_tps);
+            states.push(controller.state());
+            if controller.state() == ControllerState::Stable {
+                break;
+            }
+        }
+
+        assert!(states.contains(&ControllerState::Searching));
+        assert!(states.contains(&ControllerState::BackingOff));
+        assert_eq!(states.last(), Some(&ControllerState::Stable));
+    }
+
+    #[test]
+    fn test_goal_tps_of_one() {
+        let one = NonZeroU32::MIN;
+        let overloaded = Measurement::new(0, 10, Duration::from_secs(1));
+
+        // Halving in BigStep
+        let mut controller = ErrorRateController::new("", 0.03);
+        controller.goal_tps = one;
+        assert_eq!(controller.limit(&overloaded, true), one);
+
+        // Backing off in SmallStep
+        assert!(matches!(controller.state, State::SmallStep(_)));
+        assert_eq!(controller.limit(&overloaded, true), one);
+
+        // At the goal error rate, but with less than 1 TPS
+        let mut controller = ErrorRateController::new("", 0.5);
+        let trickle = Measurement::new(1, 1, Duration::from_secs(4));
+        assert_eq!(controller.limit(&trickle, true), one);
+        assert!(matches!(controller.state, State::Stable));
+    }
+
+    #[test]
+    fn test_descend_never_exceeds_start() {
+        let start_tps = NonZeroU32::new(10_000).unwrap();
+        let capacity = 3_000;
+
+        let mut controller = ErrorRateController::descending("", 0.03, start_tps);
+        // A probe which could otherwise raise the starting TPS.
+        controller.calibrate(&Measurement::new(40_000, 0, Duration::from_secs(1)));
+        assert_eq!(controller.initial_tps(), start_tps);
+
+        let mut goal_tps = controller.initial_tps();
+        for _ in 0..100 {
+            goal_tps = controller.limit(&measure(goal_tps, capacity), true);
+            assert!(goal_tps <= start_tps);
+            if controller.state() == ControllerState::Stable {
+                break;
+            }
+        }
+        assert_eq!(controller.state(), ControllerState::Stable);
+        assert!(goal_tps.get() as u64 <= capacity);
+
+        // Never ramps back up once the error rate is met.
+        for _ in 0..10 {
+            assert_eq!(
+                controller.limit(&measure(goal_tps, capacity), true),
+                goal_tps
+            );
+        }
+    }
+
+    #[test]
+    fn test_descend_holds_sustainable_start() {
+        let start_tps = NonZeroU32::new(1_000).unwrap();
+        let mut controller = ErrorRateController::descending("", 0.03, start_tps);
+        assert_eq!(
+            controller.limit(&measure(start_tps, 3_000), true),
+            start_tps
+        );
+        assert_eq!(controller.state(), ControllerState::Stable);
+    }
+
+    #[test]
+    fn test_tps_floor() {
+        let floor = NonZeroU32::new(100).unwrap();
+        let always_erroring = |goal_tps: NonZeroU32| measure(goal_tps, 0);
+
+        let mut controller = ErrorRateController::new("", 0.03).with_floor(Some(floor));
+        let mut goal_tps = controller.initial_tps();
+        for _ in 0..20 {
+            assert!(!controller.error_target_unachievable());
+            goal_tps = controller.limit(&always_erroring(goal_tps), true);
+            assert!(goal_tps >= floor);
+            if controller.error_target_unachievable() {
+                break;
+            }
+        }
+        assert_eq!(goal_tps, floor);
+        assert!(controller.error_target_unachievable());
+
+        // NOTE: A single sample over the error rate at the floor may just be noise.
+        let mut controller = ErrorRateController::new("", 0.03).with_floor(Some(floor));
+        controller.goal_tps = floor;
+        controller.limit(&always_erroring(floor), true);
+        controller.limit(&measure(floor, 1_000), true);
+        assert!(!controller.error_target_unachievable());
+
+        // Without a floor, the goal TPS keeps decreasing past it.
+        let mut controller = ErrorRateController::new("", 0.03);
+        let mut goal_tps = controller.initial_tps();
+        for _ in 0..30 {
+            goal_tps = controller.limit(&always_erroring(goal_tps), true);
+        }
+        assert!(goal_tps < floor);
+        assert!(!controller.error_target_unachievable());
+    }
+
+    #[test]
+    fn test_fast_start_ignores_erroring_probe() {
+        let mut controller = ErrorRateController::new("", 0.03);
+        controller.calibrate(&Measurement::new(1_000, 1_000, Duration::from_secs(1)));
+        assert_eq!(controller.initial_tps(), BASE_TPS);
+    }
 }
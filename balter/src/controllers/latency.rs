@@ -1,36 +1,50 @@
-use crate::controllers::Controller;
+use crate::controllers::{clamp_tps, Controller, ControllerState};
 use crate::measurement::Measurement;
 use balter_core::BASE_TPS;
 use std::num::NonZeroU32;
+use std::sync::Arc;
 use std::time::Duration;
 #[allow(unused)]
 use tracing::{debug, error, trace};
 
 const KP: f64 = 0.9;
+/// Normalized latency error within which the controller is considered stable, unless configured
+/// with `tps_tolerance()`.
+const STABLE_TOLERANCE: f64 = 0.05;
 
 #[allow(unused)]
 pub(crate) struct LatencyController {
-    base_label: String,
+    goal_tps_label: Arc<str>,
     latency: Duration,
     quantile: f64,
     goal_tps: NonZeroU32,
+    tolerance: f64,
+    state: ControllerState,
 }
 
 impl LatencyController {
-    pub fn new(name: &str, latency: Duration, quantile: f64) -> Self {
+    pub fn new(base_label: &str, latency: Duration, quantile: f64) -> Self {
         let s = Self {
-            base_label: format!("balter_{name}"),
+            goal_tps_label: Arc::from(format!("{base_label}_lc_goal_tps")),
             latency,
             quantile,
             goal_tps: BASE_TPS,
+            tolerance: STABLE_TOLERANCE,
+            state: ControllerState::Searching,
         };
         s.goal_tps_metric();
         s
     }
 
+    /// Consider the controller stable within `tolerance` of the latency goal, if set.
+    pub fn with_tolerance(mut self, tolerance: Option<f64>) -> Self {
+        self.tolerance = tolerance.unwrap_or(STABLE_TOLERANCE);
+        self
+    }
+
     fn goal_tps_metric(&self) {
         if cfg!(feature = "metrics") {
-            metrics::gauge!(format!("{}_lc_goal_tps", &self.base_label)).set(self.goal_tps.get());
+            metrics::gauge!(self.goal_tps_label.clone()).set(self.goal_tps.get());
         }
     }
 }
@@ -49,20 +63,31 @@ impl Controller for LatencyController {
         let normalized_err = 1. - measured_latency.as_secs_f64() / self.latency.as_secs_f64();
         trace!("LATENCY: Error {normalized_err:?}");
 
-        let new_goal = self.goal_tps.get() as f64 * (1. + KP * normalized_err);
+        let new_goal = clamp_tps(self.goal_tps.get() as f64 * (1. + KP * normalized_err));
         trace!("LATENCY: New Goal {new_goal:?}");
 
-        if let Some(new_goal) = NonZeroU32::new(new_goal as u32) {
-            if new_goal < self.goal_tps || stable {
-                self.goal_tps = new_goal;
-                self.goal_tps_metric();
+        if new_goal < self.goal_tps || stable {
+            self.state = if normalized_err.abs() <= self.tolerance {
+                ControllerState::Stable
+            } else if new_goal < self.goal_tps {
+                ControllerState::BackingOff
             } else {
-                debug!("TPS not stabalized; holding off on increasing TPS");
-            }
+                ControllerState::Searching
+            };
+            self.goal_tps = new_goal;
+            self.goal_tps_metric();
         } else {
-            error!("Error in the LatencyController. Calculated a goal_tps of {new_goal} which is invalid.");
+            debug!("TPS not stabalized; holding off on increasing TPS");
         }
 
         self.goal_tps
     }
+
+    fn current_goal(&self) -> NonZeroU32 {
+        self.goal_tps
+    }
+
+    fn state(&self) -> ControllerState {
+        self.state
+    }
 }
@@ -0,0 +1,151 @@
+use crate::controllers::{clamp_tps, Controller, ControllerState};
+use crate::measurement::Measurement;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Instant;
+#[allow(unused)]
+use tracing::{debug, error, trace};
+
+/// Factor by which the goal TPS is raised per stable window once rejections stop.
+const RECOVERY_FACTOR: f64 = 1.25;
+
+/// Scales down TPS when the target rejects Transactions via rate limiting (see
+/// [`record_rate_limited()`](crate::record_rate_limited)).
+///
+/// Like the `SloController`, this is unconstrained until rate limiting is observed. On rejection,
+/// the goal is set to the TPS which was accepted by the target (the attempted TPS reduced by the
+/// rejection rate), and held for at least the longest `Retry-After` reported. Once rejections
+/// stop, the goal is raised gradually so as to not immediately trip the rate limit again.
+pub(crate) struct RateLimitController {
+    goal_tps_label: Arc<str>,
+    goal_tps: Option<NonZeroU32>,
+    hold_until: Option<Instant>,
+    state: ControllerState,
+}
+
+impl RateLimitController {
+    pub fn new(base_label: &str) -> Self {
+        Self {
+            goal_tps_label: Arc::from(format!("{base_label}_rlc_goal_tps")),
+            goal_tps: None,
+            hold_until: None,
+            state: ControllerState::Stable,
+        }
+    }
+
+    fn set_goal(&mut self, goal_tps: f64) {
+        let goal_tps = clamp_tps(goal_tps.round());
+        self.goal_tps = Some(goal_tps);
+        if cfg!(feature = "metrics") {
+            metrics::gauge!(self.goal_tps_label.clone()).set(goal_tps.get());
+        }
+    }
+}
+
+impl Controller for RateLimitController {
+    fn initial_tps(&self) -> NonZeroU32 {
+        NonZeroU32::MAX
+    }
+
+    fn limit(&mut self, sample: &Measurement, stable: bool) -> NonZeroU32 {
+        let rejected = sample.rate_limited_ratio();
+
+        if rejected > 0. {
+            let attempted = (sample.success_count() + sample.error_count()) as f64
+                / sample.elapsed.as_secs_f64();
+            let new_goal = (attempted * (1. - rejected))
+                .min(self.current_goal().get() as f64)
+                .max(1.);
+            trace!("RLC: {rejected:.2} rejected of {attempted:.2} TPS, new goal {new_goal:.2}");
+            self.set_goal(new_goal);
+
+            if let Some(retry_after) = sample.retry_after() {
+                let until = Instant::now() + retry_after;
+                self.hold_until = Some(self.hold_until.map_or(until, |hold| hold.max(until)));
+            }
+            self.state = ControllerState::BackingOff;
+        } else if let Some(goal) = self.goal_tps {
+            if self.hold_until.is_some_and(|hold| Instant::now() < hold) {
+                debug!("Holding TPS for Retry-After");
+                self.state = ControllerState::Stable;
+            } else if stable {
+                self.hold_until = None;
+                self.set_goal(goal.get() as f64 * RECOVERY_FACTOR);
+                self.state = ControllerState::Searching;
+            }
+        }
+
+        self.current_goal()
+    }
+
+    fn current_goal(&self) -> NonZeroU32 {
+        self.goal_tps.unwrap_or(NonZeroU32::MAX)
+    }
+
+    fn state(&self) -> ControllerState {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::RateLimits;
+    use std::time::Duration;
+
+    fn sample(
+        success: u64,
+        error: u64,
+        rejected: u64,
+        retry_after: Option<Duration>,
+    ) -> Measurement {
+        let rate_limits = RateLimits::default();
+        for _ in 0..rejected {
+            rate_limits.record(retry_after);
+        }
+
+        let mut sample = Measurement::new(success, error, Duration::from_secs(1));
+        rate_limits.collect_into(&mut sample);
+        sample
+    }
+
+    #[test]
+    fn test_unconstrained_without_rejections() {
+        let mut controller = RateLimitController::new("test_unconstrained_without_rejections");
+
+        let goal = controller.limit(&sample(1_000, 10, 0, None), true);
+        assert_eq!(goal, NonZeroU32::MAX);
+        assert_eq!(controller.state(), ControllerState::Stable);
+    }
+
+    #[test]
+    fn test_backs_off_proportionally() {
+        let mut controller = RateLimitController::new("test_backs_off_proportionally");
+
+        // Half of the 1,000 attempted Transactions were rejected.
+        let goal = controller.limit(&sample(500, 500, 500, None), true);
+        assert_eq!(goal.get(), 500);
+        assert_eq!(controller.state(), ControllerState::BackingOff);
+
+        // Once rejections stop, TPS recovers (only when stable).
+        let recovered = sample(500, 0, 0, None);
+        assert_eq!(controller.limit(&recovered, false).get(), 500);
+        assert_eq!(controller.limit(&recovered, true).get(), 625);
+        assert_eq!(controller.state(), ControllerState::Searching);
+    }
+
+    #[test]
+    fn test_holds_for_retry_after() {
+        let mut controller = RateLimitController::new("test_holds_for_retry_after");
+
+        let goal = controller.limit(
+            &sample(900, 100, 100, Some(Duration::from_secs(3600))),
+            true,
+        );
+        assert_eq!(goal.get(), 900);
+
+        // NOTE: Even when stable, the goal is held until the Retry-After has passed.
+        assert_eq!(controller.limit(&sample(900, 0, 0, None), true).get(), 900);
+        assert_eq!(controller.state(), ControllerState::Stable);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::controllers::{clamp_tps, Controller, ControllerState};
+use crate::measurement::Measurement;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+#[allow(unused)]
+use tracing::{debug, error, trace};
+
+const KP: f64 = 0.9;
+/// Normalized latency error within which an SLO is considered met, unless configured with
+/// `tps_tolerance()`.
+const STABLE_TOLERANCE: f64 = 0.05;
+
+/// Scales down TPS if any Transaction violates its `#[transaction(latency_slo = ...)]`.
+///
+/// Unlike the other controllers, this doesn't search for a goal TPS of its own. It is
+/// unconstrained until an SLO is violated, and afterwards it tracks the measured TPS scaled by
+/// the worst (normalized) SLO error, so it only binds while an SLO is at risk.
+pub(crate) struct SloController {
+    goal_tps_label: Arc<str>,
+    goal_tps: Option<NonZeroU32>,
+    tolerance: f64,
+    state: ControllerState,
+}
+
+impl SloController {
+    pub fn new(base_label: &str) -> Self {
+        Self {
+            goal_tps_label: Arc::from(format!("{base_label}_slo_goal_tps")),
+            goal_tps: None,
+            tolerance: STABLE_TOLERANCE,
+            state: ControllerState::Stable,
+        }
+    }
+
+    /// Consider an SLO met within `tolerance` of its latency, if set.
+    pub fn with_tolerance(mut self, tolerance: Option<f64>) -> Self {
+        self.tolerance = tolerance.unwrap_or(STABLE_TOLERANCE);
+        self
+    }
+
+    fn goal_tps_metric(&self) {
+        if cfg!(feature = "metrics") {
+            if let Some(goal_tps) = self.goal_tps {
+                metrics::gauge!(self.goal_tps_label.clone()).set(goal_tps.get());
+            }
+        }
+    }
+}
+
+impl Controller for SloController {
+    fn initial_tps(&self) -> NonZeroU32 {
+        NonZeroU32::MAX
+    }
+
+    fn limit(&mut self, sample: &Measurement, stable: bool) -> NonZeroU32 {
+        let worst_err = sample
+            .slo_latencies()
+            .map(|(slo, measured)| {
+                let err = 1. - measured.as_secs_f64() / slo.latency.as_secs_f64();
+                trace!(
+                    "SLO: {} measured {measured:?}, expected {:?} (error {err:.2})",
+                    slo.name,
+                    slo.latency
+                );
+                err
+            })
+            .min_by(f64::total_cmp);
+
+        let Some(worst_err) = worst_err else {
+            return self.current_goal();
+        };
+
+        if self.goal_tps.is_none() && worst_err >= -self.tolerance {
+            return self.current_goal();
+        }
+
+        let new_goal = sample.tps * (1. + KP * worst_err);
+        trace!("SLO: New Goal {new_goal:?}");
+
+        let new_goal = clamp_tps(new_goal.round());
+        if self.goal_tps.is_none_or(|goal| new_goal < goal) || stable {
+            self.state = if worst_err < -self.tolerance {
+                ControllerState::BackingOff
+            } else {
+                ControllerState::Stable
+            };
+            self.goal_tps = Some(new_goal);
+            self.goal_tps_metric();
+        } else {
+            debug!("TPS not stabalized; holding off on increasing TPS");
+        }
+
+        self.current_goal()
+    }
+
+    fn current_goal(&self) -> NonZeroU32 {
+        self.goal_tps.unwrap_or(NonZeroU32::MAX)
+    }
+
+    fn state(&self) -> ControllerState {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::SloLatencies;
+    use balter_core::LatencySlo;
+    use std::time::Duration;
+
+    const SEARCH: LatencySlo = LatencySlo {
+        name: "search",
+        latency: Duration::from_millis(200),
+        quantile: 0.99,
+    };
+
+    const HEALTH: LatencySlo = LatencySlo {
+        name: "health",
+        latency: Duration::from_millis(10),
+        quantile: 0.99,
+    };
+
+    fn sample(tps: u64, search: Duration, health: Duration) -> Measurement {
+        let latencies = SloLatencies::default();
+        for _ in 0..100 {
+            latencies.record(SEARCH, search);
+            latencies.record(HEALTH, health);
+        }
+
+        let mut sample = Measurement::new(tps, 0, Duration::from_secs(1));
+        latencies.collect_into(&mut sample);
+        sample
+    }
+
+    #[test]
+    fn test_unconstrained_within_slos() {
+        let mut controller = SloController::new("test_unconstrained_within_slos");
+
+        // NOTE: The search is slower than the health check, but within its own SLO.
+        let goal = controller.limit(
+            &sample(1_000, Duration::from_millis(150), Duration::from_millis(5)),
+            true,
+        );
+        assert_eq!(goal, NonZeroU32::MAX);
+        assert_eq!(controller.state(), ControllerState::Stable);
+    }
+
+    #[test]
+    fn test_backs_off_on_any_violation() {
+        let mut controller = SloController::new("test_backs_off_on_any_violation");
+
+        // Search is within its SLO, but the health check is at double its SLO.
+        let goal = controller.limit(
+            &sample(1_000, Duration::from_millis(150), Duration::from_millis(20)),
+            true,
+        );
+        assert_eq!(goal.get(), 100);
+        assert_eq!(controller.state(), ControllerState::BackingOff);
+
+        // Once the SLOs are met again, TPS recovers (only when stable).
+        let recovered = sample(100, Duration::from_millis(100), Duration::from_millis(5));
+        assert_eq!(controller.limit(&recovered, false).get(), 100);
+        assert!(controller.limit(&recovered, true).get() > 100);
+        assert_eq!(controller.state(), ControllerState::Stable);
+    }
+
+    #[test]
+    fn test_tolerance() {
+        // NOTE: The health check is 10% over its SLO, which is only met with a looser tolerance.
+        let slow = sample(1_000, Duration::from_millis(100), Duration::from_millis(11));
+
+        let mut controller = SloController::new("test_tolerance");
+        assert!(controller.limit(&slow, true) < NonZeroU32::MAX);
+        assert_eq!(controller.state(), ControllerState::BackingOff);
+
+        let mut controller = SloController::new("test_tolerance").with_tolerance(Some(0.2));
+        assert_eq!(controller.limit(&slow, true), NonZeroU32::MAX);
+        assert_eq!(controller.state(), ControllerState::Stable);
+    }
+}
@@ -0,0 +1,227 @@
+//! Settings shared by many Scenarios, see [`ScenarioDefaults`].
+use balter_core::{LatencyConfig, ScenarioConfig};
+use std::num::NonZeroU32;
+use std::sync::RwLock;
+use std::time::Duration;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+static GLOBAL_DEFAULTS: RwLock<Option<ScenarioDefaults>> = RwLock::new(None);
+
+/// Settings shared by many Scenarios, to avoid repeating them on each.
+///
+/// Defaults only fill in settings a Scenario has not set itself, and apply either to every
+/// Scenario via [`set_defaults()`], or to a single Scenario via
+/// [`apply_template()`](crate::scenario::ConfigurableScenario::apply_template). The precedence,
+/// from highest to lowest, is:
+///
+/// 1. Settings made with the Scenario's own methods (e.g. `.duration()`)
+/// 2. The Scenario's template
+/// 3. The global defaults
+///
+/// Peer tags are inherited as a whole, only if the Scenario (or template) requires none.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use balter::ScenarioDefaults;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     balter::set_defaults(
+///         ScenarioDefaults::new()
+///             .error_rate(0.03)
+///             .duration(Duration::from_secs(120)),
+///     );
+///
+///     // Runs for 120s, scaling up to a 1% error rate.
+///     checkout().error_rate(0.01).await;
+/// }
+///
+/// #[scenario]
+/// async fn checkout() {
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScenarioDefaults {
+    max_tps: Option<NonZeroU32>,
+    error_rate: Option<f64>,
+    latency: Option<LatencyConfig>,
+    duration: Option<Duration>,
+    base_url: Option<String>,
+    max_duration: Option<Duration>,
+    cooldown: Option<Duration>,
+    metrics_prefix: Option<String>,
+    sanitize_metric_names: bool,
+    peer_tags: Vec<String>,
+}
+
+impl ScenarioDefaults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Default TPS goal, see [`tps()`](crate::scenario::ConfigurableScenario::tps).
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the provided TPS is zero
+    pub fn tps(mut self, tps: u32) -> Self {
+        self.max_tps =
+            Some(NonZeroU32::new(tps).expect("TPS provided must be non-zero. Given: {tps}"));
+        self
+    }
+
+    /// Default error rate goal, see
+    /// [`error_rate()`](crate::scenario::ConfigurableScenario::error_rate).
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the error_rate is not between 0 and 1.
+    pub fn error_rate(mut self, error_rate: f64) -> Self {
+        if !(0. ..=1.).contains(&error_rate) {
+            panic!(
+                "Specified error rate must be between 0 and 1. Value provided was {error_rate}."
+            );
+        }
+        self.error_rate = Some(error_rate);
+        self
+    }
+
+    /// Default latency goal, see [`latency()`](crate::scenario::ConfigurableScenario::latency).
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the quantile is not between 0 and 1.
+    pub fn latency(mut self, latency: Duration, quantile: f64) -> Self {
+        if !(0. ..=1.).contains(&quantile) {
+            panic!("Specified quantile must be between 0 and 1. Value provided was {quantile}.");
+        }
+        self.latency = Some(LatencyConfig::new(latency, quantile));
+        self
+    }
+
+    /// Default duration, see [`duration()`](crate::scenario::ConfigurableScenario::duration).
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Default base URL, see
+    /// [`with_base_url()`](crate::scenario::ConfigurableScenario::with_base_url).
+    pub fn with_base_url(mut self, url: &str) -> Self {
+        self.base_url = Some(url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Default safety cap, see
+    /// [`max_duration()`](crate::scenario::ConfigurableScenario::max_duration).
+    pub fn max_duration(mut self, cap: Duration) -> Self {
+        self.max_duration = Some(cap);
+        self
+    }
+
+    /// Default ramp down, see [`cooldown()`](crate::scenario::ConfigurableScenario::cooldown).
+    pub fn cooldown(mut self, ramp_down: Duration) -> Self {
+        self.cooldown = Some(ramp_down);
+        self
+    }
+
+    /// Default metrics prefix, see
+    /// [`metrics_prefix()`](crate::scenario::ConfigurableScenario::metrics_prefix).
+    pub fn metrics_prefix(mut self, prefix: &str) -> Self {
+        self.metrics_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Replace the characters of Scenario metric names which statsd (or DogStatsD) would
+    /// misinterpret, such as the `:`, `|`, `@` and `#` of its wire format, commas and whitespace,
+    /// with `_` (see [`sanitize_metric_name()`](balter_core::sanitize_metric_name)).
+    ///
+    /// Off by default, since it renames the metrics of Scenarios (or prefixes) with any such
+    /// characters.
+    pub fn sanitize_metric_names(mut self) -> Self {
+        self.sanitize_metric_names = true;
+        self
+    }
+
+    /// Default peer tag, see
+    /// [`require_peer_tag()`](crate::scenario::ConfigurableScenario::require_peer_tag).
+    pub fn require_peer_tag(mut self, tag: &str) -> Self {
+        self.peer_tags.push(tag.to_string());
+        self
+    }
+
+    /// Fill in the settings which `config` has not set.
+    pub(crate) fn merge_into(&self, config: &mut ScenarioConfig) {
+        config.max_tps = config.max_tps.or(self.max_tps);
+        config.error_rate = config.error_rate.or(self.error_rate);
+        config.latency = config.latency.or(self.latency);
+        config.duration = config.duration.or(self.duration);
+        config.max_duration = config.max_duration.or(self.max_duration);
+        config.cooldown = config.cooldown.or(self.cooldown);
+        if config.base_url.is_none() {
+            config.base_url.clone_from(&self.base_url);
+        }
+        if config.metrics_prefix.is_none() {
+            config.metrics_prefix.clone_from(&self.metrics_prefix);
+        }
+        config.sanitize_metric_names |= self.sanitize_metric_names;
+        if config.peer_tags.is_empty() {
+            config.peer_tags.clone_from(&self.peer_tags);
+        }
+    }
+}
+
+/// Set the defaults inherited by every Scenario run from now on, see [`ScenarioDefaults`].
+///
+/// Replaces any previously set defaults. Scenarios which are already running are unaffected.
+///
+/// NOTE: A Scenario with no goal of its own inherits the default goal, and so is load tested
+/// rather than run once as a plain function.
+pub fn set_defaults(defaults: ScenarioDefaults) {
+    match GLOBAL_DEFAULTS.write() {
+        Ok(mut global) => *global = Some(defaults),
+        Err(_) => error!("Global Scenario defaults are poisoned."),
+    }
+}
+
+/// Fill in the settings which `config` has not set from the global defaults.
+pub(crate) fn apply_global_defaults(config: &mut ScenarioConfig) {
+    let Ok(global) = GLOBAL_DEFAULTS.read() else {
+        error!("Global Scenario defaults are poisoned.");
+        return;
+    };
+
+    if let Some(defaults) = global.as_ref() {
+        defaults.merge_into(config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_into() {
+        let defaults = ScenarioDefaults::new()
+            .error_rate(0.03)
+            .duration(Duration::from_secs(120))
+            .with_base_url("http://localhost:3002/")
+            .sanitize_metric_names()
+            .require_peer_tag("gpu");
+
+        let mut config = ScenarioConfig::new("test_merge_into");
+        config.duration = Some(Duration::from_secs(30));
+        config.peer_tags = vec!["cpu".to_string()];
+        defaults.merge_into(&mut config);
+
+        assert_eq!(config.error_rate, Some(0.03));
+        assert_eq!(config.duration, Some(Duration::from_secs(30)));
+        assert_eq!(config.base_url.as_deref(), Some("http://localhost:3002"));
+        assert_eq!(config.peer_tags, vec!["cpu".to_string()]);
+        assert!(config.sanitize_metric_names);
+        assert_eq!(config.max_tps, None);
+    }
+}
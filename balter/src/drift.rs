@@ -0,0 +1,159 @@
+//! Detecting Scenarios which accumulate state on the target.
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+/// Number of consecutive samples over which the error rate must rise.
+const WINDOW: usize = 5;
+/// Minimum total rise in error rate over the window.
+const MIN_RISE: f64 = 0.02;
+
+/// Detects an error rate which climbs steadily at a constant TPS, see
+/// [`detect_state_drift()`](crate::scenario::ConfigurableScenario::detect_state_drift).
+///
+/// More load legitimately causes more errors, so the history is reset whenever the TPS limit is
+/// raised. An error rate which keeps rising without any increase in load suggests the target is
+/// accumulating state (e.g. records which are created but never cleaned up).
+pub(crate) struct StateDriftDetector {
+    history: Vec<(Duration, f64)>,
+    tps_limit: Option<NonZeroU32>,
+    warned: bool,
+}
+
+impl StateDriftDetector {
+    pub fn new() -> Self {
+        Self {
+            history: vec![],
+            tps_limit: None,
+            warned: false,
+        }
+    }
+
+    /// Record a sample, returning the slope of the error rate (per second) the first time a
+    /// sustained upward trend is detected.
+    pub fn observe(
+        &mut self,
+        elapsed: Duration,
+        error_rate: f64,
+        tps_limit: NonZeroU32,
+    ) -> Option<f64> {
+        if self.tps_limit.is_some_and(|prev| tps_limit > prev) {
+            self.history.clear();
+        }
+        self.tps_limit = Some(tps_limit);
+
+        self.history.push((elapsed, error_rate));
+        if self.history.len() > WINDOW {
+            self.history.remove(0);
+        }
+
+        if self.warned || self.history.len() < WINDOW {
+            return None;
+        }
+
+        let monotonic = self.history.windows(2).all(|pair| pair[1].1 > pair[0].1);
+        let rise = self.history[WINDOW - 1].1 - self.history[0].1;
+        if !monotonic || rise < MIN_RISE {
+            return None;
+        }
+
+        self.warned = true;
+        Some(slope(&self.history))
+    }
+}
+
+/// Least-squares slope of the error rate over time.
+fn slope(history: &[(Duration, f64)]) -> f64 {
+    let n = history.len() as f64;
+    let mean_t = history.iter().map(|(t, _)| t.as_secs_f64()).sum::<f64>() / n;
+    let mean_e = history.iter().map(|(_, e)| e).sum::<f64>() / n;
+
+    let (num, den) = history.iter().fold((0., 0.), |(num, den), (t, e)| {
+        let dt = t.as_secs_f64() - mean_t;
+        (num + dt * (e - mean_e), den + dt * dt)
+    });
+
+    if den == 0. {
+        0.
+    } else {
+        num / den
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe_all(detector: &mut StateDriftDetector, samples: &[(f64, u32)]) -> Vec<Option<f64>> {
+        samples
+            .iter()
+            .enumerate()
+            .map(|(idx, (error_rate, tps))| {
+                detector.observe(
+                    Duration::from_secs(idx as u64 * 10),
+                    *error_rate,
+                    NonZeroU32::new(*tps).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detects_rising_error_rate() {
+        let mut detector = StateDriftDetector::new();
+        let res = observe_all(
+            &mut detector,
+            &[
+                (0.00, 500),
+                (0.01, 500),
+                (0.02, 500),
+                (0.03, 500),
+                (0.04, 500),
+                (0.05, 500),
+            ],
+        );
+
+        assert!(res[..4].iter().all(Option::is_none));
+        // NOTE: 1% every 10s.
+        assert!((res[4].unwrap() - 0.001).abs() < 1e-9);
+        // Only warn once.
+        assert_eq!(res[5], None);
+    }
+
+    #[test]
+    fn test_ignores_rising_load() {
+        let mut detector = StateDriftDetector::new();
+        let res = observe_all(
+            &mut detector,
+            &[
+                (0.00, 100),
+                (0.01, 200),
+                (0.02, 400),
+                (0.03, 800),
+                (0.04, 1_600),
+                (0.05, 3_200),
+            ],
+        );
+        assert!(res.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_ignores_noise() {
+        let mut detector = StateDriftDetector::new();
+        let res = observe_all(
+            &mut detector,
+            &[
+                (0.010, 500),
+                (0.011, 500),
+                (0.012, 500),
+                (0.013, 500),
+                (0.014, 500),
+                (0.03, 500),
+                (0.02, 500),
+                (0.04, 500),
+                (0.05, 500),
+                (0.06, 500),
+            ],
+        );
+        assert!(res.iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,399 @@
+//! Running Scenarios as a graph of dependencies.
+use balter_core::RunStatistics;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::{Future, IntoFuture};
+use std::pin::Pin;
+use thiserror::Error;
+use tokio::task::JoinSet;
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+type GraphScenario = Pin<Box<dyn Future<Output = RunStatistics> + Send>>;
+
+#[doc(hidden)]
+pub type GraphFuture = Pin<Box<dyn Future<Output = HashMap<String, RunStatistics>> + Send>>;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum GraphError {
+    #[error("Scenario `{0}` was added to the graph more than once")]
+    DuplicateScenario(String),
+
+    #[error("Scenario `{scenario}` depends on `{dependency}`, which isn't in the graph")]
+    UnknownDependency {
+        scenario: String,
+        dependency: String,
+    },
+
+    /// The names of the Scenarios in the cycle, starting and ending with the same Scenario.
+    #[error("Scenarios form a dependency cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+}
+
+struct Node {
+    dependencies: BTreeSet<String>,
+    scenario: GraphScenario,
+}
+
+/// Scenarios run as a graph of dependencies, e.g. setup, then load alongside a background
+/// Scenario, then verification.
+///
+/// Each Scenario is added with a unique name and the names of the Scenarios it depends on, and
+/// is started once all of them have completed. Scenarios which don't depend on each other run
+/// concurrently. [`build()`](Self::build) checks the graph before anything is run, rejecting
+/// cycles and dependencies on Scenarios which were never added.
+///
+/// Awaiting the built [`ScenarioPlan`] returns the statistics of each Scenario by name.
+///
+/// NOTE: A Scenario is skipped if any of its dependencies failed (see
+/// [`RunStatistics::failed`]) or were skipped themselves, and has no entry in the returned map.
+/// If a Scenario panics, the remaining Scenarios are cancelled and the panic is propagated. Each
+/// Scenario is spawned as its own Tokio task, so the plan must be awaited from within a Tokio
+/// runtime.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use balter::ScenarioGraph;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let short = Duration::from_secs(10);
+///     let long = Duration::from_secs(300);
+///     let results = ScenarioGraph::new()
+///         .scenario("setup", &[], setup().tps(10).duration(short))
+///         .scenario("load", &["setup"], load().tps(1_000).duration(long))
+///         .scenario("reads", &["setup"], reads().tps(500).duration(long))
+///         .scenario("verify", &["load", "reads"], verify().tps(10).duration(short))
+///         .build()
+///         .unwrap()
+///         .await;
+///
+///     for (name, stats) in results {
+///         println!("{name}: {stats}");
+///     }
+/// }
+///
+/// #[scenario]
+/// async fn setup() {
+/// }
+///
+/// #[scenario]
+/// async fn load() {
+/// }
+///
+/// #[scenario]
+/// async fn reads() {
+/// }
+///
+/// #[scenario]
+/// async fn verify() {
+/// }
+/// ```
+#[derive(Default)]
+pub struct ScenarioGraph {
+    nodes: Vec<(String, Vec<String>, GraphScenario)>,
+}
+
+impl ScenarioGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a Scenario, which is started once all of the Scenarios named in `dependencies` have
+    /// completed.
+    pub fn scenario<S>(mut self, name: &str, dependencies: &[&str], scenario: S) -> Self
+    where
+        S: Future<Output = RunStatistics> + Send + 'static,
+    {
+        self.nodes.push((
+            name.to_string(),
+            dependencies.iter().map(|dep| dep.to_string()).collect(),
+            Box::pin(scenario),
+        ));
+        self
+    }
+
+    /// Check the graph, returning a [`ScenarioPlan`] to run it.
+    pub fn build(self) -> Result<ScenarioPlan, GraphError> {
+        let mut nodes = BTreeMap::new();
+        for (name, dependencies, scenario) in self.nodes {
+            if nodes.contains_key(&name) {
+                return Err(GraphError::DuplicateScenario(name));
+            }
+            let node = Node {
+                dependencies: dependencies.into_iter().collect(),
+                scenario,
+            };
+            nodes.insert(name, node);
+        }
+
+        for (name, node) in &nodes {
+            if let Some(dep) = node
+                .dependencies
+                .iter()
+                .find(|dep| !nodes.contains_key(*dep))
+            {
+                return Err(GraphError::UnknownDependency {
+                    scenario: name.clone(),
+                    dependency: dep.clone(),
+                });
+            }
+        }
+
+        let dependencies = nodes
+            .iter()
+            .map(|(name, node)| (name.as_str(), &node.dependencies))
+            .collect();
+        if let Some(cycle) = find_cycle(dependencies) {
+            return Err(GraphError::Cycle(cycle));
+        }
+
+        Ok(ScenarioPlan { nodes })
+    }
+}
+
+/// A [`ScenarioGraph`] without cycles, ready to run.
+pub struct ScenarioPlan {
+    nodes: BTreeMap<String, Node>,
+}
+
+impl IntoFuture for ScenarioPlan {
+    type Output = HashMap<String, RunStatistics>;
+    type IntoFuture = GraphFuture;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(run_plan(self.nodes))
+    }
+}
+
+async fn run_plan(mut waiting: BTreeMap<String, Node>) -> HashMap<String, RunStatistics> {
+    crate::scenario::require_tokio_runtime();
+
+    let mut results = HashMap::new();
+    // NOTE: Whether each completed (or skipped) Scenario succeeded.
+    let mut completed: HashMap<String, bool> = HashMap::new();
+    let mut set = JoinSet::new();
+    loop {
+        while let Some(name) = next_ready(&waiting, &completed) {
+            let Some(node) = waiting.remove(&name) else {
+                continue;
+            };
+
+            if let Some(dep) = node.dependencies.iter().find(|dep| !completed[*dep]) {
+                warn!("Skipping Scenario {name}, as its dependency {dep} did not succeed.");
+                completed.insert(name, false);
+            } else {
+                debug!("Starting Scenario {name}.");
+                set.spawn(async move { (name, node.scenario.await) });
+            }
+        }
+
+        let Some(res) = set.join_next().await else {
+            break;
+        };
+
+        match res {
+            Ok((name, stats)) => {
+                completed.insert(name.clone(), !stats.failed);
+                results.insert(name, stats);
+            }
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(err) => error!("Scenario cancelled: {err}"),
+        }
+    }
+
+    results
+}
+
+/// A waiting Scenario whose dependencies have all completed.
+fn next_ready(
+    waiting: &BTreeMap<String, Node>,
+    completed: &HashMap<String, bool>,
+) -> Option<String> {
+    waiting
+        .iter()
+        .find(|(_, node)| {
+            node.dependencies
+                .iter()
+                .all(|dep| completed.contains_key(dep))
+        })
+        .map(|(name, _)| name.clone())
+}
+
+/// A cycle among the dependencies of each Scenario, if there is one.
+fn find_cycle(mut remaining: BTreeMap<&str, &BTreeSet<String>>) -> Option<Vec<String>> {
+    // NOTE: Repeatedly remove the Scenarios which could run once the removed ones have completed.
+    loop {
+        let ready: Vec<&str> = remaining
+            .iter()
+            .filter(|(_, deps)| deps.iter().all(|dep| !remaining.contains_key(dep.as_str())))
+            .map(|(name, _)| *name)
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+        for name in ready {
+            remaining.remove(name);
+        }
+    }
+
+    // Each remaining Scenario depends on another remaining Scenario, so following those
+    // dependencies eventually leads back to a Scenario already visited.
+    let mut path = vec![*remaining.keys().next()?];
+    loop {
+        let last = path.last()?;
+        let next = remaining[last]
+            .iter()
+            .map(String::as_str)
+            .find(|dep| remaining.contains_key(dep))?;
+
+        if let Some(start) = path.iter().position(|name| *name == next) {
+            let mut cycle: Vec<String> =
+                path[start..].iter().map(|name| name.to_string()).collect();
+            cycle.push(next.to_string());
+            return Some(cycle);
+        }
+        path.push(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::{ConfigurableScenario, Scenario};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    type Timeline = Arc<Mutex<HashMap<&'static str, (Instant, Instant)>>>;
+
+    fn timed(
+        name: &'static str,
+        timeline: &Timeline,
+    ) -> impl Future<Output = RunStatistics> + Send + 'static {
+        let timeline = timeline.clone();
+        let scenario = Scenario::new(name, || async {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        })
+        .tps(200)
+        .duration(Duration::from_millis(500));
+
+        async move {
+            let start = Instant::now();
+            let stats = scenario.await;
+            timeline
+                .lock()
+                .unwrap()
+                .insert(name, (start, Instant::now()));
+            stats
+        }
+    }
+
+    #[tokio::test]
+    async fn test_diamond_graph() {
+        let timeline = Timeline::default();
+        let results = ScenarioGraph::new()
+            .scenario("verify", &["load", "reads"], timed("verify", &timeline))
+            .scenario("load", &["setup"], timed("load", &timeline))
+            .scenario("reads", &["setup"], timed("reads", &timeline))
+            .scenario("setup", &[], timed("setup", &timeline))
+            .build()
+            .unwrap()
+            .await;
+
+        let mut names: Vec<_> = results.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["load", "reads", "setup", "verify"]);
+        assert!(results.values().all(|stats| !stats.failed));
+
+        let timeline = timeline.lock().unwrap();
+        let (_, setup_end) = timeline["setup"];
+        let (load_start, load_end) = timeline["load"];
+        let (reads_start, reads_end) = timeline["reads"];
+        let (verify_start, _) = timeline["verify"];
+
+        assert!(load_start >= setup_end);
+        assert!(reads_start >= setup_end);
+        // NOTE: Independent branches overlap.
+        assert!(load_start < reads_end && reads_start < load_end);
+        assert!(verify_start >= load_end.max(reads_end));
+    }
+
+    #[tokio::test]
+    async fn test_skip_after_failure() {
+        let failed = RunStatistics {
+            failed: true,
+            ..Default::default()
+        };
+        let results = ScenarioGraph::new()
+            .scenario("setup", &[], std::future::ready(failed))
+            .scenario("other", &[], std::future::ready(RunStatistics::default()))
+            .scenario(
+                "load",
+                &["setup", "other"],
+                std::future::ready(RunStatistics::default()),
+            )
+            .scenario(
+                "verify",
+                &["load"],
+                std::future::ready(RunStatistics::default()),
+            )
+            .build()
+            .unwrap()
+            .await;
+
+        assert!(results["setup"].failed);
+        assert!(!results["other"].failed);
+        assert!(!results.contains_key("load"));
+        assert!(!results.contains_key("verify"));
+    }
+
+    #[test]
+    fn test_invalid_graph() {
+        fn graph(edges: &[(&str, &[&str])]) -> Result<ScenarioPlan, GraphError> {
+            edges
+                .iter()
+                .fold(ScenarioGraph::new(), |graph, (name, deps)| {
+                    graph.scenario(name, deps, std::future::ready(RunStatistics::default()))
+                })
+                .build()
+        }
+
+        assert!(graph(&[("a", &[]), ("b", &["a"]), ("c", &["a", "b"])]).is_ok());
+
+        assert_eq!(
+            graph(&[("a", &[]), ("a", &[])]).err(),
+            Some(GraphError::DuplicateScenario("a".to_string()))
+        );
+        assert_eq!(
+            graph(&[("a", &["b"])]).err(),
+            Some(GraphError::UnknownDependency {
+                scenario: "a".to_string(),
+                dependency: "b".to_string(),
+            })
+        );
+
+        let err = graph(&[("a", &["a"])]).err().unwrap();
+        assert_eq!(err.to_string(), "Scenarios form a dependency cycle: a -> a");
+
+        // NOTE: Only the Scenarios in the cycle are reported, not those depending on it.
+        let err = graph(&[
+            ("setup", &[]),
+            ("a", &["setup", "c"]),
+            ("b", &["a"]),
+            ("c", &["b"]),
+            ("verify", &["a"]),
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(
+            err,
+            GraphError::Cycle(vec![
+                "a".to_string(),
+                "c".to_string(),
+                "b".to_string(),
+                "a".to_string()
+            ])
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 /// User provided hints for setting autoscaling parameters.
 ///
 /// Balter attempts to find the optimal values for all parameters, however sometimes the control
@@ -6,4 +8,9 @@ pub enum Hint {
     /// Provide the starting concurrency value. Useful for Scenarios with low TPS (which Balter can
     /// take a long time to stablize on).
     Concurrency(usize),
+    /// Provide the expected latency of the Scenario. Balter estimates the concurrency needed to
+    /// reach the starting TPS from it (see [crate::estimate_concurrency]), which is useful for
+    /// Scenarios with high TPS and high latency (which Balter would otherwise take many steps to
+    /// scale up to).
+    ExpectedLatency(Duration),
 }
@@ -0,0 +1,152 @@
+//! User-provided hooks which customize how a Scenario is measured.
+//!
+//! Unlike [`ScenarioConfig`](balter_core::ScenarioConfig), hooks are closures and so can't be
+//! serialized. They live on the Scenario itself and are carried along to each task.
+use crate::auth::TokenCache;
+use crate::measurement::Measurement;
+use std::any::Any;
+use std::future::Future;
+use std::io;
+use std::num::NonZeroU32;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Classification of a Transaction's result, see
+/// [`classify()`](crate::scenario::ConfigurableScenario::classify).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Counts towards the TPS.
+    Success,
+    /// Counts towards the error rate.
+    Error,
+    /// Excluded from all measurements (TPS, error rate and latency).
+    Ignore,
+    /// The load generator ran out of a local resource, such as file descriptors (`EMFILE`),
+    /// rather than the target failing. Excluded from all measurements like `Ignore`, and stops
+    /// the concurrency of the Scenario from being raised any further.
+    ResourceExhausted,
+}
+
+/// Raw OS errors for running out of file descriptors, for the process (`EMFILE`) and for the
+/// whole system (`ENFILE`).
+///
+/// NOTE: These are the same on Linux and macOS.
+const FD_EXHAUSTED: [i32; 2] = [24, 23];
+
+pub(crate) type ClassifierFn<R, E> = Box<dyn Fn(&Result<R, E>) -> Outcome + Send + Sync>;
+pub(crate) type ErrorWeightFn<E> = Box<dyn Fn(&E) -> f64 + Send + Sync>;
+pub(crate) type DynamicGoalFn = dyn Fn(&[Measurement]) -> Option<NonZeroU32> + Send + Sync;
+pub(crate) type PreconditionFn =
+    dyn Fn() -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync;
+pub(crate) type ConcurrencyChangeFn = dyn Fn(usize) + Send + Sync;
+
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    /// A type-erased `ClassifierFn<R, E>`, which only applies to Transactions returning exactly
+    /// `Result<R, E>`.
+    pub classifier: Option<Arc<dyn Any + Send + Sync>>,
+    /// A type-erased `ErrorWeightFn<E>`, see
+    /// [`error_weight()`](crate::scenario::ConfigurableScenario::error_weight).
+    pub error_weight: Option<Arc<dyn Any + Send + Sync>>,
+    /// Override of the goal TPS, see
+    /// [`dynamic_goal()`](crate::scenario::ConfigurableScenario::dynamic_goal).
+    pub dynamic_goal: Option<Arc<DynamicGoalFn>>,
+    /// Shared state of the Scenario, see
+    /// [`with_state()`](crate::scenario::ConfigurableScenario::with_state).
+    pub state: Option<Arc<dyn Any + Send + Sync>>,
+    /// Auth tokens shared by every task of the Scenario, see
+    /// [`with_token_provider()`](crate::scenario::ConfigurableScenario::with_token_provider).
+    pub token: Option<Arc<TokenCache>>,
+    /// Check which must pass for the Scenario to run at all, see
+    /// [`precondition()`](crate::scenario::ConfigurableScenario::precondition).
+    pub precondition: Option<Arc<PreconditionFn>>,
+    /// Called with the new concurrency whenever it changes, see
+    /// [`on_concurrency_change()`](crate::scenario::ConfigurableScenario::on_concurrency_change).
+    pub on_concurrency_change: Option<Arc<ConcurrencyChangeFn>>,
+}
+
+impl Hooks {
+    pub fn set_classifier<R, E>(&mut self, classifier: ClassifierFn<R, E>)
+    where
+        R: 'static,
+        E: 'static,
+    {
+        self.classifier = Some(Arc::new(classifier));
+    }
+
+    pub fn set_error_weight<E: 'static>(&mut self, weight: ErrorWeightFn<E>) {
+        self.error_weight = Some(Arc::new(weight));
+    }
+
+    /// Whether the `precondition` hook allows the Scenario to run (always, if there is none).
+    pub async fn precondition_holds(&self) -> bool {
+        match &self.precondition {
+            Some(precondition) => precondition().await,
+            None => true,
+        }
+    }
+
+    /// Goal TPS requested by the `dynamic_goal` hook for the given sample set, if any.
+    pub fn dynamic_goal(&self, samples: &[Measurement]) -> Option<NonZeroU32> {
+        self.dynamic_goal.as_ref().and_then(|f| f(samples))
+    }
+}
+
+pub(crate) fn classify<R: 'static, E: 'static>(
+    classifier: Option<&Arc<dyn Any + Send + Sync>>,
+    res: &Result<R, E>,
+) -> Outcome {
+    match classifier.and_then(|classifier| classifier.downcast_ref::<ClassifierFn<R, E>>()) {
+        Some(classifier) => classifier(res),
+        None => match res {
+            Ok(_) => Outcome::Success,
+            Err(err) if is_resource_exhausted(err) => Outcome::ResourceExhausted,
+            Err(_) => Outcome::Error,
+        },
+    }
+}
+
+/// Weight of a result classified as an error towards the effective error rate, clamped to
+/// `[0, 1]`. Results other than an `Err(E)` with a weighting for `E` count fully.
+pub(crate) fn error_weight<R: 'static, E: 'static>(
+    weighting: Option<&Arc<dyn Any + Send + Sync>>,
+    res: &Result<R, E>,
+) -> f64 {
+    let Err(err) = res else {
+        return 1.;
+    };
+    match weighting.and_then(|weighting| weighting.downcast_ref::<ErrorWeightFn<E>>()) {
+        Some(weight) => match weight(err) {
+            weight if weight.is_nan() => 0.,
+            weight => weight.clamp(0., 1.),
+        },
+        None => 1.,
+    }
+}
+
+/// Whether an error is the load generator running out of file descriptors: an `io::Error`, or
+/// with the `reqwest` feature a `reqwest::Error` caused by one.
+fn is_resource_exhausted<E: 'static>(err: &E) -> bool {
+    let err: &dyn Any = err;
+    if let Some(err) = err.downcast_ref::<io::Error>() {
+        return is_fd_exhausted(err);
+    }
+
+    #[cfg(feature = "reqwest")]
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        let mut source = std::error::Error::source(err);
+        while let Some(err) = source {
+            if err.downcast_ref::<io::Error>().is_some_and(is_fd_exhausted) {
+                return true;
+            }
+            source = err.source();
+        }
+    }
+
+    false
+}
+
+fn is_fd_exhausted(err: &io::Error) -> bool {
+    err.raw_os_error()
+        .is_some_and(|code| FD_EXHAUSTED.contains(&code))
+}
@@ -0,0 +1,506 @@
+//! Helpers for HTTP load tests using `reqwest` (requires the `reqwest` feature).
+use crate::transaction::{with_rng, TRANSACTION_HOOK};
+use balter_core::RequiredHeader;
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::service::Service;
+use rand::Rng;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{ClientBuilder, Response, StatusCode};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of DNS lookup times kept for the p50 of a `WarmupReport`, beyond which a random sample
+/// of them is kept.
+const MAX_DNS_LATENCIES: usize = 1_024;
+
+/// The target failed the pre-flight check of
+/// [`require_header()`](crate::scenario::ConfigurableScenario::require_header).
+#[derive(Debug, Error)]
+pub(crate) enum PreflightError {
+    #[error("a required header needs a base URL to probe, see `with_base_url()`")]
+    NoBaseUrl,
+    #[error("probe request to {url} failed: {source}")]
+    Request { url: String, source: reqwest::Error },
+    #[error("{url} did not respond with the required header {name}")]
+    Missing { url: String, name: String },
+    #[error("{url} responded with {name}: {actual}, rather than the required {expected}")]
+    Mismatch {
+        url: String,
+        name: String,
+        actual: String,
+        expected: String,
+    },
+}
+
+/// Send a single probe request to the base URL, and check that the target responds with the
+/// required header.
+pub(crate) async fn check_required_header(
+    base_url: Option<&str>,
+    header: &RequiredHeader,
+) -> Result<(), PreflightError> {
+    let url = base_url.ok_or(PreflightError::NoBaseUrl)?;
+    let request = |source| PreflightError::Request {
+        url: url.to_string(),
+        source,
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(PREFLIGHT_TIMEOUT)
+        .build()
+        .map_err(request)?;
+    let res = client.get(url).send().await.map_err(request)?;
+
+    let Some(actual) = res.headers().get(header.name.as_str()) else {
+        return Err(PreflightError::Missing {
+            url: url.to_string(),
+            name: header.name.clone(),
+        });
+    };
+
+    if actual.as_bytes() != header.value.as_bytes() {
+        return Err(PreflightError::Mismatch {
+            url: url.to_string(),
+            name: header.name.clone(),
+            actual: String::from_utf8_lossy(actual.as_bytes()).into_owned(),
+            expected: header.value.clone(),
+        });
+    }
+
+    debug!("{url} responded with the required header {}", header.name);
+    Ok(())
+}
+
+/// Check whether a response was rejected by rate limiting (HTTP 429), and if so report it via
+/// [`record_rate_limited()`](crate::record_rate_limited) along with its `Retry-After`.
+///
+/// Returns `true` if the response was rate limited.
+///
+/// NOTE: Only the delay-seconds form of `Retry-After` is supported; an HTTP-date is treated as
+/// if no `Retry-After` was provided.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+///
+/// #[transaction]
+/// async fn get_users(client: &reqwest::Client) -> Result<(), String> {
+///     let res = client
+///         .get("http://localhost:3002/users")
+///         .send()
+///         .await
+///         .map_err(|err| err.to_string())?;
+///
+///     if balter::check_rate_limit(&res) {
+///         return Err("Rate limited".into());
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn check_rate_limit(response: &Response) -> bool {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS {
+        return false;
+    }
+
+    crate::record_rate_limited(retry_after(response.headers()));
+    true
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let secs = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Instrument the connection setup of a `reqwest` client, so that DNS resolution shows up in
+/// the [`WarmupReport`].
+///
+/// This replaces the client's DNS resolver with one which resolves in the same way, but times
+/// each lookup. As `reqwest` resolves the host for every new connection, the number of lookups
+/// is also the number of connections the client attempted.
+///
+/// NOTE: Hosts which are IP addresses are never resolved, so connections to them are not
+/// counted. Neither are lookups made outside of a Scenario's tasks, since the diagnostics are
+/// collected per Scenario.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+///
+/// #[scenario]
+/// async fn my_scenario() {
+///     let client = balter::instrument_connections(reqwest::Client::builder())
+///         .build()
+///         .unwrap();
+///
+///     loop {
+///         let _ = get_users(&client).await;
+///     }
+/// }
+///
+/// #[transaction]
+/// async fn get_users(client: &reqwest::Client) -> Result<(), reqwest::Error> {
+///     client
+///         .get("http://localhost:3002/users")
+///         .send()
+///         .await
+///         .map_err(|err| {
+///             balter::check_connect_error(&err);
+///             err
+///         })?;
+///     Ok(())
+/// }
+/// ```
+pub fn instrument_connections(builder: ClientBuilder) -> ClientBuilder {
+    builder.dns_resolver(Arc::new(TimedResolver::new()))
+}
+
+/// Check whether a request failed to connect, and if so count it in the [`WarmupReport`]
+/// (separately noting TLS handshake failures).
+///
+/// Returns `true` if the error was a connection error.
+///
+/// NOTE: Failed DNS lookups also surface as connection errors. TLS failures are detected from
+/// the error messages, as `reqwest` doesn't expose them as a distinct kind of error.
+pub fn check_connect_error(error: &reqwest::Error) -> bool {
+    if !error.is_connect() {
+        return false;
+    }
+
+    let tls = is_tls_error(error);
+    if let Some(diagnostics) = ConnectionDiagnostics::current() {
+        let mut diagnostics = diagnostics.lock();
+        diagnostics.connect_failures += 1;
+        if tls {
+            diagnostics.tls_failures += 1;
+        }
+    }
+    true
+}
+
+/// Connection setup diagnostics collected from clients set up with [`instrument_connections()`]
+/// and errors passed to [`check_connect_error()`], separately for each Scenario.
+///
+/// Slow DNS resolution or failing TLS handshakes are easily mistaken for a slow service, since
+/// they only show up as Transaction latency and errors. Running a short warmup phase (see
+/// [`PhasedScenario`](crate::PhasedScenario)) surfaces them before the measured phases: the
+/// report is logged at the end of each phase, or can be taken directly from within the Scenario
+/// with [`take()`](Self::take).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WarmupReport {
+    /// New connections attempted, i.e. successful DNS lookups.
+    pub connections: usize,
+    pub dns_failures: usize,
+    pub dns_p50: Duration,
+    pub dns_max: Duration,
+    /// Connection failures (including failed DNS lookups and TLS handshakes).
+    pub connect_failures: usize,
+    pub tls_failures: usize,
+}
+
+impl WarmupReport {
+    /// Take the diagnostics of the current Scenario collected since the last call, resetting
+    /// them. Outside of a Scenario's tasks, the report is empty.
+    pub fn take() -> Self {
+        ConnectionDiagnostics::current()
+            .map(|diagnostics| diagnostics.take())
+            .unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.dns_failures > 0 || self.connect_failures > 0
+    }
+}
+
+impl fmt::Display for WarmupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} connections (DNS p50 {:?}, max {:?}), {} DNS failures, {} connection failures ({} TLS)",
+            self.connections,
+            self.dns_p50,
+            self.dns_max,
+            self.dns_failures,
+            self.connect_failures,
+            self.tls_failures,
+        )
+    }
+}
+
+/// Log the [`WarmupReport`] for a phase of a Scenario, if there was anything to report.
+pub(crate) fn log_phase_connections(phase: &str, report: WarmupReport) {
+    if report.has_failures() {
+        warn!("Connection issues during phase {phase}: {report}");
+    } else if !report.is_empty() {
+        info!("Connections during phase {phase}: {report}");
+    }
+}
+
+/// Connection diagnostics of a Scenario, shared by its tasks, see [`WarmupReport`].
+#[derive(Default)]
+pub(crate) struct ConnectionDiagnostics(Mutex<Diagnostics>);
+
+#[derive(Default)]
+struct Diagnostics {
+    dns_lookups: usize,
+    /// A sample of at most `MAX_DNS_LATENCIES` of the lookup times.
+    dns_latencies: Vec<Duration>,
+    dns_max: Duration,
+    dns_failures: usize,
+    connect_failures: usize,
+    tls_failures: usize,
+}
+
+impl ConnectionDiagnostics {
+    /// Diagnostics of the Scenario whose task this is called from.
+    fn current() -> Option<Arc<Self>> {
+        TRANSACTION_HOOK
+            .try_with(|hook| hook.connections.clone())
+            .ok()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Diagnostics> {
+        // NOTE: The diagnostics are plain counters, so they are still valid if a holder panicked.
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn take(&self) -> WarmupReport {
+        std::mem::take(&mut *self.lock()).report()
+    }
+}
+
+impl Diagnostics {
+    /// Record the time of a successful lookup, keeping a uniform sample of the lookup times once
+    /// there are more than `MAX_DNS_LATENCIES` of them (reservoir sampling).
+    fn record_lookup(&mut self, latency: Duration) {
+        self.dns_lookups += 1;
+        self.dns_max = self.dns_max.max(latency);
+        if self.dns_latencies.len() < MAX_DNS_LATENCIES {
+            self.dns_latencies.push(latency);
+        } else {
+            let idx = with_rng(|rng| rng.gen_range(0..self.dns_lookups));
+            if let Some(sampled) = self.dns_latencies.get_mut(idx) {
+                *sampled = latency;
+            }
+        }
+    }
+
+    fn report(mut self) -> WarmupReport {
+        self.dns_latencies.sort();
+        WarmupReport {
+            connections: self.dns_lookups,
+            dns_failures: self.dns_failures,
+            dns_p50: self
+                .dns_latencies
+                .get(self.dns_latencies.len() / 2)
+                .copied()
+                .unwrap_or_default(),
+            dns_max: self.dns_max,
+            connect_failures: self.connect_failures,
+            tls_failures: self.tls_failures,
+        }
+    }
+}
+
+struct TimedResolver {
+    resolver: GaiResolver,
+}
+
+impl TimedResolver {
+    fn new() -> Self {
+        Self {
+            resolver: GaiResolver::new(),
+        }
+    }
+}
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let mut resolver = self.resolver.clone();
+        // NOTE: Looked up before the lookup is spawned, as it may not run in the Scenario's task.
+        let diagnostics = ConnectionDiagnostics::current();
+        Box::pin(async move {
+            let start = Instant::now();
+            let res = resolver.call(name).await;
+            let elapsed = start.elapsed();
+
+            if let Some(diagnostics) = diagnostics {
+                match res {
+                    Ok(_) => diagnostics.lock().record_lookup(elapsed),
+                    Err(_) => diagnostics.lock().dns_failures += 1,
+                }
+            }
+            Ok(Box::new(res?) as Addrs)
+        })
+    }
+}
+
+fn is_tls_error(error: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(err) = source {
+        let message = err.to_string().to_lowercase();
+        if ["tls", "ssl", "certificate", "handshake"]
+            .iter()
+            .any(|pattern| message.contains(pattern))
+        {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::Hooks;
+    use crate::sampler::TaskAtomics;
+    use balter_core::ScenarioConfig;
+    use reqwest::header::HeaderValue;
+    use std::num::NonZeroU32;
+    use std::str::FromStr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve a single HTTP response with the given extra header lines, returning its URL.
+    async fn serve_once(headers: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\n{headers}content-length: 0\r\nconnection: close\r\n\r\n"
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_check_required_header() {
+        let header = RequiredHeader::new("X-Environment", "staging");
+
+        let url = serve_once("x-environment: staging\r\n").await;
+        check_required_header(Some(&url), &header).await.unwrap();
+
+        let url = serve_once("").await;
+        let err = check_required_header(Some(&url), &header)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, PreflightError::Missing { .. }), "{err}");
+
+        let url = serve_once("X-Environment: production\r\n").await;
+        let err = check_required_header(Some(&url), &header)
+            .await
+            .unwrap_err();
+        match err {
+            PreflightError::Mismatch { actual, .. } => assert_eq!(actual, "production"),
+            err => panic!("Unexpected error: {err}"),
+        }
+
+        let err = check_required_header(None, &header).await.unwrap_err();
+        assert!(matches!(err, PreflightError::NoBaseUrl));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_is_tls_error() {
+        #[derive(Debug)]
+        struct Wrapper(std::io::Error);
+
+        impl fmt::Display for Wrapper {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "error trying to connect")
+            }
+        }
+
+        impl Error for Wrapper {
+            fn source(&self) -> Option<&(dyn Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let tls = Wrapper(std::io::Error::other(
+            "invalid peer certificate: UnknownIssuer",
+        ));
+        assert!(is_tls_error(&tls));
+
+        let refused = Wrapper(std::io::Error::other("Connection refused (os error 111)"));
+        assert!(!is_tls_error(&refused));
+    }
+
+    #[tokio::test]
+    async fn test_timed_resolver() {
+        let atomics =
+            TaskAtomics::new(&ScenarioConfig::new(""), &Hooks::default(), NonZeroU32::MAX);
+        let other = TaskAtomics::new(&ScenarioConfig::new(""), &Hooks::default(), NonZeroU32::MAX);
+
+        let resolver = TimedResolver::new();
+        let lookup = async { resolver.resolve(Name::from_str("localhost").unwrap()).await };
+        let addrs = TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), lookup)
+            .await
+            .unwrap();
+        assert!(addrs.count() > 0);
+
+        // NOTE: Only counted for the Scenario which made the lookup.
+        assert!(other.warmup_report().is_empty());
+        let report = atomics.warmup_report();
+        assert_eq!(report.connections, 1);
+        assert!(report.dns_max >= report.dns_p50);
+        assert!(!report.has_failures());
+        assert!(atomics.warmup_report().is_empty());
+    }
+
+    #[test]
+    fn test_dns_latencies_bounded() {
+        let mut diagnostics = Diagnostics::default();
+        for millis in 0..10_000 {
+            diagnostics.record_lookup(Duration::from_millis(millis));
+        }
+        assert_eq!(diagnostics.dns_latencies.len(), MAX_DNS_LATENCIES);
+
+        let report = diagnostics.report();
+        assert_eq!(report.connections, 10_000);
+        assert_eq!(report.dns_max, Duration::from_millis(9_999));
+        // NOTE: A uniform sample has a median near that of all of the lookups.
+        assert!(
+            report.dns_p50.abs_diff(Duration::from_millis(5_000)) < Duration::from_millis(1_000),
+            "{report}"
+        );
+    }
+}
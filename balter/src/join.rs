@@ -0,0 +1,260 @@
+//! Running several Scenarios concurrently, failing fast if any of them fail.
+use balter_core::RunStatistics;
+use pin_project::pin_project;
+use std::any::Any;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use thiserror::Error;
+use tokio::task::JoinSet;
+use tracing::error;
+
+/// A hard failure of a Scenario run with
+/// [`run()`](crate::scenario::ConfigurableScenario::run), or of one of the Scenarios passed to
+/// [`try_join_scenarios()`].
+#[derive(Debug, Error)]
+pub enum ScenarioError {
+    /// A task of the Scenario panicked, see [`RunStatistics::failed`].
+    #[error("Scenario {index} failed")]
+    Failed {
+        index: usize,
+        stats: Box<RunStatistics>,
+    },
+
+    /// The Scenario itself panicked (e.g. due to an invalid configuration).
+    #[error("Scenario {index} panicked: {message}")]
+    Panicked { index: usize, message: String },
+
+    /// There is no Tokio runtime to run the Scenario on.
+    #[error(
+        "Balter requires a Tokio runtime. Run Scenarios from within `#[tokio::main]` or \
+        `tokio::runtime::Runtime::block_on` rather than another executor."
+    )]
+    NoRuntime,
+
+    /// The Scenario has no goal (TPS, error rate or latency) to run towards.
+    #[error("Scenario {name} has no goal to run towards")]
+    Unconfigured { name: String },
+
+    /// The Scenario's configuration is invalid.
+    #[error("Scenario {name} has an invalid configuration: {message}")]
+    InvalidConfig { name: String, message: String },
+
+    /// The target failed the pre-flight check of
+    /// [`require_header()`](crate::scenario::ConfigurableScenario::require_header).
+    #[error("Aborting Scenario {name} before sending load: {message}")]
+    Preflight { name: String, message: String },
+}
+
+impl ScenarioError {
+    /// Index of the failed Scenario in the list passed to [`try_join_scenarios()`], or `None`
+    /// for an error returned by [`run()`](crate::scenario::ConfigurableScenario::run).
+    pub fn index(&self) -> Option<usize> {
+        match self {
+            Self::Failed { index, .. } | Self::Panicked { index, .. } => Some(*index),
+            _ => None,
+        }
+    }
+}
+
+/// Run several Scenarios concurrently, returning the statistics of each in the order given.
+///
+/// A Scenario has failed if one of its tasks panicked (which terminates it early, see
+/// [`RunStatistics::failed`]) or if the Scenario itself panicked. With `abort_on_error`, the
+/// first failure cancels all other Scenarios and is returned immediately. Otherwise all
+/// Scenarios are run to completion and the first failure (in order of completion) is returned.
+///
+/// NOTE: Cancelled Scenarios are dropped, which aborts their tasks. Transactions in flight are
+/// abandoned at their next `.await` point, and no statistics are reported for them. Each
+/// Scenario is spawned as its own Tokio task, so this must be called from within a Tokio
+/// runtime.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let results = balter::try_join_scenarios(
+///         vec![
+///             Box::pin(load_scenario().tps(500).duration(Duration::from_secs(30))),
+///             Box::pin(load_scenario().tps(50).duration(Duration::from_secs(30))),
+///         ],
+///         true,
+///     )
+///     .await;
+///
+///     match results {
+///         Ok(stats) => println!("Scenarios completed: {stats:?}"),
+///         Err(err) => println!("Scenario {:?} failed: {err}", err.index()),
+///     }
+/// }
+///
+/// #[scenario]
+/// async fn load_scenario() {
+///     load_transaction().await;
+/// }
+///
+/// #[transaction]
+/// async fn load_transaction() -> Result<u32, String> {
+///     // Some request logic...
+///
+///     Ok(0)
+/// }
+/// ```
+pub async fn try_join_scenarios<S>(
+    scenarios: Vec<S>,
+    abort_on_error: bool,
+) -> Result<Vec<RunStatistics>, ScenarioError>
+where
+    S: Future<Output = RunStatistics> + Send + 'static,
+{
+    crate::scenario::require_tokio_runtime();
+
+    let mut results: Vec<Option<RunStatistics>> = scenarios.iter().map(|_| None).collect();
+    let mut set = JoinSet::new();
+    for (index, scenario) in scenarios.into_iter().enumerate() {
+        set.spawn(async move { (index, CatchUnwind(scenario).await) });
+    }
+
+    let mut first_error = None;
+    while let Some(res) = set.join_next().await {
+        // NOTE: Panics are caught within the task, so the only JoinError is cancellation.
+        let Ok((index, res)) = res else {
+            continue;
+        };
+
+        let err = match res {
+            Ok(stats) if !stats.failed => {
+                results[index] = Some(stats);
+                continue;
+            }
+            Ok(stats) => ScenarioError::Failed {
+                index,
+                stats: Box::new(stats),
+            },
+            Err(panic) => ScenarioError::Panicked {
+                index,
+                message: panic_message(panic),
+            },
+        };
+        error!("{err}");
+
+        if abort_on_error {
+            set.abort_all();
+            return Err(err);
+        }
+        first_error.get_or_insert(err);
+    }
+
+    match first_error {
+        Some(err) => Err(err),
+        None => Ok(results.into_iter().flatten().collect()),
+    }
+}
+
+#[pin_project]
+struct CatchUnwind<F>(#[pin] F);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let fut = self.project().0;
+        match catch_unwind(AssertUnwindSafe(|| fut.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::{ConfigurableScenario, Scenario};
+    use std::time::{Duration, Instant};
+
+    fn scenario(
+        name: &str,
+        panics: bool,
+        duration: Duration,
+    ) -> Pin<Box<dyn Future<Output = RunStatistics> + Send + 'static>> {
+        Box::pin(
+            Scenario::new(name, move || async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                if panics {
+                    panic!("Transaction exploded");
+                }
+            })
+            .tps(200)
+            .duration(duration),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_join_all_succeed() {
+        let stats = try_join_scenarios(
+            vec![
+                scenario("a", false, Duration::from_secs(2)),
+                scenario("b", false, Duration::from_secs(2)),
+            ],
+            true,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stats.len(), 2);
+        assert!(stats.iter().all(|stats| !stats.failed));
+    }
+
+    #[tokio::test]
+    async fn test_join_abort_on_error() {
+        let start = Instant::now();
+        let err = try_join_scenarios(
+            vec![
+                scenario("a", false, Duration::from_secs(30)),
+                scenario("b", true, Duration::from_secs(30)),
+            ],
+            true,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.index(), Some(1));
+        assert!(matches!(err, ScenarioError::Failed { .. }));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_join_scenario_panic() {
+        let panicking: Pin<Box<dyn Future<Output = RunStatistics> + Send>> =
+            Box::pin(async { panic!("Invalid configuration") });
+        let err = try_join_scenarios(
+            vec![scenario("a", false, Duration::from_secs(2)), panicking],
+            false,
+        )
+        .await
+        .unwrap_err();
+
+        match err {
+            ScenarioError::Panicked { index, message } => {
+                assert_eq!(index, 1);
+                assert_eq!(message, "Invalid configuration");
+            }
+            _ => panic!("Expected a panic"),
+        }
+    }
+}
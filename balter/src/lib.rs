@@ -37,7 +37,26 @@ pub mod scenario;
 #[doc(hidden)]
 pub mod transaction;
 
+mod auth;
+mod barrier;
+mod defaults;
+mod drift;
+mod graph;
 mod hints;
+mod hooks;
+#[cfg(feature = "reqwest")]
+mod http;
+mod join;
+mod measure;
+mod phases;
+#[cfg(feature = "indicatif")]
+mod progress;
+mod replay;
+#[cfg(feature = "influxdb")]
+mod results;
+mod sweep;
+mod think;
+mod trace;
 
 #[macro_use]
 #[doc(hidden)]
@@ -47,10 +66,35 @@ pub(crate) mod controllers;
 pub(crate) mod measurement;
 pub(crate) mod sampler;
 
+pub use auth::Token;
 #[cfg(not(feature = "rt"))]
 pub use balter_macros::{scenario, transaction};
+pub use barrier::barrier;
+pub use controllers::{CompositeController, Controller, ControllerState};
+pub use defaults::{set_defaults, ScenarioDefaults};
+pub use graph::{GraphError, ScenarioGraph, ScenarioPlan};
 pub use hints::Hint;
+pub use hooks::Outcome;
+pub use join::{try_join_scenarios, ScenarioError};
+pub use measure::measure_once;
+pub use measurement::Measurement;
+pub use phases::PhasedScenario;
+pub use replay::{ReplayError, TrafficReplay};
+#[cfg(feature = "test-util")]
+#[doc(hidden)]
+pub use sampler::MetricsProbe;
 pub use scenario::Scenario;
+pub use sweep::{CapacitySweepResult, MaxTpsResult};
+pub use think::{think_dist, ThinkDistribution};
+pub use trace::{replay_trace, TraceError, TraceMismatch, TraceReplay};
+pub use transaction::{
+    auth_token, base_url, fail_after, on_scenario_end, phase, phase_async, record, record_latency,
+    record_rate_limited, state, variant, with_host,
+};
+
+#[cfg(feature = "reqwest")]
+#[cfg_attr(docsrs, doc(cfg(feature = "reqwest")))]
+pub use http::{check_connect_error, check_rate_limit, instrument_connections, WarmupReport};
 
 cfg_rt! {
     pub use balter_runtime::runtime::{self, BalterRuntime};
@@ -62,7 +106,10 @@ pub mod core {
     pub use balter_core::*;
 }
 
-pub use core::RunStatistics;
+pub use core::{
+    estimate_concurrency, JunitReport, LatencyConfig, OverflowPolicy, RunStatistics, SampleData,
+    ScenarioConfig, Weighting, WindowMode,
+};
 
 pub mod prelude {
     pub use crate::scenario::ConfigurableScenario;
@@ -0,0 +1,329 @@
+//! One-shot benchmarking without the Scenario machinery.
+use crate::hooks::Hooks;
+use crate::measurement::{Measurement, TTFB_LABEL};
+use crate::sampler::BaseSampler;
+use crate::transaction::transaction_hook;
+use balter_core::{RunStatistics, ScenarioConfig, TransactionLabels};
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+const LABELS: TransactionLabels = TransactionLabels {
+    success: "balter_measure_once_success",
+    error: "balter_measure_once_error",
+    latency: "balter_measure_once_latency",
+    slo: None,
+};
+
+/// Run a function at a fixed concurrency for the given duration, and return the raw numbers.
+///
+/// This is the "I just want raw numbers" entry point: there is no `#[scenario]` or
+/// `#[transaction]` required, and none of the adaptive controllers are involved. The function is
+/// called in a loop from `concurrency` tasks with no TPS limit, and the returned statistics cover
+/// the whole `duration`. As with Transactions, an `Err()` return value counts towards the error
+/// rate.
+///
+/// NOTE: `goal_tps` is not meaningful for a one-shot measurement and is left as `0`.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let stats = balter::measure_once(10, Duration::from_secs(5), || async {
+///         // Some request logic...
+///         Ok::<_, String>(())
+///     })
+///     .await;
+///
+///     println!("TPS: {}, p99: {:?}", stats.actual_tps, stats.latency_p99);
+/// }
+/// ```
+pub async fn measure_once<T, F, R, E>(
+    concurrency: usize,
+    duration: Duration,
+    func: T,
+) -> RunStatistics
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = Result<R, E>> + Send,
+    R: 'static,
+    E: 'static,
+{
+    crate::scenario::require_tokio_runtime();
+
+    let scenario = move || {
+        let func = func.clone();
+        async move {
+            let _ = transaction_hook(LABELS, func()).await;
+        }
+    };
+
+    let mut config = ScenarioConfig::new("measure_once");
+    config.unbounded_concurrency = Some(concurrency);
+    let mut sampler = BaseSampler::with_interval(
+        &config,
+        &Hooks::default(),
+        scenario,
+        NonZeroU32::MAX,
+        duration,
+    )
+    .await;
+    sampler.set_concurrency(concurrency);
+    let measurement = sampler.sample().await;
+    let measurements = sampler.custom_measurements();
+    let phases = sampler.phase_timings();
+    let error_categories = sampler.error_categories();
+    sampler.shutdown().await;
+
+    let latency_modes = measurement.latency_modes();
+    RunStatistics {
+        concurrency,
+        actual_tps: measurement.tps,
+        total_tps: measurement.total_tps(),
+        latency_p50: measurement.latency(0.5),
+        latency_p90: measurement.latency(0.9),
+        latency_p95: measurement.latency(0.95),
+        latency_p99: measurement.latency(0.99),
+        latency_multimodal: !latency_modes.is_empty(),
+        latency_modes,
+        error_rate: measurement.error_rate,
+        ttfb_p50: measurement.labeled_latency(TTFB_LABEL, 0.5),
+        ttfb_p99: measurement.labeled_latency(TTFB_LABEL, 0.99),
+        slos: measurement.slo_statistics(),
+        variants: measurement.variant_statistics(),
+        tps_goal_met: true,
+        measurements,
+        phases,
+        error_categories,
+        ..Default::default()
+    }
+}
+
+/// Run a Scenario with no TPS limit, at the fixed concurrency set by
+/// [`unbounded_concurrency()`](crate::scenario::ConfigurableScenario::unbounded_concurrency) or
+/// on the schedule set by
+/// [`concurrency_ramp()`](crate::scenario::ConfigurableScenario::concurrency_ramp).
+///
+/// Measurement windows are taken as usual (so metrics are emitted throughout), and the returned
+/// statistics are those of the final window, as with the controllers. The concurrency is only
+/// changed between windows, so each window measures a single concurrency level.
+pub(crate) async fn run_unbounded<T, F>(
+    scenario: T,
+    config: &ScenarioConfig,
+    hooks: &Hooks,
+) -> RunStatistics
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    let mut sampler = BaseSampler::new(config, hooks, scenario, NonZeroU32::MAX).await;
+
+    let start = Instant::now();
+    let mut last_sample = None;
+    let mut capped = false;
+    let mut panicked_tasks = 0;
+    let mut breaking_concurrency = None;
+    loop {
+        let concurrency = scheduled_concurrency(config, start.elapsed());
+        if concurrency != sampler.concurrency() {
+            sampler.set_concurrency(concurrency);
+        }
+
+        let sample = if let Some(cap) = config.max_duration {
+            let remaining = cap.saturating_sub(start.elapsed());
+            match tokio::time::timeout(remaining, sampler.sample()).await {
+                Ok(sample) => sample,
+                Err(_) => {
+                    warn!("Scenario exceeded max_duration of {cap:?}, terminating.");
+                    capped = true;
+                    break;
+                }
+            }
+        } else {
+            sampler.sample().await
+        };
+        debug!("Unbounded sample at {concurrency} concurrency: {sample}");
+        if breaking_concurrency.is_none() && exceeds_limits(config, &sample) {
+            warn!("Limits exceeded at {concurrency} concurrency: {sample}");
+            breaking_concurrency = Some(concurrency);
+        }
+        last_sample = Some(sample);
+
+        if sampler.has_failed_tasks() {
+            panicked_tasks = sampler.reap_failed_tasks();
+            error!(
+                "{panicked_tasks} task(s) of Scenario {} panicked, terminating.",
+                config.name
+            );
+            break;
+        }
+
+        // NOTE: A ramp always finishes with a window measured at its end concurrency.
+        let ramp_duration = config.concurrency_ramp.map(|ramp| ramp.duration);
+        let ramp_done = config
+            .concurrency_ramp
+            .is_none_or(|ramp| concurrency == ramp.end);
+        if ramp_done
+            && config
+                .duration
+                .max(ramp_duration)
+                .is_some_and(|duration| start.elapsed() > duration)
+        {
+            break;
+        }
+    }
+
+    let mut stats = RunStatistics {
+        concurrency: sampler.concurrency(),
+        tps_goal_met: true,
+        capped,
+        concurrency_capped: sampler.concurrency_capped(),
+        failed: panicked_tasks > 0,
+        panicked_tasks,
+        measurements: sampler.custom_measurements(),
+        phases: sampler.phase_timings(),
+        error_categories: sampler.error_categories(),
+        breaking_concurrency,
+        ..Default::default()
+    };
+    sampler.shutdown().await;
+
+    // NOTE: A capped run may be terminated before the first measurement window completes.
+    if let Some(sample) = last_sample {
+        stats.actual_tps = sample.tps;
+        stats.total_tps = sample.total_tps();
+        stats.latency_p50 = sample.latency(0.5);
+        stats.latency_p90 = sample.latency(0.9);
+        stats.latency_p95 = sample.latency(0.95);
+        stats.latency_p99 = sample.latency(0.99);
+        stats.latency_modes = sample.latency_modes();
+        stats.latency_multimodal = !stats.latency_modes.is_empty();
+        stats.error_rate = sample.error_rate;
+        stats.ttfb_p50 = sample.labeled_latency(TTFB_LABEL, 0.5);
+        stats.ttfb_p99 = sample.labeled_latency(TTFB_LABEL, 0.99);
+        stats.slos = sample.slo_statistics();
+        stats.variants = sample.variant_statistics();
+    }
+
+    stats
+}
+
+/// Concurrency to run an unbounded Scenario at, `elapsed` into the run. A ramp takes precedence
+/// over a fixed concurrency.
+fn scheduled_concurrency(config: &ScenarioConfig, elapsed: Duration) -> usize {
+    match (config.concurrency_ramp, config.unbounded_concurrency) {
+        (Some(ramp), _) => ramp.concurrency_at(elapsed),
+        (None, Some(concurrency)) => concurrency,
+        (None, None) => unreachable!("Unbounded run without a concurrency"),
+    }
+}
+
+/// The window's error rate or latency exceeded the limit set by `error_rate()` or `latency()`.
+fn exceeds_limits(config: &ScenarioConfig, sample: &Measurement) -> bool {
+    config
+        .error_rate
+        .is_some_and(|error_rate| sample.error_rate > error_rate)
+        || config
+            .latency
+            .is_some_and(|latency| sample.latency(latency.quantile) > latency.latency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::{ConfigurableScenario, Scenario};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_measure_once() {
+        let stats = measure_once(4, Duration::from_secs(1), || async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok::<_, ()>(())
+        })
+        .await;
+
+        assert_eq!(stats.concurrency, 4);
+        assert!(stats.actual_tps > 300. && stats.actual_tps <= 400.);
+        assert!(stats.latency_p50 >= Duration::from_millis(10));
+        assert_eq!(stats.error_rate, 0.);
+    }
+
+    #[tokio::test]
+    async fn test_measure_once_errors() {
+        let fail = Arc::new(AtomicBool::new(false));
+        let stats = measure_once(1, Duration::from_millis(500), move || {
+            let fail = fail.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                if !fail.fetch_xor(true, Ordering::Relaxed) {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            }
+        })
+        .await;
+
+        assert!((stats.error_rate - 0.5).abs() < 0.05);
+        // NOTE: Only the successful half of the Transactions count towards the goodput.
+        let goodput = stats.total_tps * (1. - stats.error_rate);
+        assert!((stats.actual_tps - goodput).abs() < 1., "{stats:?}");
+    }
+
+    #[tokio::test]
+    async fn test_unbounded_concurrency() {
+        // NOTE: A TPS goal would cap this at 100 TPS, were it not ignored.
+        let stats = Scenario::new("test_unbounded_concurrency", || async {
+            let _ = transaction_hook(LABELS, async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Ok::<_, ()>(())
+            })
+            .await;
+        })
+        .tps(100)
+        .unbounded_concurrency(20)
+        .duration(Duration::from_secs(2))
+        .await;
+
+        assert_eq!(stats.concurrency, 20);
+        assert_eq!(stats.goal_tps, 0);
+        assert!(
+            stats.actual_tps > 1_500. && stats.actual_tps <= 2_000.,
+            "actual: {}",
+            stats.actual_tps
+        );
+        assert!(stats.latency_p50 >= Duration::from_millis(10));
+        assert_eq!(stats.breaking_concurrency, None);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_ramp() {
+        // NOTE: Emulates a target which only accepts 5 concurrent connections.
+        let connections = Arc::new(tokio::sync::Semaphore::new(5));
+        let stats = Scenario::new("test_concurrency_ramp", move || {
+            let connections = connections.clone();
+            async move {
+                let _ = transaction_hook(LABELS, async {
+                    let permit = connections.try_acquire();
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    permit.map(|_| ()).map_err(|_| ())
+                })
+                .await;
+            }
+        })
+        .error_rate(0.05)
+        .concurrency_ramp(1, 10, Duration::from_secs(3))
+        .await;
+
+        assert_eq!(stats.concurrency, 10);
+        assert_eq!(stats.goal_tps, 0);
+        assert!(stats.error_rate > 0.05);
+        let breaking = stats.breaking_concurrency.unwrap();
+        assert!(breaking > 5 && breaking <= 10, "breaking: {breaking}");
+    }
+}
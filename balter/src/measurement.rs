@@ -1,52 +1,357 @@
+use balter_core::{
+    per_sec, LatencySlo, MeasurementStatistics, SampleData, SloStatistics, VariantStatistics,
+};
+use metrics_util::AtomicBucket;
 use pdatastructs::tdigest::{TDigest, K1};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tracing::error;
 
 const TDIGEST_BACKLOG_SIZE: usize = 100;
 
+/// Label of the latency surfaced as `ttfb_*` in the `RunStatistics`.
+pub(crate) const TTFB_LABEL: &str = "ttfb";
+
+/// Width of the gap between two latency modes, in percentiles.
+const MODE_GAP_WIDTH: usize = 5;
+/// Minimum fraction of Transactions in each latency mode, in percentiles.
+const MODE_MIN_MASS: usize = 5;
+/// Minimum ratio between the latencies on either side of a gap, for it to separate two modes.
+const MODE_GAP_RATIO: f64 = 3.;
+/// Number of latency buckets per doubling of the latency, for the mean of each latency mode.
+const MODE_BUCKETS_PER_DOUBLING: f64 = 4.;
+
+/// Measurement of a single sampling window.
+///
+/// All values are per-window (since the previous sample was taken), not cumulative over the run.
 #[allow(unused)]
 #[derive(Debug, Clone)]
 pub struct Measurement {
+    /// Successful transactions per second over the window.
     pub tps: f64,
+    /// Fraction of transactions in the window which returned an error.
     pub error_rate: f64,
+    /// Error rate with each error weighted, see
+    /// [`error_weight()`](crate::scenario::ConfigurableScenario::error_weight).
+    effective_error_rate: f64,
+    /// Length of the window.
     pub elapsed: Duration,
+    success: u64,
+    error: u64,
+    latency: TDigest<K1>,
+    /// Count and sum (in nanoseconds) of the latencies, bucketed by their log.
+    latency_buckets: BTreeMap<i32, (u64, u128)>,
+    labeled_latency: HashMap<&'static str, TDigest<K1>>,
+    slo_latency: HashMap<&'static str, (LatencySlo, TDigest<K1>)>,
+    rate_limited: u64,
+    retry_after: Option<Duration>,
+    limiter_waits: u64,
+    variants: HashMap<&'static str, VariantMeasurement>,
+}
+
+/// Rebuild the window of a [`RunStatistics::raw_samples`](balter_core::RunStatistics), without
+/// latencies.
+impl From<SampleData> for Measurement {
+    fn from(sample: SampleData) -> Self {
+        Measurement::new(sample.success, sample.error, sample.elapsed)
+    }
+}
+
+/// Transactions run within a single [`variant()`](crate::variant) during a sampling window.
+#[derive(Debug, Clone)]
+struct VariantMeasurement {
+    success: u64,
+    error: u64,
     latency: TDigest<K1>,
 }
 
 impl Measurement {
+    /// Measurement of a window of `elapsed` length in which `success` Transactions succeeded and
+    /// `error` Transactions returned an error, e.g. to drive a [`CompositeController`] from
+    /// load generated outside of Balter.
+    ///
+    /// The counts are of that window alone (since the previous measurement), not cumulative over
+    /// the run. Controllers react to each window in turn, so windows should be long enough for
+    /// the latest goal TPS to take effect and for their rates to be meaningful (see
+    /// [`WindowMode`](crate::WindowMode) for the windows Scenarios use).
+    ///
+    /// NOTE: `elapsed` must be non-zero, and a window without any Transactions has an undefined
+    /// (NaN) error rate.
+    ///
+    /// [`CompositeController`]: crate::CompositeController
     pub fn new(success: u64, error: u64, elapsed: Duration) -> Self {
         let tps = success as f64 / elapsed.as_secs_f64();
         let error_rate = error as f64 / (success + error) as f64;
         Self {
             tps,
             error_rate,
+            effective_error_rate: error_rate,
             elapsed,
+            success,
+            error,
             latency: default_tdigest(),
+            latency_buckets: BTreeMap::new(),
+            labeled_latency: HashMap::new(),
+            slo_latency: HashMap::new(),
+            rate_limited: 0,
+            retry_after: None,
+            limiter_waits: 0,
+            variants: HashMap::new(),
         }
     }
 
-    pub fn populate_latencies(&mut self, dur: &[Duration]) {
+    /// Set the error rate with each error weighted (see
+    /// [`error_weight()`](crate::scenario::ConfigurableScenario::error_weight)).
+    pub(crate) fn with_effective_error_rate(mut self, effective_error_rate: f64) -> Self {
+        self.effective_error_rate = effective_error_rate;
+        self
+    }
+
+    /// Error rate with each error weighted by
+    /// [`error_weight()`](crate::scenario::ConfigurableScenario::error_weight), which drives the
+    /// error rate goal. The same as `error_rate` without a weighting.
+    pub fn effective_error_rate(&self) -> f64 {
+        self.effective_error_rate
+    }
+
+    /// Set the latencies of the Transactions in the window, which a latency goal is controlled
+    /// on.
+    pub fn with_latencies(mut self, latencies: &[Duration]) -> Self {
+        self.populate_latencies(latencies);
+        self
+    }
+
+    /// Set the rate limit rejections of the window (see
+    /// [`record_rate_limited()`](crate::record_rate_limited)), e.g. when rebuilding a recorded
+    /// window, along with the longest `Retry-After` among them.
+    pub fn with_rate_limits(mut self, rate_limited: u64, retry_after: Option<Duration>) -> Self {
+        self.rate_limited = rate_limited;
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Set the number of Transactions in the window which had to wait for the TPS limit.
+    pub(crate) fn with_limiter_waits(mut self, limiter_waits: u64) -> Self {
+        self.limiter_waits = limiter_waits;
+        self
+    }
+
+    /// Transactions per second over the window, including those which returned an error.
+    pub(crate) fn total_tps(&self) -> f64 {
+        per_sec(self.success + self.error, self.elapsed)
+    }
+
+    /// Number of successful transactions in the window.
+    pub fn success_count(&self) -> u64 {
+        self.success
+    }
+
+    /// Number of transactions in the window which returned an error.
+    pub fn error_count(&self) -> u64 {
+        self.error
+    }
+
+    /// Number of rate limit rejections reported via
+    /// [`record_rate_limited()`](crate::record_rate_limited) in the window.
+    pub fn rate_limited_count(&self) -> u64 {
+        self.rate_limited
+    }
+
+    /// Fraction of transactions in the window which were rejected by rate limiting.
+    pub(crate) fn rate_limited_ratio(&self) -> f64 {
+        let total = self.success + self.error;
+        if total == 0 {
+            0.
+        } else {
+            (self.rate_limited as f64 / total as f64).min(1.)
+        }
+    }
+
+    /// Fraction of transactions in the window which had to wait for the TPS limit, i.e. which
+    /// were held back by the rate limiter rather than by the target.
+    pub(crate) fn limiter_wait_ratio(&self) -> f64 {
+        let total = self.success + self.error;
+        if total == 0 {
+            0.
+        } else {
+            (self.limiter_waits as f64 / total as f64).min(1.)
+        }
+    }
+
+    /// Longest `Retry-After` reported in the window.
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    pub(crate) fn populate_latencies(&mut self, dur: &[Duration]) {
         for latency in dur {
-            self.latency.insert(latency.as_secs_f64());
+            insert_latency(&mut self.latency, latency);
+
+            let nanos = latency.as_nanos();
+            let bucket = ((nanos as f64).log2() * MODE_BUCKETS_PER_DOUBLING).floor() as i32;
+            let (count, sum) = self.latency_buckets.entry(bucket).or_default();
+            *count += 1;
+            *sum += nanos;
+        }
+    }
+
+    pub(crate) fn populate_labeled_latencies(&mut self, label: &'static str, dur: &[Duration]) {
+        let digest = self
+            .labeled_latency
+            .entry(label)
+            .or_insert_with(default_tdigest);
+        for latency in dur {
+            insert_latency(digest, latency);
+        }
+    }
+
+    pub(crate) fn populate_slo_latencies(&mut self, slo: LatencySlo, dur: &[Duration]) {
+        let (_, digest) = self
+            .slo_latency
+            .entry(slo.name)
+            .or_insert_with(|| (slo, default_tdigest()));
+        for latency in dur {
+            insert_latency(digest, latency);
         }
     }
 
+    /// Latency of the transactions in the window at the given quantile.
     pub fn latency(&self, quantile: f64) -> Duration {
-        let secs = self.latency.quantile(quantile);
+        digest_latency(&self.latency, quantile)
+    }
 
-        // TODO: Unfortunately TDigest sometimes returns NaN which we need to filter for.
-        let secs = if secs.is_finite() {
-            secs
-        } else {
-            error!("NaN Latency Calculation. This is a known bug in Balter.");
-            0.
+    /// Mean latency of each mode of the window's latency distribution, if it is bimodal (e.g.
+    /// fast cache hits and slow misses), and otherwise empty.
+    ///
+    /// A single percentile such as p99 hides bimodality, so a latency goal or SLO may be
+    /// misleading for such a target. Detection is a simple gap heuristic over the latency
+    /// percentiles: the distribution is bimodal if the latency at least triples across 5
+    /// percentiles, with at least 5% of the Transactions on either side. Each mode is then the
+    /// mean of the latencies on its side of the gap.
+    ///
+    /// NOTE: This is a heuristic over a summary, not a statistical test. Modes closer together
+    /// than a factor of 3, rarer than 5% of the Transactions, or more than two of them are not
+    /// told apart, and an extremely heavy tail can be mistaken for a second mode. With few
+    /// Transactions in the window, the percentiles (and so the result) are noisy.
+    pub fn latency_modes(&self) -> Vec<Duration> {
+        let percentiles: Vec<f64> = (0..=100)
+            .map(|p| self.latency.quantile(p as f64 / 100.))
+            .collect();
+        if self.latency.is_empty() || percentiles.iter().any(|secs| !secs.is_finite()) {
+            return vec![];
+        }
+
+        let ratio = |p: usize| percentiles[p + MODE_GAP_WIDTH] / percentiles[p];
+        let split = (MODE_MIN_MASS..=100 - MODE_MIN_MASS - MODE_GAP_WIDTH)
+            .filter(|&p| percentiles[p] > 0. && ratio(p) >= MODE_GAP_RATIO)
+            .max_by(|&a, &b| ratio(a).total_cmp(&ratio(b)));
+
+        let Some(p) = split else {
+            return vec![];
         };
 
-        Duration::from_secs_f64(secs)
+        // NOTE: The digest's centroids may straddle the gap, so the mean of each mode is taken
+        // over the latency buckets instead, which are far narrower than any gap.
+        let threshold = (percentiles[p] * percentiles[p + MODE_GAP_WIDTH]).sqrt();
+        let (mut below, mut above) = ((0, 0), (0, 0));
+        for &(count, sum) in self.latency_buckets.values() {
+            let side = if (sum as f64 / count as f64) < threshold {
+                &mut below
+            } else {
+                &mut above
+            };
+            side.0 += count;
+            side.1 += sum;
+        }
+
+        if below.0 == 0 || above.0 == 0 {
+            return vec![];
+        }
+        [below, above]
+            .into_iter()
+            .map(|(count, sum)| Duration::from_nanos((sum / count as u128) as u64))
+            .collect()
+    }
+
+    /// Latency recorded via [`record_latency()`](crate::record_latency) under the given label,
+    /// at the given quantile.
+    ///
+    /// Returns `None` if nothing was recorded under the label in this window.
+    pub fn labeled_latency(&self, label: &str, quantile: f64) -> Option<Duration> {
+        self.labeled_latency
+            .get(label)
+            .map(|digest| digest_latency(digest, quantile))
+    }
+
+    pub(crate) fn slo_statistics(&self) -> HashMap<String, SloStatistics> {
+        self.slo_latencies()
+            .map(|(slo, achieved)| {
+                let stats = SloStatistics {
+                    target: slo.latency,
+                    quantile: slo.quantile,
+                    achieved,
+                };
+                (slo.name.to_string(), stats)
+            })
+            .collect()
+    }
+
+    pub(crate) fn variant_statistics(&self) -> HashMap<String, VariantStatistics> {
+        self.variants
+            .iter()
+            .map(|(name, variant)| {
+                let total = variant.success + variant.error;
+                let stats = VariantStatistics {
+                    actual_tps: variant.success as f64 / self.elapsed.as_secs_f64(),
+                    error_rate: if total == 0 {
+                        0.
+                    } else {
+                        variant.error as f64 / total as f64
+                    },
+                    latency_p50: digest_latency(&variant.latency, 0.5),
+                    latency_p90: digest_latency(&variant.latency, 0.9),
+                    latency_p99: digest_latency(&variant.latency, 0.99),
+                };
+                (name.to_string(), stats)
+            })
+            .collect()
+    }
+
+    /// Latency SLOs of the Transactions run in the window, along with the achieved latency at
+    /// each SLO's quantile.
+    pub(crate) fn slo_latencies(&self) -> impl Iterator<Item = (LatencySlo, Duration)> + '_ {
+        self.slo_latency
+            .values()
+            .map(|(slo, digest)| (*slo, digest_latency(digest, slo.quantile)))
     }
 }
 
+/// Latencies are stored in whole nanoseconds, so that each centroid's sum is exact.
+///
+/// NOTE: With fractional seconds, fusing many equal latencies (e.g. a fast cached endpoint) can
+/// round a centroid's mean outside of the recorded range, which breaks `TDigest::quantile()`'s
+/// interpolation (and trips its debug assertion).
+fn insert_latency(digest: &mut TDigest<K1>, latency: &Duration) {
+    digest.insert(latency.as_nanos() as f64);
+}
+
+fn digest_latency(digest: &TDigest<K1>, quantile: f64) -> Duration {
+    let nanos = digest.quantile(quantile);
+
+    // TODO: Unfortunately TDigest sometimes returns NaN which we need to filter for.
+    let nanos = if nanos.is_finite() {
+        nanos
+    } else {
+        error!("NaN Latency Calculation. This is a known bug in Balter.");
+        0.
+    };
+
+    Duration::from_nanos(nanos as u64)
+}
+
 impl fmt::Display for Measurement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -61,7 +366,449 @@ impl fmt::Display for Measurement {
     }
 }
 
+/// Registry of the custom measurements recorded during a Scenario.
+#[derive(Default)]
+pub(crate) struct CustomMeasurements {
+    inner: Mutex<HashMap<&'static str, CustomMeasurement>>,
+}
+
+impl CustomMeasurements {
+    pub fn record(&self, name: &'static str, value: f64) {
+        let Ok(mut inner) = self.inner.lock() else {
+            error!("Custom measurement registry is poisoned.");
+            return;
+        };
+
+        inner
+            .entry(name)
+            .or_insert_with(CustomMeasurement::new)
+            .record(value);
+    }
+
+    pub fn statistics(&self) -> HashMap<String, MeasurementStatistics> {
+        let Ok(inner) = self.inner.lock() else {
+            error!("Custom measurement registry is poisoned.");
+            return HashMap::new();
+        };
+
+        inner
+            .iter()
+            .map(|(name, measurement)| (name.to_string(), measurement.statistics()))
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        match self.inner.lock() {
+            Ok(mut inner) => inner.clear(),
+            Err(_) => error!("Custom measurement registry is poisoned."),
+        }
+    }
+}
+
+/// Errors of the Transactions run during a Scenario, counted by category (see
+/// `#[transaction(categorize = ...)]`).
+#[derive(Default)]
+pub(crate) struct ErrorCategories {
+    inner: Mutex<HashMap<&'static str, u64>>,
+}
+
+impl ErrorCategories {
+    pub fn record(&self, category: &'static str) {
+        let Ok(mut inner) = self.inner.lock() else {
+            error!("Error category registry is poisoned.");
+            return;
+        };
+
+        *inner.entry(category).or_default() += 1;
+    }
+
+    pub fn counts(&self) -> HashMap<String, u64> {
+        let Ok(inner) = self.inner.lock() else {
+            error!("Error category registry is poisoned.");
+            return HashMap::new();
+        };
+
+        inner
+            .iter()
+            .map(|(category, count)| (category.to_string(), *count))
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        match self.inner.lock() {
+            Ok(mut inner) => inner.clear(),
+            Err(_) => error!("Error category registry is poisoned."),
+        }
+    }
+}
+
+/// Latencies recorded during a sampling window, bucketed by label.
+#[derive(Default)]
+pub(crate) struct LabeledLatencies {
+    inner: Mutex<HashMap<&'static str, Arc<AtomicBucket<Duration>>>>,
+}
+
+impl LabeledLatencies {
+    pub fn record(&self, label: &'static str, latency: Duration) {
+        let bucket = {
+            let Ok(mut inner) = self.inner.lock() else {
+                error!("Labeled latency registry is poisoned.");
+                return;
+            };
+            inner.entry(label).or_default().clone()
+        };
+
+        bucket.push(latency);
+    }
+
+    pub fn collect_into(&self, measurement: &mut Measurement) {
+        let Ok(inner) = self.inner.lock() else {
+            error!("Labeled latency registry is poisoned.");
+            return;
+        };
+
+        for (label, bucket) in inner.iter() {
+            bucket.clear_with(|dur| measurement.populate_labeled_latencies(label, dur));
+        }
+    }
+}
+
+/// Latencies of the Transactions with a latency SLO during a sampling window.
+#[derive(Default)]
+pub(crate) struct SloLatencies {
+    inner: Mutex<HashMap<&'static str, SloBucket>>,
+}
+
+type SloBucket = (LatencySlo, Arc<AtomicBucket<Duration>>);
+
+impl SloLatencies {
+    pub fn record(&self, slo: LatencySlo, latency: Duration) {
+        let bucket = {
+            let Ok(mut inner) = self.inner.lock() else {
+                error!("SLO latency registry is poisoned.");
+                return;
+            };
+            let (_, bucket) = inner
+                .entry(slo.name)
+                .or_insert_with(|| (slo, Arc::new(AtomicBucket::new())));
+            bucket.clone()
+        };
+
+        bucket.push(latency);
+    }
+
+    pub fn collect_into(&self, measurement: &mut Measurement) {
+        let Ok(inner) = self.inner.lock() else {
+            error!("SLO latency registry is poisoned.");
+            return;
+        };
+
+        for (slo, bucket) in inner.values() {
+            bucket.clear_with(|dur| measurement.populate_slo_latencies(*slo, dur));
+        }
+    }
+}
+
+/// Transactions run within each [`variant()`](crate::variant) during a sampling window.
+#[derive(Default)]
+pub(crate) struct Variants {
+    inner: Mutex<HashMap<&'static str, Arc<VariantAtomics>>>,
+}
+
+#[derive(Default)]
+struct VariantAtomics {
+    success: AtomicU64,
+    error: AtomicU64,
+    latency: AtomicBucket<Duration>,
+}
+
+impl Variants {
+    pub fn record(&self, variant: &'static str, success: bool, latency: Duration) {
+        let atomics = {
+            let Ok(mut inner) = self.inner.lock() else {
+                error!("Variant registry is poisoned.");
+                return;
+            };
+            inner.entry(variant).or_default().clone()
+        };
+
+        if success {
+            atomics.success.fetch_add(1, Ordering::Relaxed);
+        } else {
+            atomics.error.fetch_add(1, Ordering::Relaxed);
+        }
+        atomics.latency.push(latency);
+    }
+
+    pub fn collect_into(&self, measurement: &mut Measurement) {
+        let Ok(inner) = self.inner.lock() else {
+            error!("Variant registry is poisoned.");
+            return;
+        };
+
+        for (name, atomics) in inner.iter() {
+            let mut variant = VariantMeasurement {
+                success: atomics.success.swap(0, Ordering::Relaxed),
+                error: atomics.error.swap(0, Ordering::Relaxed),
+                latency: default_tdigest(),
+            };
+            atomics.latency.clear_with(|dur| {
+                for latency in dur {
+                    insert_latency(&mut variant.latency, latency);
+                }
+            });
+            measurement.variants.insert(name, variant);
+        }
+    }
+}
+
+/// Rate limit rejections (e.g. HTTP 429) reported during a sampling window.
+#[derive(Default)]
+pub(crate) struct RateLimits {
+    rejected: AtomicU64,
+    /// Longest `Retry-After` reported, in milliseconds.
+    retry_after_ms: AtomicU64,
+}
+
+impl RateLimits {
+    pub fn record(&self, retry_after: Option<Duration>) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        if let Some(retry_after) = retry_after {
+            self.retry_after_ms
+                .fetch_max(retry_after.as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn collect_into(&self, measurement: &mut Measurement) {
+        measurement.rate_limited = self.rejected.swap(0, Ordering::Relaxed);
+        let retry_after_ms = self.retry_after_ms.swap(0, Ordering::Relaxed);
+        measurement.retry_after =
+            (retry_after_ms > 0).then(|| Duration::from_millis(retry_after_ms));
+    }
+}
+
+struct CustomMeasurement {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    digest: TDigest<K1>,
+}
+
+impl CustomMeasurement {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            sum: 0.,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            digest: default_tdigest(),
+        }
+    }
+
+    fn record(&mut self, value: f64) {
+        if !value.is_finite() {
+            error!("Ignoring non-finite custom measurement: {value}");
+            return;
+        }
+
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.digest.insert(value);
+    }
+
+    fn statistics(&self) -> MeasurementStatistics {
+        // NOTE: Only non-finite values may have been recorded, which are ignored.
+        if self.count == 0 {
+            return MeasurementStatistics::default();
+        }
+
+        MeasurementStatistics {
+            count: self.count,
+            mean: self.sum / self.count as f64,
+            min: self.min,
+            max: self.max,
+            p50: self.digest.quantile(0.5),
+            p90: self.digest.quantile(0.9),
+            p99: self.digest.quantile(0.99),
+        }
+    }
+}
+
 fn default_tdigest() -> TDigest<K1> {
     // TODO: Double-check these values
     TDigest::new(K1::new(10.), TDIGEST_BACKLOG_SIZE)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_measurements() {
+        let measurements = CustomMeasurements::default();
+        for value in 1..=100 {
+            measurements.record("foo", value as f64);
+        }
+        measurements.record("bar", 42.);
+
+        let stats = measurements.statistics();
+        assert_eq!(stats.len(), 2);
+
+        let foo = &stats["foo"];
+        assert_eq!(foo.count, 100);
+        assert_eq!(foo.min, 1.);
+        assert_eq!(foo.max, 100.);
+        assert!((foo.mean - 50.5).abs() < f64::EPSILON);
+        assert!((foo.p50 - 50.).abs() < 5.);
+
+        assert_eq!(stats["bar"].count, 1);
+        assert_eq!(stats["bar"].max, 42.);
+
+        let measurements = CustomMeasurements::default();
+        measurements.record("nan", f64::NAN);
+        let nan = &measurements.statistics()["nan"];
+        assert_eq!(nan.count, 0);
+        assert!([nan.mean, nan.min, nan.max, nan.p50, nan.p99]
+            .iter()
+            .all(|value| *value == 0.));
+    }
+
+    #[test]
+    fn test_measurement_counts() {
+        let measurement = Measurement::new(90, 10, Duration::from_secs(2));
+        assert_eq!(measurement.success_count(), 90);
+        assert_eq!(measurement.error_count(), 10);
+        assert_eq!(measurement.elapsed, Duration::from_secs(2));
+        assert_eq!(measurement.tps, 45.);
+        assert_eq!(measurement.total_tps(), 50.);
+        assert_eq!(measurement.error_rate, 0.1);
+
+        let empty = Measurement::new(0, 0, Duration::ZERO);
+        assert_eq!(empty.total_tps(), 0.);
+    }
+
+    #[test]
+    fn test_external_measurement() {
+        let latencies: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+        let measurement = Measurement::new(100, 0, Duration::from_secs(1))
+            .with_latencies(&latencies)
+            .with_rate_limits(5, Some(Duration::from_secs(2)));
+        let p50 = measurement.latency(0.5);
+        assert!(p50 >= Duration::from_millis(49) && p50 <= Duration::from_millis(51));
+        assert_eq!(measurement.rate_limited_count(), 5);
+
+        let sample = SampleData {
+            success: 1_200,
+            error: 800,
+            elapsed: Duration::from_secs(2),
+        };
+        let measurement = Measurement::from(sample);
+        assert_eq!(measurement.tps, sample.goodput());
+        assert_eq!(measurement.total_tps(), sample.tps());
+        assert_eq!(measurement.error_rate, 0.4);
+    }
+
+    #[test]
+    fn test_latency_modes() {
+        // NOTE: 80% cache hits around 1ms, and 20% misses around 50ms.
+        let latencies: Vec<_> = (0..1_000)
+            .map(|i| match i % 5 {
+                0 => Duration::from_micros(49_000 + i),
+                _ => Duration::from_micros(1_000 + i / 5),
+            })
+            .collect();
+        let mut measurement = Measurement::new(1_000, 0, Duration::from_secs(1));
+        measurement.populate_latencies(&latencies);
+
+        let modes = measurement.latency_modes();
+        assert_eq!(modes.len(), 2);
+        assert!(modes[0] > Duration::from_millis(1) && modes[0] < Duration::from_millis(2));
+        assert!(modes[1] > Duration::from_millis(49) && modes[1] < Duration::from_millis(50));
+
+        // NOTE: A long but continuous tail is not a second mode.
+        let latencies: Vec<_> = (0..1_000)
+            .map(|i| Duration::from_secs_f64(-(1. - i as f64 / 1_000.).ln() / 1_000.))
+            .collect();
+        let mut measurement = Measurement::new(1_000, 0, Duration::from_secs(1));
+        measurement.populate_latencies(&latencies);
+        assert!(measurement.latency_modes().is_empty());
+
+        let measurement = Measurement::new(0, 0, Duration::from_secs(1));
+        assert!(measurement.latency_modes().is_empty());
+    }
+
+    #[test]
+    fn test_labeled_latencies() {
+        let latencies = LabeledLatencies::default();
+        for ms in 1..=100 {
+            latencies.record(TTFB_LABEL, Duration::from_millis(ms));
+        }
+
+        let mut measurement = Measurement::new(100, 0, Duration::from_secs(1));
+        latencies.collect_into(&mut measurement);
+
+        let p50 = measurement.labeled_latency(TTFB_LABEL, 0.5).unwrap();
+        assert!(p50 > Duration::from_millis(45) && p50 < Duration::from_millis(55));
+        assert!(measurement.labeled_latency("transfer", 0.5).is_none());
+
+        // NOTE: Buckets are drained on collection, so each window starts empty.
+        let mut next = Measurement::new(0, 0, Duration::from_secs(1));
+        latencies.collect_into(&mut next);
+        assert!(next.labeled_latency(TTFB_LABEL, 0.5).is_none());
+    }
+
+    #[test]
+    fn test_slo_latencies() {
+        let search = LatencySlo {
+            name: "search",
+            latency: Duration::from_millis(200),
+            quantile: 0.9,
+        };
+        let health = LatencySlo {
+            name: "health",
+            latency: Duration::from_millis(10),
+            quantile: 0.99,
+        };
+
+        let latencies = SloLatencies::default();
+        for ms in 1..=100 {
+            latencies.record(search, Duration::from_millis(ms * 2));
+            latencies.record(health, Duration::from_millis(1));
+        }
+
+        let mut measurement = Measurement::new(200, 0, Duration::from_secs(1));
+        latencies.collect_into(&mut measurement);
+
+        let achieved: HashMap<_, _> = measurement
+            .slo_latencies()
+            .map(|(slo, latency)| (slo.name, latency))
+            .collect();
+        assert_eq!(achieved.len(), 2);
+        assert!(achieved["search"] > Duration::from_millis(170));
+        assert!(achieved["search"] < Duration::from_millis(190));
+        assert_eq!(achieved["health"], Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_variants() {
+        let variants = Variants::default();
+        for ms in 1..=100 {
+            variants.record("A", true, Duration::from_millis(ms));
+            variants.record("B", ms % 10 != 0, Duration::from_millis(ms * 2));
+        }
+
+        let mut measurement = Measurement::new(190, 10, Duration::from_secs(2));
+        variants.collect_into(&mut measurement);
+
+        let stats = measurement.variant_statistics();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats["A"].actual_tps, 50.);
+        assert_eq!(stats["A"].error_rate, 0.);
+        assert_eq!(stats["B"].actual_tps, 45.);
+        assert_eq!(stats["B"].error_rate, 0.1);
+        assert!(stats["B"].latency_p50 > stats["A"].latency_p50);
+    }
+}
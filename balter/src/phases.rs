@@ -0,0 +1,220 @@
+//! Running a Scenario as a sequence of named phases.
+use crate::scenario::ConfigurableScenario;
+use std::future::{Future, IntoFuture};
+use std::marker::PhantomData;
+use std::pin::Pin;
+
+/// A named phase, configuring a copy of the base Scenario.
+#[doc(hidden)]
+pub type Phase<S> = (String, Box<dyn FnOnce(S) -> S + Send>);
+
+#[doc(hidden)]
+pub type PhasesFuture<T> = Pin<Box<dyn Future<Output = Vec<(String, T)>> + Send>>;
+
+/// A single logical Scenario run as a sequence of named phases (e.g. "warmup", "soak" and
+/// "spike"), each with its own goals and controllers. Created with
+/// [`ConfigurableScenario::phased()`].
+///
+/// Each phase is configured from a copy of the base Scenario, so settings applied before
+/// `.phased()` (such as [`with_base_url()`](ConfigurableScenario::with_base_url)) apply to every
+/// phase. The phases run one after the other on the same set of Scenario tasks, so any setup
+/// (e.g. creating a client or connection pool) done at the start of the Scenario function is
+/// not repeated, and each phase starts at the concurrency the previous phase ended with.
+///
+/// Awaiting a `PhasedScenario` returns the statistics of each phase, in order.
+///
+/// NOTE: The base URL, metric labels, classifier and sampling interval are taken from the base
+/// Scenario, and setting them within a phase has no effect. The statistics of each phase only
+/// cover that phase after its warmup: custom measurements (see [`record()`](crate::record)),
+/// phase timings and error categories start afresh with every phase. A phase without a
+/// [`duration()`](ConfigurableScenario::duration) runs indefinitely, so every phase but the last
+/// should have one. If a task of the Scenario panics, the remaining phases are skipped.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let report = my_scenario()
+///         .with_base_url("http://localhost:3000")
+///         .phased()
+///         .phase("warmup", |s| s.tps(100).duration(Duration::from_secs(60)))
+///         .phase("soak", |s| s.tps(1_000).duration(Duration::from_secs(600)))
+///         .phase("spike", |s| s.tps(5_000).duration(Duration::from_secs(60)))
+///         .await;
+///
+///     for (phase, stats) in report {
+///         println!("{phase}: {stats}");
+///     }
+/// }
+///
+/// #[scenario]
+/// async fn my_scenario() {
+///     my_transaction().await;
+/// }
+///
+/// #[transaction]
+/// async fn my_transaction() -> Result<u32, String> {
+///     // Some request logic...
+///
+///     Ok(0)
+/// }
+/// ```
+pub struct PhasedScenario<S, T> {
+    scenario: S,
+    phases: Vec<Phase<S>>,
+    _output: PhantomData<fn() -> T>,
+}
+
+impl<S, T> PhasedScenario<S, T>
+where
+    S: ConfigurableScenario<T>,
+    T: Send,
+{
+    pub(crate) fn new(scenario: S) -> Self {
+        Self {
+            scenario,
+            phases: vec![],
+            _output: PhantomData,
+        }
+    }
+
+    /// Add a phase, which is run after all previously added phases.
+    ///
+    /// `configure` is given a copy of the base Scenario to set the goals of the phase on, in the
+    /// same way as for a standalone Scenario.
+    pub fn phase(mut self, name: &str, configure: impl FnOnce(S) -> S + Send + 'static) -> Self {
+        self.phases.push((name.to_string(), Box::new(configure)));
+        self
+    }
+}
+
+impl<S, T> IntoFuture for PhasedScenario<S, T>
+where
+    S: ConfigurableScenario<T>,
+    T: Send + 'static,
+{
+    type Output = Vec<(String, T)>;
+    type IntoFuture = PhasesFuture<T>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.scenario.run_phases(self.phases)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Scenario;
+    use crate::transaction::transaction_hook;
+    use balter_core::TransactionLabels;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const LABELS: TransactionLabels = TransactionLabels {
+        success: "test_phases_success",
+        error: "test_phases_error",
+        latency: "test_phases_latency",
+        slo: None,
+    };
+
+    #[tokio::test]
+    async fn test_phases_share_setup() {
+        let setups = Arc::new(AtomicUsize::new(0));
+        let scenario = {
+            let setups = setups.clone();
+            move || {
+                let setups = setups.clone();
+                async move {
+                    setups.fetch_add(1, Ordering::Relaxed);
+                    loop {
+                        let _ = transaction_hook(LABELS, async {
+                            tokio::time::sleep(Duration::from_millis(10)).await;
+                            Ok::<_, ()>(())
+                        })
+                        .await;
+                    }
+                }
+            }
+        };
+
+        let report = Scenario::new("test_phases_share_setup", scenario)
+            .phased()
+            .phase("fast", |s| s.tps(400).duration(Duration::from_secs(1)))
+            .phase("unconfigured", |s| s.duration(Duration::from_secs(1)))
+            .phase("slow", |s| s.tps(200).duration(Duration::from_secs(1)))
+            .await;
+
+        let names: Vec<_> = report.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["fast", "unconfigured", "slow"]);
+
+        let (_, fast) = &report[0];
+        let (_, slow) = &report[2];
+        assert_eq!(fast.goal_tps, 400);
+        assert_eq!(slow.goal_tps, 200);
+        assert_eq!(report[1].1.goal_tps, 0);
+
+        // NOTE: Concurrency never decreases, so the tasks of the first phase are all reused and
+        // only additional tasks are set up. The last tasks spawned may not have started yet.
+        assert!(slow.concurrency >= fast.concurrency);
+        assert!(setups.load(Ordering::Relaxed) <= slow.concurrency);
+    }
+
+    #[tokio::test]
+    async fn test_phases_slow_warmup() {
+        const SLOW: Duration = Duration::from_millis(50);
+
+        // NOTE: The target is slow until the measured phase lowers the concurrency to 4, after
+        // which Transactions complete without waiting at all. A latency of at least `SLOW` in
+        // the measured phase can only come from a warmup Transaction.
+        let fast = Arc::new(AtomicBool::new(false));
+        let scenario = {
+            let fast = fast.clone();
+            move || {
+                let fast = fast.clone();
+                async move {
+                    loop {
+                        let _ = transaction_hook(LABELS, async {
+                            if !fast.load(Ordering::Relaxed) {
+                                tokio::time::sleep(SLOW).await;
+                            }
+                            Ok::<_, ()>(())
+                        })
+                        .await;
+                    }
+                }
+            }
+        };
+
+        let report = Scenario::new("test_phases_slow_warmup", scenario)
+            .on_concurrency_change(move |concurrency| {
+                if concurrency == 4 {
+                    fast.store(true, Ordering::Relaxed);
+                }
+            })
+            .phased()
+            .phase("warmup", |s| {
+                s.tps(200)
+                    .at_concurrency(20)
+                    .duration(Duration::from_secs(1))
+            })
+            .phase("measured", |s| {
+                s.tps(200)
+                    .at_concurrency(4)
+                    .duration(Duration::from_secs(1))
+            })
+            .await;
+
+        let (_, warmup) = &report[0];
+        let (_, measured) = &report[1];
+        assert!(warmup.latency_p99 >= SLOW);
+        assert!(
+            measured.latency_p99 < SLOW,
+            "p99 of {:?}",
+            measured.latency_p99
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Live progress of a Scenario for interactive runs, see
+//! [`with_progress_bar()`](crate::scenario::ConfigurableScenario::with_progress_bar).
+use crate::measurement::Measurement;
+use balter_core::ScenarioConfig;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How often progress is logged when the output is not a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Progress of a single run (or phase) of a Scenario, updated once per sampling window.
+///
+/// Rendered as a live bar if stderr is a terminal, and otherwise (e.g. in CI) logged every
+/// `LOG_INTERVAL`, since the redraws of a bar only clutter a log file.
+pub(crate) struct Progress {
+    name: String,
+    duration: Option<Duration>,
+    start: Instant,
+    output: Output,
+}
+
+enum Output {
+    Bar(ProgressBar),
+    Log { last: Instant },
+}
+
+impl Progress {
+    pub fn new(config: &ScenarioConfig) -> Self {
+        let start = Instant::now();
+        let output = if std::io::stderr().is_terminal() {
+            Output::Bar(progress_bar(&config.name, config.duration))
+        } else {
+            Output::Log { last: start }
+        };
+
+        Self {
+            name: config.name.clone(),
+            duration: config.duration,
+            start,
+            output,
+        }
+    }
+
+    pub fn update(&mut self, measurement: &Measurement, goal_tps: NonZeroU32) {
+        let elapsed = self.start.elapsed();
+        let status = format!(
+            "{:.0} TPS (goal {goal_tps}), {:.2}% errors",
+            measurement.tps,
+            measurement.error_rate * 100.
+        );
+
+        match &mut self.output {
+            Output::Bar(bar) => {
+                bar.set_position(elapsed.as_secs());
+                bar.set_message(status);
+            }
+            Output::Log { last } => {
+                if last.elapsed() < LOG_INTERVAL {
+                    return;
+                }
+                *last = Instant::now();
+
+                let elapsed = humantime::format_duration(Duration::from_secs(elapsed.as_secs()));
+                match self.duration {
+                    Some(duration) => info!(
+                        "{}: {elapsed} of {}, {status}",
+                        self.name,
+                        humantime::format_duration(duration)
+                    ),
+                    None => info!("{}: {elapsed}, {status}", self.name),
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) {
+        if let Output::Bar(bar) = self.output {
+            bar.finish();
+        }
+    }
+}
+
+/// A bar over the Scenario's duration, or a spinner if it has none.
+fn progress_bar(name: &str, duration: Option<Duration>) -> ProgressBar {
+    let (bar, template) = match duration {
+        Some(duration) => (
+            ProgressBar::new(duration.as_secs()),
+            "{prefix} [{elapsed_precise}] {wide_bar} {pos}/{len}s {msg}",
+        ),
+        None => (
+            ProgressBar::new_spinner(),
+            "{prefix} {spinner} [{elapsed_precise}] {msg}",
+        ),
+    };
+
+    match ProgressStyle::with_template(template) {
+        Ok(style) => bar.set_style(style),
+        Err(err) => error!("Invalid progress bar template: {err}"),
+    }
+    bar.set_prefix(name.to_string());
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
@@ -0,0 +1,357 @@
+//! Replaying a recorded timeline of Transactions.
+use crate::hooks::Hooks;
+use crate::measurement::TTFB_LABEL;
+use crate::sampler::TaskAtomics;
+use crate::transaction::TRANSACTION_HOOK;
+use balter_core::{RunStatistics, ScenarioConfig};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+type ReplayTransaction = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("Unable to read the traffic timeline: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid traffic timeline on line {line}: {message}")]
+    Parse { line: usize, message: String },
+
+    #[error("No Transaction registered for `{0}` in the traffic timeline")]
+    UnknownTransaction(String),
+}
+
+/// Replay a recorded timeline of Transactions (e.g. from production traffic logs), starting each
+/// Transaction at its recorded offset.
+///
+/// Unlike a Scenario, the replay is open-loop: Transactions are started on schedule regardless of
+/// how many are still running, so a slow target sees the same arrival pattern as it did in
+/// production rather than a reduced one. There are no controllers involved, and the returned
+/// statistics cover the whole replay.
+///
+/// # File Format
+///
+/// Timelines are newline-delimited, with one Transaction per line in the form
+/// `<offset_ms>,<transaction_name>`, where the offset is the number of milliseconds since the
+/// start of the replay. Empty lines and lines starting with `#` are ignored, and lines need not
+/// be in order.
+///
+/// ```text
+/// # offset_ms,transaction_name
+/// 0,get_user
+/// 12,get_user
+/// 15,checkout
+/// ```
+///
+/// Each name is mapped to a Transaction with [`transaction()`](Self::transaction).
+///
+/// NOTE: `concurrency` is not meaningful for an open-loop replay and is left as `0`, and
+/// `goal_tps` is the average TPS of the recorded timeline. Transactions still running when the
+/// timeline ends are awaited, so the replay can take longer than the timeline itself.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use balter::TrafficReplay;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), balter::ReplayError> {
+///     let stats = TrafficReplay::from_file("traffic.log")?
+///         .transaction("get_user", get_user)
+///         .transaction("checkout", checkout)
+///         .run()
+///         .await?;
+///
+///     println!("{stats}");
+///     Ok(())
+/// }
+///
+/// #[transaction]
+/// async fn get_user() -> Result<(), String> {
+///     // Some request logic...
+///     Ok(())
+/// }
+///
+/// #[transaction]
+/// async fn checkout() -> Result<(), String> {
+///     // Some request logic...
+///     Ok(())
+/// }
+/// ```
+pub struct TrafficReplay {
+    timeline: Vec<(Duration, String)>,
+    transactions: HashMap<String, ReplayTransaction>,
+}
+
+impl TrafficReplay {
+    /// Parse a timeline in the format described [above](Self#file-format).
+    pub fn parse(timeline: &str) -> Result<Self, ReplayError> {
+        let mut parsed = vec![];
+        for (idx, line) in timeline.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parse_error = |message: &str| ReplayError::Parse {
+                line: idx + 1,
+                message: message.to_string(),
+            };
+
+            let (offset, name) = line
+                .split_once(',')
+                .ok_or_else(|| parse_error("expected `<offset_ms>,<transaction_name>`"))?;
+            let offset: u64 = offset
+                .trim()
+                .parse()
+                .map_err(|_| parse_error("offset must be a whole number of milliseconds"))?;
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(parse_error("missing transaction name"));
+            }
+
+            parsed.push((Duration::from_millis(offset), name.to_string()));
+        }
+        parsed.sort_by_key(|(offset, _)| *offset);
+
+        Ok(Self {
+            timeline: parsed,
+            transactions: HashMap::new(),
+        })
+    }
+
+    /// Read and parse a timeline file, see [`parse()`](Self::parse).
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Run `func` for each entry in the timeline with the given name.
+    ///
+    /// Any return value of `func` is discarded, so `#[transaction]` functions which take no
+    /// arguments can be passed directly.
+    pub fn transaction<T, F>(mut self, name: &str, func: T) -> Self
+    where
+        T: Fn() -> F + Send + Sync + 'static,
+        F: Future + Send + 'static,
+    {
+        self.transactions.insert(
+            name.to_string(),
+            Arc::new(move || {
+                let fut = func();
+                Box::pin(async move {
+                    fut.await;
+                })
+            }),
+        );
+        self
+    }
+
+    /// Length of the recorded timeline, i.e. the offset of the last Transaction.
+    pub fn duration(&self) -> Duration {
+        self.timeline
+            .last()
+            .map(|(offset, _)| *offset)
+            .unwrap_or_default()
+    }
+
+    /// Run the replay, returning the statistics of all Transactions in the timeline.
+    ///
+    /// Returns an error before starting if any name in the timeline has no Transaction.
+    pub async fn run(self) -> Result<RunStatistics, ReplayError> {
+        crate::scenario::require_tokio_runtime();
+
+        let timeline = self
+            .timeline
+            .iter()
+            .map(|(offset, name)| match self.transactions.get(name) {
+                Some(func) => Ok((*offset, func.clone())),
+                None => Err(ReplayError::UnknownTransaction(name.clone())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let goal_tps = timeline.len() as f64 / self.duration().as_secs_f64().max(1.);
+        info!(
+            "Replaying {} Transactions over {:?}",
+            timeline.len(),
+            self.duration()
+        );
+
+        let config = ScenarioConfig::new("traffic_replay");
+        let atomics = TaskAtomics::new(&config, &Hooks::default(), NonZeroU32::MAX);
+
+        let start = Instant::now();
+        let mut tasks = JoinSet::new();
+        for (offset, func) in timeline {
+            tokio::time::sleep_until(start + offset).await;
+            tasks.spawn(TRANSACTION_HOOK.scope(atomics.clone_to_transaction_data(), func()));
+        }
+
+        while let Some(res) = tasks.join_next().await {
+            if let Err(err) = res {
+                error!("Replayed Transaction failed: {err}");
+            }
+        }
+
+        let measurement = atomics.collect(start.elapsed());
+        let latency_modes = measurement.latency_modes();
+        Ok(RunStatistics {
+            goal_tps: goal_tps.ceil() as u32,
+            actual_tps: measurement.tps,
+            latency_p50: measurement.latency(0.5),
+            latency_p90: measurement.latency(0.9),
+            latency_p95: measurement.latency(0.95),
+            latency_p99: measurement.latency(0.99),
+            latency_multimodal: !latency_modes.is_empty(),
+            latency_modes,
+            error_rate: measurement.error_rate,
+            ttfb_p50: measurement.labeled_latency(TTFB_LABEL, 0.5),
+            ttfb_p99: measurement.labeled_latency(TTFB_LABEL, 0.99),
+            slos: measurement.slo_statistics(),
+            variants: measurement.variant_statistics(),
+            tps_goal_met: true,
+            measurements: atomics.custom_measurements(),
+            phases: atomics.phase_timings(),
+            error_categories: atomics.error_categories(),
+            ..Default::default()
+        })
+    }
+}
+
+impl fmt::Debug for TrafficReplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrafficReplay")
+            .field("timeline", &self.timeline)
+            .field(
+                "transactions",
+                &self.transactions.keys().collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::transaction_hook;
+    use balter_core::TransactionLabels;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const LABELS: TransactionLabels = TransactionLabels {
+        success: "test_replay_success",
+        error: "test_replay_error",
+        latency: "test_replay_latency",
+        slo: None,
+    };
+
+    #[test]
+    fn test_parse() {
+        let replay = TrafficReplay::parse(
+            "# offset_ms,transaction_name\n\
+            20, checkout\n\
+            \n\
+            0,get_user\n\
+            10,get_user\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            replay.timeline,
+            [
+                (Duration::from_millis(0), "get_user".to_string()),
+                (Duration::from_millis(10), "get_user".to_string()),
+                (Duration::from_millis(20), "checkout".to_string()),
+            ]
+        );
+        assert_eq!(replay.duration(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = TrafficReplay::parse("0,get_user\nget_user\n").unwrap_err();
+        assert!(matches!(err, ReplayError::Parse { line: 2, .. }));
+
+        let err = TrafficReplay::parse("-5,get_user").unwrap_err();
+        assert!(matches!(err, ReplayError::Parse { line: 1, .. }));
+
+        let err = TrafficReplay::parse("5,").unwrap_err();
+        assert!(matches!(err, ReplayError::Parse { line: 1, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_transaction() {
+        let err = TrafficReplay::parse("0,get_user")
+            .unwrap()
+            .run()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ReplayError::UnknownTransaction(name) if name == "get_user"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_schedule() {
+        let started = Arc::new(std::sync::Mutex::new(vec![]));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let transaction = |name: &'static str| {
+            let started = started.clone();
+            let calls = calls.clone();
+            move || {
+                let started = started.clone();
+                let calls = calls.clone();
+                async move {
+                    started.lock().unwrap().push((name, Instant::now()));
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    transaction_hook(LABELS, async {
+                        // NOTE: Longer than the gaps in the timeline, so the replay must not wait
+                        // for a Transaction to complete before starting the next.
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        if name == "checkout" {
+                            Err(())
+                        } else {
+                            Ok(())
+                        }
+                    })
+                    .await
+                }
+            }
+        };
+
+        let start = Instant::now();
+        let stats = TrafficReplay::parse("0,get_user\n10,get_user\n40,checkout\n1000,get_user")
+            .unwrap()
+            .transaction("get_user", transaction("get_user"))
+            .transaction("checkout", transaction("checkout"))
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+        let offsets: Vec<_> = started
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, at)| (*name, (*at - start).as_millis()))
+            .collect();
+        assert_eq!(
+            offsets,
+            [
+                ("get_user", 0),
+                ("get_user", 10),
+                ("checkout", 40),
+                ("get_user", 1000)
+            ]
+        );
+        assert_eq!(stats.error_rate, 0.25);
+    }
+}
@@ -0,0 +1,328 @@
+//! Persisting the results of a Scenario to a time-series database (requires the `influxdb`
+//! feature), see [`persist_to()`](crate::scenario::ConfigurableScenario::persist_to).
+use balter_core::{RunStatistics, SampleData};
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Environment variables checked, in order, for the git SHA the results are tagged with.
+const GIT_SHA_VARS: [&str; 3] = ["BALTER_GIT_SHA", "GITHUB_SHA", "CI_COMMIT_SHA"];
+
+/// Environment variable holding the API token sent with each write, if set.
+const TOKEN_VAR: &str = "INFLUXDB_TOKEN";
+
+#[derive(Debug, Error)]
+pub(crate) enum ResultsError {
+    #[error("write to {url} failed: {source}")]
+    Request { url: String, source: reqwest::Error },
+    #[error("{url} rejected the write with {status}: {body}")]
+    Rejected {
+        url: String,
+        status: reqwest::StatusCode,
+        body: String,
+    },
+}
+
+/// Write the results of a run to the InfluxDB write URL `dsn`, logging rather than failing if
+/// they can't be written.
+pub(crate) async fn persist(dsn: &str, name: &str, phase: Option<&str>, stats: &RunStatistics) {
+    let tags = Tags::new(name, phase);
+    let body = line_protocol(&tags, stats, SystemTime::now());
+
+    match write(dsn, body).await {
+        Ok(()) => debug!("Persisted the results of {name} to {dsn}"),
+        Err(err) => error!("Failed to persist the results of {name}: {err}"),
+    }
+}
+
+async fn write(url: &str, body: String) -> Result<(), ResultsError> {
+    let request = |source| ResultsError::Request {
+        url: url.to_string(),
+        source,
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(WRITE_TIMEOUT)
+        .build()
+        .map_err(request)?;
+    let mut req = client.post(url).body(body);
+    if let Ok(token) = std::env::var(TOKEN_VAR) {
+        req = req.header(reqwest::header::AUTHORIZATION, format!("Token {token}"));
+    }
+
+    let res = req.send().await.map_err(request)?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(ResultsError::Rejected {
+            url: url.to_string(),
+            status,
+            body: res.text().await.unwrap_or_default(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Tags identifying the rows of a run.
+struct Tags {
+    scenario: String,
+    phase: Option<String>,
+    git_sha: Option<String>,
+}
+
+impl Tags {
+    fn new(name: &str, phase: Option<&str>) -> Self {
+        Self {
+            scenario: name.to_string(),
+            phase: phase.map(str::to_string),
+            git_sha: GIT_SHA_VARS
+                .iter()
+                .find_map(|var| std::env::var(var).ok())
+                .filter(|sha| !sha.is_empty()),
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        let _ = write!(out, ",scenario={}", escape_tag(&self.scenario));
+        if let Some(phase) = &self.phase {
+            let _ = write!(out, ",phase={}", escape_tag(phase));
+        }
+        if let Some(sha) = &self.git_sha {
+            let _ = write!(out, ",git_sha={}", escape_tag(sha));
+        }
+    }
+}
+
+/// Rows of a run in the InfluxDB line protocol: a `balter_run` row with the final statistics,
+/// and a `balter_sample` row for each raw sampling window if `collect_raw_samples()` is set.
+///
+/// NOTE: Raw windows carry no timestamps of their own, so they are timestamped by counting back
+/// their elapsed time from the end of the run.
+fn line_protocol(tags: &Tags, stats: &RunStatistics, end: SystemTime) -> String {
+    let mut out = String::new();
+
+    out.push_str("balter_run");
+    tags.write(&mut out);
+    let mut fields = Fields::default();
+    fields.int("concurrency", stats.concurrency as u64);
+    fields.int("goal_tps", stats.goal_tps as u64);
+    fields.float("actual_tps", stats.actual_tps);
+    fields.float("total_tps", stats.total_tps);
+    fields.duration("latency_p50", stats.latency_p50);
+    fields.duration("latency_p90", stats.latency_p90);
+    fields.duration("latency_p95", stats.latency_p95);
+    fields.duration("latency_p99", stats.latency_p99);
+    fields.float("error_rate", stats.error_rate);
+    fields.bool("tps_limited", stats.tps_limited);
+    fields.bool("tps_goal_met", stats.tps_goal_met);
+    if let Some(ttfb) = stats.ttfb_p50 {
+        fields.duration("ttfb_p50", ttfb);
+    }
+    if let Some(ttfb) = stats.ttfb_p99 {
+        fields.duration("ttfb_p99", ttfb);
+    }
+    fields.bool("generator_saturated", stats.generator_saturated);
+    fields.bool(
+        "generator_resource_exhausted",
+        stats.generator_resource_exhausted,
+    );
+    fields.bool("concurrency_capped", stats.concurrency_capped);
+    fields.float("rate_limiter_accuracy", stats.rate_limiter_accuracy);
+    fields.bool("capped", stats.capped);
+    fields.bool("failed", stats.failed);
+    fields.int("panicked_tasks", stats.panicked_tasks as u64);
+    fields.bool("skipped", stats.skipped);
+    if let Some(concurrency) = stats.breaking_concurrency {
+        fields.int("breaking_concurrency", concurrency as u64);
+    }
+    let _ = writeln!(out, " {} {}", fields.0, timestamp(end));
+
+    let samples = stats.raw_samples.as_deref().unwrap_or_default();
+    let mut sample_end = end;
+    let mut rows: Vec<String> = samples
+        .iter()
+        .rev()
+        .map(|sample| {
+            let row = sample_row(tags, sample, sample_end);
+            sample_end = sample_end.checked_sub(sample.elapsed).unwrap_or(UNIX_EPOCH);
+            row
+        })
+        .collect();
+    rows.reverse();
+    out.extend(rows);
+
+    out
+}
+
+fn sample_row(tags: &Tags, sample: &SampleData, end: SystemTime) -> String {
+    let mut row = "balter_sample".to_string();
+    tags.write(&mut row);
+
+    let mut fields = Fields::default();
+    fields.int("success", sample.success);
+    fields.int("error", sample.error);
+    fields.duration("elapsed", sample.elapsed);
+    fields.float("tps", sample.tps());
+    fields.float("goodput", sample.goodput());
+    let _ = writeln!(row, " {} {}", fields.0, timestamp(end));
+    row
+}
+
+/// Field set of a row, with durations in (fractional) seconds.
+#[derive(Default)]
+struct Fields(String);
+
+impl Fields {
+    fn push(&mut self, key: &str, value: std::fmt::Arguments) {
+        if !self.0.is_empty() {
+            self.0.push(',');
+        }
+        let _ = write!(self.0, "{key}={value}");
+    }
+
+    fn int(&mut self, key: &str, value: u64) {
+        self.push(key, format_args!("{value}i"));
+    }
+
+    fn float(&mut self, key: &str, value: f64) {
+        // NOTE: The line protocol has no representation for NaN or infinity.
+        if value.is_finite() {
+            self.push(key, format_args!("{value:?}"));
+        }
+    }
+
+    fn duration(&mut self, key: &str, value: Duration) {
+        self.float(key, value.as_secs_f64());
+    }
+
+    fn bool(&mut self, key: &str, value: bool) {
+        self.push(key, format_args!("{value}"));
+    }
+}
+
+fn timestamp(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn tags() -> Tags {
+        Tags {
+            scenario: "checkout flow".to_string(),
+            phase: None,
+            git_sha: Some("4f2a9c1".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_line_protocol() {
+        let stats = RunStatistics {
+            concurrency: 12,
+            goal_tps: 500,
+            actual_tps: 498.5,
+            latency_p99: Duration::from_millis(20),
+            raw_samples: Some(vec![
+                SampleData {
+                    success: 490,
+                    error: 10,
+                    elapsed: Duration::from_secs(1),
+                },
+                SampleData {
+                    success: 500,
+                    error: 0,
+                    elapsed: Duration::from_secs(1),
+                },
+            ]),
+            ..Default::default()
+        };
+        let end = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let lines = line_protocol(&tags(), &stats, end);
+        let lines: Vec<_> = lines.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(
+            "balter_run,scenario=checkout\\ flow,git_sha=4f2a9c1 concurrency=12i,goal_tps=500i,actual_tps=498.5,"
+        ));
+        assert!(lines[0].contains(",latency_p99=0.02,"));
+        assert!(!lines[0].contains("ttfb_p50"));
+        assert!(lines[0].ends_with(" 1700000000000000000"));
+        assert_eq!(
+            lines[1],
+            "balter_sample,scenario=checkout\\ flow,git_sha=4f2a9c1 success=490i,error=10i,elapsed=1.0,tps=500.0,goodput=490.0 1699999999000000000"
+        );
+        assert!(lines[2].ends_with(" 1700000000000000000"));
+    }
+
+    #[tokio::test]
+    async fn test_persist() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/write?db=balter", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            stream.read_line(&mut request_line).await.unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                stream.read_line(&mut header).await.unwrap();
+                let header = header.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            stream.read_exact(&mut body).await.unwrap();
+            stream
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+
+            (request_line, String::from_utf8(body).unwrap())
+        });
+
+        let stats = RunStatistics {
+            goal_tps: 200,
+            ..Default::default()
+        };
+        write(&url, line_protocol(&tags(), &stats, SystemTime::now()))
+            .await
+            .unwrap();
+
+        let (request_line, body) = server.await.unwrap();
+        assert!(request_line.starts_with("POST /write?db=balter "));
+        assert_eq!(body.lines().count(), 1);
+        assert!(body.starts_with("balter_run,scenario=checkout\\ flow,git_sha=4f2a9c1 "));
+        assert!(body.contains("goal_tps=200i"));
+    }
+}
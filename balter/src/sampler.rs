@@ -1,21 +1,101 @@
 mod base_sampler;
+mod count_window;
+#[cfg(feature = "deterministic")]
+mod deterministic;
+mod generator_monitor;
+mod limiter_accuracy;
+#[cfg(feature = "metrics")]
+mod live_metrics;
 mod outlier_detection;
+mod shedding;
 mod task_atomics;
 mod timer;
+mod warmup;
 
+pub(crate) use base_sampler::BaseSampler;
+#[cfg(feature = "test-util")]
+pub use base_sampler::MetricsProbe;
+pub(crate) use count_window::{CountWindow, WindowSlot};
+pub(crate) use task_atomics::TaskAtomics;
+
+use crate::controllers::clamp_tps;
+use crate::hooks::Hooks;
 use crate::measurement::Measurement;
+use balter_core::{MeasurementStatistics, ScenarioConfig, Weighting};
+use generator_monitor::GeneratorMonitor;
+use limiter_accuracy::LimiterAccuracy;
+use shedding::ConcurrencyShedder;
+use std::collections::HashMap;
 use std::future::Future;
 use std::num::NonZeroU32;
+use std::time::Duration;
 #[allow(unused)]
 use tracing::{debug, error, info, trace, warn};
+use warmup::Warmup;
+
+/// Run `f` with the RNG used by Balter's tests: seeded under the `deterministic` feature so
+/// that failing runs can be replayed exactly.
+#[cfg(test)]
+pub(crate) fn with_test_rng<R>(f: impl FnOnce(&mut dyn rand::RngCore) -> R) -> R {
+    #[cfg(feature = "deterministic")]
+    {
+        use rand::{rngs::SmallRng, SeedableRng};
+        use std::cell::RefCell;
+
+        thread_local! {
+            static RNG: RefCell<SmallRng> =
+                RefCell::new(SmallRng::seed_from_u64(DETERMINISTIC_SEED));
+        }
+        RNG.with(|rng| f(&mut *rng.borrow_mut()))
+    }
+
+    #[cfg(not(feature = "deterministic"))]
+    f(&mut rand::thread_rng())
+}
 
+/// Seed of Balter's RNGs under the `deterministic` feature.
+#[cfg(feature = "deterministic")]
+pub(crate) const DETERMINISTIC_SEED: u64 = 0xba17e5;
 const MIN_SAMPLES: usize = 5;
 const MAX_RETRIES: usize = 4;
+/// Fraction of the goal TPS within which the goal counts as reached, unless configured with
+/// `tps_tolerance()`.
+const DEFAULT_TPS_TOLERANCE: f64 = 0.02;
+/// Gain in TPS from added concurrency, relative to the TPS per task, below which the Scenario is
+/// TPS limited, unless configured with `underpowered_slope_threshold()`.
+const DEFAULT_UNDERPOWERED_SLOPE: f64 = 0.1;
 
 pub(crate) struct Sampler<T> {
     sampler: base_sampler::BaseSampler<T>,
     concurrency_history: Vec<(usize, f64)>,
     tps_limited: Option<(usize, NonZeroU32)>,
+    /// Normalized slopes which marked the Scenario as TPS limited, see `detect_zero_slope()`.
+    underpowered_evidence: Vec<f64>,
+    /// Concurrency the Scenario is pinned at, see `at_concurrency()`.
+    fixed_concurrency: Option<usize>,
+    monitor: GeneratorMonitor,
+    generator_saturated: bool,
+    /// Transactions which failed as the load generator ran out of local resources, as of the
+    /// last window.
+    resource_exhausted: u64,
+    /// Concurrency at which the load generator ran out of local resources, which it isn't raised
+    /// past. NOTE: Not reset between phases, since the limits of the machine don't change.
+    resource_ceiling: Option<usize>,
+    limiter_accuracy: LimiterAccuracy,
+    /// NOTE: Not reset between phases, since the tasks of the Scenario are already warm.
+    warmup: Warmup,
+    /// Discard the aggregated statistics at the end of the next window, see `sample()`.
+    reset_pending: bool,
+    last_measurement: Option<Measurement>,
+    last_sample_set: Vec<Measurement>,
+    smoothing: Option<f64>,
+    weighting: Weighting,
+    shedder: Option<ConcurrencyShedder>,
+    tps_tolerance: f64,
+    underpowered_slope: f64,
+    transactions: u64,
+    peer_transactions: u64,
+    max_transactions: Option<u64>,
 }
 
 impl<T, F> Sampler<T>
@@ -23,46 +103,112 @@ where
     T: Fn() -> F + Send + Sync + 'static + Clone,
     F: Future<Output = ()> + Send,
 {
-    pub async fn new(name: &str, scenario: T, tps_limit: NonZeroU32, concurrency: usize) -> Self {
-        let mut sampler = base_sampler::BaseSampler::new(name, scenario, tps_limit).await;
-        sampler.set_concurrency(concurrency);
+    pub async fn new(
+        config: &ScenarioConfig,
+        hooks: &Hooks,
+        scenario: T,
+        tps_limit: NonZeroU32,
+    ) -> Self {
+        let mut sampler = base_sampler::BaseSampler::new(config, hooks, scenario, tps_limit).await;
+        sampler.set_concurrency(running_concurrency(config.starting_concurrency(tps_limit)));
         Self {
             sampler,
             concurrency_history: vec![],
             tps_limited: None,
+            underpowered_evidence: vec![],
+            fixed_concurrency: config.fixed_concurrency,
+            monitor: GeneratorMonitor::new(),
+            generator_saturated: false,
+            resource_exhausted: 0,
+            resource_ceiling: None,
+            limiter_accuracy: LimiterAccuracy::new(config),
+            warmup: Warmup::new(),
+            reset_pending: false,
+            last_measurement: None,
+            last_sample_set: vec![],
+            smoothing: config.tps_smoothing,
+            weighting: config.sample_weighting,
+            shedder: shedder(config),
+            tps_tolerance: tps_tolerance(config),
+            underpowered_slope: underpowered_slope(config),
+            transactions: 0,
+            peer_transactions: 0,
+            max_transactions: config.max_transactions,
         }
     }
 
     pub async fn sample(&mut self) -> (bool, Measurement) {
         let mut retries = 0;
         let mut prev = vec![];
-        loop {
-            let measurement = self.sampler.sample().await;
-            prev.push(measurement.clone());
+        let res = loop {
+            let mut measurement = self.sampler.sample().await;
+            self.monitor.sample();
+            self.check_resource_exhausted();
+            self.limiter_accuracy
+                .observe(&measurement, self.sampler.tps_limit());
+            self.last_measurement = Some(measurement.clone());
+            // NOTE: The aggregated statistics would otherwise include the Transactions of the
+            // ramp-up (or of the previous phase). They are discarded a window after the
+            // boundary, so that the Transactions still in flight at the boundary are dropped
+            // along with that window's, since they can't be told apart.
+            if self.reset_pending {
+                self.sampler.reset_statistics();
+                self.reset_pending = false;
+            }
+            let warming_up = !self.warmup.is_done();
+            prev.extend(self.warmup.admit(measurement.clone()));
+            if warming_up && self.warmup.is_done() {
+                self.reset_pending = true;
+            }
+
+            if self.sampler.has_failed_tasks() {
+                break (false, measurement);
+            }
+
+            // NOTE: Checked for every window rather than every sample, to limit overshooting.
+            self.transactions += measurement.success_count() + measurement.error_count();
+            if self.reached_max_transactions() {
+                break (false, measurement);
+            }
 
             if prev.len() < MIN_SAMPLES {
                 continue;
             }
 
-            let stats = calculate_stats(&prev);
+            let stats = calculate_stats(&prev, self.smoothing, self.weighting);
             trace!("Stats: {stats:?}");
 
             // Check if the statistics have stabilized, if not we retry, and if
             // we have retried too many times we note with a warning.
             // TODO: Would be nice to have adaptable interval here.
             if stats.outlier_count > 0 || stats.std_percent() > 0.25 {
-                prev.clear();
                 retries += 1;
 
+                // NOTE: Once out of retries, the noisy set is still used rather than discarded.
                 if retries > MAX_RETRIES {
                     warn!("Significant statistical noise in measurements.");
                 } else {
+                    prev.clear();
                     continue;
                 }
             }
 
-            if !self.check_underpowered() {
+            if self.fixed_concurrency.is_some() {
+                self.check_concurrency_limited(&prev);
+            } else if self.check_queueing(stats, &prev) {
+                // NOTE: Shedding concurrency changes the TPS per task, so the history used to
+                // detect being TPS limited no longer applies.
+                self.concurrency_history.clear();
+            } else if !self.check_underpowered() {
                 self.adjust_concurrency(stats);
+            } else {
+                self.check_generator_saturated();
+            }
+
+            // NOTE: With smoothing (or weighting), the controllers are fed the smoothed TPS rather
+            // than that of the latest window.
+            if self.smoothing.is_some() || self.weighting != Weighting::Uniform {
+                measurement.tps = stats.mean;
             }
 
             if self.at_goal(stats) {
@@ -70,45 +216,152 @@ where
             } else {
                 break (false, measurement);
             }
+        };
+
+        self.last_sample_set = prev;
+        res
+    }
+
+    /// Reset the per-run state for the next phase of a Scenario, keeping the running tasks (and
+    /// their concurrency) as-is.
+    pub fn start_phase(&mut self, config: &ScenarioConfig) {
+        self.reset_pending = true;
+        self.concurrency_history.clear();
+        self.tps_limited = None;
+        self.underpowered_evidence.clear();
+        self.fixed_concurrency = config.fixed_concurrency;
+        if let Some(concurrency) = self.fixed_concurrency {
+            self.sampler
+                .set_concurrency(running_concurrency(concurrency));
         }
+        // NOTE: Unlike the aggregated statistics, the latencies of the Transactions in flight at
+        // the boundary can be told apart, so they are dropped right away.
+        self.sampler.reset_latencies();
+        self.generator_saturated = false;
+        self.limiter_accuracy = LimiterAccuracy::new(config);
+        self.last_measurement = None;
+        self.last_sample_set.clear();
+        self.smoothing = config.tps_smoothing;
+        self.weighting = config.sample_weighting;
+        self.shedder = shedder(config);
+        self.tps_tolerance = tps_tolerance(config);
+        self.underpowered_slope = underpowered_slope(config);
+        self.transactions = 0;
+        self.peer_transactions = 0;
+        self.max_transactions = config.max_transactions;
     }
 
     pub fn set_tps_limit(&mut self, tps_limit: NonZeroU32) {
         self.sampler.set_tps_limit(tps_limit);
     }
 
-    pub fn shutdown(self) -> SamplerStats {
-        let concurrency = self.sampler.concurrency();
-        let tps_limit = self.sampler.tps_limit();
-        self.sampler.shutdown();
+    /// Take a single measurement at the given TPS limit, without adjusting concurrency.
+    pub async fn probe(&mut self, tps_limit: NonZeroU32) -> Measurement {
+        let prev_limit = self.sampler.tps_limit();
+        self.sampler.set_tps_limit(tps_limit);
+        let measurement = self.sampler.sample().await;
+        self.sampler.set_tps_limit(prev_limit);
+        measurement
+    }
+
+    /// Most recent measurement, regardless of whether the statistics had stabilized.
+    pub fn last_measurement(&self) -> Option<&Measurement> {
+        self.last_measurement.as_ref()
+    }
 
+    /// Measurement windows which the most recent [`sample()`](Self::sample) was based on.
+    pub fn last_sample_set(&self) -> &[Measurement] {
+        &self.last_sample_set
+    }
+
+    pub fn stats(&self) -> SamplerStats {
         SamplerStats {
-            tps_limit,
-            concurrency,
+            tps_limit: self.sampler.tps_limit(),
+            concurrency: self.sampler.concurrency(),
             tps_limited: self.tps_limited.is_some(),
+            underpowered_evidence: self.underpowered_evidence.clone(),
+            generator_saturated: self.generator_saturated,
+            generator_resource_exhausted: self.resource_ceiling.is_some(),
+            concurrency_capped: self.sampler.concurrency_capped(),
+            rate_limiter_accuracy: self.limiter_accuracy.accuracy(),
+            measurements: self.sampler.custom_measurements(),
+            phases: self.sampler.phase_timings(),
+            error_categories: self.sampler.error_categories(),
         }
     }
 
+    pub async fn shutdown(self) {
+        self.sampler.shutdown().await;
+    }
+
+    /// Take the connection diagnostics collected since the last call, see `WarmupReport`.
+    #[cfg(feature = "reqwest")]
+    pub fn warmup_report(&self) -> crate::WarmupReport {
+        self.sampler.warmup_report()
+    }
+
     pub fn tps_limit(&self) -> NonZeroU32 {
         self.sampler.tps_limit()
     }
 
+    pub fn concurrency(&self) -> usize {
+        self.sampler.concurrency()
+    }
+
+    /// Number of Transactions run so far (in this phase).
+    #[cfg(feature = "rt")]
+    pub fn transactions(&self) -> u64 {
+        self.transactions
+    }
+
+    /// Set the number of Transactions run by peers, which count towards `max_transactions`.
+    #[cfg(feature = "rt")]
+    pub fn set_peer_transactions(&mut self, transactions: u64) {
+        self.peer_transactions = transactions;
+    }
+
+    pub fn reached_max_transactions(&self) -> bool {
+        self.max_transactions
+            .is_some_and(|max| self.transactions + self.peer_transactions >= max)
+    }
+
+    pub fn has_failed(&mut self) -> bool {
+        self.sampler.has_failed_tasks()
+    }
+
+    /// Remove the tasks which panicked, returning how many did.
+    pub fn reap_failed(&mut self) -> usize {
+        self.sampler.reap_failed_tasks()
+    }
+
+    #[allow(unused)]
+    pub fn is_tps_limited(&self) -> bool {
+        self.tps_limited.is_some()
+    }
+
     fn check_underpowered(&mut self) -> bool {
         if self.tps_limited.is_some() {
             return true;
         }
 
-        if self.concurrency_history.len() > 4
-            && detect_zero_slope(&self.concurrency_history[self.concurrency_history.len() - 3..])
-        {
+        if self.concurrency_history.len() <= 4 {
+            return false;
+        }
+
+        if let Some(slopes) = detect_zero_slope(
+            &self.concurrency_history[self.concurrency_history.len() - 3..],
+            self.underpowered_slope,
+        ) {
             let (max_concurrency, max_tps) =
                 self.concurrency_history[self.concurrency_history.len() - 3];
 
             let max_tps = max_tps * 0.9;
-            let max_tps = NonZeroU32::new(max_tps.ceil().max(1.) as u32).unwrap();
+            let max_tps = clamp_tps(max_tps.ceil());
             self.tps_limited = Some((max_concurrency, max_tps));
+            self.underpowered_evidence = slopes;
             self.sampler.set_tps_limit(max_tps);
-            self.sampler.set_concurrency(max_concurrency);
+            self.sampler
+                .set_concurrency(running_concurrency(max_concurrency));
             self.concurrency_history.clear();
             true
         } else {
@@ -116,21 +369,103 @@ where
         }
     }
 
-    fn at_goal(&self, stats: Stats) -> bool {
+    /// With a fixed concurrency, lower the goal TPS to the measured TPS if the concurrency can't
+    /// reach it, since raising the concurrency isn't an option.
+    ///
+    /// NOTE: Errors count towards the TPS here, since a goal missed due to errors is for the
+    /// controllers to handle. Unlike `check_underpowered()` this isn't permanent: the controllers
+    /// may raise the goal again, which is limited once more if it isn't reached.
+    fn check_concurrency_limited(&mut self, samples: &[Measurement]) {
+        let total_tps =
+            samples.iter().map(Measurement::total_tps).sum::<f64>() / samples.len() as f64;
         let goal_tps = self.sampler.tps_limit().get() as f64;
-        (stats.mean + stats.std) >= (goal_tps * 0.98)
+        if total_tps >= goal_tps * (1. - self.tps_tolerance) {
+            return;
+        }
+
+        let max_tps = clamp_tps(total_tps.ceil());
+        debug!(
+            "Fixed concurrency of {} limited to {max_tps} TPS",
+            self.sampler.concurrency()
+        );
+        self.tps_limited = Some((self.sampler.concurrency(), max_tps));
+        self.sampler.set_tps_limit(max_tps);
+    }
+
+    /// Shed concurrency if Transactions are queueing at the target (only with
+    /// `adaptive_concurrency_shedding`).
+    fn check_queueing(&mut self, stats: Stats, samples: &[Measurement]) -> bool {
+        let Some(shedder) = &mut self.shedder else {
+            return false;
+        };
+
+        let concurrency = self.sampler.concurrency();
+        match shedder.check(concurrency, stats.mean, median_latency(samples)) {
+            Some(shed) => {
+                self.sampler.set_concurrency(running_concurrency(shed));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn check_generator_saturated(&mut self) {
+        if !self.generator_saturated && self.monitor.is_saturated() {
+            warn!(
+                "TPS limited while the load generator is near 100% CPU usage. The bottleneck is \
+                likely this Balter instance rather than the target service."
+            );
+            self.generator_saturated = true;
+        }
+    }
+
+    /// Stop raising the concurrency once Transactions fail as the load generator runs out of
+    /// local resources (see `Outcome::ResourceExhausted`), since more tasks would only exhaust
+    /// them further rather than load the target.
+    fn check_resource_exhausted(&mut self) {
+        let exhausted = self.sampler.resource_exhausted();
+        if exhausted == self.resource_exhausted {
+            return;
+        }
+        self.resource_exhausted = exhausted;
+
+        let concurrency = self.sampler.concurrency();
+        if self.resource_ceiling.is_none() {
+            warn!(
+                "generator_resource_exhausted: Transactions are failing as the load generator ran \
+                out of file descriptors (or other local resources) at a concurrency of \
+                {concurrency}, which is no longer raised. Consider raising the limit with \
+                `ulimit -n`."
+            );
+        }
+        self.resource_ceiling = Some(
+            self.resource_ceiling
+                .map_or(concurrency, |ceiling| ceiling.min(concurrency)),
+        );
+    }
+
+    fn at_goal(&self, stats: Stats) -> bool {
+        at_goal(stats, self.sampler.tps_limit(), self.tps_tolerance)
     }
 
     fn adjust_concurrency(&mut self, stats: Stats) {
         self.concurrency_history
             .push((self.sampler.concurrency(), stats.mean));
 
-        let tps_per_task = stats.mean / self.sampler.concurrency() as f64;
-        let new_concurrency =
-            (self.sampler.tps_limit().get() as f64 / tps_per_task).ceil() as usize;
-        let new_concurrency = new_concurrency.max(self.sampler.concurrency()).max(1);
+        let mut new_concurrency = next_concurrency(
+            self.sampler.concurrency(),
+            self.sampler.tps_limit(),
+            stats.mean,
+        );
+        if let Some(ceiling) = self.shedder.as_ref().and_then(ConcurrencyShedder::ceiling) {
+            new_concurrency = new_concurrency.min(ceiling);
+        }
+        if let Some(ceiling) = self.resource_ceiling {
+            new_concurrency = new_concurrency.min(ceiling);
+        }
 
-        self.sampler.set_concurrency(new_concurrency);
+        self.sampler
+            .set_concurrency(running_concurrency(new_concurrency));
     }
 }
 
@@ -138,6 +473,14 @@ pub(crate) struct SamplerStats {
     pub tps_limit: NonZeroU32,
     pub concurrency: usize,
     pub tps_limited: bool,
+    pub underpowered_evidence: Vec<f64>,
+    pub generator_saturated: bool,
+    pub generator_resource_exhausted: bool,
+    pub concurrency_capped: bool,
+    pub rate_limiter_accuracy: f64,
+    pub measurements: HashMap<String, MeasurementStatistics>,
+    pub phases: HashMap<String, MeasurementStatistics>,
+    pub error_categories: HashMap<String, u64>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -155,10 +498,18 @@ impl Stats {
     }
 }
 
-fn calculate_stats(measurements: &[Measurement]) -> Stats {
+/// NOTE: `smoothing` takes precedence over `weighting`, as both weigh recent windows more heavily.
+fn calculate_stats(
+    measurements: &[Measurement],
+    smoothing: Option<f64>,
+    weighting: Weighting,
+) -> Stats {
     let tps: Vec<f64> = measurements.iter().map(|m| m.tps).collect();
 
-    let mean = tps.iter().sum::<f64>() / tps.len() as f64;
+    let mean = match smoothing {
+        Some(alpha) => ema(&tps, alpha),
+        None => weighted_mean(&tps, weighting),
+    };
     let var = tps.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / tps.len() as f64;
     let std = var.sqrt();
 
@@ -171,27 +522,550 @@ fn calculate_stats(measurements: &[Measurement]) -> Stats {
     }
 }
 
-fn detect_zero_slope(values: &[(usize, f64)]) -> bool {
+/// Concurrency required to reach the goal TPS, given the mean TPS at the current concurrency.
+///
+/// Concurrency is never decreased here, and is left unchanged if there is no measured TPS to
+/// extrapolate from (which would otherwise produce an infinite or NaN concurrency).
+fn next_concurrency(concurrency: usize, goal_tps: NonZeroU32, mean_tps: f64) -> usize {
+    let tps_per_task = mean_tps / concurrency as f64;
+    if !tps_per_task.is_finite() || tps_per_task <= 0. {
+        return concurrency.max(1);
+    }
+
+    let new_concurrency = (goal_tps.get() as f64 / tps_per_task).ceil() as usize;
+    new_concurrency.max(concurrency).max(1)
+}
+
+fn shedder(config: &ScenarioConfig) -> Option<ConcurrencyShedder> {
+    config
+        .adaptive_concurrency_shedding
+        .then(ConcurrencyShedder::new)
+}
+
+/// Whether the measured TPS is within `tolerance` (a fraction of the goal) of `goal_tps`.
+fn at_goal(stats: Stats, goal_tps: NonZeroU32, tolerance: f64) -> bool {
+    (stats.mean + stats.std) >= (goal_tps.get() as f64 * (1. - tolerance))
+}
+
+fn tps_tolerance(config: &ScenarioConfig) -> f64 {
+    config.tps_tolerance.unwrap_or(DEFAULT_TPS_TOLERANCE)
+}
+
+fn underpowered_slope(config: &ScenarioConfig) -> f64 {
+    config
+        .underpowered_slope_threshold
+        .unwrap_or(DEFAULT_UNDERPOWERED_SLOPE)
+}
+
+/// Median of the p50 latencies of each window, which is robust to a single noisy window.
+fn median_latency(measurements: &[Measurement]) -> Duration {
+    let mut latencies: Vec<_> = measurements.iter().map(|m| m.latency(0.5)).collect();
+    latencies.sort();
+    latencies
+        .get(latencies.len() / 2)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Guard for setting the concurrency of a running sampler.
+///
+/// NOTE: `BaseSampler::set_concurrency(0)` aborts every task, which is only valid on shutdown.
+/// Mid-run it silently kills all load, so we clamp to 1 instead.
+fn running_concurrency(concurrency: usize) -> usize {
+    if concurrency == 0 {
+        error!("Attempted to set a concurrency of 0 while running, using 1 instead.");
+        1
+    } else {
+        concurrency
+    }
+}
+
+fn weighted_mean(values: &[f64], weighting: Weighting) -> f64 {
+    let (sum, total_weight) = values
+        .iter()
+        .enumerate()
+        .fold((0., 0.), |(sum, total), (i, val)| {
+            let weight = weighting.weight(i);
+            (sum + weight * val, total + weight)
+        });
+    sum / total_weight
+}
+
+/// Exponential moving average, seeded with the first value.
+fn ema(values: &[f64], alpha: f64) -> f64 {
+    values
+        .iter()
+        .skip(1)
+        .fold(values[0], |avg, val| alpha * val + (1. - alpha) * avg)
+}
+
+/// Whether each of the `(concurrency, tps)` points gained less than `threshold` of the TPS per
+/// task from the added concurrency, i.e. adding tasks no longer increases the TPS. If so, the
+/// slopes are returned as the evidence for it.
+///
+/// NOTE: The slope is normalized by the TPS per task, so that a single threshold works for both
+/// fast targets (thousands of TPS per task, where an absolute slope stays well above 1 even when
+/// flat) and slow ones (a fraction of a TPS per task, where it is below 1 even when scaling
+/// linearly). A normalized slope of 1 is perfectly linear scaling, and 0 is flat.
+fn detect_zero_slope(values: &[(usize, f64)], threshold: f64) -> Option<Vec<f64>> {
     let slopes: Vec<_> = values
         .windows(2)
         .map(|arr| {
             let (c0, t0) = arr[0];
             let (c1, t1) = arr[1];
 
-            let slope = (t1 - t0) / (c1 - c0) as f64;
+            let tps_per_task = t0 / c0 as f64;
+            let slope = (t1 - t0) / (c1 - c0) as f64 / tps_per_task;
 
             if slope.is_nan() {
                 error!("NaN Slope detected. Ignoring.");
                 return 0.;
             }
 
-            let b = t1 - slope * c0 as f64;
             trace!("({}, {:.2}), ({}, {:.2})", c0, t0, c1, t1,);
-            trace!("y = {:.2}x + {:.2}", slope, b);
+            trace!("Normalized slope {:.2}", slope);
 
             slope
         })
         .collect();
 
-    slopes.iter().all(|m| *m < 1.)
+    let underpowered = slopes.iter().all(|m| *m < threshold);
+    debug!(
+        ?slopes,
+        threshold, underpowered, "Normalized slopes of TPS by concurrency"
+    );
+    underpowered.then_some(slopes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_scenario;
+    use crate::transaction::transaction_hook;
+    use balter_core::TransactionLabels;
+    use rand_distr::{Distribution, SkewNormal};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::Semaphore;
+
+    fn measurements(tps: &[u64]) -> Vec<Measurement> {
+        tps.iter()
+            .map(|tps| Measurement::new(*tps, 0, Duration::from_secs(1)))
+            .collect()
+    }
+
+    #[test]
+    fn test_smoothing_step_change() {
+        // NOTE: The target's capacity drops from 1,000 to 500 TPS part-way through.
+        let windows = measurements(&[1_000, 1_000, 1_000, 500, 500]);
+
+        let flat = calculate_stats(&windows, None, Weighting::Uniform);
+        let smoothed = calculate_stats(&windows, Some(0.7), Weighting::Uniform);
+
+        assert_eq!(flat.mean, 800.);
+        assert!((smoothed.mean - 545.).abs() < 1e-6);
+        assert!((smoothed.mean - 500.).abs() < (flat.mean - 500.).abs());
+
+        // An alpha of 1 only considers the latest window.
+        assert_eq!(
+            calculate_stats(&windows, Some(1.), Weighting::Uniform).mean,
+            500.
+        );
+    }
+
+    #[test]
+    fn test_weighting_ramp_tracking() {
+        // NOTE: The target's capacity ramps up by 100 TPS each window.
+        let windows = measurements(&[500, 600, 700, 800, 900]);
+        let tracking_error = |weighting| 900. - calculate_stats(&windows, None, weighting).mean;
+
+        let uniform = tracking_error(Weighting::Uniform);
+        let linear = tracking_error(Weighting::Linear);
+        let exponential = tracking_error(Weighting::Exponential);
+
+        assert_eq!(uniform, 200.);
+        assert!((linear - 400. / 3.).abs() < 1e-6);
+        assert!((exponential - 2_600. / 31.).abs() < 1e-6);
+        assert!(exponential < linear && linear < uniform);
+
+        // Smoothing takes precedence over weighting.
+        assert_eq!(
+            calculate_stats(&windows, Some(1.), Weighting::Linear).mean,
+            900.
+        );
+    }
+
+    #[test]
+    fn test_detect_zero_slope_fast_target() {
+        // NOTE: ~1,000 TPS per task (1ms latency), limited to ~10,000 TPS.
+        let scaling = [(2, 2_000.), (4, 3_990.), (8, 8_010.)];
+        assert!(detect_zero_slope(&scaling, DEFAULT_UNDERPOWERED_SLOPE).is_none());
+
+        // Noise keeps the absolute slope well above 1 TPS per task, but the limit is still found.
+        let limited = [(16, 10_000.), (32, 10_200.), (64, 10_500.)];
+        let slopes = detect_zero_slope(&limited, DEFAULT_UNDERPOWERED_SLOPE).unwrap();
+        assert_eq!(slopes.len(), 2);
+        assert!((slopes[0] - 0.02).abs() < 1e-6);
+        assert!((slopes[1] - 300. / 10_200.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_detect_zero_slope_slow_target() {
+        // NOTE: ~0.5 TPS per task (2s latency), so the absolute slope is below 1 TPS per task
+        // even while scaling linearly.
+        let scaling = [(10, 5.), (20, 9.8), (40, 20.1)];
+        assert!(detect_zero_slope(&scaling, DEFAULT_UNDERPOWERED_SLOPE).is_none());
+
+        let limited = [(40, 20.), (80, 20.5), (160, 20.3)];
+        assert!(detect_zero_slope(&limited, DEFAULT_UNDERPOWERED_SLOPE).is_some());
+
+        // A target scaling at half efficiency is only limited with a stricter threshold.
+        let diminishing = [(10, 5.), (20, 7.5), (40, 12.5)];
+        assert!(detect_zero_slope(&diminishing, DEFAULT_UNDERPOWERED_SLOPE).is_none());
+        assert!(detect_zero_slope(&diminishing, 0.8).is_some());
+    }
+
+    #[test]
+    fn test_next_concurrency() {
+        let goal_tps = NonZeroU32::new(1_000).unwrap();
+
+        assert_eq!(next_concurrency(10, goal_tps, 500.), 20);
+        assert_eq!(next_concurrency(10, goal_tps, 2_000.), 10);
+
+        // A controller backed all the way off to 1 TPS
+        assert_eq!(next_concurrency(1, NonZeroU32::MIN, 0.5), 2);
+        assert_eq!(next_concurrency(1, NonZeroU32::MIN, 20.), 1);
+    }
+
+    #[test]
+    fn test_next_concurrency_never_zero() {
+        let goal_tps = NonZeroU32::new(1_000).unwrap();
+
+        // NOTE: Regressions for inputs which produced a concurrency of 0 (NaN TPS per task) or
+        // usize::MAX (infinite TPS per task).
+        assert_eq!(next_concurrency(0, goal_tps, 0.), 1);
+        assert_eq!(next_concurrency(0, goal_tps, f64::NAN), 1);
+        assert_eq!(next_concurrency(10, goal_tps, 0.), 10);
+        assert_eq!(running_concurrency(0), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sheds_concurrency_when_queueing() {
+        const LABELS: TransactionLabels = TransactionLabels {
+            success: "test_sheds_concurrency_when_queueing_success",
+            error: "test_sheds_concurrency_when_queueing_error",
+            latency: "test_sheds_concurrency_when_queueing_latency",
+            slo: None,
+        };
+
+        let mut config = ScenarioConfig::new("test_sheds_concurrency_when_queueing");
+        config.adaptive_concurrency_shedding = true;
+
+        // NOTE: The mock target serves 4 Transactions at a time (400 TPS), so any more
+        // concurrency only queues Transactions and slows each one down.
+        let target = Arc::new(Semaphore::new(4));
+        let scenario = move || {
+            let target = target.clone();
+            async move {
+                let _ = transaction_hook(LABELS, async {
+                    let _permit = target.acquire().await;
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    Ok::<_, ()>(())
+                })
+                .await;
+            }
+        };
+
+        let mut sampler = Sampler::new(
+            &config,
+            &Hooks::default(),
+            scenario,
+            NonZeroU32::new(2_000).unwrap(),
+        )
+        .await;
+
+        let mut max_concurrency = 0;
+        for _ in 0..3 {
+            sampler.sample().await;
+            max_concurrency = max_concurrency.max(sampler.sampler.concurrency());
+        }
+
+        let ceiling = sampler
+            .shedder
+            .as_ref()
+            .and_then(ConcurrencyShedder::ceiling);
+        assert!(ceiling.is_some());
+        assert!(sampler.sampler.concurrency() < max_concurrency);
+        sampler.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_sheds_concurrency_until_recovered() {
+        const LABELS: TransactionLabels = TransactionLabels {
+            success: "test_sheds_concurrency_until_recovered_success",
+            error: "test_sheds_concurrency_until_recovered_error",
+            latency: "test_sheds_concurrency_until_recovered_latency",
+            slo: None,
+        };
+
+        let mut config = ScenarioConfig::new("test_sheds_concurrency_until_recovered");
+        config.adaptive_concurrency_shedding = true;
+
+        // NOTE: As in `test_sheds_concurrency_when_queueing()`, until the target recovers.
+        let target = Arc::new(Semaphore::new(4));
+        let scenario = {
+            let target = target.clone();
+            move || {
+                let target = target.clone();
+                async move {
+                    let _ = transaction_hook(LABELS, async {
+                        let _permit = target.acquire().await;
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        Ok::<_, ()>(())
+                    })
+                    .await;
+                }
+            }
+        };
+
+        let mut sampler = Sampler::new(
+            &config,
+            &Hooks::default(),
+            scenario,
+            NonZeroU32::new(2_000).unwrap(),
+        )
+        .await;
+
+        let mut ceiling = None;
+        for _ in 0..5 {
+            sampler.sample().await;
+            ceiling = sampler
+                .shedder
+                .as_ref()
+                .and_then(ConcurrencyShedder::ceiling);
+            if ceiling.is_some() {
+                break;
+            }
+        }
+        let shed_to = ceiling.expect("Concurrency was never shed.");
+
+        target.add_permits(1_000);
+        for _ in 0..10 {
+            sampler.sample().await;
+            ceiling = sampler
+                .shedder
+                .as_ref()
+                .and_then(ConcurrencyShedder::ceiling);
+            if ceiling.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(ceiling, None);
+        assert!(sampler.sampler.concurrency() > shed_to);
+        sampler.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_stops_scaling_when_resources_exhausted() {
+        const LABELS: TransactionLabels = TransactionLabels {
+            success: "test_stops_scaling_when_resources_exhausted_success",
+            error: "test_stops_scaling_when_resources_exhausted_error",
+            latency: "test_stops_scaling_when_resources_exhausted_latency",
+            slo: None,
+        };
+
+        let config = ScenarioConfig::new("test_stops_scaling_when_resources_exhausted");
+
+        // NOTE: The load generator only has 8 file descriptors, so past a concurrency of 8 the
+        // extra tasks fail with `EMFILE` before reaching the target.
+        let fds = Arc::new(Semaphore::new(8));
+        let scenario = move || {
+            let fds = fds.clone();
+            async move {
+                let _ = transaction_hook(LABELS, async {
+                    let Ok(_fd) = fds.try_acquire() else {
+                        tokio::time::sleep(Duration::from_millis(1)).await;
+                        return Err(std::io::Error::from_raw_os_error(24));
+                    };
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    Ok(())
+                })
+                .await;
+            }
+        };
+
+        let mut sampler = Sampler::new(
+            &config,
+            &Hooks::default(),
+            scenario,
+            NonZeroU32::new(2_000).unwrap(),
+        )
+        .await;
+
+        let mut ceiling = None;
+        for _ in 0..10 {
+            sampler.sample().await;
+            if sampler.resource_ceiling.is_some() {
+                ceiling = sampler.resource_ceiling;
+                break;
+            }
+        }
+        let ceiling = ceiling.expect("Resource exhaustion was never detected");
+        assert!(sampler.stats().generator_resource_exhausted);
+
+        for _ in 0..3 {
+            sampler.sample().await;
+            assert!(sampler.sampler.concurrency() <= ceiling);
+        }
+        assert!(sampler.resource_ceiling.unwrap() <= ceiling);
+        sampler.shutdown().await;
+    }
+
+    #[test]
+    fn test_tight_tps_tolerance() {
+        let goal_tps = NonZeroU32::new(500).unwrap();
+
+        // NOTE: 1.5% short of the goal, which is only reached with the default tolerance.
+        let stats = Stats {
+            mean: 490.,
+            std: 2.5,
+            outlier_count: 0,
+        };
+        assert!(at_goal(stats, goal_tps, DEFAULT_TPS_TOLERANCE));
+        assert!(!at_goal(stats, goal_tps, 0.01));
+        assert!(at_goal(
+            Stats {
+                mean: 496.,
+                ..stats
+            },
+            goal_tps,
+            0.01
+        ));
+
+        let mut config = ScenarioConfig::new("test_tight_tps_tolerance");
+        assert_eq!(tps_tolerance(&config), DEFAULT_TPS_TOLERANCE);
+        config.tps_tolerance = Some(0.01);
+        assert_eq!(tps_tolerance(&config), 0.01);
+    }
+
+    #[tokio::test]
+    async fn test_fixed_concurrency() {
+        let mut config = ScenarioConfig::new("test_fixed_concurrency");
+        config.fixed_concurrency = Some(4);
+
+        // NOTE: 4 tasks of 10ms Transactions manage at most 400 TPS.
+        let mut sampler = Sampler::new(
+            &config,
+            &Hooks::default(),
+            mock_scenario!(Duration::from_millis(10), Duration::from_micros(10)),
+            NonZeroU32::new(2_000).unwrap(),
+        )
+        .await;
+
+        for _ in 0..3 {
+            sampler.sample().await;
+            assert_eq!(sampler.concurrency(), 4);
+        }
+        assert!(sampler.is_tps_limited());
+        let tps_limit = sampler.tps_limit().get();
+        assert!((300..=400).contains(&tps_limit), "{tps_limit} TPS");
+        sampler.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_statistics_reset() {
+        const LABELS: TransactionLabels = TransactionLabels {
+            success: "test_statistics_reset_success",
+            error: "test_statistics_reset_error",
+            latency: "test_statistics_reset_latency",
+            slo: None,
+        };
+
+        let slow = Arc::new(AtomicBool::new(true));
+        let recorded = Arc::new(AtomicU64::new(0));
+        let scenario = {
+            let (slow, recorded) = (slow.clone(), recorded.clone());
+            move || {
+                let (slow, recorded) = (slow.clone(), recorded.clone());
+                async move {
+                    let _ = transaction_hook(LABELS, async {
+                        let latency_ms = if slow.load(Ordering::Relaxed) { 20 } else { 1 };
+                        tokio::time::sleep(Duration::from_millis(latency_ms)).await;
+                        crate::transaction::record("test_statistics_reset_ms", latency_ms as f64);
+                        recorded.fetch_add(1, Ordering::Relaxed);
+                        Ok::<_, ()>(())
+                    })
+                    .await;
+                }
+            }
+        };
+
+        let config = ScenarioConfig::new("test_statistics_reset");
+        let mut sampler = Sampler::new(
+            &config,
+            &Hooks::default(),
+            scenario,
+            NonZeroU32::new(200).unwrap(),
+        )
+        .await;
+
+        // NOTE: The Transactions of the warmup aren't included.
+        sampler.sample().await;
+        let warm = &sampler.stats().measurements["test_statistics_reset_ms"];
+        assert!(warm.count > 0);
+        assert!(warm.count < recorded.load(Ordering::Relaxed));
+
+        // NOTE: Nor are those of a previous phase, including those still in flight when it
+        // ended.
+        slow.store(false, Ordering::Relaxed);
+        sampler.start_phase(&config);
+        sampler.sample().await;
+        let measured = &sampler.stats().measurements["test_statistics_reset_ms"];
+        assert_eq!(measured.p90, 1.);
+        assert!(measured.mean < 2.);
+        sampler.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_zero_concurrency_hint() {
+        let mut config = ScenarioConfig::new("test_zero_concurrency_hint");
+        config.hints.concurrency = 0;
+
+        let sampler = Sampler::new(
+            &config,
+            &Hooks::default(),
+            || async { tokio::time::sleep(Duration::from_millis(1)).await },
+            NonZeroU32::new(100).unwrap(),
+        )
+        .await;
+
+        assert_eq!(sampler.sampler.concurrency(), 1);
+        sampler.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_expected_latency_hint() {
+        let mut config = ScenarioConfig::new("test_expected_latency_hint");
+        config.hints.expected_latency = Some(Duration::from_millis(200));
+
+        let goal_tps = NonZeroU32::new(1_000).unwrap();
+        let sampler = Sampler::new(
+            &config,
+            &Hooks::default(),
+            || async { tokio::time::sleep(Duration::from_millis(200)).await },
+            goal_tps,
+        )
+        .await;
+
+        // NOTE: Starting from the default concurrency would take several doublings to reach this.
+        let estimate =
+            balter_core::estimate_concurrency(goal_tps.get(), Duration::from_millis(200));
+        let concurrency = sampler.sampler.concurrency();
+        assert!(
+            (estimate..=estimate * 2).contains(&concurrency),
+            "Seeded concurrency {concurrency}, estimated {estimate}"
+        );
+        sampler.shutdown().await;
+    }
 }
@@ -1,19 +1,49 @@
+#[cfg(feature = "metrics")]
+use super::live_metrics::LiveMetrics;
 use super::task_atomics::TaskAtomics;
 use super::timer::Timer;
+use crate::hooks::{ConcurrencyChangeFn, Hooks};
 use crate::measurement::Measurement;
-use crate::transaction::TRANSACTION_HOOK;
+use crate::transaction::{with_rng, TASK_RNG, TRANSACTION_HOOK};
+use balter_core::{MeasurementStatistics, ScenarioConfig, WindowMode};
+use futures_util::FutureExt;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::future::Future;
 use std::num::NonZeroU32;
-use tokio::task::JoinHandle;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::Instant;
 #[allow(unused)]
 use tracing::{debug, error, info, trace, warn};
 
 pub(crate) struct BaseSampler<T> {
-    base_label: String,
+    // NOTE: Metric names are built once up-front rather than `format!`-ed every window.
+    goal_tps_label: Arc<str>,
+    concurrency_label: Arc<str>,
     scenario: T,
     tasks: Vec<JoinHandle<()>>,
+    /// Tasks which panicked and were removed from `tasks`, see `check_finished_tasks()`.
+    panicked_tasks: usize,
     timer: Timer,
+    /// Start of the current window, if windows hold a fixed number of Transactions rather than
+    /// being timed (see `WindowMode::Count`).
+    window_start: Instant,
+    max_interval: Duration,
+    /// Whether each task runs the Scenario once and is replaced by a new one, rather than looping
+    /// over it (see `run_once_per_task()`).
+    run_once_per_task: bool,
+    /// Hard cap on the number of tasks, see `max_concurrency()`.
+    max_concurrency: usize,
+    /// Whether the concurrency was ever clamped to `max_concurrency`.
+    concurrency_capped: bool,
     task_atomics: TaskAtomics,
+    on_concurrency_change: Option<Arc<ConcurrencyChangeFn>>,
+    /// Set if metrics are emitted at a fixed cadence rather than as they change.
+    #[cfg(feature = "metrics")]
+    live_metrics: Option<LiveMetrics>,
 }
 
 impl<T, F> BaseSampler<T>
@@ -21,32 +51,111 @@ where
     T: Fn() -> F + Send + Sync + 'static + Clone,
     F: Future<Output = ()> + Send,
 {
-    pub async fn new(name: &str, scenario: T, tps_limit: NonZeroU32) -> Self {
+    pub async fn new(
+        config: &ScenarioConfig,
+        hooks: &Hooks,
+        scenario: T,
+        tps_limit: NonZeroU32,
+    ) -> Self {
         let interval = if tps_limit.get() < 150 {
             balter_core::BASE_INTERVAL_SLOW
         } else {
             balter_core::BASE_INTERVAL
         };
+        let mut sampler = Self::with_interval(config, hooks, scenario, tps_limit, interval).await;
+        if let WindowMode::Count(size) = config.window_mode {
+            sampler.task_atomics.set_count_window(size);
+        }
+        sampler
+    }
+
+    /// NOTE: Windows are always timed, regardless of the `WindowMode` of the config, since the
+    /// interval is what the caller asked for.
+    pub async fn with_interval(
+        config: &ScenarioConfig,
+        hooks: &Hooks,
+        scenario: T,
+        tps_limit: NonZeroU32,
+        interval: Duration,
+    ) -> Self {
         let timer = Timer::new(interval).await;
+        let base_label = config.metrics_base_label();
         Self {
-            base_label: format!("balter_{name}"),
+            goal_tps_label: Arc::from(format!("{base_label}_goal_tps")),
+            concurrency_label: Arc::from(format!("{base_label}_concurrency")),
             scenario,
             tasks: vec![],
+            panicked_tasks: 0,
             timer,
-            task_atomics: TaskAtomics::new(tps_limit),
+            window_start: Instant::now(),
+            max_interval: config
+                .max_sample_interval
+                .unwrap_or(balter_core::MAX_SAMPLE_INTERVAL),
+            run_once_per_task: config.run_once_per_task,
+            max_concurrency: config
+                .max_concurrency
+                .unwrap_or(balter_core::MAX_CONCURRENCY),
+            concurrency_capped: false,
+            task_atomics: TaskAtomics::new(config, hooks, tps_limit),
+            on_concurrency_change: hooks.on_concurrency_change.clone(),
+            #[cfg(feature = "metrics")]
+            live_metrics: config.metrics_interval.map(|interval| {
+                let live_metrics = LiveMetrics::spawn(&base_label, interval);
+                live_metrics.set_goal_tps(tps_limit);
+                live_metrics
+            }),
         }
     }
 
     pub async fn sample(&mut self) -> Measurement {
-        let elapsed = self.timer.tick().await;
-        let measurements = self.task_atomics.collect(elapsed);
-        trace!("{measurements}");
-        measurements
+        loop {
+            let elapsed = match self.task_atomics.count_window() {
+                Some(window) => {
+                    window.wait_full(self.max_interval).await;
+                    self.window_start.elapsed()
+                }
+                None => self.timer.tick().await,
+            };
+            let measurements = self.task_atomics.collect(elapsed);
+            trace!("{measurements}");
+
+            if let Some(window) = self.task_atomics.count_window() {
+                self.window_start = Instant::now();
+                window.open();
+            }
+
+            // NOTE: If Transactions are running but none completed in the window (e.g. against a
+            // very slow target), the window is too short to measure anything at all.
+            if measurements.success_count() + measurements.error_count() == 0
+                && self.task_atomics.in_flight() > 0
+                && self.timer.double(self.max_interval).await
+            {
+                debug!(
+                    "No transactions completed; increased sampling interval to {}",
+                    self.timer
+                );
+                continue;
+            }
+
+            #[cfg(feature = "metrics")]
+            if let Some(live_metrics) = &self.live_metrics {
+                live_metrics.record(&measurements);
+            }
+
+            return measurements;
+        }
+    }
+
+    #[allow(unused)]
+    pub fn interval(&self) -> Duration {
+        self.timer.interval_dur()
     }
 
     pub fn set_tps_limit(&mut self, tps_limit: NonZeroU32) {
-        if cfg!(feature = "metrics") {
-            metrics::gauge!(format!("{}_goal_tps", &self.base_label)).set(tps_limit.get());
+        #[cfg(feature = "metrics")]
+        match &self.live_metrics {
+            Some(live_metrics) => live_metrics.set_goal_tps(tps_limit),
+            None => metrics::gauge!(self.goal_tps_label.clone()).set(tps_limit.get()),
         }
 
         self.task_atomics.set_tps_limit(tps_limit);
@@ -57,51 +166,262 @@ where
     }
 
     pub fn set_concurrency(&mut self, concurrency: usize) {
-        if cfg!(feature = "metrics") {
-            metrics::gauge!(format!("{}_concurrency", &self.base_label)).set(concurrency as f64);
+        self.resize(concurrency);
+    }
+
+    /// Spawn or abort tasks to reach `concurrency`, returning the handles of the aborted ones.
+    fn resize(&mut self, concurrency: usize) -> Vec<JoinHandle<()>> {
+        // NOTE: Spawning hundreds of thousands of tasks can exhaust the memory of the load
+        // generator, so a controller (or goal TPS) scaling out of control is stopped here.
+        let concurrency = if concurrency > self.max_concurrency {
+            if !self.concurrency_capped {
+                warn!(
+                    "Concurrency of {concurrency} exceeds the maximum of {}, so it was capped. \
+                    Raise the limit with `max_concurrency()` if this is intended.",
+                    self.max_concurrency
+                );
+                self.concurrency_capped = true;
+            }
+            self.max_concurrency
+        } else {
+            concurrency
+        };
+
+        #[cfg(feature = "metrics")]
+        match &self.live_metrics {
+            Some(live_metrics) => live_metrics.set_concurrency(concurrency),
+            None => metrics::gauge!(self.concurrency_label.clone()).set(concurrency as f64),
         }
 
-        #[allow(clippy::comparison_chain)]
         if self.tasks.len() == concurrency {
-            #[allow(clippy::needless_return)]
-            return;
-        } else if self.tasks.len() > concurrency {
+            return vec![];
+        }
+
+        // NOTE: Called before any tasks are spawned, so that resources sized to the concurrency
+        // are ready for them.
+        if let Some(on_concurrency_change) = &self.on_concurrency_change {
+            on_concurrency_change(concurrency);
+        }
+
+        let mut aborted = vec![];
+        if self.tasks.len() > concurrency {
             for handle in self.tasks.drain(concurrency..) {
                 handle.abort();
+                aborted.push(handle);
             }
         } else {
             while self.tasks.len() < concurrency {
                 let scenario = self.scenario.clone();
                 let transaction_data = self.task_atomics.clone_to_transaction_data();
+                let rng = RefCell::new(self.task_atomics.task_rng());
+                let jitter = start_jitter(self.tasks.len(), self.tps_limit());
+                let run_once_per_task = self.run_once_per_task;
 
-                self.tasks.push(tokio::spawn(TRANSACTION_HOOK.scope(
+                self.tasks.push(spawn(TRANSACTION_HOOK.scope(
                     transaction_data,
-                    async move {
+                    TASK_RNG.scope(rng, async move {
+                        tokio::time::sleep(jitter).await;
+
+                        if run_once_per_task {
+                            return run_journeys(scenario).await;
+                        }
+
                         // NOTE: We have an outer loop just in case the user-provided
                         // scenario does not have a loop.
                         loop {
                             scenario().await;
                         }
-                    },
+                    }),
                 )));
             }
         }
+        self.task_atomics.barrier().resize(concurrency);
+        aborted
     }
 
     pub fn concurrency(&self) -> usize {
         self.tasks.len()
     }
 
-    pub fn shutdown(mut self) {
-        self.set_concurrency(0);
+    /// Whether a concurrency above `max_concurrency()` was ever requested (and clamped).
+    pub fn concurrency_capped(&self) -> bool {
+        self.concurrency_capped
+    }
+
+    /// Whether any task has panicked.
+    pub fn has_failed_tasks(&mut self) -> bool {
+        self.check_finished_tasks();
+        self.panicked_tasks > 0
+    }
+
+    /// Take the number of tasks which have panicked (see `has_failed_tasks()`), which no longer
+    /// count towards the concurrency.
+    pub fn reap_failed_tasks(&mut self) -> usize {
+        self.check_finished_tasks();
+        std::mem::take(&mut self.panicked_tasks)
+    }
+
+    /// Remove the tasks which have stopped running, counting those which panicked.
+    ///
+    /// NOTE: Tasks loop over the Scenario forever (or keep spawning new runs of it, see
+    /// `run_journeys()`) and are only aborted when the concurrency is lowered, so a running task
+    /// which has finished has almost always panicked. One which returned is simply dropped.
+    fn check_finished_tasks(&mut self) {
+        self.tasks.retain_mut(|handle| {
+            if !handle.is_finished() {
+                return true;
+            }
+
+            match handle.now_or_never() {
+                Some(Err(err)) if err.is_panic() => self.panicked_tasks += 1,
+                _ => debug!("Task stopped without panicking."),
+            }
+            false
+        });
+    }
+
+    pub fn custom_measurements(&self) -> HashMap<String, MeasurementStatistics> {
+        self.task_atomics.custom_measurements()
+    }
+
+    pub fn phase_timings(&self) -> HashMap<String, MeasurementStatistics> {
+        self.task_atomics.phase_timings()
+    }
+
+    pub fn error_categories(&self) -> HashMap<String, u64> {
+        self.task_atomics.error_categories()
+    }
+
+    #[cfg(feature = "reqwest")]
+    pub fn warmup_report(&self) -> crate::WarmupReport {
+        self.task_atomics.warmup_report()
+    }
+
+    pub fn resource_exhausted(&self) -> u64 {
+        self.task_atomics.resource_exhausted()
+    }
+
+    pub fn reset_statistics(&self) {
+        self.task_atomics.reset_statistics();
+    }
+
+    pub fn reset_latencies(&self) {
+        self.task_atomics.reset_latencies();
+    }
+
+    /// Stop every task, waiting until they have been dropped so that nothing of the Scenario is
+    /// still running once it returns (see `on_scenario_end()`).
+    pub async fn shutdown(mut self) {
+        for task in self.resize(0) {
+            let _ = task.await;
+        }
+    }
+}
+
+/// Sampler without any tasks, whose metrics can be emitted again without changing its state.
+///
+/// NOTE: Only for `tests/metric_allocations.rs`, which counts allocations with a global allocator
+/// of its own rather than swapping out the allocator of the library's unit tests.
+#[cfg(feature = "test-util")]
+#[doc(hidden)]
+pub struct MetricsProbe(BaseSampler<fn() -> std::future::Ready<()>>);
+
+#[cfg(feature = "test-util")]
+impl MetricsProbe {
+    pub async fn new(name: &str, tps_limit: NonZeroU32) -> Self {
+        let scenario: fn() -> std::future::Ready<()> = || std::future::ready(());
+        let config = ScenarioConfig::new(name);
+        Self(BaseSampler::new(&config, &Hooks::default(), scenario, tps_limit).await)
+    }
+
+    /// Set the TPS limit and concurrency to what they already are, so the only work done is
+    /// emitting metrics.
+    pub fn emit_metrics(&mut self) {
+        self.0.set_tps_limit(self.0.tps_limit());
+        self.0.set_concurrency(0);
+    }
+}
+
+// NOTE: Ensures tasks don't outlive a Scenario which is dropped before completion (e.g. when
+// cancelled by `try_join_scenarios()`).
+impl<T> Drop for BaseSampler<T> {
+    fn drop(&mut self) {
+        for handle in self.tasks.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+/// Run the Scenario once per task, spawning a new task for each run as soon as the previous one
+/// completes, so that the concurrency is maintained.
+///
+/// Each run gets the task-locals a looping task would have, with an RNG seeded from the RNG of its
+/// slot so that runs are still reproducible with `seed()`.
+///
+/// NOTE: This runs within the task spawned by `set_concurrency()`, which aborts the current run
+/// when it is aborted itself. If a run panics, the panic is resumed, so the task fails just as a
+/// looping task which panicked (see `has_failed_tasks()`).
+async fn run_journeys<T, F>(scenario: T)
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    loop {
+        let scenario = scenario.clone();
+        let transaction_data = TRANSACTION_HOOK.with(Clone::clone);
+        let rng = RefCell::new(SmallRng::seed_from_u64(with_rng(|rng| rng.gen())));
+
+        let journey = tokio::spawn(TRANSACTION_HOOK.scope(
+            transaction_data,
+            TASK_RNG.scope(rng, async move { scenario().await }),
+        ));
+        let _guard = AbortOnDrop(journey.abort_handle());
+        match journey.await {
+            Ok(()) => {}
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(_) => return,
+        }
+    }
+}
+
+pub(super) struct AbortOnDrop(pub(super) AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
     }
 }
 
+#[cfg(not(feature = "deterministic"))]
+fn spawn<F>(task: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(task)
+}
+
+#[cfg(feature = "deterministic")]
+use super::deterministic::spawn;
+
+/// Initial delay for a newly spawned task, within one rate limiter period (`1/tps`).
+///
+/// Tasks spawned together otherwise enter their loops at the same instant and tend to stay
+/// synchronized behind the burst-1 rate limiter, which produces a pulsing load pattern. The offset
+/// is derived from the task id using the golden ratio sequence, so any number of tasks are spread
+/// evenly over the period.
+fn start_jitter(task_id: usize, tps_limit: NonZeroU32) -> Duration {
+    const GOLDEN_RATIO_CONJUGATE: f64 = 0.618_033_988_749_895;
+
+    let fraction = (task_id as f64 * GOLDEN_RATIO_CONJUGATE).fract();
+    Duration::from_secs_f64(fraction / tps_limit.get() as f64)
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
     use rand_distr::{Distribution, SkewNormal};
-    use std::time::Duration;
+    use std::sync::Mutex;
+    use std::time::Instant;
 
     #[macro_export]
     macro_rules! mock_scenario {
@@ -111,13 +431,14 @@ pub(crate) mod tests {
                     success: "",
                     error: "",
                     latency: "",
+                    slo: None,
                 };
                 let mean: std::time::Duration = $m;
                 let std: std::time::Duration = $s;
                 let _ = $crate::transaction::transaction_hook::<_, (), ()>(labels, async {
                     let normal =
                         SkewNormal::new(mean.as_secs_f64(), std.as_secs_f64(), 20.).unwrap();
-                    let v: f64 = normal.sample(&mut rand::thread_rng()).max(0.);
+                    let v: f64 = $crate::sampler::with_test_rng(|rng| normal.sample(rng)).max(0.);
                     tokio::time::sleep(std::time::Duration::from_secs_f64(v)).await;
                     Ok(())
                 })
@@ -130,7 +451,8 @@ pub(crate) mod tests {
     #[tokio::test]
     async fn test_simple() {
         let mut sampler = BaseSampler::new(
-            "",
+            &ScenarioConfig::new(""),
+            &Hooks::default(),
             mock_scenario!(Duration::from_millis(1), Duration::from_micros(10)),
             NonZeroU32::new(1_000).unwrap(),
         )
@@ -146,7 +468,8 @@ pub(crate) mod tests {
     #[tokio::test]
     async fn test_noisy() {
         let mut sampler = BaseSampler::new(
-            "",
+            &ScenarioConfig::new(""),
+            &Hooks::default(),
             mock_scenario!(Duration::from_millis(10), Duration::from_millis(5)),
             NonZeroU32::new(1_000).unwrap(),
         )
@@ -158,12 +481,336 @@ pub(crate) mod tests {
         assert!(sample.tps >= 900. && sample.tps <= 1100.);
     }
 
+    // NOTE: The deterministic executor runs the tasks on its own runtime, whose clock isn't paused
+    // along with the test's, so the Transactions would take 15s of real time.
+    #[cfg(not(feature = "deterministic"))]
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_target_grows_interval() {
+        // NOTE: Transactions take 15s, so none complete within the default 1s interval. The TPS
+        // is unlimited since the rate limiter runs on real rather than paused time.
+        let mut sampler = BaseSampler::new(
+            &ScenarioConfig::new("test_slow_target_grows_interval"),
+            &Hooks::default(),
+            mock_scenario!(Duration::from_secs(15), Duration::from_millis(10)),
+            NonZeroU32::MAX,
+        )
+        .await;
+        sampler.set_concurrency(10);
+
+        let sample = sampler.sample().await;
+        assert_eq!(sampler.interval(), Duration::from_secs(16));
+        assert!(sample.success_count() >= 10);
+    }
+
+    // NOTE: Transactions would take 15s of real time, see `test_slow_target_grows_interval()`.
+    #[cfg(not(feature = "deterministic"))]
+    #[tokio::test(start_paused = true)]
+    async fn test_max_sample_interval() {
+        let mut config = ScenarioConfig::new("test_max_sample_interval");
+        config.max_sample_interval = Some(Duration::from_secs(8));
+
+        let mut sampler = BaseSampler::new(
+            &config,
+            &Hooks::default(),
+            mock_scenario!(Duration::from_secs(15), Duration::from_millis(10)),
+            NonZeroU32::MAX,
+        )
+        .await;
+        sampler.set_concurrency(10);
+
+        let sample = sampler.sample().await;
+        assert_eq!(sampler.interval(), Duration::from_secs(8));
+        assert_eq!(sample.success_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_count_window() {
+        let mut config = ScenarioConfig::new("test_count_window");
+        config.window_mode = WindowMode::Count(50);
+
+        let mut sampler = BaseSampler::new(
+            &config,
+            &Hooks::default(),
+            mock_scenario!(Duration::from_millis(1), Duration::from_micros(500)),
+            NonZeroU32::new(1_000).unwrap(),
+        )
+        .await;
+        sampler.set_concurrency(20);
+
+        for _ in 0..5 {
+            let sample = sampler.sample().await;
+            assert_eq!(sample.success_count() + sample.error_count(), 50);
+            // NOTE: The window lasts as long as 50 Transactions take at 1,000 TPS.
+            assert!(sample.elapsed < Duration::from_millis(500), "{sample}");
+
+            // NOTE: Tasks wait for the next window rather than overflowing into it.
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_count_window_max_interval() {
+        let mut config = ScenarioConfig::new("test_count_window_max_interval");
+        config.window_mode = WindowMode::Count(1_000);
+        config.max_sample_interval = Some(Duration::from_millis(200));
+
+        let mut sampler = BaseSampler::new(
+            &config,
+            &Hooks::default(),
+            mock_scenario!(Duration::from_millis(1), Duration::from_micros(500)),
+            NonZeroU32::new(100).unwrap(),
+        )
+        .await;
+        sampler.set_concurrency(10);
+
+        // NOTE: At 100 TPS, the window would take 10s to fill up.
+        for _ in 0..3 {
+            let sample = sampler.sample().await;
+            let count = sample.success_count() + sample.error_count();
+            assert!(count > 0 && count < 100, "{sample}");
+            assert!(sample.elapsed < Duration::from_millis(400), "{sample}");
+        }
+    }
+
+    #[test]
+    fn test_start_jitter() {
+        let tps_limit = NonZeroU32::new(100).unwrap();
+        let mut offsets: Vec<_> = (0..10).map(|id| start_jitter(id, tps_limit)).collect();
+        offsets.sort();
+
+        assert_eq!(offsets[0], Duration::ZERO);
+        assert!(offsets[9] < Duration::from_millis(10));
+        // NOTE: Golden ratio offsets leave no gap larger than ~2/n of the period.
+        for pair in offsets.windows(2) {
+            assert!(pair[1] - pair[0] < Duration::from_secs_f64(0.002));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_start_dispersion() {
+        let starts = Arc::new(Mutex::new(vec![]));
+        let spawned = Instant::now();
+
+        let scenario = {
+            let starts = starts.clone();
+            move || {
+                let starts = starts.clone();
+                async move {
+                    starts.lock().unwrap().push(spawned.elapsed());
+                    tokio::time::sleep(Duration::from_secs(3600)).await;
+                }
+            }
+        };
+
+        let mut sampler = BaseSampler::new(
+            &ScenarioConfig::new(""),
+            &Hooks::default(),
+            scenario,
+            NonZeroU32::new(20).unwrap(),
+        )
+        .await;
+        sampler.set_concurrency(20);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut starts = starts.lock().unwrap().clone();
+        starts.sort();
+        assert_eq!(starts.len(), 20);
+
+        // NOTE: Without jitter, every task starts within a fraction of a millisecond.
+        let spread = starts[19] - starts[0];
+        assert!(spread > Duration::from_millis(25), "spread: {spread:?}");
+        assert!(spread < Duration::from_millis(75), "spread: {spread:?}");
+    }
+
+    #[tokio::test]
+    async fn test_run_once_per_task() {
+        let mut config = ScenarioConfig::new("test_run_once_per_task");
+        config.run_once_per_task = true;
+
+        let runs = Arc::new(Mutex::new(vec![]));
+        let scenario = {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                async move {
+                    runs.lock().unwrap().push(tokio::task::id());
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        };
+
+        let mut sampler = BaseSampler::new(
+            &config,
+            &Hooks::default(),
+            scenario,
+            NonZeroU32::new(1_000).unwrap(),
+        )
+        .await;
+        sampler.set_concurrency(5);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(sampler.concurrency(), 5);
+        assert!(!sampler.has_failed_tasks());
+
+        sampler.set_concurrency(0);
+        let runs = runs.lock().unwrap().clone();
+        // NOTE: Finished tasks are replaced, so the 5 tasks run many more than 5 journeys...
+        assert!(runs.len() > 25, "{} runs", runs.len());
+        // ...each on a task of its own.
+        let tasks: std::collections::HashSet<_> = runs.iter().collect();
+        assert_eq!(tasks.len(), runs.len());
+    }
+
+    #[tokio::test]
+    async fn test_run_once_per_task_panic() {
+        let mut config = ScenarioConfig::new("test_run_once_per_task_panic");
+        config.run_once_per_task = true;
+
+        let mut sampler = BaseSampler::new(
+            &config,
+            &Hooks::default(),
+            || async { panic!("journey failed") },
+            NonZeroU32::new(1_000).unwrap(),
+        )
+        .await;
+        sampler.set_concurrency(2);
+        // NOTE: Unwinding is slow when backtraces are captured (`RUST_BACKTRACE`), so wait for
+        // both tasks to stop rather than a fixed time.
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if sampler.has_failed_tasks() && sampler.concurrency() == 0 {
+                break;
+            }
+        }
+
+        assert!(sampler.has_failed_tasks());
+        assert_eq!(sampler.reap_failed_tasks(), 2);
+        assert!(!sampler.has_failed_tasks());
+    }
+
+    // NOTE: Runs the tasks on the deterministic executor, through to the RNG each one draws from.
+    #[cfg(feature = "deterministic")]
+    #[tokio::test]
+    async fn test_deterministic_task_rngs() {
+        use crate::sampler::DETERMINISTIC_SEED;
+
+        let draws = Arc::new(Mutex::new(vec![]));
+        let scenario = {
+            let draws = draws.clone();
+            move || {
+                let draws = draws.clone();
+                async move {
+                    let drawn: Vec<u64> = (0..3).map(|_| with_rng(|rng| rng.gen())).collect();
+                    draws.lock().unwrap().push(drawn);
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+
+        let mut sampler = BaseSampler::new(
+            &ScenarioConfig::new("test_deterministic_task_rngs"),
+            &Hooks::default(),
+            scenario,
+            NonZeroU32::new(1_000).unwrap(),
+        )
+        .await;
+        sampler.set_concurrency(4);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        sampler.set_concurrency(0);
+
+        // NOTE: Without a seed, the n-th task is seeded from the fixed deterministic seed.
+        let mut expected: Vec<Vec<u64>> = (0..4)
+            .map(|task| {
+                let mut rng = SmallRng::seed_from_u64(DETERMINISTIC_SEED.wrapping_add(task));
+                (0..3).map(|_| rng.gen()).collect()
+            })
+            .collect();
+        expected.sort();
+
+        let mut draws = draws.lock().unwrap().clone();
+        draws.sort();
+        assert_eq!(draws, expected);
+    }
+
+    #[tokio::test]
+    async fn test_returned_task_not_failed() {
+        let mut sampler = BaseSampler::new(
+            &ScenarioConfig::new("test_returned_task_not_failed"),
+            &Hooks::default(),
+            mock_scenario!(Duration::from_millis(1), Duration::from_micros(10)),
+            NonZeroU32::new(1_000).unwrap(),
+        )
+        .await;
+        sampler.set_concurrency(2);
+        sampler.tasks.push(tokio::spawn(async {}));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(!sampler.has_failed_tasks());
+        assert_eq!(sampler.reap_failed_tasks(), 0);
+        assert_eq!(sampler.concurrency(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrency() {
+        let mut config = ScenarioConfig::new("test_max_concurrency");
+        config.max_concurrency = Some(8);
+
+        let mut sampler = BaseSampler::new(
+            &config,
+            &Hooks::default(),
+            mock_scenario!(Duration::from_millis(1), Duration::from_micros(10)),
+            NonZeroU32::new(1_000).unwrap(),
+        )
+        .await;
+        sampler.set_concurrency(8);
+        assert!(!sampler.concurrency_capped());
+
+        sampler.set_concurrency(250_000);
+        assert_eq!(sampler.concurrency(), 8);
+        assert!(sampler.concurrency_capped());
+
+        // NOTE: The run stays flagged once it was capped.
+        sampler.set_concurrency(4);
+        assert_eq!(sampler.concurrency(), 4);
+        assert!(sampler.concurrency_capped());
+        sampler.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_on_concurrency_change() {
+        let changes = Arc::new(Mutex::new(vec![]));
+        let hooks = Hooks {
+            on_concurrency_change: Some(Arc::new({
+                let changes = changes.clone();
+                move |concurrency| changes.lock().unwrap().push(concurrency)
+            })),
+            ..Default::default()
+        };
+
+        let mut sampler = BaseSampler::new(
+            &ScenarioConfig::new("test_on_concurrency_change"),
+            &hooks,
+            mock_scenario!(Duration::from_millis(1), Duration::from_micros(10)),
+            NonZeroU32::new(1_000).unwrap(),
+        )
+        .await;
+        sampler.set_concurrency(4);
+        sampler.set_concurrency(4);
+        sampler.set_concurrency(10);
+        sampler.set_concurrency(2);
+        sampler.shutdown().await;
+
+        // NOTE: Setting the same concurrency again isn't a change.
+        assert_eq!(*changes.lock().unwrap(), vec![4, 10, 2, 0]);
+    }
+
     /*
     #[tracing_test::traced_test]
     #[tokio::test]
     async fn test_slow() {
         let mut sampler = BaseSampler::new(
-            "",
+            &ScenarioConfig::new(""),
+            &Hooks::default(),
             mock_scenario!(Duration::from_millis(400), Duration::from_millis(100)),
             NonZeroU32::new(50).unwrap(),
         )
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Sampling window which closes once a fixed number of Transactions have completed (see
+/// `WindowMode::Count`).
+///
+/// Each Transaction claims one of the window's slots before it starts, so that every window holds
+/// exactly `size` Transactions. Once the slots run out, Transactions wait until the sampler has
+/// collected the window and opened the next one.
+///
+/// NOTE: A window which doesn't fill up in time is collected early. Only the slots recorded by
+/// then are handed back when the next window opens, so that the Transactions still in flight count
+/// towards it instead.
+pub(crate) struct CountWindow {
+    size: u32,
+    slots: Semaphore,
+    completed: Semaphore,
+}
+
+impl CountWindow {
+    pub fn new(size: usize) -> Self {
+        let size = u32::try_from(size).unwrap_or(u32::MAX);
+        Self {
+            size,
+            slots: Semaphore::new(size as usize),
+            completed: Semaphore::new(0),
+        }
+    }
+
+    /// Claim a slot in the current window, waiting for the next window if it is full.
+    pub async fn claim(self: &Arc<Self>) -> WindowSlot {
+        if let Ok(permit) = self.slots.acquire().await {
+            permit.forget();
+        }
+        WindowSlot {
+            window: self.clone(),
+            recorded: false,
+        }
+    }
+
+    /// Wait until every slot of the current window has been recorded, or at most `max`.
+    pub async fn wait_full(&self, max: Duration) {
+        // NOTE: The permits are only counted here, and taken when the next window opens.
+        let _ = tokio::time::timeout(max, self.completed.acquire_many(self.size)).await;
+    }
+
+    /// Open the next window, once the current one has been collected.
+    pub fn open(&self) {
+        let recorded = self.completed.available_permits().min(self.size as usize);
+        if let Ok(permit) = self.completed.try_acquire_many(recorded as u32) {
+            permit.forget();
+        }
+        self.slots.add_permits(recorded);
+    }
+}
+
+/// A slot claimed by a Transaction in a `CountWindow`.
+///
+/// NOTE: A slot which is dropped without being recorded (the Transaction's result was ignored, or
+/// its task was aborted) is handed back to the window for another Transaction.
+pub(crate) struct WindowSlot {
+    window: Arc<CountWindow>,
+    recorded: bool,
+}
+
+impl WindowSlot {
+    /// Mark the slot as recorded, once the Transaction's result is in the measurements.
+    pub fn recorded(mut self) {
+        self.recorded = true;
+        self.window.completed.add_permits(1);
+    }
+}
+
+impl Drop for WindowSlot {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.window.slots.add_permits(1);
+        }
+    }
+}
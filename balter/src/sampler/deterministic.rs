@@ -0,0 +1,70 @@
+//! Single-threaded task execution for reproducible runs (requires the `deterministic` feature).
+//!
+//! Rather than spawning Scenario tasks onto the (potentially multi-threaded) ambient Tokio
+//! runtime, all tasks are run on a dedicated thread within a `LocalSet`, so they are polled in a
+//! consistent order. This serializes all execution and is not intended for production load.
+use super::base_sampler::AbortOnDrop;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::{JoinHandle, LocalSet};
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Spawner = UnboundedSender<(Task, oneshot::Sender<JoinHandle<()>>)>;
+
+static SPAWNER: OnceLock<Spawner> = OnceLock::new();
+
+/// Spawn `task` onto the deterministic executor, returning a handle on the ambient runtime which
+/// relays to it.
+///
+/// NOTE: The caller never waits on the executor thread. Aborting the returned handle aborts the
+/// task (or, if it isn't spawned yet, the executor aborts it once it is), and a panic of the task
+/// is resumed by the relay, so the handle behaves as that of `tokio::spawn()`.
+pub(crate) fn spawn<F>(task: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let spawner = SPAWNER.get_or_init(start_executor);
+
+    let (tx, rx) = oneshot::channel();
+    spawner
+        .send((Box::pin(task), tx))
+        .expect("Deterministic executor has shut down.");
+
+    tokio::spawn(async move {
+        let handle = rx.await.expect("Deterministic executor has shut down.");
+        let _guard = AbortOnDrop(handle.abort_handle());
+        if let Err(err) = handle.await {
+            if err.is_panic() {
+                std::panic::resume_unwind(err.into_panic());
+            }
+        }
+    })
+}
+
+fn start_executor() -> Spawner {
+    let (tx, mut rx) = unbounded_channel::<(Task, oneshot::Sender<JoinHandle<()>>)>();
+
+    std::thread::Builder::new()
+        .name("balter-deterministic".to_string())
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Unable to build deterministic executor.");
+
+            LocalSet::new().block_on(&runtime, async move {
+                while let Some((task, reply)) = rx.recv().await {
+                    if let Err(handle) = reply.send(tokio::task::spawn_local(task)) {
+                        // NOTE: The relay was aborted before the task was spawned.
+                        handle.abort();
+                    }
+                }
+            });
+        })
+        .expect("Unable to spawn deterministic executor thread.");
+
+    tx
+}
@@ -0,0 +1,90 @@
+//! Self-monitoring of the load generator process.
+//!
+//! When Balter detects that it is TPS limited, the bottleneck is usually assumed to be the
+//! target. However, it is just as common for the load generator itself to be CPU-bound. This
+//! module samples the CPU usage of the Balter process so that the two cases can be told apart.
+//! Requires the `sysinfo` feature; otherwise no measurements are taken.
+#[cfg(feature = "sysinfo")]
+use sysinfo::{Pid, ProcessRefreshKind, System};
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Fraction of the total CPU capacity (all cores) above which the load generator is considered
+/// saturated.
+const CPU_SATURATION_THRESHOLD: f64 = 0.9;
+
+pub(crate) struct GeneratorMonitor {
+    #[cfg(feature = "sysinfo")]
+    system: System,
+    #[cfg(feature = "sysinfo")]
+    pid: Option<Pid>,
+    cpu_usage: Option<f64>,
+}
+
+impl GeneratorMonitor {
+    pub fn new() -> Self {
+        #[cfg(feature = "sysinfo")]
+        {
+            let pid = sysinfo::get_current_pid()
+                .map_err(|err| error!("Unable to monitor load generator CPU usage: {err}"))
+                .ok();
+            let mut monitor = Self {
+                system: System::new(),
+                pid,
+                cpu_usage: None,
+            };
+            // NOTE: CPU usage is calculated between refreshes, so the first refresh only sets the
+            // baseline.
+            monitor.refresh();
+            monitor.cpu_usage = None;
+            monitor
+        }
+
+        #[cfg(not(feature = "sysinfo"))]
+        Self { cpu_usage: None }
+    }
+
+    /// Measure the CPU usage of the load generator since the last call.
+    pub fn sample(&mut self) {
+        #[cfg(feature = "sysinfo")]
+        self.refresh();
+    }
+
+    /// CPU usage of the load generator as a fraction of the total CPU capacity.
+    #[allow(unused)]
+    pub fn cpu_usage(&self) -> Option<f64> {
+        self.cpu_usage
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.cpu_usage
+            .is_some_and(|usage| usage >= CPU_SATURATION_THRESHOLD)
+    }
+
+    #[cfg(feature = "sysinfo")]
+    fn refresh(&mut self) {
+        let Some(pid) = self.pid else {
+            return;
+        };
+
+        let refresh_kind = ProcessRefreshKind::new().with_cpu().with_memory();
+        if !self.system.refresh_process_specifics(pid, refresh_kind) {
+            error!("Unable to refresh load generator process information.");
+            return;
+        }
+
+        if let Some(process) = self.system.process(pid) {
+            let cores = std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1);
+            // NOTE: sysinfo reports CPU usage as a percentage of a single core.
+            let usage = process.cpu_usage() as f64 / (100. * cores as f64);
+            trace!(
+                "Load generator CPU usage: {:.1}%, memory: {}MB",
+                usage * 100.,
+                process.memory() / 1_000_000
+            );
+            self.cpu_usage = Some(usage);
+        }
+    }
+}
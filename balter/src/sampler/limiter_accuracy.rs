@@ -0,0 +1,106 @@
+use crate::measurement::Measurement;
+use balter_core::ScenarioConfig;
+use std::num::NonZeroU32;
+use std::time::Duration;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Fraction of a window's Transactions which must have waited for the TPS limit for the rate
+/// limiter to count as the bottleneck.
+const LIMITER_BOUND: f64 = 0.5;
+/// Error rate up to which the target counts as healthy, for a Scenario without an error rate
+/// goal.
+const NOMINAL_ERROR_RATE: f64 = 0.01;
+
+/// Tracks how closely the rate limiter delivers the TPS it is set to, see
+/// `RunStatistics::rate_limiter_accuracy`.
+///
+/// Only windows in which the rate limiter, rather than the target, was the bottleneck say
+/// anything about it: those where most Transactions had to wait for the TPS limit, and the
+/// target's error rate and latency were within the Scenario's goals. Over those, the accuracy
+/// is the number of Transactions delivered over the number the TPS limit allowed for.
+pub(crate) struct LimiterAccuracy {
+    max_error_rate: f64,
+    latency_goal: Option<(Duration, f64)>,
+    delivered: f64,
+    requested: f64,
+}
+
+impl LimiterAccuracy {
+    pub fn new(config: &ScenarioConfig) -> Self {
+        Self {
+            max_error_rate: config.error_rate.unwrap_or(NOMINAL_ERROR_RATE),
+            latency_goal: config
+                .latency
+                .map(|latency| (latency.latency, latency.quantile)),
+            delivered: 0.,
+            requested: 0.,
+        }
+    }
+
+    pub fn observe(&mut self, measurement: &Measurement, tps_limit: NonZeroU32) {
+        let slow = self
+            .latency_goal
+            .is_some_and(|(goal, quantile)| measurement.latency(quantile) > goal);
+
+        if measurement.limiter_wait_ratio() < LIMITER_BOUND
+            || measurement.error_rate > self.max_error_rate
+            || slow
+        {
+            return;
+        }
+
+        self.delivered += (measurement.success_count() + measurement.error_count()) as f64;
+        self.requested += tps_limit.get() as f64 * measurement.elapsed.as_secs_f64();
+    }
+
+    /// Fraction of the TPS limit the rate limiter delivered, or `0` if no window was limited by
+    /// it.
+    pub fn accuracy(&self) -> f64 {
+        if self.requested > 0. {
+            self.delivered / self.requested
+        } else {
+            0.
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use balter_core::LatencyConfig;
+
+    fn window(success: u64, error: u64, limiter_waits: u64) -> Measurement {
+        Measurement::new(success, error, Duration::from_secs(1)).with_limiter_waits(limiter_waits)
+    }
+
+    #[test]
+    fn test_limiter_accuracy() {
+        let mut config = ScenarioConfig::new("test_limiter_accuracy");
+        config.error_rate = Some(0.05);
+        let mut accuracy = LimiterAccuracy::new(&config);
+        let tps_limit = NonZeroU32::new(1_000).unwrap();
+        assert_eq!(accuracy.accuracy(), 0.);
+
+        accuracy.observe(&window(950, 10, 900), tps_limit);
+        accuracy.observe(&window(990, 0, 800), tps_limit);
+        // NOTE: Neither window was held back by the rate limiter.
+        accuracy.observe(&window(500, 0, 10), tps_limit);
+        accuracy.observe(&window(500, 400, 900), tps_limit);
+
+        assert_eq!(accuracy.accuracy(), 0.975);
+    }
+
+    #[test]
+    fn test_limiter_accuracy_latency_goal() {
+        let mut config = ScenarioConfig::new("test_limiter_accuracy_latency_goal");
+        config.latency = Some(LatencyConfig::new(Duration::from_millis(10), 0.99));
+        let mut accuracy = LimiterAccuracy::new(&config);
+
+        let mut slow = window(1_000, 0, 1_000);
+        slow.populate_latencies(&[Duration::from_millis(50); 100]);
+        accuracy.observe(&slow, NonZeroU32::new(1_000).unwrap());
+
+        assert_eq!(accuracy.accuracy(), 0.);
+    }
+}
@@ -0,0 +1,244 @@
+//! Emission of a Scenario's metrics at a fixed cadence (see `metrics_interval()`).
+//!
+//! Sampling windows adapt their duration, so metrics emitted once per window are irregularly
+//! spaced, which makes rate calculations jumpy. Instead, the sampler stores its latest state here
+//! and a background task emits it on a fixed wall-clock interval.
+use crate::measurement::Measurement;
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tokio::time::MissedTickBehavior;
+
+pub(crate) struct LiveMetrics {
+    state: Arc<State>,
+    task: AbortHandle,
+}
+
+impl LiveMetrics {
+    pub fn spawn(base_label: &str, interval: Duration) -> Self {
+        let state = Arc::new(State::default());
+        let labels = Labels::new(base_label);
+        let task = tokio::spawn(emit(state.clone(), labels, interval)).abort_handle();
+        Self { state, task }
+    }
+
+    pub fn set_goal_tps(&self, goal_tps: NonZeroU32) {
+        self.state.goal_tps.store(goal_tps.get(), Ordering::Relaxed);
+    }
+
+    pub fn set_concurrency(&self, concurrency: usize) {
+        self.state.concurrency.store(concurrency, Ordering::Relaxed);
+    }
+
+    pub fn record(&self, measurement: &Measurement) {
+        store_f64(&self.state.tps, measurement.tps);
+        store_f64(&self.state.error_rate, measurement.error_rate);
+        store_f64(
+            &self.state.latency_p50,
+            measurement.latency(0.5).as_secs_f64(),
+        );
+        store_f64(
+            &self.state.latency_p99,
+            measurement.latency(0.99).as_secs_f64(),
+        );
+    }
+}
+
+impl Drop for LiveMetrics {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Latest state of the sampler.
+///
+/// NOTE: `f64`s are stored as their bits, as there is no `AtomicF64`.
+#[derive(Default)]
+struct State {
+    goal_tps: AtomicU32,
+    concurrency: AtomicUsize,
+    tps: AtomicU64,
+    error_rate: AtomicU64,
+    latency_p50: AtomicU64,
+    latency_p99: AtomicU64,
+}
+
+struct Labels {
+    goal_tps: Arc<str>,
+    concurrency: Arc<str>,
+    tps: Arc<str>,
+    error_rate: Arc<str>,
+    latency_p50: Arc<str>,
+    latency_p99: Arc<str>,
+}
+
+impl Labels {
+    fn new(base_label: &str) -> Self {
+        Self {
+            goal_tps: Arc::from(format!("{base_label}_goal_tps")),
+            concurrency: Arc::from(format!("{base_label}_concurrency")),
+            tps: Arc::from(format!("{base_label}_tps")),
+            error_rate: Arc::from(format!("{base_label}_error_rate")),
+            latency_p50: Arc::from(format!("{base_label}_latency_p50")),
+            latency_p99: Arc::from(format!("{base_label}_latency_p99")),
+        }
+    }
+}
+
+async fn emit(state: Arc<State>, labels: Labels, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+
+        metrics::gauge!(labels.goal_tps.clone()).set(state.goal_tps.load(Ordering::Relaxed));
+        metrics::gauge!(labels.concurrency.clone())
+            .set(state.concurrency.load(Ordering::Relaxed) as f64);
+        metrics::gauge!(labels.tps.clone()).set(load_f64(&state.tps));
+        metrics::gauge!(labels.error_rate.clone()).set(load_f64(&state.error_rate));
+        metrics::gauge!(labels.latency_p50.clone()).set(load_f64(&state.latency_p50));
+        metrics::gauge!(labels.latency_p99.clone()).set(load_f64(&state.latency_p99));
+    }
+}
+
+fn store_f64(atomic: &AtomicU64, value: f64) {
+    atomic.store(value.to_bits(), Ordering::Relaxed);
+}
+
+fn load_f64(atomic: &AtomicU64) -> f64 {
+    f64::from_bits(atomic.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::Hooks;
+    use crate::sampler::BaseSampler;
+    use balter_core::ScenarioConfig;
+    use metrics::{
+        Counter, Gauge, GaugeFn, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    type Gauges = Arc<Mutex<HashMap<String, f64>>>;
+
+    /// Recorder which only keeps the latest value of each gauge.
+    #[derive(Default)]
+    struct GaugeRecorder(Gauges);
+
+    struct TestGauge(String, Gauges);
+
+    impl GaugeFn for TestGauge {
+        fn increment(&self, _: f64) {}
+        fn decrement(&self, _: f64) {}
+        fn set(&self, value: f64) {
+            self.1.lock().unwrap().insert(self.0.clone(), value);
+        }
+    }
+
+    impl Recorder for GaugeRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, _: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::noop()
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(Arc::new(TestGauge(key.name().to_string(), self.0.clone())))
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fixed_cadence() {
+        let recorder = GaugeRecorder::default();
+        let gauges = recorder.0.clone();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let live = LiveMetrics::spawn("balter_test", Duration::from_secs(1));
+        live.set_goal_tps(NonZeroU32::new(500).unwrap());
+        live.set_concurrency(12);
+        live.record(&Measurement::new(90, 10, Duration::from_secs(1)));
+
+        // NOTE: The first tick completes immediately, before any state was set.
+        tokio::time::sleep(Duration::from_millis(1_500)).await;
+        {
+            let gauges = gauges.lock().unwrap();
+            assert_eq!(gauges["balter_test_goal_tps"], 500.);
+            assert_eq!(gauges["balter_test_concurrency"], 12.);
+            assert_eq!(gauges["balter_test_tps"], 90.);
+            assert_eq!(gauges["balter_test_error_rate"], 0.1);
+        }
+
+        // Emitted again on the next tick, without any new state.
+        gauges.lock().unwrap().clear();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(gauges.lock().unwrap()["balter_test_goal_tps"], 500.);
+
+        drop(live);
+        gauges.lock().unwrap().clear();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(gauges.lock().unwrap().is_empty());
+    }
+
+    /// Names of the gauges emitted by a `BaseSampler` for `config`, either every window or (with
+    /// `interval`) by its `LiveMetrics`.
+    async fn emitted_names(mut config: ScenarioConfig, interval: Option<Duration>) -> Vec<String> {
+        let recorder = GaugeRecorder::default();
+        let gauges = recorder.0.clone();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        config.metrics_interval = interval;
+        let scenario: fn() -> std::future::Ready<()> = || std::future::ready(());
+        let mut sampler = BaseSampler::new(
+            &config,
+            &Hooks::default(),
+            scenario,
+            NonZeroU32::new(100).unwrap(),
+        )
+        .await;
+        sampler.set_tps_limit(NonZeroU32::new(200).unwrap());
+        sampler.set_concurrency(0);
+        tokio::time::sleep(Duration::from_millis(1_500)).await;
+
+        let names = gauges.lock().unwrap().keys().cloned().collect();
+        names
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_statsd_metric_names() {
+        // NOTE: Whether statsd would parse the whole name as a metric name, rather than as part
+        // of the value, type, sample rate or tags of the line.
+        let is_statsd_name = |name: &str| {
+            name.chars()
+                .all(|c| c.is_ascii_graphic() && !matches!(c, ':' | '|' | '@' | '#' | ','))
+        };
+
+        let mut config = ScenarioConfig::new("search @ p99, EU");
+        config.metrics_prefix = Some("team:search#1".to_string());
+
+        for interval in [None, Some(Duration::from_secs(1))] {
+            let names = emitted_names(config.clone(), interval).await;
+            assert!(!names.is_empty());
+            assert!(names.iter().all(|name| !is_statsd_name(name)), "{names:?}");
+
+            let mut sanitized = config.clone();
+            sanitized.sanitize_metric_names = true;
+            let names = emitted_names(sanitized, interval).await;
+            assert!(names.len() >= 2);
+            for name in names {
+                assert!(name.starts_with("team_search_1_search___p99__EU_"));
+                assert!(is_statsd_name(&name), "{name}");
+            }
+        }
+    }
+}
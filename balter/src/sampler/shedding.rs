@@ -0,0 +1,167 @@
+use std::time::Duration;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Ratio of latency between sample sets which counts as a sharp rise.
+const LATENCY_RISE: f64 = 1.5;
+/// Relative increase in TPS between sample sets which still counts as flat.
+const TPS_FLAT: f64 = 0.1;
+/// Ratio of latency to that before the shedding within which the target has recovered.
+const LATENCY_RECOVERED: f64 = 1.1;
+/// Growth of the ceiling for each sample set at which the target has recovered.
+const CEILING_GROWTH: f64 = 1.5;
+
+/// Sheds concurrency when Transactions are queueing at the target, see
+/// [`adaptive_concurrency_shedding()`](crate::scenario::ConfigurableScenario::adaptive_concurrency_shedding).
+///
+/// Queueing shows up as latency rising sharply while TPS stays flat: the extra concurrency is
+/// only adding requests to the target's queue. By Little's law, the concurrency which sustains
+/// the same TPS at the previous latency is `concurrency * previous_latency / latency`, so that
+/// is what we shed to. The reduced concurrency is then kept as a ceiling, so that the sampler
+/// doesn't immediately pile the requests back on.
+///
+/// The ceiling is raised again while latency stays at its level from before the shedding (the
+/// target has recovered, or was never degraded past the ceiling), and lifted once back at the
+/// concurrency it was shed from. If latency stays up, so does the ceiling.
+pub(crate) struct ConcurrencyShedder {
+    previous: Option<(f64, Duration)>,
+    ceiling: Option<usize>,
+    /// Latency before the first shedding since the ceiling was last lifted.
+    baseline: Option<Duration>,
+    /// Highest concurrency shed from since the ceiling was last lifted.
+    shed_from: usize,
+}
+
+impl ConcurrencyShedder {
+    pub fn new() -> Self {
+        Self {
+            previous: None,
+            ceiling: None,
+            baseline: None,
+            shed_from: 0,
+        }
+    }
+
+    /// Returns the reduced concurrency if Transactions are queueing.
+    pub fn check(&mut self, concurrency: usize, tps: f64, latency: Duration) -> Option<usize> {
+        // NOTE: No latency means no Transactions completed, which says nothing about queueing.
+        if latency.is_zero() {
+            return None;
+        }
+
+        let previous = self.previous.replace((tps, latency));
+        let (prev_tps, prev_latency) = previous?;
+
+        let latency_rise = latency.as_secs_f64() / prev_latency.as_secs_f64();
+        if latency_rise >= LATENCY_RISE && tps <= prev_tps * (1. + TPS_FLAT) {
+            let shed = ((concurrency as f64 / latency_rise).ceil() as usize).max(1);
+            if shed < concurrency {
+                debug!(
+                    "Latency rose from {prev_latency:?} to {latency:?} at {tps:.2} TPS, shedding concurrency from {concurrency} to {shed}"
+                );
+                self.ceiling = Some(shed);
+                self.baseline.get_or_insert(prev_latency);
+                self.shed_from = self.shed_from.max(concurrency);
+                return Some(shed);
+            }
+        }
+
+        self.check_recovered(latency);
+        None
+    }
+
+    /// Raise the ceiling if latency is back to where it was before the shedding.
+    fn check_recovered(&mut self, latency: Duration) {
+        let (Some(ceiling), Some(baseline)) = (self.ceiling, self.baseline) else {
+            return;
+        };
+
+        if latency.as_secs_f64() > baseline.as_secs_f64() * LATENCY_RECOVERED {
+            return;
+        }
+
+        let raised = ((ceiling as f64 * CEILING_GROWTH).ceil() as usize).max(ceiling + 1);
+        if raised >= self.shed_from {
+            debug!("Latency recovered to {latency:?}, lifting the concurrency ceiling");
+            self.ceiling = None;
+            self.baseline = None;
+            self.shed_from = 0;
+        } else {
+            debug!("Latency recovered to {latency:?}, raising the concurrency ceiling to {raised}");
+            self.ceiling = Some(raised);
+        }
+    }
+
+    /// Maximum concurrency since the last shedding, if any.
+    pub fn ceiling(&self) -> Option<usize> {
+        self.ceiling
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sheds_when_queueing() {
+        let mut shedder = ConcurrencyShedder::new();
+
+        assert_eq!(shedder.check(10, 400., Duration::from_millis(25)), None);
+        // NOTE: Five times the concurrency for the same TPS, so five times the latency.
+        assert_eq!(
+            shedder.check(50, 400., Duration::from_millis(125)),
+            Some(10)
+        );
+        assert_eq!(shedder.ceiling(), Some(10));
+    }
+
+    #[test]
+    fn test_ceiling_lifted_when_latency_recovers() {
+        let mut shedder = ConcurrencyShedder::new();
+
+        shedder.check(10, 400., Duration::from_millis(25));
+        assert_eq!(
+            shedder.check(50, 400., Duration::from_millis(125)),
+            Some(10)
+        );
+
+        // NOTE: Still queueing at the ceiling, so it holds.
+        assert_eq!(shedder.check(10, 400., Duration::from_millis(40)), None);
+        assert_eq!(shedder.ceiling(), Some(10));
+
+        // Latency is back to where it was before the shedding, so the ceiling is raised...
+        assert_eq!(shedder.check(10, 400., Duration::from_millis(25)), None);
+        assert_eq!(shedder.ceiling(), Some(15));
+        assert_eq!(shedder.check(15, 600., Duration::from_millis(26)), None);
+        assert_eq!(shedder.ceiling(), Some(23));
+        assert_eq!(shedder.check(23, 900., Duration::from_millis(25)), None);
+        assert_eq!(shedder.ceiling(), Some(35));
+        // ...until it's back at the concurrency it was shed from.
+        assert_eq!(shedder.check(35, 1_400., Duration::from_millis(25)), None);
+        assert_eq!(shedder.ceiling(), None);
+    }
+
+    #[test]
+    fn test_ceiling_held_while_degraded() {
+        let mut shedder = ConcurrencyShedder::new();
+
+        shedder.check(10, 400., Duration::from_millis(25));
+        shedder.check(50, 400., Duration::from_millis(125));
+        for _ in 0..10 {
+            assert_eq!(shedder.check(10, 400., Duration::from_millis(30)), None);
+        }
+        assert_eq!(shedder.ceiling(), Some(10));
+    }
+
+    #[test]
+    fn test_no_shedding_when_tps_scales() {
+        let mut shedder = ConcurrencyShedder::new();
+
+        assert_eq!(shedder.check(10, 400., Duration::from_millis(25)), None);
+        // NOTE: Latency rose, but so did TPS, so the extra concurrency is doing useful work.
+        assert_eq!(shedder.check(50, 1_000., Duration::from_millis(50)), None);
+        // A small rise in latency is noise.
+        assert_eq!(shedder.check(50, 1_000., Duration::from_millis(60)), None);
+        assert_eq!(shedder.ceiling(), None);
+    }
+}
@@ -1,36 +1,124 @@
-use crate::measurement::Measurement;
-use crate::transaction::TransactionData;
+use super::count_window::CountWindow;
+use crate::auth::TokenCache;
+use crate::barrier::ScenarioBarrier;
+use crate::hooks::Hooks;
+#[cfg(feature = "reqwest")]
+use crate::http::ConnectionDiagnostics;
+use crate::measurement::{
+    CustomMeasurements, ErrorCategories, LabeledLatencies, Measurement, RateLimits, SloLatencies,
+    Variants,
+};
+use crate::transaction::{default_seed, Cleanup, TransactionData, ERROR_WEIGHT_SCALE};
 use arc_swap::ArcSwap;
+use balter_core::{MeasurementStatistics, ScenarioConfig};
 use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
 use metrics_util::AtomicBucket;
+use rand::{rngs::SmallRng, SeedableRng};
+use std::any::Any;
+use std::collections::HashMap;
 use std::num::NonZeroU32;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 pub(crate) struct TaskAtomics {
-    limiter: Arc<ArcSwap<DefaultDirectRateLimiter>>,
+    /// `None` if Transactions run with no TPS limit (see `unbounded_concurrency()` and
+    /// `concurrency_ramp()`).
+    limiter: Option<Arc<ArcSwap<DefaultDirectRateLimiter>>>,
     tps_limit: NonZeroU32,
+    host_limiters: Arc<HashMap<String, DefaultDirectRateLimiter>>,
     success: Arc<AtomicU64>,
     error: Arc<AtomicU64>,
-    latency: Arc<AtomicBucket<Duration>>,
+    weighted_error: Arc<AtomicU64>,
+    /// Transactions which failed as the load generator ran out of local resources, see
+    /// `Outcome::ResourceExhausted`.
+    resource_exhausted: Arc<AtomicU64>,
+    in_flight: Arc<AtomicU64>,
+    limiter_waits: Arc<AtomicU64>,
+    /// Swapped out for an empty bucket at each phase boundary, see `reset_latencies()`.
+    latency: Arc<ArcSwap<AtomicBucket<Duration>>>,
+    latency_sample_rate: f64,
+    added_latency: Option<Duration>,
+    labeled_latency: Arc<LabeledLatencies>,
+    slo_latency: Arc<SloLatencies>,
+    rate_limits: Arc<RateLimits>,
+    variants: Arc<Variants>,
+    base_url: Option<Arc<str>>,
+    /// Prefix of the Scenario's metric names, see `ScenarioConfig::metrics_base_label()`.
+    base_label: Arc<str>,
+    custom: Arc<CustomMeasurements>,
+    phases: Arc<CustomMeasurements>,
+    error_categories: Arc<ErrorCategories>,
+    cleanup: Arc<Cleanup>,
+    /// Calls of `balter::fail_after()` so far, shared by every task of the Scenario.
+    fail_after: Arc<AtomicU64>,
+    classifier: Option<Arc<dyn Any + Send + Sync>>,
+    error_weight: Option<Arc<dyn Any + Send + Sync>>,
+    state: Option<Arc<dyn Any + Send + Sync>>,
+    token: Option<Arc<TokenCache>>,
+    /// Set if sampling windows hold a fixed number of Transactions (see `WindowMode::Count`).
+    count_window: Option<Arc<CountWindow>>,
+    /// Sized to the Scenario's concurrency, see `balter::barrier()`.
+    barrier: Arc<ScenarioBarrier>,
+    #[cfg(feature = "reqwest")]
+    connections: Arc<ConnectionDiagnostics>,
+    seed: u64,
+    /// Number of tasks whose RNG has been seeded, so that each gets a distinct one.
+    seeded_tasks: AtomicU64,
 }
 
 impl TaskAtomics {
-    pub fn new(tps_limit: NonZeroU32) -> Self {
+    pub fn new(config: &ScenarioConfig, hooks: &Hooks, tps_limit: NonZeroU32) -> Self {
         Self {
-            limiter: Arc::new(ArcSwap::new(Arc::new(rate_limiter(tps_limit)))),
+            limiter: (config.unbounded_concurrency.is_none() && config.concurrency_ramp.is_none())
+                .then(|| Arc::new(ArcSwap::new(Arc::new(rate_limiter(tps_limit))))),
             tps_limit,
+            host_limiters: Arc::new(
+                config
+                    .tps_per_host
+                    .iter()
+                    .map(|(host, tps)| (host.clone(), rate_limiter(*tps)))
+                    .collect(),
+            ),
             success: Arc::new(AtomicU64::new(0)),
             error: Arc::new(AtomicU64::new(0)),
-            latency: Arc::new(AtomicBucket::new()),
+            weighted_error: Arc::new(AtomicU64::new(0)),
+            resource_exhausted: Arc::new(AtomicU64::new(0)),
+            in_flight: Arc::new(AtomicU64::new(0)),
+            limiter_waits: Arc::new(AtomicU64::new(0)),
+            latency: Arc::new(ArcSwap::from_pointee(AtomicBucket::new())),
+            latency_sample_rate: config.latency_sample_rate.unwrap_or(1.),
+            added_latency: config.added_latency,
+            labeled_latency: Arc::new(LabeledLatencies::default()),
+            slo_latency: Arc::new(SloLatencies::default()),
+            rate_limits: Arc::new(RateLimits::default()),
+            variants: Arc::new(Variants::default()),
+            base_url: config.base_url.as_deref().map(Arc::from),
+            base_label: Arc::from(config.metrics_base_label()),
+            custom: Arc::new(CustomMeasurements::default()),
+            phases: Arc::new(CustomMeasurements::default()),
+            error_categories: Arc::new(ErrorCategories::default()),
+            cleanup: Arc::new(Cleanup::default()),
+            fail_after: Arc::new(AtomicU64::new(0)),
+            classifier: hooks.classifier.clone(),
+            error_weight: hooks.error_weight.clone(),
+            state: hooks.state.clone(),
+            token: hooks.token.clone(),
+            count_window: None,
+            barrier: Arc::new(ScenarioBarrier::new()),
+            #[cfg(feature = "reqwest")]
+            connections: Arc::new(ConnectionDiagnostics::default()),
+            seed: config.seed.unwrap_or_else(default_seed),
+            seeded_tasks: AtomicU64::new(0),
         }
     }
 
     pub fn set_tps_limit(&mut self, tps_limit: NonZeroU32) {
         if tps_limit != self.tps_limit {
             self.tps_limit = tps_limit;
-            self.limiter.store(Arc::new(rate_limiter(tps_limit)));
+            if let Some(limiter) = &self.limiter {
+                limiter.store(Arc::new(rate_limiter(tps_limit)));
+            }
         }
     }
 
@@ -38,21 +126,127 @@ impl TaskAtomics {
         self.tps_limit
     }
 
+    /// Close sampling windows once `size` Transactions have completed rather than on a timer.
+    ///
+    /// NOTE: Must be set before any tasks are spawned, since they only pick up the window when
+    /// cloning their `TransactionData`.
+    pub fn set_count_window(&mut self, size: usize) {
+        self.count_window = Some(Arc::new(CountWindow::new(size)));
+    }
+
+    pub fn count_window(&self) -> Option<&CountWindow> {
+        self.count_window.as_deref()
+    }
+
     pub fn clone_to_transaction_data(&self) -> TransactionData {
         TransactionData {
             limiter: self.limiter.clone(),
+            host_limiters: self.host_limiters.clone(),
             success: self.success.clone(),
             error: self.error.clone(),
+            weighted_error: self.weighted_error.clone(),
+            resource_exhausted: self.resource_exhausted.clone(),
+            in_flight: self.in_flight.clone(),
+            limiter_waits: self.limiter_waits.clone(),
             latency: self.latency.clone(),
+            latency_sample_rate: self.latency_sample_rate,
+            added_latency: self.added_latency,
+            labeled_latency: self.labeled_latency.clone(),
+            slo_latency: self.slo_latency.clone(),
+            rate_limits: self.rate_limits.clone(),
+            variants: self.variants.clone(),
+            base_url: self.base_url.clone(),
+            base_label: self.base_label.clone(),
+            custom: self.custom.clone(),
+            phases: self.phases.clone(),
+            error_categories: self.error_categories.clone(),
+            cleanup: self.cleanup.clone(),
+            fail_after: self.fail_after.clone(),
+            classifier: self.classifier.clone(),
+            error_weight: self.error_weight.clone(),
+            state: self.state.clone(),
+            token: self.token.clone(),
+            count_window: self.count_window.clone(),
+            barrier: self.barrier.clone(),
+            #[cfg(feature = "reqwest")]
+            connections: self.connections.clone(),
         }
     }
 
+    /// RNG for the next task of the Scenario. The n-th task spawned is always seeded the same
+    /// for a given seed, so a run can be reproduced with `seed()`.
+    pub fn task_rng(&self) -> SmallRng {
+        let task = self.seeded_tasks.fetch_add(1, Ordering::Relaxed);
+        SmallRng::seed_from_u64(self.seed.wrapping_add(task))
+    }
+
+    pub fn barrier(&self) -> &ScenarioBarrier {
+        &self.barrier
+    }
+
+    /// Number of Transactions so far which failed as the load generator ran out of local
+    /// resources.
+    pub fn resource_exhausted(&self) -> u64 {
+        self.resource_exhausted.load(Ordering::Relaxed)
+    }
+
+    /// Number of Transactions which are currently running.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn custom_measurements(&self) -> HashMap<String, MeasurementStatistics> {
+        self.custom.statistics()
+    }
+
+    /// Timings of the phases recorded via `balter::phase()`, in seconds.
+    pub fn phase_timings(&self) -> HashMap<String, MeasurementStatistics> {
+        self.phases.statistics()
+    }
+
+    pub fn error_categories(&self) -> HashMap<String, u64> {
+        self.error_categories.counts()
+    }
+
+    /// Take the connection diagnostics collected since the last call, see `WarmupReport`.
+    #[cfg(feature = "reqwest")]
+    pub fn warmup_report(&self) -> crate::WarmupReport {
+        self.connections.take()
+    }
+
+    /// Discard the custom measurements, phase timings and error categories recorded so far,
+    /// which are otherwise aggregated over the lifetime of the Scenario's tasks.
+    ///
+    /// NOTE: Latencies need no reset here, since they are collected per window.
+    pub fn reset_statistics(&self) {
+        self.custom.clear();
+        self.phases.clear();
+        self.error_categories.clear();
+    }
+
+    /// Start collecting latencies afresh, discarding those of the Transactions which are still
+    /// in flight: each Transaction records its latency into the bucket it started with, which is
+    /// dropped here rather than cleared.
+    pub fn reset_latencies(&self) {
+        self.latency.store(Arc::new(AtomicBucket::new()));
+    }
+
     pub fn collect(&self, elapsed: Duration) -> Measurement {
         let success = self.success.swap(0, Ordering::Relaxed);
         let error = self.error.swap(0, Ordering::Relaxed);
-        let mut measurements = Measurement::new(success, error, elapsed);
+        let weighted_error =
+            self.weighted_error.swap(0, Ordering::Relaxed) as f64 / ERROR_WEIGHT_SCALE;
+        let limiter_waits = self.limiter_waits.swap(0, Ordering::Relaxed);
+        let mut measurements = Measurement::new(success, error, elapsed)
+            .with_effective_error_rate(weighted_error / (success + error) as f64)
+            .with_limiter_waits(limiter_waits);
         self.latency
+            .load()
             .clear_with(|dur| measurements.populate_latencies(dur));
+        self.labeled_latency.collect_into(&mut measurements);
+        self.slo_latency.collect_into(&mut measurements);
+        self.rate_limits.collect_into(&mut measurements);
+        self.variants.collect_into(&mut measurements);
         measurements
     }
 }
@@ -64,3 +258,178 @@ fn rate_limiter(tps_limit: NonZeroU32) -> DefaultDirectRateLimiter {
             .allow_burst(NonZeroU32::new(1).unwrap()),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::{Controller, ErrorRateController};
+    use crate::transaction::{transaction_hook, with_rng, TASK_RNG, TRANSACTION_HOOK};
+    use balter_core::{TransactionLabels, BASE_TPS};
+    use rand::Rng;
+    use std::cell::RefCell;
+
+    const LABELS: TransactionLabels = TransactionLabels {
+        success: "",
+        error: "",
+        latency: "",
+        slo: None,
+    };
+
+    /// Run `count` Transactions, returning the number of latencies pushed into the bucket.
+    async fn latencies_recorded(latency_sample_rate: Option<f64>, count: u64) -> usize {
+        let mut config = ScenarioConfig::new("");
+        config.latency_sample_rate = latency_sample_rate;
+        let atomics = TaskAtomics::new(&config, &Hooks::default(), NonZeroU32::MAX);
+
+        let rng = RefCell::new(atomics.task_rng());
+        let transactions = TRANSACTION_HOOK.scope(atomics.clone_to_transaction_data(), async {
+            for _ in 0..count {
+                let _ = transaction_hook(LABELS, async { Ok::<_, ()>(()) }).await;
+            }
+        });
+        TASK_RNG.scope(rng, transactions).await;
+
+        // NOTE: Every Transaction still counts.
+        assert_eq!(atomics.success.load(Ordering::Relaxed), count);
+
+        let mut recorded = 0;
+        atomics
+            .latency
+            .load()
+            .clear_with(|dur| recorded += dur.len());
+        recorded
+    }
+
+    /// Values drawn through `with_rng()` by each of `tasks` tasks of a Scenario with `seed`.
+    async fn task_draws(seed: u64, tasks: usize) -> Vec<u64> {
+        let mut config = ScenarioConfig::new("");
+        config.seed = Some(seed);
+        let atomics = TaskAtomics::new(&config, &Hooks::default(), NonZeroU32::MAX);
+
+        let mut draws = vec![];
+        for _ in 0..tasks {
+            let rng = RefCell::new(atomics.task_rng());
+            draws.push(
+                TASK_RNG
+                    .scope(rng, async { with_rng(|rng| rng.gen()) })
+                    .await,
+            );
+        }
+        draws
+    }
+
+    #[tokio::test]
+    async fn test_seeded_task_rngs() {
+        let draws = task_draws(42, 3).await;
+        assert_eq!(draws, task_draws(42, 3).await);
+        assert_ne!(draws, task_draws(43, 3).await);
+
+        // NOTE: Each task gets its own random sequence.
+        assert_ne!(draws[0], draws[1]);
+        assert_ne!(draws[1], draws[2]);
+    }
+
+    /// Measurement of a window of 100 Transactions, 10 of which fail with a 400 and 1 with a 503.
+    async fn status_window(hooks: &Hooks) -> Measurement {
+        let atomics = TaskAtomics::new(&ScenarioConfig::new(""), hooks, NonZeroU32::MAX);
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                for i in 0..100 {
+                    let res = match i {
+                        0..10 => Err(400u16),
+                        10 => Err(503),
+                        _ => Ok(()),
+                    };
+                    let _ = transaction_hook(LABELS, async { res }).await;
+                }
+            })
+            .await;
+        atomics.collect(Duration::from_secs(1))
+    }
+
+    #[tokio::test]
+    async fn test_error_weight() {
+        let unweighted = status_window(&Hooks::default()).await;
+        assert_eq!(unweighted.error_rate, 0.11);
+        assert_eq!(unweighted.effective_error_rate(), 0.11);
+
+        let mut hooks = Hooks::default();
+        hooks.set_error_weight::<u16>(Box::new(|status| match status {
+            503 => 1.,
+            _ => 0.,
+        }));
+        let weighted = status_window(&hooks).await;
+        assert_eq!(weighted.error_rate, 0.11);
+        assert_eq!(weighted.effective_error_rate(), 0.01);
+
+        // NOTE: Against a 5% error rate goal, the 400s alone would halve the TPS, whereas the
+        // weighted window is well within the goal and the TPS keeps doubling.
+        let limit = |sample: &Measurement| {
+            let mut controller = ErrorRateController::new("", 0.05);
+            controller.limit(sample, true).get()
+        };
+        assert_eq!(limit(&unweighted), BASE_TPS.get() / 2);
+        assert_eq!(limit(&weighted), BASE_TPS.get() * 2);
+
+        // Weights outside of [0, 1] are clamped.
+        hooks.set_error_weight::<u16>(Box::new(|status| match status {
+            503 => 100.,
+            _ => f64::NAN,
+        }));
+        assert_eq!(status_window(&hooks).await.effective_error_rate(), 0.01);
+    }
+
+    #[tokio::test]
+    async fn test_latency_sample_rate() {
+        let count = 100_000;
+
+        let all = latencies_recorded(None, count).await;
+        assert_eq!(all, count as usize);
+
+        let sampled = latencies_recorded(Some(0.1), count).await;
+        assert!(
+            (8_000..12_000).contains(&sampled),
+            "recorded {sampled} of {count} latencies"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reset_latencies_drops_in_flight() {
+        let atomics =
+            TaskAtomics::new(&ScenarioConfig::new(""), &Hooks::default(), NonZeroU32::MAX);
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let in_flight = tokio::spawn(TRANSACTION_HOOK.scope(
+            atomics.clone_to_transaction_data(),
+            async move {
+                let _ = transaction_hook(LABELS, async move {
+                    let _ = released.await;
+                    Ok::<_, ()>(())
+                })
+                .await;
+            },
+        ));
+        while atomics.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        atomics.reset_latencies();
+        let data = atomics.clone_to_transaction_data();
+        TRANSACTION_HOOK
+            .scope(data, async {
+                let _ = transaction_hook(LABELS, async { Ok::<_, ()>(()) }).await;
+            })
+            .await;
+        let _ = release.send(());
+        in_flight.await.unwrap();
+
+        // NOTE: Both Transactions count, but only the latency of the one started after the reset
+        // is kept.
+        let mut recorded = 0;
+        atomics
+            .latency
+            .load()
+            .clear_with(|dur| recorded += dur.len());
+        assert_eq!(recorded, 1);
+        assert_eq!(atomics.success.load(Ordering::Relaxed), 2);
+    }
+}
@@ -30,26 +30,27 @@ impl Timer {
     }
 
     #[allow(unused)]
-    pub async fn set_interval_dur(&mut self, dur: Duration) {
-        if dur < Duration::from_secs(10) {
+    pub async fn set_interval_dur(&mut self, dur: Duration, max: Duration) {
+        if dur <= max {
             *self = Self::new(dur).await;
         } else {
-            error!("Balter's polling interval is greater than 10s. This is likely a sign of an issue; not increasing the polling interval.")
+            error!("Balter's polling interval of {dur:?} is greater than the maximum of {max:?}. This is likely a sign of an issue; not increasing the polling interval.")
         }
     }
 
-    #[allow(unused)]
     pub fn interval_dur(&self) -> Duration {
         self.interval_dur
     }
 
-    #[allow(unused)]
-    pub async fn double(&mut self) {
-        if self.interval_dur < Duration::from_secs(10) {
-            self.interval_dur *= 2;
-            *self = Self::new(self.interval_dur).await;
+    /// Double the sampling interval, clamped to `max`. Returns whether the interval grew.
+    pub async fn double(&mut self, max: Duration) -> bool {
+        let dur = (self.interval_dur * 2).min(max);
+        if dur > self.interval_dur {
+            *self = Self::new(dur).await;
+            true
         } else {
-            error!("Balter's Sampling interval is greater than 10s. This is likely a sign of an issue; not increasing the sampling interval.")
+            error!("Balter's Sampling interval has reached the maximum of {max:?}. This is likely a sign of an issue; not increasing the sampling interval.");
+            false
         }
     }
 }
@@ -0,0 +1,116 @@
+use crate::measurement::Measurement;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Relative difference in TPS within which two consecutive windows count as stable.
+const STABLE_TOLERANCE: f64 = 0.1;
+/// Most windows discarded while waiting for the Scenario to stabilize.
+const MAX_SKIP: usize = 5;
+
+/// Discards the windows at the start of a Scenario until its TPS has stabilized, so that the
+/// ramp-up of its tasks (connections being established, caches warming) isn't fed to the
+/// controllers.
+///
+/// Rather than a fixed number of windows, which is wasted time for a Scenario that is stable
+/// right away and too short for one that takes a while, windows are discarded until two
+/// consecutive windows are within `STABLE_TOLERANCE` of each other, both of which are then
+/// kept. After `MAX_SKIP` discarded windows the Scenario is taken as it is.
+pub(crate) struct Warmup {
+    held: Option<Measurement>,
+    skipped: usize,
+    done: bool,
+}
+
+impl Warmup {
+    pub fn new() -> Self {
+        Self {
+            held: None,
+            skipped: 0,
+            done: false,
+        }
+    }
+
+    /// Whether the Scenario has stabilized (or is no longer waited for).
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Windows to keep as of `measurement`: none while warming up, the stable pair once it has
+    /// stabilized, and each window as-is after that.
+    pub fn admit(&mut self, measurement: Measurement) -> Vec<Measurement> {
+        if self.done {
+            return vec![measurement];
+        }
+
+        match self.held.take() {
+            Some(held) if is_stable(held.tps, measurement.tps) => {
+                debug!("Stabilized after discarding {} windows", self.skipped);
+                self.done = true;
+                vec![held, measurement]
+            }
+            Some(_) if self.skipped + 1 >= MAX_SKIP => {
+                debug!("Not stabilized after discarding {MAX_SKIP} windows; no longer waiting");
+                self.skipped += 1;
+                self.done = true;
+                vec![measurement]
+            }
+            held => {
+                if held.is_some() {
+                    self.skipped += 1;
+                }
+                self.held = Some(measurement);
+                vec![]
+            }
+        }
+    }
+}
+
+fn is_stable(prev: f64, next: f64) -> bool {
+    (prev - next).abs() <= STABLE_TOLERANCE * prev.max(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Feed windows at the given TPS until the warmup keeps one, returning how many were
+    /// discarded and how many kept.
+    fn warm_up(tps: &[u64]) -> (usize, usize) {
+        let mut warmup = Warmup::new();
+        for &tps in tps {
+            let kept = warmup.admit(Measurement::new(tps, 0, Duration::from_secs(1)));
+            if !kept.is_empty() {
+                return (warmup.skipped, kept.len());
+            }
+        }
+        panic!("Warmup never completed");
+    }
+
+    #[test]
+    fn test_fast_stabilizing() {
+        assert_eq!(warm_up(&[980, 1_000, 1_010, 990]), (0, 2));
+    }
+
+    #[test]
+    fn test_slow_stabilizing() {
+        // NOTE: A slow ramp-up, e.g. a cold cache filling up.
+        assert_eq!(warm_up(&[200, 400, 600, 800, 950, 1_000, 1_000]), (4, 2));
+    }
+
+    #[test]
+    fn test_max_skip() {
+        let noisy = [100, 1_000, 100, 1_000, 100, 1_000, 100, 1_000];
+        assert_eq!(warm_up(&noisy), (MAX_SKIP, 1));
+    }
+
+    #[test]
+    fn test_kept_after_warmup() {
+        let mut warmup = Warmup::new();
+        warmup.admit(Measurement::new(1_000, 0, Duration::from_secs(1)));
+        warmup.admit(Measurement::new(1_000, 0, Duration::from_secs(1)));
+
+        let kept = warmup.admit(Measurement::new(100, 0, Duration::from_secs(1)));
+        assert_eq!(kept.len(), 1);
+    }
+}
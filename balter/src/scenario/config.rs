@@ -0,0 +1,2214 @@
+//! Options of a Scenario, see [`ConfigurableScenario`].
+use super::run::{
+    duration_problem, precondition_holds, preflight, require_tokio_runtime, run_checked_scenario,
+    run_phases, run_scenario, skipped, tokio_runtime, validate_config,
+};
+use super::{RunFuture, Scenario};
+use crate::auth::{Token, TokenCache};
+use crate::defaults::{apply_global_defaults, ScenarioDefaults};
+use crate::hints::Hint;
+use crate::hooks::Outcome;
+use crate::join::ScenarioError;
+use crate::measurement::Measurement;
+use crate::phases::{Phase, PhasedScenario, PhasesFuture};
+use crate::sweep::{CapacitySweepFuture, MaxTpsFuture, MaxTpsResult};
+use balter_core::{
+    ConcurrencyRamp, LatencyConfig, OverflowPolicy, RunStatistics, Weighting, WindowMode,
+};
+use std::{
+    collections::HashMap, fmt::Display, future::Future, num::NonZeroU32, path::Path, sync::Arc,
+    time::Duration,
+};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, instrument, trace, warn, Instrument};
+
+pub trait ConfigurableScenario<T: Send>: Future<Output = T> + Sized + Send {
+    fn error_rate(self, error_rate: f64) -> Self;
+    fn tps(self, tps: u32) -> Self;
+    fn latency(self, latency: Duration, quantile: f64) -> Self;
+    fn duration(self, duration: Duration) -> Self;
+    fn hint(self, hint: Hint) -> Self;
+    fn with_base_url(self, url: &str) -> Self;
+    fn fast_start(self) -> Self;
+    fn max_duration(self, cap: Duration) -> Self;
+    fn cooldown(self, ramp_down: Duration) -> Self;
+    fn require_peer_tag(self, tag: &str) -> Self;
+    fn tps_smoothing(self, alpha: f64) -> Self;
+    fn metrics_prefix(self, prefix: &str) -> Self;
+    fn backoff_on_rate_limit(self) -> Self;
+    fn require_tps(self, fraction: f64) -> Self;
+    fn collect_raw_samples(self) -> Self;
+    fn max_sample_interval(self, max: Duration) -> Self;
+    fn adaptive_concurrency_shedding(self) -> Self;
+    fn tps_tolerance(self, fraction: f64) -> Self;
+    fn detect_state_drift(self, enabled: bool) -> Self;
+    fn max_transactions(self, max: u64) -> Self;
+    fn tps_per_host(self, limits: HashMap<String, u32>) -> Self;
+    fn metrics_interval(self, interval: Duration) -> Self;
+    fn latency_sample_rate(self, rate: f64) -> Self;
+    fn descend_from(self, start_tps: u32) -> Self;
+    fn tps_floor(self, min: u32) -> Self;
+    fn run_once_per_task(self) -> Self;
+    #[cfg(feature = "reqwest")]
+    fn require_header(self, name: &str, value: &str) -> Self;
+    fn trace_to(self, path: impl AsRef<Path>) -> Self;
+    fn trace_overflow(self, policy: OverflowPolicy) -> Self;
+    #[cfg(feature = "influxdb")]
+    fn persist_to(self, dsn: &str) -> Self;
+    fn unbounded_concurrency(self, concurrency: usize) -> Self;
+    fn at_concurrency(self, concurrency: usize) -> Self;
+    fn max_concurrency(self, concurrency: usize) -> Self;
+    fn sample_weighting(self, weighting: Weighting) -> Self;
+    fn window_mode(self, mode: WindowMode) -> Self;
+    fn seed(self, seed: u64) -> Self;
+    #[cfg(feature = "indicatif")]
+    fn with_progress_bar(self) -> Self;
+    fn concurrency_ramp(self, start: usize, end: usize, duration: Duration) -> Self;
+    fn underpowered_slope_threshold(self, threshold: f64) -> Self;
+    fn apply_template(self, template: &ScenarioDefaults) -> Self;
+    fn classify<R, E>(
+        self,
+        classifier: impl Fn(&Result<R, E>) -> Outcome + Send + Sync + 'static,
+    ) -> Self
+    where
+        R: 'static,
+        E: 'static;
+    fn error_weight<E: 'static>(self, weight: impl Fn(&E) -> f64 + Send + Sync + 'static) -> Self;
+    fn dynamic_goal(
+        self,
+        f: impl Fn(&[Measurement]) -> Option<NonZeroU32> + Send + Sync + 'static,
+    ) -> Self;
+    fn with_state<S: Send + Sync + 'static>(self, state: S) -> Self;
+    fn with_token_provider<P, Fut, E>(self, provider: P) -> Self
+    where
+        P: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Token, E>> + Send + 'static,
+        E: Display;
+    fn precondition<P, Fut>(self, precondition: P) -> Self
+    where
+        P: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static;
+    fn on_concurrency_change(self, f: impl Fn(usize) + Send + Sync + 'static) -> Self;
+    fn capacity_sweep(
+        self,
+        concurrency: usize,
+        tps_steps: Vec<u32>,
+        step_duration: Duration,
+    ) -> CapacitySweepFuture;
+    fn find_max_tps(self) -> MaxTpsFuture;
+
+    /// Run the Scenario as a sequence of named phases, see [`PhasedScenario`].
+    fn phased(self) -> PhasedScenario<Self, T> {
+        PhasedScenario::new(self)
+    }
+
+    #[doc(hidden)]
+    fn run_phases(self, phases: Vec<Phase<Self>>) -> PhasesFuture<T>;
+    fn run(self) -> RunFuture<T>;
+}
+
+impl<T, F> ConfigurableScenario<RunStatistics> for Scenario<T>
+where
+    T: Fn() -> F + Send + 'static + Clone + Sync,
+    F: Future<Output = ()> + Send,
+{
+    /// Run the scenario at the specified TPS.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         // Scale scenario until 5K TPS
+    ///         .tps(5_000)
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the provided TPS is zero
+    fn tps(mut self, tps: u32) -> Self {
+        self.config.max_tps =
+            Some(NonZeroU32::new(tps).expect("TPS provided must be non-zero. Given: {tps}"));
+        self
+    }
+
+    /// Run the scenario increasing TPS until a custom error rate is reached.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         // Scale scenario until 25% error rate
+    ///         .error_rate(0.25)
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the error_rate is not between 0 and 1.
+    fn error_rate(mut self, error_rate: f64) -> Self {
+        if !(0. ..=1.).contains(&error_rate) {
+            panic!(
+                "Specified error rate must be between 0 and 1. Value provided was {error_rate}."
+            );
+        }
+        self.config.error_rate = Some(error_rate);
+        self
+    }
+
+    /// Run the scenario up to the specified latency, given a quantile.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    /// use std::num::NonZeroU32;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         // Scale scenario until p95 latency is 200ms
+    ///         .latency(Duration::from_millis(200), 0.95)
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the quantile is not between 0 and 1.
+    fn latency(mut self, latency: Duration, quantile: f64) -> Self {
+        if !(0. ..=1.).contains(&quantile) {
+            panic!("Specified quantile must be between 0 and 1. Value provided was {quantile}.");
+        }
+
+        self.config.latency = Some(LatencyConfig::new(latency, quantile));
+        self
+    }
+
+    /// Run the scenario for the given duration.
+    ///
+    /// NOTE: This method doesn't make much sense without one of the other
+    /// load-testing methods (`tps()`/`error_rate()`/`latency()`)
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    /// use std::num::NonZeroU32;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(10_000)
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn duration(mut self, duration: Duration) -> Self {
+        self.config.duration = Some(duration);
+        self
+    }
+
+    /// Apply a hint for how to run the Scenario
+    ///
+    /// By default Balter attempts to autoscale all parameters to find the optimal values for
+    /// various scenarios. However, this process can be slow due to the control loop processes
+    /// underneath (and the requirements to be adaptable to all sorts of timing
+    /// characteristics).
+    ///
+    /// This method allows providing hints to Balter to speed up finding optimal
+    /// parameters. See [Hint] for more information.
+    fn hint(mut self, hint: Hint) -> Self {
+        match hint {
+            Hint::Concurrency(concurrency) => {
+                self.config.hints.concurrency = concurrency;
+            }
+            Hint::ExpectedLatency(latency) => {
+                self.config.hints.expected_latency = Some(latency);
+            }
+        }
+        self
+    }
+
+    /// Set the base URL for the Scenario, retrievable within transactions via
+    /// [`balter::base_url()`](crate::base_url).
+    ///
+    /// This allows running the same Scenario against different environments (staging,
+    /// production, etc.) without recompiling or relying on globals.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         .with_base_url("https://staging.example.com")
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    ///     my_transaction().await;
+    /// }
+    ///
+    /// #[transaction]
+    /// async fn my_transaction() -> Result<(), String> {
+    ///     let url = format!("{}/api", balter::base_url().unwrap());
+    ///     // Some request logic using `url`...
+    ///     Ok(())
+    /// }
+    /// ```
+    fn with_base_url(mut self, url: &str) -> Self {
+        self.config.base_url = Some(url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Calibrate the starting TPS with a probe measurement.
+    ///
+    /// By default the error rate search starts at a conservative TPS and doubles until errors
+    /// appear, which can take many sampling windows for services which handle tens of thousands
+    /// of TPS. With `fast_start()`, Balter first measures the TPS achievable at the starting
+    /// concurrency (without a TPS limit) and starts the search from there.
+    ///
+    /// NOTE: The probe sends load as fast as the starting concurrency allows, so avoid this for
+    /// fragile targets.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .error_rate(0.03)
+    ///         .fast_start()
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn fast_start(mut self) -> Self {
+        self.config.fast_start = true;
+        self
+    }
+
+    /// Set a hard ceiling on how long the Scenario can run.
+    ///
+    /// Unlike [`duration()`](Self::duration), which sets the length of the run, this is a safety
+    /// cap which terminates the Scenario regardless of what it is doing (e.g. an error rate search
+    /// which never stabilizes). A capped run returns the statistics of the latest measurement
+    /// window, with [`RunStatistics::capped`] set.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let stats = my_scenario()
+    ///         .error_rate(0.03)
+    ///         .max_duration(Duration::from_secs(600))
+    ///         .await;
+    ///
+    ///     assert!(!stats.capped);
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn max_duration(mut self, cap: Duration) -> Self {
+        self.config.max_duration = Some(cap);
+        self
+    }
+
+    /// Ramp the load down gradually once the run is complete.
+    ///
+    /// Rather than dropping straight from the goal TPS to zero, which can cause the target's
+    /// autoscaler to thrash, the TPS is linearly ramped down to a small floor over `ramp_down`
+    /// before the Scenario shuts down. This is useful for observing how a system recovers as the
+    /// load decreases.
+    ///
+    /// NOTE: The cooldown phase is excluded from the returned [`RunStatistics`], which only
+    /// cover the main run. Each cooldown measurement window is logged at the `info` level
+    /// (including its p99 latency). The cooldown is skipped if the run
+    /// [`failed`](RunStatistics::failed) or was [`capped`](RunStatistics::capped), and is
+    /// otherwise still bounded by [`max_duration()`](Self::max_duration).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(10_000)
+    ///         .duration(Duration::from_secs(300))
+    ///         .cooldown(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn cooldown(mut self, ramp_down: Duration) -> Self {
+        self.config.cooldown = Some(ramp_down);
+        self
+    }
+
+    /// Only delegate work to peers with the given tag (requires the `rt` feature to be useful).
+    ///
+    /// Peers are tagged via the runtime (e.g. `-t gpu`), and can be required multiple times, in
+    /// which case a peer must have all of the tags. This has no effect on the server the
+    /// Scenario was started on.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(50_000)
+    ///         .duration(Duration::from_secs(300))
+    ///         .require_peer_tag("gpu")
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn require_peer_tag(mut self, tag: &str) -> Self {
+        self.config.peer_tags.push(tag.to_string());
+        self
+    }
+
+    /// Smooth TPS measurements with an exponential moving average rather than a flat mean.
+    ///
+    /// By default, the sampling windows used to judge whether the TPS has stabilized are weighed
+    /// equally. With smoothing, recent windows are weighed more heavily, by a factor of `alpha`.
+    /// A higher `alpha` responds faster to changes in the target's capacity, but is noisier
+    /// (`1.0` only considers the latest window), while a lower `alpha` is more stable but slower
+    /// to react. The smoothed TPS is what the controllers are fed.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .error_rate(0.03)
+    ///         .tps_smoothing(0.5)
+    ///         .duration(Duration::from_secs(300))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if alpha is not within (0, 1].
+    fn tps_smoothing(mut self, alpha: f64) -> Self {
+        if !(alpha > 0. && alpha <= 1.) {
+            panic!("Specified smoothing alpha must be within (0, 1]. Value provided was {alpha}.");
+        }
+        self.config.tps_smoothing = Some(alpha);
+        self
+    }
+
+    /// Override the `balter` prefix of the Scenario's metrics (requires the `metrics` feature).
+    ///
+    /// Scenario metrics are named `balter_{scenario}_*` by default (e.g.
+    /// `balter_checkout_goal_tps`), which can be changed to fit into an existing metrics
+    /// namespace. Transaction metrics are named after the Transaction and are unaffected.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     // Emits `loadtest_checkout_goal_tps`, `loadtest_checkout_concurrency`, etc.
+    ///     checkout()
+    ///         .tps(1_000)
+    ///         .metrics_prefix("loadtest")
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn checkout() {
+    /// }
+    /// ```
+    fn metrics_prefix(mut self, prefix: &str) -> Self {
+        self.config.metrics_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Back off when the target rejects Transactions via rate limiting (e.g. HTTP 429).
+    ///
+    /// Rejections are reported from Transactions with
+    /// [`record_rate_limited()`](crate::record_rate_limited), or with
+    /// [`check_rate_limit()`](crate::check_rate_limit) for `reqwest` responses (requires the
+    /// `reqwest` feature). The goal TPS is then reduced in proportion to the fraction of rejected
+    /// Transactions, held for at least the longest `Retry-After` seen, and gradually raised again
+    /// once rejections stop. This applies on top of any other goal (e.g. `.tps()`), so Balter
+    /// behaves as a well-mannered client of rate-limited services.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         .backoff_on_rate_limit()
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    ///     my_transaction().await;
+    /// }
+    ///
+    /// #[transaction]
+    /// async fn my_transaction() -> Result<(), String> {
+    ///     let status = 429;
+    ///     if status == 429 {
+    ///         balter::record_rate_limited(None);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn backoff_on_rate_limit(mut self) -> Self {
+        self.config.rate_limit_backoff = true;
+        self
+    }
+
+    /// Require the final TPS to be within a fraction of the goal, for capacity-requirement checks.
+    ///
+    /// At the end of the run, the TPS of the final measurement window is compared against the
+    /// `.tps()` goal (or the controllers' goal if none was set), and
+    /// [`RunStatistics::tps_goal_met`] is set to `false` if it fell short of `fraction` of it.
+    /// Unlike [`RunStatistics::tps_limited`], which reports that Balter discovered a limit, this
+    /// is a pass/fail assertion of the user's requirement.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let stats = my_scenario()
+    ///         .tps(10_000)
+    ///         // Fail if we don't reach at least 9,500 TPS
+    ///         .require_tps(0.95)
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    ///
+    ///     assert!(stats.tps_goal_met);
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if fraction is not within (0, 1].
+    fn require_tps(mut self, fraction: f64) -> Self {
+        if !(fraction > 0. && fraction <= 1.) {
+            panic!("Specified TPS fraction must be within (0, 1]. Value provided was {fraction}.");
+        }
+        self.config.required_tps_fraction = Some(fraction);
+        self
+    }
+
+    /// Include the raw measurement windows of the final sample set in the
+    /// [`RunStatistics::raw_samples`], for offline analysis (e.g. when tuning controllers).
+    ///
+    /// Each [`SampleData`](balter_core::SampleData) holds the success and error counts and the
+    /// length of a single window. With the `rt` feature they can be serialized, for example to
+    /// JSON for analysis in Python or R.
+    ///
+    /// NOTE: Only the windows of the final sample set (which the last controller decision was
+    /// based on) are captured, not the whole run. This is opt-in since it bloats the statistics.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let stats = my_scenario()
+    ///         .tps(1_000)
+    ///         .collect_raw_samples()
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    ///
+    ///     for sample in stats.raw_samples.unwrap_or_default() {
+    ///         println!("{},{},{}", sample.success, sample.error, sample.elapsed.as_secs_f64());
+    ///     }
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn collect_raw_samples(mut self) -> Self {
+        self.config.collect_raw_samples = true;
+        self
+    }
+
+    /// Set the maximum length of a sampling window (60s by default).
+    ///
+    /// If no Transaction completes within a sampling window, Balter doubles the window length
+    /// until one does, up to this maximum. Raise it for very slow targets, such as a batch
+    /// endpoint with a latency of several minutes.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     batch_scenario()
+    ///         .tps(10)
+    ///         .max_sample_interval(Duration::from_secs(300))
+    ///         .duration(Duration::from_secs(3600))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn batch_scenario() {
+    /// }
+    /// ```
+    fn max_sample_interval(mut self, max: Duration) -> Self {
+        self.config.max_sample_interval = Some(max);
+        self
+    }
+
+    /// Reduce concurrency when the target starts queueing Transactions.
+    ///
+    /// By default concurrency is only ever increased. If the target degrades mid-run, the extra
+    /// concurrency just adds to its queue, raising latency further without any gain in TPS. In
+    /// this mode, a sharp rise in latency while TPS stays flat sheds concurrency back to what
+    /// sustains the same TPS at the previous latency. Concurrency is not raised past that until
+    /// latency is back to where it was before the shedding, from when the ceiling is gradually
+    /// raised again.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(10_000)
+    ///         .adaptive_concurrency_shedding()
+    ///         .duration(Duration::from_secs(600))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn adaptive_concurrency_shedding(mut self) -> Self {
+        self.config.adaptive_concurrency_shedding = true;
+        self
+    }
+
+    /// Set how close the measured TPS must be to the goal TPS for the sampler to consider it
+    /// reached, as a fraction of the goal (0.02 by default).
+    ///
+    /// Until the goal is reached, concurrency keeps being adjusted and the controllers treat the
+    /// measurements as unstable. A tighter tolerance targets the TPS more precisely, while a
+    /// looser one avoids chasing noise on targets with variable throughput.
+    ///
+    /// The same fraction is how close the measured latency must be to a
+    /// [`latency()`](Self::latency) goal or `#[transaction(latency_slo = ...)]` for its controller
+    /// to be stable (0.05 by default).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         .tps_tolerance(0.01)
+    ///         .duration(Duration::from_secs(300))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the fraction is not within (0, 0.5).
+    fn tps_tolerance(mut self, fraction: f64) -> Self {
+        if !(fraction > 0. && fraction < 0.5) {
+            panic!(
+                "Specified TPS tolerance must be within (0, 0.5). Value provided was {fraction}."
+            );
+        }
+        self.config.tps_tolerance = Some(fraction);
+        self
+    }
+
+    /// Warn if the error rate climbs steadily while the TPS stays the same (enabled by default
+    /// in debug builds).
+    ///
+    /// A sustained rise in errors without any increase in load usually means the target is
+    /// accumulating state, for example because a Transaction isn't idempotent and creates
+    /// records without cleaning them up. This only logs a warning (with the slope of the error
+    /// rate) and has no effect on the run.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         .detect_state_drift(true)
+    ///         .duration(Duration::from_secs(3600))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn detect_state_drift(mut self, enabled: bool) -> Self {
+        self.config.state_drift_detection = Some(enabled);
+        self
+    }
+
+    /// Stop the Scenario once `max` Transactions have been run, e.g. to bound the cost of a test
+    /// against a paid API. Every Transaction counts, whether it succeeded or not.
+    ///
+    /// With the distributed runtime, the cap is on the total number of Transactions across all
+    /// peers running the Scenario: each peer gossips its count, and every peer stops once the
+    /// counts it knows of add up to `max`.
+    ///
+    /// NOTE: The cap is soft. The count is checked after each measurement window, so a single
+    /// server may overshoot by up to a window's worth of Transactions. Across peers, counts are
+    /// only as fresh as the last gossip (every few seconds), so the cluster may overshoot by up
+    /// to a gossip interval's worth of its combined TPS. Leave headroom accordingly.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         .max_transactions(100_000)
+    ///         .duration(Duration::from_secs(3600))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `max` is 0.
+    fn max_transactions(mut self, max: u64) -> Self {
+        if max == 0 {
+            panic!("Specified max transactions must be greater than 0.");
+        }
+        self.config.max_transactions = Some(max);
+        self
+    }
+
+    /// Limit the TPS of each host independently, for Scenarios which hit multiple services.
+    ///
+    /// Transactions run within [`balter::with_host()`](crate::with_host) are subject to the
+    /// limit of their host, in addition to the Scenario's own TPS limit. Transactions against
+    /// other hosts are unaffected.
+    ///
+    /// NOTE: Per-host limits are fixed; the controllers (e.g. `.tps()` or `.error_rate()`) only
+    /// set the Scenario's combined goal TPS. If a host's limit holds back the Scenario such that
+    /// its goal can't be reached, Balter increases concurrency until TPS stops increasing, and
+    /// then settles just under the highest TPS reached (as it would for an underpowered Scenario). With
+    /// the distributed runtime, the limits apply to each server separately.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::collections::HashMap;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_200)
+    ///         .tps_per_host(HashMap::from([
+    ///             ("users.example.com".to_string(), 1_000),
+    ///             ("billing.example.com".to_string(), 200),
+    ///         ]))
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if any of the limits is 0.
+    fn tps_per_host(mut self, limits: HashMap<String, u32>) -> Self {
+        for (host, tps) in limits {
+            let Some(tps) = NonZeroU32::new(tps) else {
+                panic!("Specified TPS for host {host} must be greater than 0.");
+            };
+            self.config.tps_per_host.insert(host, tps);
+        }
+        self
+    }
+
+    /// Emit the Scenario's metrics at a fixed interval (requires the `metrics` feature).
+    ///
+    /// Sampling windows adapt their duration, so by default the Scenario's metrics are emitted
+    /// at an irregular cadence, which makes rate calculations (e.g. in Grafana) jumpy. With a
+    /// fixed interval, a background task instead emits the latest goal TPS and concurrency, as
+    /// well as the latest measured TPS, error rate and latency (`_tps`, `_error_rate`,
+    /// `_latency_p50` and `_latency_p99`), every `interval`.
+    ///
+    /// NOTE: The measurements are still taken once per sampling window, so consecutive points
+    /// repeat the same values until the next window completes. Controller metrics (e.g.
+    /// `_erc_goal_tps`) and Transaction metrics are unaffected.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         .metrics_interval(Duration::from_secs(1))
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `interval` is zero.
+    fn metrics_interval(mut self, interval: Duration) -> Self {
+        if interval.is_zero() {
+            panic!("Specified metrics interval must be greater than zero.");
+        }
+        self.config.metrics_interval = Some(interval);
+        self
+    }
+
+    /// Only record the latency of a random fraction of Transactions, to reduce the overhead of
+    /// latency measurement at very high TPS (e.g. `0.1` records 10% of latencies).
+    ///
+    /// Success and error counts still include every Transaction, so TPS and error rate are
+    /// unaffected. Latency percentiles are estimated from the recorded fraction instead, which
+    /// makes tail latencies (e.g. p99) noisier: a window needs roughly `1 / rate` times as many
+    /// Transactions for the same accuracy. Only use this where TPS is high enough that each
+    /// window still records plenty of latencies, particularly with `.latency()`, whose
+    /// controller is driven by the measured latency.
+    ///
+    /// NOTE: Only the Scenario's latency measurements are sampled. Transaction latency metrics,
+    /// latency SLOs (see `#[transaction(latency_slo = ...)]`) and per-variant latencies still
+    /// record every Transaction.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(500_000)
+    ///         .latency_sample_rate(0.1)
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the rate is not within (0, 1].
+    fn latency_sample_rate(mut self, rate: f64) -> Self {
+        if !(rate > 0. && rate <= 1.) {
+            panic!(
+                "Specified latency sample rate must be within (0, 1]. Value provided was {rate}."
+            );
+        }
+        self.config.latency_sample_rate = Some(rate);
+        self
+    }
+
+    /// Search for the `error_rate()` downwards, starting at the given TPS.
+    ///
+    /// By default, the error rate search starts low and ramps up until the error rate is
+    /// exceeded, which stresses the target hard while overshooting. For fragile targets, this
+    /// instead starts at a known safe-high TPS and decreases until the error rate is within
+    /// bounds. The goal TPS never exceeds `start_tps`, and once the error rate is met it is not
+    /// increased again.
+    ///
+    /// NOTE: Only applies together with `error_rate()`, and takes precedence over
+    /// `fast_start()`. Other goals (e.g. `tps()` or `latency()`) still apply, with the lowest
+    /// goal TPS winning.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .error_rate(0.01)
+    ///         .descend_from(5_000)
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the starting TPS is zero.
+    fn descend_from(mut self, start_tps: u32) -> Self {
+        let Some(start_tps) = NonZeroU32::new(start_tps) else {
+            panic!("Specified starting TPS must be non-zero.");
+        };
+        self.config.descend_from = Some(start_tps);
+        self
+    }
+
+    /// Never decrease the goal TPS of the `error_rate()` search below `min`.
+    ///
+    /// Against a target which errors at any load, the error rate controller otherwise keeps
+    /// backing off towards 1 TPS, and spends the rest of the run there. With a floor, the goal
+    /// TPS stops at `min`, and if the error rate is still exceeded there the run is terminated
+    /// early, returning statistics with `error_target_unachievable` set: the target can't meet
+    /// the error rate at any useful load.
+    ///
+    /// NOTE: Only applies together with `error_rate()`. The search also starts no lower than the
+    /// floor.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let stats = my_scenario()
+    ///         .error_rate(0.01)
+    ///         .tps_floor(100)
+    ///         .duration(Duration::from_secs(300))
+    ///         .await;
+    ///
+    ///     assert!(!stats.error_target_unachievable);
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the floor is zero.
+    fn tps_floor(mut self, min: u32) -> Self {
+        let Some(min) = NonZeroU32::new(min) else {
+            panic!("Specified TPS floor must be non-zero.");
+        };
+        self.config.tps_floor = Some(min);
+        self
+    }
+
+    /// Run the Scenario once per task, replacing each task with a new one when it completes,
+    /// rather than running it in a loop.
+    ///
+    /// By default a Scenario runs closed-loop: each task calls the Scenario over and over (which
+    /// is why a Scenario doesn't need a loop of its own), so state local to the Scenario function
+    /// is carried from one iteration to the next. With `run_once_per_task()`, a Scenario is
+    /// instead one complete user journey (e.g. log in, browse, check out) run by a fresh virtual
+    /// user: every run is a new task, and a new one is spawned as soon as a run completes, so the
+    /// concurrency (and with it the arrival rate of the goal TPS) is maintained.
+    ///
+    /// NOTE: The Scenario should not loop itself, since its task is then never replaced. A run
+    /// which panics still fails the Scenario like a looping task would.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     checkout_journey()
+    ///         .tps(200)
+    ///         .run_once_per_task()
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn checkout_journey() {
+    ///     let _ = log_in().await;
+    ///     let _ = check_out().await;
+    /// }
+    ///
+    /// #[transaction]
+    /// async fn log_in() -> Result<(), String> {
+    ///     Ok(())
+    /// }
+    ///
+    /// #[transaction]
+    /// async fn check_out() -> Result<(), String> {
+    ///     Ok(())
+    /// }
+    /// ```
+    fn run_once_per_task(mut self) -> Self {
+        self.config.run_once_per_task = true;
+        self
+    }
+
+    /// Verify that the target is a test environment before sending any load.
+    ///
+    /// Before the Scenario starts, Balter sends a single `GET` request to the base URL (see
+    /// `with_base_url()`) and checks that the response has the given header and value (e.g.
+    /// `X-Environment: staging`). If the header is missing or has another value, the Scenario
+    /// panics without running, guarding against accidentally load testing production.
+    ///
+    /// NOTE: This requires cooperation from the target, which must set the header on its
+    /// responses (e.g. via its load balancer or middleware in non-production environments).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .with_base_url("https://staging.example.com")
+    ///         .require_header("X-Environment", "staging")
+    ///         .tps(10_000)
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    #[cfg(feature = "reqwest")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "reqwest")))]
+    fn require_header(mut self, name: &str, value: &str) -> Self {
+        self.config.required_header = Some(balter_core::RequiredHeader::new(name, value));
+        self
+    }
+
+    /// Record every decision of the controllers to a file, for replay-debugging.
+    ///
+    /// Each sampling window is written as a row with the measurement the controllers were given
+    /// (TPS, error rate, latency, rate limiting), their resulting goal TPS and state, and the
+    /// goal TPS and concurrency the Scenario ran at. [`replay_trace()`](crate::replay_trace) re-runs
+    /// the controllers over a trace, to check that they decide the same way (e.g. after changing
+    /// them, or to reproduce a run which misbehaved).
+    ///
+    /// NOTE: The file is overwritten, and written as the Scenario runs. Failing to write it is
+    /// logged, but doesn't fail the Scenario. For a distributed Scenario, each peer writes the
+    /// decisions of its own controllers to the path on its own machine.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .error_rate(0.03)
+    ///         .trace_to("controller-trace.csv")
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn trace_to(mut self, path: impl AsRef<Path>) -> Self {
+        self.config.trace_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// What to do when the controller trace of `trace_to()` is written faster than the file can
+    /// keep up with, e.g. on a slow or network-mounted disk.
+    ///
+    /// The trace is written by a background thread, which buffers up to 1,024 rows. By default
+    /// ([`OverflowPolicy::Block`]) the Scenario waits for room in a full buffer, so the trace is
+    /// complete but the stalled sampling loop distorts the measurements. With
+    /// [`OverflowPolicy::DropOldest`] or [`OverflowPolicy::DropNewest`] the Scenario never
+    /// waits, and rows are dropped instead. The number of dropped rows is logged once the
+    /// Scenario completes.
+    ///
+    /// NOTE: A trace with dropped rows can't be replayed faithfully by `replay_trace()`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use balter::OverflowPolicy;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .error_rate(0.03)
+    ///         .trace_to("/mnt/nfs/controller-trace.csv")
+    ///         .trace_overflow(OverflowPolicy::DropOldest)
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn trace_overflow(mut self, policy: OverflowPolicy) -> Self {
+        self.config.trace_overflow = policy;
+        self
+    }
+
+    /// Persist the results of the Scenario to InfluxDB once it completes, for tracking them
+    /// across runs (e.g. on a dashboard of nightly load tests).
+    ///
+    /// `dsn` is an InfluxDB write URL, either of the v1 API (`/write?db=...`) or of the v2 API
+    /// (`/api/v2/write?org=...&bucket=...`); the token of the `INFLUXDB_TOKEN` environment
+    /// variable is sent with the write, if set. A `balter_run` row with the final
+    /// [`RunStatistics`] is written, tagged with the Scenario's name (and phase, for
+    /// `run_phases()`) and the git SHA of the `BALTER_GIT_SHA`, `GITHUB_SHA` or `CI_COMMIT_SHA`
+    /// environment variable, if set. With `collect_raw_samples()`, a `balter_sample` row is also
+    /// written for each raw sampling window. Latencies are in seconds.
+    ///
+    /// NOTE: A failed write is logged, and doesn't fail the Scenario. TimescaleDB and other
+    /// databases can ingest the rows via an InfluxDB line protocol listener (e.g. Telegraf).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(500)
+    ///         .collect_raw_samples()
+    ///         .persist_to("http://localhost:8086/write?db=load_tests")
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `dsn` is not an `http://` or `https://` URL.
+    #[cfg(feature = "influxdb")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "influxdb")))]
+    fn persist_to(mut self, dsn: &str) -> Self {
+        if !(dsn.starts_with("http://") || dsn.starts_with("https://")) {
+            panic!("Results database must be an http(s) InfluxDB write URL. Given: {dsn}");
+        }
+        self.config.results_db = Some(dsn.to_string());
+        self
+    }
+
+    /// Run the Scenario at a fixed concurrency as fast as it can go, with no TPS limit.
+    ///
+    /// This is the simplest way to find out how fast a target can go at a given concurrency:
+    /// `concurrency` tasks run the Scenario back to back, and the returned statistics are the
+    /// raw TPS and latency achieved. None of the controllers are involved, so goals set with
+    /// `tps()`, `error_rate()` or `latency()` are ignored, and `goal_tps` is left as `0`.
+    ///
+    /// NOTE: Limits set with `tps_per_host()` still apply. Not supported with `run_phases()` or
+    /// `capacity_sweep()`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let stats = my_scenario()
+    ///         .unbounded_concurrency(50)
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    ///
+    ///     println!("TPS at 50 concurrency: {:.2}", stats.actual_tps);
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `concurrency` is 0.
+    fn unbounded_concurrency(mut self, concurrency: usize) -> Self {
+        if concurrency == 0 {
+            panic!("Specified concurrency must be greater than 0.");
+        }
+        self.config.unbounded_concurrency = Some(concurrency);
+        self
+    }
+
+    /// Pin the Scenario at exactly `concurrency` tasks, and only adjust its TPS.
+    ///
+    /// The controllers run as usual, but rather than raising the concurrency to reach the goal
+    /// TPS, the goal is lowered to the TPS the tasks manage. This answers connection-budget
+    /// questions, e.g. what the target sustains within its `error_rate()` over exactly 50
+    /// connections. See `find_max_tps()` for the resulting TPS.
+    ///
+    /// NOTE: Ignored by `unbounded_concurrency()` and `concurrency_ramp()`, which run without
+    /// controllers.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .error_rate(0.01)
+    ///         .at_concurrency(50)
+    ///         .duration(Duration::from_secs(120))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `concurrency` is 0.
+    fn at_concurrency(mut self, concurrency: usize) -> Self {
+        if concurrency == 0 {
+            panic!("Specified concurrency must be greater than 0.");
+        }
+        self.config.fixed_concurrency = Some(concurrency);
+        self
+    }
+
+    /// Never run more than `concurrency` tasks of the Scenario (100,000 by default).
+    ///
+    /// This is a last-resort safety net rather than a way to shape the load: should a controller
+    /// misbehave, or an extreme goal TPS against a slow target call for hundreds of thousands of
+    /// tasks, the load generator could run out of memory or stall its runtime. Any higher
+    /// concurrency is clamped to this limit with a warning, and the returned [`RunStatistics`]
+    /// have `concurrency_capped` set.
+    ///
+    /// NOTE: Also applies to `unbounded_concurrency()`, `concurrency_ramp()`, `at_concurrency()`
+    /// and `capacity_sweep()`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000_000)
+    ///         .max_concurrency(500_000)
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `concurrency` is 0.
+    fn max_concurrency(mut self, concurrency: usize) -> Self {
+        if concurrency == 0 {
+            panic!("Specified concurrency must be greater than 0.");
+        }
+        self.config.max_concurrency = Some(concurrency);
+        self
+    }
+
+    /// Weigh recent sampling windows more heavily when calculating the mean TPS.
+    ///
+    /// By default, the windows of a sample set are weighed equally ([`Weighting::Uniform`]),
+    /// which is the most stable but lags behind a target whose capacity is changing (e.g. while
+    /// it autoscales). [`Weighting::Linear`] and [`Weighting::Exponential`] track such changes
+    /// progressively more closely, at the cost of a noisier mean and so a less stable
+    /// controller. The weighted TPS is what the controllers are fed.
+    ///
+    /// NOTE: Ignored if `tps_smoothing()` is set, which weighs recent windows by its own factor.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use balter::Weighting;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .error_rate(0.03)
+    ///         .sample_weighting(Weighting::Linear)
+    ///         .duration(Duration::from_secs(300))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn sample_weighting(mut self, weighting: Weighting) -> Self {
+        self.config.sample_weighting = weighting;
+        self
+    }
+
+    /// Close each sampling window once a fixed number of Transactions have completed, rather
+    /// than after a fixed time.
+    ///
+    /// By default ([`WindowMode::Time`]) every window spans the same time (1s, or 5s at low TPS),
+    /// so the number of Transactions behind each measurement varies with the TPS: a window at
+    /// 20 TPS holds far fewer latencies than one at 20,000 TPS. With [`WindowMode::Count`] every
+    /// measurement has the same statistical weight, which keeps latency percentiles and error
+    /// rates equally reliable across a Scenario whose TPS varies widely.
+    ///
+    /// The tradeoff is that windows take a variable wall time: short at a high TPS, and long at
+    /// a low one, which slows down how quickly the controllers react. Once a window's last
+    /// Transactions have started, the others wait for the next window to open, which briefly
+    /// pauses the load at each window boundary. A window which doesn't fill up within the
+    /// maximum sampling interval (see `max_sample_interval()`) is closed early, with however many
+    /// Transactions were recorded by then.
+    ///
+    /// NOTE: Only applies to the controllers' measurements. `measure_once()` and
+    /// `capacity_sweep()` steps are always timed.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use balter::WindowMode;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .latency(Duration::from_millis(20), 0.99)
+    ///         .window_mode(WindowMode::Count(1_000))
+    ///         .duration(Duration::from_secs(300))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the count is 0.
+    fn window_mode(mut self, mode: WindowMode) -> Self {
+        if mode == WindowMode::Count(0) {
+            panic!("Specified window count must be greater than 0.");
+        }
+        self.config.window_mode = mode;
+        self
+    }
+
+    /// Seed the RNGs of the Scenario's tasks, to reproduce an earlier run.
+    ///
+    /// Each task has its own RNG, which drives Balter's randomness within it, such as
+    /// `balter::think_dist()` pauses and `latency_sample_rate()` sampling. If no seed is set, one
+    /// is generated when the Scenario starts, logged, and returned in `RunStatistics::seed`, so
+    /// any run can be reproduced after the fact by passing its seed here.
+    ///
+    /// NOTE: The same seed gives each task the same random sequence, but tasks are still
+    /// scheduled non-deterministically (see the `deterministic` feature), and the controllers
+    /// react to the target's actual behavior.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         // Seed reported by a previous run
+    ///         .seed(8_046_352_174_621)
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    /// Show a live progress bar while the Scenario runs, with the elapsed and total duration,
+    /// and the TPS and error rate of the latest sampling window.
+    ///
+    /// This is meant for interactive runs (e.g. on a laptop). If stderr isn't a terminal (e.g.
+    /// in CI), the progress is logged every 10s instead.
+    ///
+    /// NOTE: Only the controllers' runs show progress, one bar per phase for `phased()`
+    /// Scenarios. For a distributed Scenario, only the server it was started on shows it.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         .duration(Duration::from_secs(120))
+    ///         .with_progress_bar()
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    #[cfg(feature = "indicatif")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "indicatif")))]
+    fn with_progress_bar(mut self) -> Self {
+        self.config.progress_bar = true;
+        self
+    }
+
+    /// Ramp the concurrency linearly from `start` to `end` over `duration`, with no TPS limit.
+    ///
+    /// This is for connection-scaling tests, where the question is how many concurrent
+    /// connections a target can hold rather than how much TPS it can serve. As with
+    /// `unbounded_concurrency()` the controllers are not involved and the TPS floats, but each
+    /// sampling window is measured at a single concurrency level. Limits set with `error_rate()`
+    /// or `latency()` are treated as thresholds rather than goals: the first concurrency at which
+    /// either is exceeded is reported as `breaking_concurrency`.
+    ///
+    /// NOTE: The run lasts for `duration` (or longer if `duration()` is set, holding at `end`),
+    /// and always finishes with a sampling window measured at `end`.
+    /// Takes precedence over `unbounded_concurrency()`. Not supported with `run_phases()` or
+    /// `capacity_sweep()`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let stats = my_scenario()
+    ///         .error_rate(0.01)
+    ///         .concurrency_ramp(10, 1_000, Duration::from_secs(300))
+    ///         .await;
+    ///
+    ///     println!("Errors started at: {:?}", stats.breaking_concurrency);
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `start` or `end` is 0, or if `duration` is zero.
+    fn concurrency_ramp(mut self, start: usize, end: usize, duration: Duration) -> Self {
+        if start == 0 || end == 0 {
+            panic!("Specified concurrency must be greater than 0.");
+        }
+        if duration.is_zero() {
+            panic!("Specified ramp duration must be greater than zero.");
+        }
+        self.config.concurrency_ramp = Some(ConcurrencyRamp::new(start, end, duration));
+        self
+    }
+
+    /// Set when Balter considers the Scenario TPS limited (0.1 by default).
+    ///
+    /// Balter keeps adding concurrency to reach the goal TPS until doing so no longer helps, at
+    /// which point the Scenario is marked `tps_limited` and settles just under the highest TPS
+    /// reached. The gain from added concurrency is measured relative to the TPS per task, where 1
+    /// is perfectly linear scaling and 0 is flat, so the threshold applies equally to targets
+    /// serving thousands of TPS per task and ones serving a fraction of one. The Scenario is TPS
+    /// limited once two consecutive increases in concurrency each gain less than `threshold`.
+    ///
+    /// Raise it to give up sooner on a target with diminishing returns, or lower it to keep
+    /// pushing a target which still scales a little.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(10_000)
+    ///         .underpowered_slope_threshold(0.3)
+    ///         .duration(Duration::from_secs(300))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the threshold is not within (0, 1].
+    fn underpowered_slope_threshold(mut self, threshold: f64) -> Self {
+        if !(threshold > 0. && threshold <= 1.) {
+            panic!(
+                "Specified underpowered slope threshold must be within (0, 1]. Value provided was {threshold}."
+            );
+        }
+        self.config.underpowered_slope_threshold = Some(threshold);
+        self
+    }
+
+    /// Inherit the settings of a template which the Scenario has not set itself.
+    ///
+    /// Settings made with the Scenario's own methods take precedence over the template, whether
+    /// they are made before or after applying it, and the template takes precedence over the
+    /// global defaults set with [`balter::set_defaults()`](crate::set_defaults). See
+    /// [`ScenarioDefaults`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use balter::ScenarioDefaults;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let checkout_suite = ScenarioDefaults::new()
+    ///         .error_rate(0.03)
+    ///         .duration(Duration::from_secs(300))
+    ///         .require_peer_tag("us-east");
+    ///
+    ///     add_to_cart().apply_template(&checkout_suite).await;
+    ///     checkout()
+    ///         .apply_template(&checkout_suite)
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn add_to_cart() {
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn checkout() {
+    /// }
+    /// ```
+    fn apply_template(mut self, template: &ScenarioDefaults) -> Self {
+        template.merge_into(&mut self.config);
+        self
+    }
+
+    /// Customize how Transaction results are classified.
+    ///
+    /// By default, `Ok()` counts as a success and `Err()` as an error. The classifier can
+    /// instead mark a result as an [`Outcome::Success`], [`Outcome::Error`], or
+    /// [`Outcome::Ignore`] which excludes the Transaction from all measurements (useful for
+    /// setup or teardown calls inside a Scenario loop). [`Outcome::ResourceExhausted`] marks the
+    /// load generator running out of local resources, which by default is only detected for
+    /// `std::io::Error`s (and `reqwest::Error`s) from running out of file descriptors.
+    ///
+    /// NOTE: Transactions are generic over their return type, so the classifier only applies to
+    /// Transactions which return exactly `Result<R, E>`. All other Transactions use the default
+    /// classification. Ignored Transactions are still subject to the TPS limit.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::{prelude::*, Outcome};
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         // Treat 404s as expected rather than as errors
+    ///         .classify(|res: &Result<u16, String>| match res {
+    ///             Ok(404) => Outcome::Success,
+    ///             Ok(status) if *status >= 400 => Outcome::Error,
+    ///             Ok(_) => Outcome::Success,
+    ///             Err(_) => Outcome::Error,
+    ///         })
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    ///     my_transaction().await;
+    /// }
+    ///
+    /// #[transaction]
+    /// async fn my_transaction() -> Result<u16, String> {
+    ///     // Some request logic returning the status code...
+    ///     Ok(200)
+    /// }
+    /// ```
+    fn classify<R, E>(
+        mut self,
+        classifier: impl Fn(&Result<R, E>) -> Outcome + Send + Sync + 'static,
+    ) -> Self
+    where
+        R: 'static,
+        E: 'static,
+    {
+        self.hooks.set_classifier(Box::new(classifier));
+        self
+    }
+
+    /// Weight each error towards the error rate goal, so that errors which don't indicate an
+    /// overloaded target (e.g. a `400 Bad Request` from a malformed request) don't hold back the
+    /// TPS as much as those which do (e.g. a `503 Service Unavailable`).
+    ///
+    /// The [`error_rate()`](Self::error_rate) goal is then controlled on the effective error
+    /// rate: the sum of the weights of the errors in a sampling window, over the number of
+    /// Transactions in it. Weights are clamped to `[0, 1]`, where `1` counts the error fully (as
+    /// without a weighting) and `0` not at all.
+    ///
+    /// NOTE: Weights only apply to results classified as errors (see
+    /// [`classify()`](Self::classify)), and only to Transactions which return exactly
+    /// `Result<_, E>`. A result the classifier marks as a success or ignores isn't weighted, and
+    /// an `Ok()` it marks as an error always counts fully. The reported `error_rate` (and the
+    /// `error_rate` metric) remain unweighted.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .error_rate(0.03)
+    ///         // Only overload counts towards the error rate goal
+    ///         .error_weight(|status: &u16| match status {
+    ///             429 | 503 => 1.,
+    ///             500..=599 => 0.5,
+    ///             _ => 0.,
+    ///         })
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    ///     my_transaction().await;
+    /// }
+    ///
+    /// #[transaction]
+    /// async fn my_transaction() -> Result<(), u16> {
+    ///     // Some request logic returning the status code of failed requests...
+    ///     Ok(())
+    /// }
+    /// ```
+    fn error_weight<E: 'static>(
+        mut self,
+        weight: impl Fn(&E) -> f64 + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.set_error_weight(Box::new(weight));
+        self
+    }
+
+    /// Override the goal TPS from a callback, for closed-loop testing against external signals
+    /// (e.g. the depth of a downstream queue).
+    ///
+    /// The callback is invoked once per sample with the measurement windows the sample was based
+    /// on. When it returns `Some(tps)`, the TPS limit is set to `tps` for the next sample,
+    /// overriding the built-in controllers (including the `.tps()` ceiling) for that window.
+    /// When it returns `None`, the built-in controllers set the goal as usual. The controllers
+    /// keep observing every sample either way, so control can be handed back at any time.
+    ///
+    /// NOTE: The callback runs inside the sampling loop, so it should be cheap (e.g. read a
+    /// value updated by another task) rather than performing I/O. It only applies on the server
+    /// running the Scenario, and not to work delegated to peers with the distributed runtime.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::num::NonZeroU32;
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    /// use std::time::Duration;
+    ///
+    /// static QUEUE_DEPTH: AtomicU64 = AtomicU64::new(0);
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         // Hold back while the downstream queue drains
+    ///         .dynamic_goal(|_samples| {
+    ///             (QUEUE_DEPTH.load(Ordering::Relaxed) > 10_000).then(|| NonZeroU32::new(100).unwrap())
+    ///         })
+    ///         .duration(Duration::from_secs(300))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn dynamic_goal(
+        mut self,
+        f: impl Fn(&[Measurement]) -> Option<NonZeroU32> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.dynamic_goal = Some(Arc::new(f));
+        self
+    }
+
+    /// Share state between all tasks of the Scenario, retrievable with
+    /// [`balter::state()`](crate::state) from within the Scenario and its Transactions.
+    ///
+    /// This is useful for per-run state which would otherwise require a global static, such as
+    /// a pool of pre-created test accounts to consume. The state is stored in an `Arc`, and each
+    /// task gets a clone of the same `Arc`, so `S` is responsible for its own synchronization
+    /// (e.g. a `Mutex` or atomics). `balter::state::<S>()` must be called with the same type
+    /// `S`, as the state is downcast to it.
+    ///
+    /// NOTE: A Scenario has a single state, so calling this again replaces it; use a struct to
+    /// share multiple values. Like the classifier, the state isn't sent to peers with the
+    /// distributed runtime, where `balter::state()` returns `None`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::sync::Mutex;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let user_ids: Vec<u64> = (0..10_000).collect();
+    ///     my_scenario()
+    ///         .tps(100)
+    ///         .with_state(Mutex::new(user_ids))
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    ///     let user_ids = balter::state::<Mutex<Vec<u64>>>().unwrap();
+    ///     loop {
+    ///         // Check out a user, so that no two tasks use the same one at once
+    ///         let user_id = user_ids.lock().unwrap().pop();
+    ///         if let Some(user_id) = user_id {
+    ///             let _ = update_user(user_id).await;
+    ///             user_ids.lock().unwrap().push(user_id);
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// #[transaction]
+    /// async fn update_user(user_id: u64) -> Result<(), String> {
+    ///     // Some request logic using `user_id`...
+    ///     Ok(())
+    /// }
+    /// ```
+    fn with_state<S: Send + Sync + 'static>(mut self, state: S) -> Self {
+        self.hooks.state = Some(Arc::new(state));
+        self
+    }
+
+    /// Acquire an auth token for the Scenario's Transactions from `provider`, retrievable with
+    /// [`balter::auth_token()`](crate::auth_token).
+    ///
+    /// The provider is an async closure returning a [`Token`] along with how long it is valid
+    /// for. The token is cached and shared by every task of the Scenario, and refreshed once 90%
+    /// of its TTL has elapsed, so Transactions never use an expired token. Refreshes happen
+    /// lazily on the next call to `balter::auth_token()`, with only one task calling the
+    /// provider while the others wait for its result. If the provider fails, the error is
+    /// logged, `balter::auth_token()` returns `None`, and the next call tries again.
+    ///
+    /// NOTE: Like the state, the provider isn't sent to peers with the distributed runtime,
+    /// where `balter::auth_token()` returns `None`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use balter::Token;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(100)
+    ///         .with_token_provider(|| async {
+    ///             // Some login logic...
+    ///             Ok::<_, String>(Token::new("secret", Duration::from_secs(300)))
+    ///         })
+    ///         .duration(Duration::from_secs(3600))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    ///     let _ = get_profile().await;
+    /// }
+    ///
+    /// #[transaction]
+    /// async fn get_profile() -> Result<(), String> {
+    ///     let token = balter::auth_token().await.ok_or("No auth token")?;
+    ///     // Some request logic using `token`...
+    ///     Ok(())
+    /// }
+    /// ```
+    fn with_token_provider<P, Fut, E>(mut self, provider: P) -> Self
+    where
+        P: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Token, E>> + Send + 'static,
+        E: Display,
+    {
+        self.hooks.token = Some(Arc::new(TokenCache::new(provider)));
+        self
+    }
+
+    /// Only run the Scenario if `precondition` returns `true` when it starts.
+    ///
+    /// This is useful in suites of Scenarios which depend on the state of the target, e.g. on
+    /// its health check reporting ready, a feature flag being enabled, or an earlier Scenario
+    /// having seeded data. The precondition is checked once, before any load is sent (and before
+    /// the pre-flight check of `require_header()`). If it fails, the Scenario is skipped with a
+    /// warning, and returns [`RunStatistics`] with `skipped` set and no measurements.
+    ///
+    /// NOTE: Applies to awaiting the Scenario, `run()` and `phased()` (where every phase is
+    /// skipped), but not to `capacity_sweep()`. A skipped Scenario isn't an error for `run()`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let stats = my_scenario()
+    ///         .tps(500)
+    ///         .precondition(|| async {
+    ///             // Some request logic checking the target's `/health`...
+    ///             true
+    ///         })
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    ///
+    ///     if stats.skipped {
+    ///         println!("Target wasn't ready");
+    ///     }
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn precondition<P, Fut>(mut self, precondition: P) -> Self
+    where
+        P: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.hooks.precondition = Some(Arc::new(move || Box::pin(precondition())));
+        self
+    }
+
+    /// Call `f` with the new concurrency whenever Balter changes the number of Scenario tasks.
+    ///
+    /// This is useful for Scenarios managing their own pool of connections (or other resources)
+    /// rather than relying on that of `reqwest`. If the concurrency grows past the size of the
+    /// pool, tasks queue for connections, which shows up as latency of the target. Resizing the
+    /// pool from this callback keeps it in lockstep with the concurrency. It is called before
+    /// any new tasks are spawned, and with `0` once the Scenario is done.
+    ///
+    /// NOTE: The callback runs inside the sampling loop, so it should be fast and must not
+    /// block, e.g. only record the new size for the pool to pick up. It only applies on the
+    /// server running the Scenario, and not to work delegated to peers with the distributed
+    /// runtime.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::time::Duration;
+    ///
+    /// static POOL_SIZE: AtomicUsize = AtomicUsize::new(0);
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     my_scenario()
+    ///         .tps(1_000)
+    ///         // One connection per task, which the pool resizes to
+    ///         .on_concurrency_change(|concurrency| POOL_SIZE.store(concurrency, Ordering::Relaxed))
+    ///         .duration(Duration::from_secs(60))
+    ///         .await;
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn on_concurrency_change(mut self, f: impl Fn(usize) + Send + Sync + 'static) -> Self {
+        self.hooks.on_concurrency_change = Some(Arc::new(f));
+        self
+    }
+
+    /// Run the Scenario at a fixed concurrency, stepping through each TPS in `tps_steps` for
+    /// `step_duration`, to produce a capacity curve (throughput vs. latency and error rate).
+    ///
+    /// None of the adaptive controllers are involved, and any goals set on the Scenario are
+    /// ignored: concurrency stays at `concurrency` for the whole sweep, and each step is limited
+    /// to its TPS. Steps run on the same set of Scenario tasks, so they are measured back-to-back
+    /// without ramping up again. A step whose TPS can't be reached at the given concurrency
+    /// shows up as an `actual_tps` below its `goal_tps`.
+    ///
+    /// NOTE: The first step includes spawning the Scenario tasks. If a Scenario task panics, the
+    /// remaining steps are skipped.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let sweep = my_scenario()
+    ///         .capacity_sweep(50, vec![500, 1_000, 1_500, 2_000], Duration::from_secs(60))
+    ///         .await;
+    ///
+    ///     for step in &sweep.steps {
+    ///         println!("{},{:?}", step.goal_tps, step.latency_p99);
+    ///     }
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `concurrency` is 0, `tps_steps` is empty or contains 0, or
+    /// `step_duration` is zero.
+    fn capacity_sweep(
+        self,
+        concurrency: usize,
+        tps_steps: Vec<u32>,
+        step_duration: Duration,
+    ) -> CapacitySweepFuture {
+        let mut config = self.config;
+        apply_global_defaults(&mut config);
+        if concurrency == 0 {
+            panic!("Specified sweep concurrency must be greater than 0.");
+        }
+        if step_duration.is_zero() {
+            panic!("Specified sweep step duration must be greater than 0.");
+        }
+        let tps_steps: Option<Vec<_>> = tps_steps.into_iter().map(NonZeroU32::new).collect();
+        let tps_steps = match tps_steps {
+            Some(steps) if !steps.is_empty() => steps,
+            _ => panic!("Specified sweep TPS steps must be non-empty and greater than 0."),
+        };
+
+        Box::pin(async move {
+            require_tokio_runtime();
+            crate::sweep::run_capacity_sweep(
+                self.func,
+                config,
+                self.hooks,
+                concurrency,
+                tps_steps,
+                step_duration,
+            )
+            .await
+        })
+    }
+
+    /// Find the highest TPS the Scenario sustains at the concurrency set with
+    /// `at_concurrency()`, within its `error_rate()` and/or `latency()` goals.
+    ///
+    /// Unlike `capacity_sweep()`, which measures preset TPS steps, the search is adaptive: the
+    /// controllers raise the goal TPS until a goal is exceeded or the pinned concurrency can't
+    /// go any faster, and the result is the goal TPS they settled on.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let result = my_scenario()
+    ///         .error_rate(0.01)
+    ///         .at_concurrency(50)
+    ///         .duration(Duration::from_secs(120))
+    ///         .find_max_tps()
+    ///         .await;
+    ///
+    ///     println!("{} TPS at 50 connections", result.max_tps);
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `at_concurrency()` isn't set, or neither `error_rate()` nor
+    /// `latency()` is.
+    fn find_max_tps(self) -> MaxTpsFuture {
+        let Some(concurrency) = self.config.fixed_concurrency else {
+            panic!("find_max_tps() requires a concurrency set with at_concurrency().");
+        };
+        if self.config.error_rate.is_none() && self.config.latency.is_none() {
+            panic!("find_max_tps() requires a goal set with error_rate() or latency().");
+        }
+
+        let mut config = self.config;
+        apply_global_defaults(&mut config);
+        Box::pin(async move {
+            require_tokio_runtime();
+            let stats = run_scenario(self.func, config, self.hooks).await;
+            MaxTpsResult {
+                concurrency,
+                max_tps: stats.goal_tps,
+                concurrency_limited: stats.tps_limited,
+                stats,
+            }
+        })
+    }
+
+    fn run_phases(mut self, phases: Vec<Phase<Self>>) -> PhasesFuture<RunStatistics> {
+        apply_global_defaults(&mut self.config);
+        let phases = phases
+            .into_iter()
+            .map(|(name, configure)| {
+                (
+                    name,
+                    configure(self.with_config(self.config.clone())).config,
+                )
+            })
+            .collect();
+
+        Box::pin(async move {
+            require_tokio_runtime();
+            run_phases(self.func, self.config, phases, self.hooks).await
+        })
+    }
+
+    /// Run the Scenario, returning an error rather than panicking if it can't be run.
+    ///
+    /// Awaiting a Scenario directly panics if there is no Tokio runtime, or if the target fails
+    /// the pre-flight check of `require_header()`. This runs it the same way, but validates the
+    /// Scenario's configuration up front and returns a [`ScenarioError`] for these instead,
+    /// which is more convenient for tools built on top of Balter. Unlike awaiting it, a Scenario
+    /// with no goal (TPS, error rate or latency), or with a goal but no (or a zero) duration, is
+    /// also an error.
+    ///
+    /// NOTE: The setters of [`ConfigurableScenario`] still panic on invalid values as they are
+    /// called. A Scenario which completes but had a task panic is not an error here either, see
+    /// [`RunStatistics::failed`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// use balter::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let res = my_scenario()
+    ///         .tps(500)
+    ///         .duration(Duration::from_secs(30))
+    ///         .run()
+    ///         .await;
+    ///
+    ///     match res {
+    ///         Ok(stats) => println!("Scenario completed: {stats:?}"),
+    ///         Err(err) => println!("Scenario could not be run: {err}"),
+    ///     }
+    /// }
+    ///
+    /// #[scenario]
+    /// async fn my_scenario() {
+    /// }
+    /// ```
+    fn run(mut self) -> RunFuture<RunStatistics> {
+        apply_global_defaults(&mut self.config);
+        Box::pin(async move {
+            tokio_runtime()?;
+            validate_config(&self.config)?;
+            if self.config.is_unconfigured() {
+                return Err(ScenarioError::Unconfigured {
+                    name: self.config.name,
+                });
+            }
+            if let Some(message) = duration_problem(&self.config) {
+                return Err(ScenarioError::InvalidConfig {
+                    name: self.config.name,
+                    message,
+                });
+            }
+            if !precondition_holds(&self.config, &self.hooks).await {
+                return Ok(skipped());
+            }
+            preflight(&self.config).await?;
+
+            Ok(run_checked_scenario(self.func, self.config, self.hooks).await)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::{Context, Poll};
+
+    #[test]
+    fn test_run_requires_tokio_runtime() {
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+
+        let mut run = Box::pin(Scenario::new("test_run_requires_tokio_runtime", || async {}).run());
+        let Poll::Ready(res) = run.as_mut().poll(&mut cx) else {
+            panic!("Scenario started without a Tokio runtime");
+        };
+        assert!(matches!(res, Err(ScenarioError::NoRuntime)));
+    }
+
+    #[test]
+    fn test_apply_template() {
+        let global = ScenarioDefaults::new()
+            .tps(1_000)
+            .error_rate(0.05)
+            .duration(Duration::from_secs(300))
+            .metrics_prefix("global");
+        let template = ScenarioDefaults::new()
+            .error_rate(0.03)
+            .duration(Duration::from_secs(120));
+
+        let scenario = Scenario::new("test_apply_template", || async {})
+            .duration(Duration::from_secs(30))
+            .apply_template(&template)
+            .tps(500);
+        let mut config = scenario.config.clone();
+        global.merge_into(&mut config);
+
+        // NOTE: Explicit settings win regardless of whether they come before the template.
+        assert_eq!(config.duration, Some(Duration::from_secs(30)));
+        assert_eq!(config.max_tps, NonZeroU32::new(500));
+        assert_eq!(config.error_rate, Some(0.03));
+        assert_eq!(config.metrics_prefix.as_deref(), Some("global"));
+    }
+}
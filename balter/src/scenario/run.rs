@@ -0,0 +1,1296 @@
+//! Running a Scenario: validating its config, driving its controllers and handing off work to
+//! peers.
+use crate::controllers::{CompositeController, Controller};
+use crate::drift::StateDriftDetector;
+use crate::hooks::Hooks;
+use crate::join::ScenarioError;
+use crate::measurement::{Measurement, TTFB_LABEL};
+use crate::sampler::Sampler;
+use crate::trace::DecisionTrace;
+use crate::transaction::default_seed;
+use balter_core::{
+    RunStatistics, SampleData, ScenarioConfig, SloStatistics, WindowMode, COOLDOWN_FLOOR_TPS,
+};
+#[cfg(feature = "rt")]
+use balter_runtime::runtime::{
+    delegated_tps, is_attached, peer_transactions, report_tps, report_transactions, RuntimeMessage,
+    BALTER_OUT,
+};
+use std::{
+    future::Future,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, instrument, trace, warn, Instrument};
+
+/// Panic with a helpful message if there is no Tokio runtime to spawn tasks onto.
+///
+/// Scenarios are plain `Future`s, but sampling spawns tasks internally, so polling one from
+/// another executor would otherwise fail with an obscure "there is no reactor running" error.
+#[track_caller]
+pub(crate) fn require_tokio_runtime() {
+    if let Err(err) = tokio_runtime() {
+        panic!("{err}");
+    }
+}
+
+pub(super) fn tokio_runtime() -> Result<(), ScenarioError> {
+    tokio::runtime::Handle::try_current()
+        .map(|_| ())
+        .map_err(|_| ScenarioError::NoRuntime)
+}
+
+/// Problem with the duration of a Scenario which has a goal: a zero duration stops it after its
+/// first measurement, and without one (or anything else which ends the run, such as
+/// `max_duration()`) it runs until it is stopped.
+pub(super) fn duration_problem(config: &ScenarioConfig) -> Option<String> {
+    if config.duration.is_some_and(|duration| duration.is_zero()) {
+        Some("duration must be greater than zero".to_string())
+    } else if config.duration.is_none()
+        && config.max_duration.is_none()
+        && config.max_transactions.is_none()
+        && config.concurrency_ramp.is_none()
+    {
+        Some("no duration is set, so it would run until stopped (see duration())".to_string())
+    } else {
+        None
+    }
+}
+
+/// Check the values the setters of [`ConfigurableScenario`] would otherwise panic on, for a
+/// config which didn't come from them (e.g. one sent to the distributed runtime).
+pub(super) fn validate_config(config: &ScenarioConfig) -> Result<(), ScenarioError> {
+    let unit: fn(f64) -> bool = |v| (0. ..=1.).contains(&v);
+    let fraction: fn(f64) -> bool = |v| v > 0. && v <= 1.;
+    let ranges = [
+        ("error rate", config.error_rate, unit, "[0, 1]"),
+        (
+            "latency quantile",
+            config.latency.map(|l| l.quantile),
+            unit,
+            "[0, 1]",
+        ),
+        ("smoothing alpha", config.tps_smoothing, fraction, "(0, 1]"),
+        (
+            "TPS fraction",
+            config.required_tps_fraction,
+            fraction,
+            "(0, 1]",
+        ),
+        (
+            "TPS tolerance",
+            config.tps_tolerance,
+            |v| v > 0. && v < 0.5,
+            "(0, 0.5)",
+        ),
+        (
+            "latency sample rate",
+            config.latency_sample_rate,
+            fraction,
+            "(0, 1]",
+        ),
+        (
+            "underpowered slope threshold",
+            config.underpowered_slope_threshold,
+            fraction,
+            "(0, 1]",
+        ),
+    ];
+
+    let message = ranges
+        .into_iter()
+        .find_map(|(name, value, valid, range)| {
+            value
+                .filter(|v| !valid(*v))
+                .map(|v| format!("{name} must be within {range}, but was {v}"))
+        })
+        .or_else(|| {
+            (config.max_transactions == Some(0))
+                .then(|| "max transactions must be greater than 0".to_string())
+        })
+        .or_else(|| {
+            (config.window_mode == WindowMode::Count(0))
+                .then(|| "window count must be greater than 0".to_string())
+        })
+        .or_else(|| {
+            (config.unbounded_concurrency == Some(0)
+                || config.fixed_concurrency == Some(0)
+                || config.max_concurrency == Some(0))
+            .then(|| "concurrency must be greater than 0".to_string())
+        })
+        .or_else(|| {
+            config
+                .concurrency_ramp
+                .is_some_and(|ramp| ramp.start == 0 || ramp.end == 0)
+                .then(|| "ramp concurrency must be greater than 0".to_string())
+        })
+        .or_else(|| {
+            config
+                .concurrency_ramp
+                .is_some_and(|ramp| ramp.duration.is_zero())
+                .then(|| "ramp duration must be greater than zero".to_string())
+        })
+        .or_else(|| {
+            config
+                .metrics_interval
+                .is_some_and(|interval| interval.is_zero())
+                .then(|| "metrics interval must be greater than zero".to_string())
+        });
+
+    match message {
+        Some(message) => Err(ScenarioError::InvalidConfig {
+            name: config.name.clone(),
+            message,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Panic unless the target responds with the header set by `require_header()`, before any load
+/// is sent.
+pub(crate) async fn check_required_header(config: &ScenarioConfig) {
+    if let Err(err) = preflight(config).await {
+        panic!("{err}");
+    }
+}
+
+pub(super) async fn preflight(config: &ScenarioConfig) -> Result<(), ScenarioError> {
+    let Some(header) = &config.required_header else {
+        return Ok(());
+    };
+
+    #[cfg(feature = "reqwest")]
+    let res = crate::http::check_required_header(config.base_url.as_deref(), header)
+        .await
+        .map_err(|err| format!("{err}. Check that it is pointed at a test environment."));
+
+    #[cfg(not(feature = "reqwest"))]
+    let res = Err(format!(
+        "the required header {} needs the `reqwest` feature to check",
+        header.name
+    ));
+
+    res.map_err(|message| ScenarioError::Preflight {
+        name: config.name.clone(),
+        message,
+    })
+}
+
+pub(crate) async fn run_scenario<T, F>(
+    scenario: T,
+    config: ScenarioConfig,
+    hooks: Hooks,
+) -> RunStatistics
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    if config.is_unconfigured() {
+        debug!(
+            "Not load testing {} with config {:?}, because it has no work to do.",
+            config.name, &config
+        );
+        return RunStatistics::default();
+    }
+
+    // NOTE: Only a warning, as an open-ended run can be intentional (e.g. stopped externally).
+    if let Some(message) = duration_problem(&config) {
+        warn!("Scenario {}: {message}", config.name);
+    }
+
+    if !precondition_holds(&config, &hooks).await {
+        return skipped();
+    }
+
+    check_required_header(&config).await;
+
+    run_checked_scenario(scenario, config, hooks).await
+}
+
+/// Whether the Scenario's precondition allows it to run, logging why it is skipped if not.
+pub(super) async fn precondition_holds(config: &ScenarioConfig, hooks: &Hooks) -> bool {
+    let holds = hooks.precondition_holds().await;
+    if !holds {
+        warn!(
+            "Skipping Scenario {}, because its precondition doesn't hold.",
+            config.name
+        );
+    }
+    holds
+}
+
+/// Statistics of a Scenario which was skipped, see `precondition()`.
+pub(super) fn skipped() -> RunStatistics {
+    RunStatistics {
+        skipped: true,
+        ..Default::default()
+    }
+}
+
+/// Run a Scenario which has a goal, and whose target has passed the pre-flight check.
+#[instrument(name="scenario", skip_all, fields(name=config.name))]
+pub(super) async fn run_checked_scenario<T, F>(
+    scenario: T,
+    mut config: ScenarioConfig,
+    hooks: Hooks,
+) -> RunStatistics
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    let seed = resolve_seed(&mut config);
+    info!("Running {} with config {:?}", config.name, &config);
+
+    if config.unbounded_concurrency.is_some() || config.concurrency_ramp.is_some() {
+        let mut stats = crate::measure::run_unbounded(scenario, &config, &hooks).await;
+        stats.seed = Some(seed);
+
+        #[cfg(feature = "rt")]
+        signal_completion().await;
+
+        info!("Scenario complete");
+        persist_results(&config, None, &stats).await;
+        return stats;
+    }
+
+    let controllers = CompositeController::new(&config);
+    //let mut sampler = ConcurrentSampler::new(&config.name, scenario, controllers.initial_tps());
+    let mut sampler = Sampler::new(&config, &hooks, scenario, controllers.initial_tps()).await;
+
+    let mut trace = config
+        .trace_path
+        .as_deref()
+        .map(|path| DecisionTrace::create(path, config.trace_overflow));
+    if let Some(trace) = trace.as_mut() {
+        trace.start(None, &config).await;
+    }
+
+    let mut stats = run_phase(&mut sampler, controllers, &config, &hooks, trace.as_mut()).await;
+    stats.seed = Some(seed);
+
+    sampler.shutdown().await;
+    if let Some(trace) = trace {
+        trace.finish().await;
+    }
+
+    #[cfg(feature = "rt")]
+    signal_completion().await;
+
+    info!("Scenario complete");
+    persist_results(&config, None, &stats).await;
+
+    stats
+}
+
+/// Seed of the Scenario's RNGs as set by `seed()`, or else a newly generated one, which is logged
+/// so that the run can be reproduced.
+fn resolve_seed(config: &mut ScenarioConfig) -> u64 {
+    let seed = *config.seed.get_or_insert_with(default_seed);
+    info!(
+        "Scenario {} is running with seed {seed}, reproduce it with `.seed({seed})`",
+        config.name
+    );
+    seed
+}
+
+/// Write the results of a run (or phase) to the database set by `persist_to()`, if any.
+#[allow(unused)]
+async fn persist_results(config: &ScenarioConfig, phase: Option<&str>, stats: &RunStatistics) {
+    #[cfg(feature = "influxdb")]
+    if let Some(dsn) = config.results_db.as_deref() {
+        crate::results::persist(dsn, &config.name, phase, stats).await;
+    }
+}
+
+/// Run each of the phases in order, reusing the tasks of the Scenario across phases.
+///
+/// NOTE: The Sampler (and so the metric labels, base URL and hooks) is set up once from the base
+/// `config`, and only the controllers are re-created for each phase. Custom measurements, phase
+/// timings and error categories are reset at each phase boundary (see `Sampler::start_phase()`),
+/// so the statistics of each phase only cover that phase after its warmup.
+#[instrument(name="scenario", skip_all, fields(name=config.name))]
+pub(crate) async fn run_phases<T, F>(
+    scenario: T,
+    mut config: ScenarioConfig,
+    phases: Vec<(String, ScenarioConfig)>,
+    hooks: Hooks,
+) -> Vec<(String, RunStatistics)>
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    let Some(initial_tps) = phases
+        .iter()
+        .find(|(_, config)| !config.is_unconfigured())
+        .map(|(_, config)| CompositeController::new(config).initial_tps())
+    else {
+        debug!(
+            "Not load testing {}, because no phase has work to do.",
+            config.name
+        );
+        return phases
+            .into_iter()
+            .map(|(name, _)| (name, RunStatistics::default()))
+            .collect();
+    };
+
+    if !precondition_holds(&config, &hooks).await {
+        return phases
+            .into_iter()
+            .map(|(name, _)| (name, skipped()))
+            .collect();
+    }
+
+    check_required_header(&config).await;
+    let seed = resolve_seed(&mut config);
+
+    let mut sampler = Sampler::new(&config, &hooks, scenario, initial_tps).await;
+    let mut trace = config
+        .trace_path
+        .as_deref()
+        .map(|path| DecisionTrace::create(path, config.trace_overflow));
+
+    let mut results = Vec::with_capacity(phases.len());
+    let mut failed = false;
+    for (name, phase) in phases {
+        if failed || phase.is_unconfigured() {
+            debug!("Skipping phase {name} of {}.", config.name);
+            results.push((name, RunStatistics::default()));
+            continue;
+        }
+
+        info!("Running phase {name} with config {:?}", &phase);
+        let controllers = CompositeController::new(&phase);
+        sampler.start_phase(&phase);
+        sampler.set_tps_limit(controllers.initial_tps());
+        if let Some(trace) = trace.as_mut() {
+            trace.start(Some(&name), &phase).await;
+        }
+
+        let mut stats = run_phase(&mut sampler, controllers, &phase, &hooks, trace.as_mut())
+            .instrument(tracing::info_span!("phase", name))
+            .await;
+        stats.seed = Some(seed);
+        #[cfg(feature = "reqwest")]
+        crate::http::log_phase_connections(&name, sampler.warmup_report());
+        failed = stats.failed;
+        persist_results(&config, Some(&name), &stats).await;
+        results.push((name, stats));
+    }
+
+    sampler.shutdown().await;
+    if let Some(trace) = trace {
+        trace.finish().await;
+    }
+
+    #[cfg(feature = "rt")]
+    signal_completion().await;
+
+    info!("Scenario complete");
+
+    results
+}
+
+/// Drive the controllers for a single run (or phase) of a Scenario on an existing Sampler.
+async fn run_phase<T, F>(
+    sampler: &mut Sampler<T>,
+    mut controllers: CompositeController,
+    config: &ScenarioConfig,
+    hooks: &Hooks,
+    mut trace: Option<&mut DecisionTrace>,
+) -> RunStatistics
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    let start = Instant::now();
+
+    let mut capped = false;
+    let mut failed = false;
+    let mut error_target_unachievable = false;
+    let mut panicked_tasks = 0;
+    let mut tps_concurrency_map = vec![];
+    #[cfg(feature = "indicatif")]
+    let mut progress = config
+        .progress_bar
+        .then(|| crate::progress::Progress::new(config));
+
+    // NOTE: Everything up to the final sample is within the `max_duration()` cap, including the
+    // fast start probe and writing the trace.
+    let run = async {
+        if config.fast_start {
+            let probe = sampler.probe(balter_core::FAST_START_PROBE_TPS).await;
+            debug!("Fast start probe: {probe}");
+            controllers.calibrate(&probe);
+            sampler.set_tps_limit(controllers.initial_tps());
+            if let Some(trace) = trace.as_mut() {
+                trace.calibration(&probe, &controllers).await;
+            }
+        }
+
+        #[cfg(feature = "rt")]
+        let mut delegated = false;
+        let mut drift = config
+            .state_drift_detection
+            .unwrap_or(cfg!(debug_assertions))
+            .then(StateDriftDetector::new);
+
+        // NOTE: This loop is time-sensitive. Any long awaits or blocking will throw off
+        // measurements
+        loop {
+            let (stable, samples) = sampler.sample().await;
+
+            #[cfg(feature = "indicatif")]
+            if let Some(progress) = progress.as_mut() {
+                progress.update(&samples, sampler.tps_limit());
+            }
+
+            if sampler.has_failed() {
+                panicked_tasks = sampler.reap_failed();
+                error!(
+                    "{panicked_tasks} task(s) of Scenario {} panicked, terminating.",
+                    config.name
+                );
+                failed = true;
+                break Some(samples);
+            }
+
+            #[cfg(feature = "rt")]
+            if config.max_transactions.is_some() {
+                report_transactions(&config.name, Some(sampler.transactions()));
+                sampler.set_peer_transactions(peer_transactions(&config.name));
+            }
+
+            if sampler.reached_max_transactions() {
+                info!(
+                    "Scenario {} reached its cap of {} Transactions, terminating.",
+                    config.name,
+                    config.max_transactions.unwrap_or_default()
+                );
+                break Some(samples);
+            }
+
+            if let Some(slope) = drift.as_mut().and_then(|drift| {
+                drift.observe(start.elapsed(), samples.error_rate, sampler.tps_limit())
+            }) {
+                warn!(
+                    "Error rate of Scenario {} is rising at a constant TPS ({:.2}% per minute, now \
+                    {:.2}%). The target may be accumulating state, e.g. from a Transaction which \
+                    isn't idempotent.",
+                    config.name,
+                    slope * 60. * 100.,
+                    samples.error_rate * 100.,
+                );
+            }
+
+            // NOTE: The sampler is only stable once it has reached the goal TPS, so this is the
+            // concurrency needed for it rather than a point on the way there.
+            if stable {
+                record_stable_point(
+                    &mut tps_concurrency_map,
+                    sampler.tps_limit().get(),
+                    sampler.concurrency(),
+                );
+            }
+
+            // NOTE: We have our break-out inside this branch so that our final sampler_stats are
+            // accurate.
+            if let Some(duration) = config.duration {
+                if start.elapsed() > duration {
+                    break Some(samples);
+                }
+            }
+
+            let new_goal_tps = controllers.limit(&samples, stable);
+            trace!(
+                "Controllers {:?} with goal {}",
+                controllers.state(),
+                controllers.current_goal()
+            );
+
+            if controllers.error_target_unachievable() {
+                warn!(
+                    "Scenario {} exceeds its error rate goal even at its TPS floor of {} TPS, \
+                    terminating.",
+                    config.name,
+                    config.tps_floor.unwrap_or(NonZeroU32::MIN)
+                );
+                error_target_unachievable = true;
+                break Some(samples);
+            }
+
+            #[cfg(feature = "rt")]
+            let new_goal_tps = share_work(
+                sampler,
+                config,
+                start.elapsed(),
+                samples.tps,
+                &mut delegated,
+                new_goal_tps,
+            );
+
+            if let Some(goal_tps) = hooks.dynamic_goal(sampler.last_sample_set()) {
+                trace!("Dynamic goal of {goal_tps} TPS overriding controllers");
+                sampler.set_tps_limit(goal_tps);
+            } else if new_goal_tps < sampler.tps_limit() || stable {
+                sampler.set_tps_limit(new_goal_tps);
+            }
+
+            if let Some(trace) = trace.as_mut() {
+                trace
+                    .decision(
+                        &samples,
+                        stable,
+                        &controllers,
+                        sampler.tps_limit(),
+                        sampler.concurrency(),
+                    )
+                    .await;
+            }
+        }
+    };
+
+    let final_sample = match config.max_duration {
+        Some(cap) => match tokio::time::timeout(cap, run).await {
+            Ok(final_sample) => final_sample,
+            Err(_) => {
+                warn!("Scenario exceeded max_duration of {cap:?}, terminating.");
+                capped = true;
+                sampler.last_measurement().cloned()
+            }
+        },
+        None => run.await,
+    };
+
+    #[cfg(feature = "indicatif")]
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    #[cfg(feature = "rt")]
+    if config.max_transactions.is_some() {
+        report_transactions(&config.name, None);
+    }
+
+    let mut stats = sampler_statistics(sampler, config);
+
+    // NOTE: Cooling down would run Transactions past the cap, or against a target which can't
+    // take any load.
+    let stopped =
+        capped || failed || error_target_unachievable || sampler.reached_max_transactions();
+    if let (Some(ramp_down), false) = (config.cooldown, stopped) {
+        let ramp_down = match config.max_duration {
+            Some(cap) => ramp_down.min(cap.saturating_sub(start.elapsed())),
+            None => ramp_down,
+        };
+        cooldown(sampler, ramp_down).await;
+    }
+
+    stats.capped = capped;
+    stats.failed = failed;
+    stats.panicked_tasks = panicked_tasks;
+    stats.error_target_unachievable = error_target_unachievable;
+    stats.tps_concurrency_map = tps_concurrency_map;
+
+    // NOTE: A capped run may be terminated before the first measurement window completes.
+    if let Some(final_sample) = final_sample {
+        record_final_sample(&mut stats, config, &final_sample);
+    }
+    stats.tps_goal_met = check_tps_goal(&stats, config);
+
+    stats
+}
+
+/// Statistics of the Sampler at the end of a run (or phase), taken before any cooldown.
+fn sampler_statistics<T, F>(sampler: &Sampler<T>, config: &ScenarioConfig) -> RunStatistics
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    let sampler_stats = sampler.stats();
+    let raw_samples = config.collect_raw_samples.then(|| {
+        sampler
+            .last_sample_set()
+            .iter()
+            .map(|sample| SampleData {
+                success: sample.success_count(),
+                error: sample.error_count(),
+                elapsed: sample.elapsed,
+            })
+            .collect()
+    });
+
+    RunStatistics {
+        concurrency: sampler_stats.concurrency,
+        goal_tps: sampler_stats.tps_limit.get(),
+        tps_limited: sampler_stats.tps_limited,
+        underpowered_evidence: sampler_stats.underpowered_evidence,
+        generator_saturated: sampler_stats.generator_saturated,
+        generator_resource_exhausted: sampler_stats.generator_resource_exhausted,
+        concurrency_capped: sampler_stats.concurrency_capped,
+        rate_limiter_accuracy: sampler_stats.rate_limiter_accuracy,
+        measurements: sampler_stats.measurements,
+        phases: sampler_stats.phases,
+        error_categories: sampler_stats.error_categories,
+        raw_samples,
+        error_rate_goal: config.error_rate,
+        ..Default::default()
+    }
+}
+
+/// Fill in the statistics measured in the final sample of a run (or phase).
+fn record_final_sample(stats: &mut RunStatistics, config: &ScenarioConfig, sample: &Measurement) {
+    stats.actual_tps = sample.tps;
+    stats.total_tps = sample.total_tps();
+    stats.latency_p50 = sample.latency(0.5);
+    stats.latency_p90 = sample.latency(0.9);
+    stats.latency_p95 = sample.latency(0.95);
+    stats.latency_p99 = sample.latency(0.99);
+    stats.latency_modes = sample.latency_modes();
+    stats.latency_multimodal = !stats.latency_modes.is_empty();
+    if stats.latency_multimodal {
+        warn!(
+            "Scenario {} has a bimodal latency distribution (modes at {:?}), so its latency percentiles may be misleading.",
+            config.name, stats.latency_modes
+        );
+    }
+    stats.error_rate = sample.error_rate;
+    stats.ttfb_p50 = sample.labeled_latency(TTFB_LABEL, 0.5);
+    stats.ttfb_p99 = sample.labeled_latency(TTFB_LABEL, 0.99);
+    stats.slos = sample.slo_statistics();
+    stats.variants = sample.variant_statistics();
+    stats.latency_goal = config.latency.map(|latency| SloStatistics {
+        target: latency.latency,
+        quantile: latency.quantile,
+        achieved: sample.latency(latency.quantile),
+    });
+}
+
+/// Whether the run reached the fraction of its goal TPS set by `require_tps()`, warning if not.
+fn check_tps_goal(stats: &RunStatistics, config: &ScenarioConfig) -> bool {
+    let Some(fraction) = config.required_tps_fraction else {
+        return true;
+    };
+
+    let goal_tps = config.max_tps.map_or(stats.goal_tps, NonZeroU32::get);
+    let met = tps_goal_met(stats.actual_tps, goal_tps, fraction);
+    if !met {
+        warn!(
+            "Scenario {} did not reach {:.0}% of its goal of {goal_tps} TPS (achieved {:.2} TPS).",
+            config.name,
+            fraction * 100.,
+            stats.actual_tps
+        );
+    }
+    met
+}
+
+/// Record the concurrency the Scenario is stable at for a goal TPS, replacing any earlier point
+/// for the same TPS (e.g. from before the target's capacity changed).
+fn record_stable_point(map: &mut Vec<(u32, usize)>, tps: u32, concurrency: usize) {
+    match map.binary_search_by_key(&tps, |(tps, _)| *tps) {
+        Ok(idx) => map[idx].1 = concurrency,
+        Err(idx) => map.insert(idx, (tps, concurrency)),
+    }
+}
+
+fn tps_goal_met(actual_tps: f64, goal_tps: u32, fraction: f64) -> bool {
+    actual_tps >= goal_tps as f64 * fraction
+}
+
+/// Linearly ramp the TPS down to [`COOLDOWN_FLOOR_TPS`] over `ramp_down`.
+async fn cooldown<T, F>(sampler: &mut Sampler<T>, ramp_down: Duration)
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    let from = sampler.tps_limit();
+    let to = COOLDOWN_FLOOR_TPS.min(from);
+    let start = Instant::now();
+
+    info!("Cooling down from {from} TPS over {ramp_down:?}");
+    while start.elapsed() < ramp_down {
+        let progress = start.elapsed().as_secs_f64() / ramp_down.as_secs_f64();
+        let goal_tps = ramp_tps(from, to, progress);
+
+        let measurement = sampler.probe(goal_tps).await;
+        info!(
+            "Cooldown at {goal_tps} TPS: {:.2} TPS, p99 {:?}, {:.2}% errors",
+            measurement.tps,
+            measurement.latency(0.99),
+            measurement.error_rate * 100.
+        );
+    }
+}
+
+/// Interpolate between two TPS values, where `progress` is in `[0, 1]`.
+fn ramp_tps(from: NonZeroU32, to: NonZeroU32, progress: f64) -> NonZeroU32 {
+    let progress = progress.clamp(0., 1.);
+    let tps = from.get() as f64 + (to.get() as f64 - from.get() as f64) * progress;
+    NonZeroU32::new(tps.round() as u32).unwrap_or(to)
+}
+
+/// Report the TPS to the runtime and, the first time the Scenario is TPS limited short of its
+/// `max_tps`, hand off the rest of the work to peers. Returns the goal TPS less what peers have
+/// taken over.
+#[cfg(feature = "rt")]
+fn share_work<T, F>(
+    sampler: &Sampler<T>,
+    config: &ScenarioConfig,
+    elapsed: Duration,
+    tps: f64,
+    delegated: &mut bool,
+    goal_tps: NonZeroU32,
+) -> NonZeroU32
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    report_tps(&config.name, tps);
+
+    if !*delegated && sampler.is_tps_limited() {
+        if let Some(max_tps) = config.max_tps {
+            if tps < max_tps.get() as f64 {
+                distribute_work(config, elapsed, tps);
+                *delegated = true;
+            }
+        }
+    }
+
+    handoff_goal(goal_tps, delegated_tps(&config.name))
+}
+
+#[cfg(feature = "rt")]
+fn distribute_work(config: &ScenarioConfig, elapsed: Duration, self_tps: f64) {
+    let Some(max_tps) = config.max_tps else {
+        return;
+    };
+
+    let mut new_config = config.clone();
+    // TODO: This does not take into account transmission time. Logic will have
+    // to be far fancier to properly time-sync various peers on a single
+    // scenario.
+    new_config.duration = config
+        .duration
+        .map(|duration| duration.saturating_sub(elapsed));
+    new_config.max_duration = config
+        .max_duration
+        .map(|max_duration| max_duration.saturating_sub(elapsed));
+
+    let Some(new_tps) = NonZeroU32::new(max_tps.get().saturating_sub(self_tps as u32)) else {
+        return;
+    };
+    new_config.set_max_tps(new_tps);
+
+    info!("Requesting help for {new_tps} TPS");
+    let (ref tx, _) = *BALTER_OUT;
+    // NOTE: Waiting on a full queue would stall the Scenario's sampling loop.
+    if tx
+        .try_send(RuntimeMessage::Help(Box::new(new_config)))
+        .is_err()
+    {
+        warn!("Runtime message queue is full, not requesting help.");
+    }
+}
+
+/// Goal TPS for this server once peers are running part of the load.
+///
+/// NOTE: Delegated work ramps up gradually, so rather than subtracting the delegated TPS up-front
+/// (dropping the total load) or not at all (briefly doubling it), we subtract what the helpers
+/// have actually achieved so far.
+#[cfg(feature = "rt")]
+fn handoff_goal(goal_tps: NonZeroU32, delegated_tps: f64) -> NonZeroU32 {
+    let remaining = (goal_tps.get() as f64 - delegated_tps).ceil().max(1.);
+    NonZeroU32::new(remaining as u32).unwrap_or(goal_tps)
+}
+
+#[cfg(feature = "rt")]
+async fn signal_completion() {
+    // TODO: We should send which scenario was actually completed so that the runtime can be
+    // intelligent about figuring out if load was alleviated or not.
+
+    // NOTE: The queue is only drained while the distributed runtime is running. Without it there
+    // is no one to signal, and waiting on the queue once it is full would hang every Scenario
+    // after the first few. With it, dropping the message would leave this server busy for good.
+    if !is_attached() {
+        return;
+    }
+
+    let (ref tx, _) = *BALTER_OUT;
+    if tx.send(RuntimeMessage::Finished).await.is_err() {
+        error!("Runtime message queue is closed, not signalling completion.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Token;
+    use crate::mock_scenario;
+    use crate::scenario::{ConfigurableScenario, Scenario};
+    use balter_core::RequiredHeader;
+    use rand_distr::{Distribution, SkewNormal};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_run_errors() {
+        let err = Scenario::new("test_run_unconfigured", || async {})
+            .run()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ScenarioError::Unconfigured { .. }));
+        assert_eq!(err.index(), None);
+
+        // NOTE: Set directly, as the setter would panic (e.g. a config sent to the runtime).
+        let mut scenario = Scenario::new("test_run_invalid_config", || async {}).tps(100);
+        scenario.config.tps_tolerance = Some(0.7);
+        let err = scenario.run().await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Scenario test_run_invalid_config has an invalid configuration: TPS tolerance must \
+            be within (0, 0.5), but was 0.7"
+        );
+
+        // NOTE: There is no base URL to probe, or no `reqwest` feature to probe it with.
+        let err = Scenario::new("test_run_no_duration", || async {})
+            .tps(100)
+            .run()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Scenario test_run_no_duration has an invalid configuration: no duration is set, so \
+            it would run until stopped (see duration())"
+        );
+
+        let err = Scenario::new("test_run_zero_duration", || async {})
+            .error_rate(0.03)
+            .duration(Duration::ZERO)
+            .run()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ScenarioError::InvalidConfig { .. }));
+
+        let mut scenario = Scenario::new("test_run_preflight", || async {})
+            .tps(100)
+            .duration(Duration::from_secs(1));
+        scenario.config.required_header = Some(RequiredHeader::new("X-Environment", "staging"));
+        let err = scenario.run().await.unwrap_err();
+        assert!(matches!(err, ScenarioError::Preflight { .. }));
+    }
+
+    #[test]
+    fn test_duration_problem() {
+        let mut config = ScenarioConfig::new("test_duration_problem");
+        config.error_rate = Some(0.03);
+        assert!(duration_problem(&config).is_some());
+
+        config.duration = Some(Duration::ZERO);
+        assert!(duration_problem(&config).is_some());
+
+        config.duration = Some(Duration::from_secs(30));
+        assert!(duration_problem(&config).is_none());
+
+        // NOTE: Anything else which ends the run is enough.
+        config.duration = None;
+        config.max_duration = Some(Duration::from_secs(600));
+        assert!(duration_problem(&config).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let stats = Scenario::new(
+            "test_run",
+            mock_scenario!(Duration::from_millis(1), Duration::from_micros(10)),
+        )
+        .tps(500)
+        .duration(Duration::from_secs(1))
+        .run()
+        .await
+        .unwrap();
+
+        assert!(stats.goal_tps > 0);
+        assert!(!stats.failed);
+    }
+
+    // NOTE: How much the limiter falls short depends on the machine (and the tests running
+    // alongside), so this is a benchmark rather than a test. See `limiter_accuracy::tests` for
+    // how the accuracy is calculated.
+    #[tokio::test]
+    #[ignore = "benchmark, run with `cargo test -- --ignored test_rate_limiter_accuracy`"]
+    async fn test_rate_limiter_accuracy() {
+        let scenario = || async {
+            let labels = balter_core::TransactionLabels {
+                success: "",
+                error: "",
+                latency: "",
+                slo: None,
+            };
+            let _ =
+                crate::transaction::transaction_hook::<_, (), ()>(labels, async { Ok(()) }).await;
+        };
+
+        let stats = Scenario::new("test_rate_limiter_accuracy", scenario)
+            .tps(20_000)
+            .duration(Duration::from_secs(3))
+            .await;
+
+        // NOTE: A trivial target is never the bottleneck, so any shortfall is the limiter's.
+        assert!(
+            (0.5..=1.01).contains(&stats.rate_limiter_accuracy),
+            "{}",
+            stats.rate_limiter_accuracy
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_duration_caps_run() {
+        let start = Instant::now();
+        // NOTE: The mock scenario never errors, so the error rate search never stabilizes.
+        let stats = Scenario::new(
+            "test_max_duration_caps_run",
+            mock_scenario!(Duration::from_millis(1), Duration::from_micros(10)),
+        )
+        .error_rate(0.03)
+        .max_duration(Duration::from_secs(3))
+        .await;
+
+        let elapsed = start.elapsed();
+        assert!(stats.capped);
+        assert!(
+            elapsed < Duration::from_millis(3_500),
+            "elapsed: {elapsed:?}"
+        );
+        assert!(stats.actual_tps > 0.);
+        assert_eq!(stats.error_rate, 0.);
+    }
+
+    #[tokio::test]
+    async fn test_max_duration_caps_fast_start() {
+        let start = Instant::now();
+        // NOTE: No Transaction completes within the cap, so the probe of `fast_start()` keeps
+        // growing its window rather than returning.
+        let stats = Scenario::new(
+            "test_max_duration_caps_fast_start",
+            mock_scenario!(Duration::from_secs(30), Duration::from_micros(10)),
+        )
+        .error_rate(0.03)
+        .fast_start()
+        .max_duration(Duration::from_secs(2))
+        .await;
+
+        let elapsed = start.elapsed();
+        assert!(stats.capped);
+        assert!(
+            elapsed < Duration::from_millis(2_500),
+            "elapsed: {elapsed:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_returns_partial_stats() {
+        let iterations = Arc::new(AtomicUsize::new(0));
+        let scenario = move || {
+            let iterations = iterations.clone();
+            async move {
+                let iteration = iterations.fetch_add(1, Ordering::Relaxed);
+                let labels = balter_core::TransactionLabels {
+                    success: "",
+                    error: "",
+                    latency: "",
+                    slo: None,
+                };
+                let _ = crate::transaction::transaction_hook::<_, (), ()>(labels, async {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    Ok(())
+                })
+                .await;
+
+                if iteration % 400 == 399 {
+                    panic!("Bad transaction");
+                }
+            }
+        };
+
+        let start = Instant::now();
+        let stats = Scenario::new("test_panicking_task_returns_partial_stats", scenario)
+            .tps(200)
+            .duration(Duration::from_secs(30))
+            .await;
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(stats.failed);
+        assert!(stats.panicked_tasks >= 1);
+        assert!(stats.actual_tps > 0.);
+    }
+
+    #[tokio::test]
+    async fn test_collect_raw_samples() {
+        let stats = Scenario::new(
+            "test_collect_raw_samples",
+            mock_scenario!(Duration::from_millis(1), Duration::from_micros(10)),
+        )
+        .tps(500)
+        .collect_raw_samples()
+        .duration(Duration::from_secs(1))
+        .await;
+
+        // NOTE: The sampler takes at least 5 windows before making a decision.
+        let raw_samples = stats.raw_samples.unwrap();
+        assert_eq!(raw_samples.len(), 5);
+        for sample in raw_samples {
+            assert!(sample.success > 0);
+            assert!(sample.elapsed >= Duration::from_millis(900));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_on_scenario_end() {
+        let registered = Arc::new(AtomicUsize::new(0));
+        let cleaned = Arc::new(AtomicUsize::new(0));
+        let scenario = || {
+            let registered = registered.clone();
+            let cleaned = cleaned.clone();
+            move || {
+                let registered = registered.clone();
+                let cleaned = cleaned.clone();
+                async move {
+                    registered.fetch_add(1, Ordering::Relaxed);
+                    crate::on_scenario_end(move || {
+                        cleaned.fetch_add(1, Ordering::Relaxed);
+                    });
+                    loop {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                }
+            }
+        };
+
+        // Normal termination
+        Scenario::new("test_cleanup_on_scenario_end", scenario())
+            .tps(200)
+            .duration(Duration::from_secs(1))
+            .await;
+        let first_run = registered.load(Ordering::Relaxed);
+        assert!(first_run > 0);
+        assert_eq!(cleaned.load(Ordering::Relaxed), first_run);
+
+        // Cancelled termination
+        let cancelled = Scenario::new("test_cleanup_on_scenario_end", scenario())
+            .tps(200)
+            .duration(Duration::from_secs(60));
+        assert!(tokio::time::timeout(Duration::from_secs(2), cancelled)
+            .await
+            .is_err());
+        // NOTE: The aborted tasks are dropped the next time the runtime polls them.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(registered.load(Ordering::Relaxed) > first_run);
+        assert_eq!(
+            cleaned.load(Ordering::Relaxed),
+            registered.load(Ordering::Relaxed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_with_token_provider() {
+        const TTL: Duration = Duration::from_millis(200);
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let expired = Arc::new(AtomicUsize::new(0));
+
+        // NOTE: Each token is its expiry, so that the tasks can check it is still valid.
+        let start = Instant::now();
+        let provider = {
+            let refreshes = refreshes.clone();
+            move || {
+                refreshes.fetch_add(1, Ordering::Relaxed);
+                let expiry = (start.elapsed() + TTL).as_micros();
+                async move { Ok::<_, String>(Token::new(expiry.to_string(), TTL)) }
+            }
+        };
+        let scenario = {
+            let expired = expired.clone();
+            move || {
+                let expired = expired.clone();
+                async move {
+                    let token = crate::auth_token().await.unwrap();
+                    if start.elapsed().as_micros() >= token.parse().unwrap() {
+                        expired.fetch_add(1, Ordering::Relaxed);
+                    }
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            }
+        };
+
+        Scenario::new("test_with_token_provider", scenario)
+            .tps(200)
+            .with_token_provider(provider)
+            .duration(Duration::from_secs(1))
+            .await;
+
+        assert!(refreshes.load(Ordering::Relaxed) >= 4);
+        assert_eq!(expired.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_precondition() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let scenario = {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                async move {
+                    runs.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            }
+        };
+
+        let stats = Scenario::new("test_precondition", scenario.clone())
+            .tps(200)
+            .precondition(|| async { true })
+            .duration(Duration::from_millis(500))
+            .await;
+        assert!(!stats.skipped);
+        assert!(runs.load(Ordering::Relaxed) > 0);
+
+        runs.store(0, Ordering::Relaxed);
+        let stats = Scenario::new("test_precondition", scenario)
+            .tps(200)
+            .precondition(|| async { false })
+            .duration(Duration::from_millis(500))
+            .await;
+        assert!(stats.skipped);
+        assert_eq!(stats.goal_tps, 0);
+        assert_eq!(runs.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_goal() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let stats = Scenario::new(
+            "test_dynamic_goal",
+            mock_scenario!(Duration::from_millis(1), Duration::from_micros(10)),
+        )
+        .tps(1_000)
+        .dynamic_goal({
+            let calls = calls.clone();
+            move |samples| {
+                assert!(!samples.is_empty());
+                calls.fetch_add(1, Ordering::Relaxed);
+                NonZeroU32::new(200)
+            }
+        })
+        .duration(Duration::from_secs(6))
+        .await;
+
+        // NOTE: The override applies from the second sample onwards, despite the `.tps()` goal.
+        assert!(calls.load(Ordering::Relaxed) > 0);
+        assert_eq!(stats.goal_tps, 200);
+        assert!(
+            (stats.actual_tps - 200.).abs() < 20.,
+            "actual: {}",
+            stats.actual_tps
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_transactions() {
+        const LABELS: balter_core::TransactionLabels = balter_core::TransactionLabels {
+            success: "test_max_transactions_success",
+            error: "test_max_transactions_error",
+            latency: "test_max_transactions_latency",
+            slo: None,
+        };
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let scenario = {
+            let count = count.clone();
+            move || {
+                let count = count.clone();
+                async move {
+                    loop {
+                        let _ = crate::transaction::transaction_hook(LABELS, async {
+                            count.fetch_add(1, Ordering::Relaxed);
+                            Ok::<_, ()>(())
+                        })
+                        .await;
+                    }
+                }
+            }
+        };
+
+        let start = Instant::now();
+        let stats = Scenario::new("test_max_transactions", scenario)
+            .tps(500)
+            .max_transactions(1_500)
+            .duration(Duration::from_secs(60))
+            .await;
+
+        // NOTE: The cap is checked after each window, so allow for one window of overshoot.
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let count = count.load(Ordering::Relaxed);
+        assert!((1_500..2_100).contains(&count), "count: {count}");
+        assert!(stats.actual_tps > 0.);
+    }
+
+    #[test]
+    fn test_record_stable_point() {
+        let mut map = vec![];
+        record_stable_point(&mut map, 1_000, 10);
+        record_stable_point(&mut map, 4_000, 45);
+        record_stable_point(&mut map, 2_000, 20);
+        assert_eq!(map, vec![(1_000, 10), (2_000, 20), (4_000, 45)]);
+
+        // The latest concurrency for a TPS level wins.
+        record_stable_point(&mut map, 2_000, 24);
+        assert_eq!(map, vec![(1_000, 10), (2_000, 24), (4_000, 45)]);
+    }
+
+    #[test]
+    fn test_tps_goal_met() {
+        assert!(tps_goal_met(9_600., 10_000, 0.95));
+        assert!(tps_goal_met(9_500., 10_000, 0.95));
+        assert!(!tps_goal_met(9_400., 10_000, 0.95));
+        assert!(!tps_goal_met(0., 10_000, 1.));
+    }
+
+    #[test]
+    fn test_ramp_tps() {
+        let from = NonZeroU32::new(1_000).unwrap();
+        let to = NonZeroU32::new(10).unwrap();
+
+        assert_eq!(ramp_tps(from, to, 0.).get(), 1_000);
+        assert_eq!(ramp_tps(from, to, 0.5).get(), 505);
+        assert_eq!(ramp_tps(from, to, 1.).get(), 10);
+        assert_eq!(ramp_tps(from, to, 1.5).get(), 10);
+    }
+
+    #[cfg(feature = "rt")]
+    #[test]
+    fn test_handoff_goal() {
+        let goal_tps = NonZeroU32::new(1_000).unwrap();
+
+        // NOTE: Simulates a helper ramping up on 400 TPS of delegated work.
+        for helper_tps in [0., 50., 175., 320., 400.] {
+            let own_tps = handoff_goal(goal_tps, helper_tps).get() as f64;
+            assert!((own_tps + helper_tps - 1_000.).abs() < 1.);
+        }
+
+        assert_eq!(handoff_goal(goal_tps, 2_000.).get(), 1);
+    }
+
+    #[cfg(feature = "rt")]
+    #[tokio::test]
+    async fn test_signal_completion_without_runtime() {
+        // NOTE: Nothing drains the queue without a runtime, so it fills up after a few Scenarios.
+        let (ref tx, ref rx) = *BALTER_OUT;
+        while tx.try_send(RuntimeMessage::Finished).is_ok() {}
+
+        let signalled = tokio::time::timeout(Duration::from_secs(1), signal_completion()).await;
+        assert!(signalled.is_ok());
+
+        while rx.try_recv().is_ok() {}
+    }
+}
@@ -0,0 +1,216 @@
+//! Sweeping and searching the TPS of a Scenario at a fixed concurrency.
+use crate::hooks::Hooks;
+use crate::measurement::{Measurement, TTFB_LABEL};
+use crate::sampler::BaseSampler;
+use balter_core::{MeasurementStatistics, RunStatistics, ScenarioConfig};
+use std::collections::HashMap;
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::pin::Pin;
+use std::time::Duration;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+#[doc(hidden)]
+pub type CapacitySweepFuture = Pin<Box<dyn Future<Output = CapacitySweepResult> + Send>>;
+
+#[doc(hidden)]
+pub type MaxTpsFuture = Pin<Box<dyn Future<Output = MaxTpsResult> + Send>>;
+
+/// Results of a [`capacity_sweep()`](crate::scenario::ConfigurableScenario::capacity_sweep),
+/// with the statistics of each TPS step in the order they were run.
+///
+/// Each step has its `goal_tps` set to the TPS of the step, so plotting `goal_tps` (or
+/// `actual_tps`) against the latency percentiles and `error_rate` gives the capacity curve of
+/// the target at the swept concurrency.
+#[derive(Debug, Clone, Default)]
+pub struct CapacitySweepResult {
+    pub concurrency: usize,
+    pub steps: Vec<RunStatistics>,
+}
+
+impl CapacitySweepResult {
+    /// Statistics of the highest TPS step which reached its goal TPS (within `tolerance`, as a
+    /// fraction of the goal) without exceeding `max_error_rate`.
+    pub fn max_sustained(&self, tolerance: f64, max_error_rate: f64) -> Option<&RunStatistics> {
+        self.steps
+            .iter()
+            .filter(|step| {
+                step.actual_tps >= step.goal_tps as f64 * (1. - tolerance)
+                    && step.error_rate <= max_error_rate
+            })
+            .max_by_key(|step| step.goal_tps)
+    }
+}
+
+/// Result of a [`find_max_tps()`](crate::scenario::ConfigurableScenario::find_max_tps).
+#[derive(Debug, Clone, Default)]
+pub struct MaxTpsResult {
+    pub concurrency: usize,
+    /// Highest TPS sustained at `concurrency` within the goals of the Scenario.
+    pub max_tps: u32,
+    /// Whether `max_tps` is limited by the concurrency rather than by the goals, i.e. more
+    /// connections would go faster.
+    pub concurrency_limited: bool,
+    pub stats: RunStatistics,
+}
+
+pub(crate) async fn run_capacity_sweep<T, F>(
+    scenario: T,
+    config: ScenarioConfig,
+    hooks: Hooks,
+    concurrency: usize,
+    tps_steps: Vec<NonZeroU32>,
+    step_duration: Duration,
+) -> CapacitySweepResult
+where
+    T: Fn() -> F + Send + Sync + 'static + Clone,
+    F: Future<Output = ()> + Send,
+{
+    crate::scenario::check_required_header(&config).await;
+
+    let mut sampler =
+        BaseSampler::with_interval(&config, &hooks, scenario, tps_steps[0], step_duration).await;
+    sampler.set_concurrency(concurrency);
+
+    let mut steps = Vec::with_capacity(tps_steps.len());
+    for tps in tps_steps {
+        info!("Sweeping {} at {tps} TPS", config.name);
+        sampler.set_tps_limit(tps);
+        sampler.reset_statistics();
+        let measurement = sampler.sample().await;
+        debug!("Sweep step at {tps} TPS: {measurement}");
+
+        let failed = sampler.has_failed_tasks();
+        let mut step = step_statistics(
+            sampler.concurrency(),
+            tps,
+            &measurement,
+            sampler.custom_measurements(),
+            sampler.phase_timings(),
+            sampler.error_categories(),
+            failed,
+        );
+        step.concurrency_capped = sampler.concurrency_capped();
+        steps.push(step);
+
+        if failed {
+            error!("Scenario task panicked; skipping the remaining sweep steps.");
+            break;
+        }
+    }
+
+    sampler.shutdown().await;
+    info!("Capacity sweep complete");
+
+    CapacitySweepResult { concurrency, steps }
+}
+
+fn step_statistics(
+    concurrency: usize,
+    tps: NonZeroU32,
+    measurement: &Measurement,
+    measurements: HashMap<String, MeasurementStatistics>,
+    phases: HashMap<String, MeasurementStatistics>,
+    error_categories: HashMap<String, u64>,
+    failed: bool,
+) -> RunStatistics {
+    let latency_modes = measurement.latency_modes();
+    RunStatistics {
+        concurrency,
+        goal_tps: tps.get(),
+        actual_tps: measurement.tps,
+        total_tps: measurement.total_tps(),
+        latency_p50: measurement.latency(0.5),
+        latency_p90: measurement.latency(0.9),
+        latency_p95: measurement.latency(0.95),
+        latency_p99: measurement.latency(0.99),
+        latency_multimodal: !latency_modes.is_empty(),
+        latency_modes,
+        error_rate: measurement.error_rate,
+        ttfb_p50: measurement.labeled_latency(TTFB_LABEL, 0.5),
+        ttfb_p99: measurement.labeled_latency(TTFB_LABEL, 0.99),
+        slos: measurement.slo_statistics(),
+        variants: measurement.variant_statistics(),
+        tps_goal_met: true,
+        failed,
+        measurements,
+        phases,
+        error_categories,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::{ConfigurableScenario, Scenario};
+    use crate::transaction::transaction_hook;
+    use balter_core::TransactionLabels;
+    use std::sync::Arc;
+    use tokio::sync::Semaphore;
+
+    const LABELS: TransactionLabels = TransactionLabels {
+        success: "test_capacity_sweep_success",
+        error: "test_capacity_sweep_error",
+        latency: "test_capacity_sweep_latency",
+        slo: None,
+    };
+
+    #[tokio::test]
+    async fn test_capacity_sweep() {
+        // NOTE: The mock target serves 4 Transactions at a time (400 TPS), so the higher steps
+        // queue at the target instead of reaching their goal.
+        let target = Arc::new(Semaphore::new(4));
+        let scenario = move || {
+            let target = target.clone();
+            async move {
+                loop {
+                    let _ = transaction_hook(LABELS, async {
+                        let _permit = target.acquire().await;
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        Ok::<_, ()>(())
+                    })
+                    .await;
+                }
+            }
+        };
+
+        let result = Scenario::new("test_capacity_sweep", scenario)
+            .capacity_sweep(20, vec![100, 200, 1_000], Duration::from_secs(1))
+            .await;
+
+        assert_eq!(result.concurrency, 20);
+        let goals: Vec<_> = result.steps.iter().map(|step| step.goal_tps).collect();
+        assert_eq!(goals, [100, 200, 1_000]);
+        assert!(result.steps.iter().all(|step| step.concurrency == 20));
+
+        let (low, high) = (&result.steps[1], &result.steps[2]);
+        assert!(high.actual_tps < 500.);
+        assert!(high.latency_p50 > low.latency_p50);
+        assert_eq!(result.max_sustained(0.1, 0.).unwrap().goal_tps, 200);
+    }
+
+    #[test]
+    fn test_max_sustained() {
+        let step = |goal_tps, actual_tps, error_rate| RunStatistics {
+            goal_tps,
+            actual_tps,
+            error_rate,
+            ..Default::default()
+        };
+        let result = CapacitySweepResult {
+            concurrency: 10,
+            steps: vec![
+                step(100, 100., 0.),
+                step(200, 199., 0.),
+                step(300, 280., 0.),
+                step(400, 400., 0.2),
+            ],
+        };
+
+        assert_eq!(result.max_sustained(0.02, 0.01).unwrap().goal_tps, 200);
+        assert_eq!(result.max_sustained(0.1, 0.01).unwrap().goal_tps, 300);
+        assert_eq!(result.max_sustained(0.1, 0.5).unwrap().goal_tps, 400);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::transaction::with_rng;
+use rand::Rng;
+use rand_distr::{Distribution, Exp, LogNormal, Normal, Pareto};
+use std::time::Duration;
+
+/// Distribution of think-times, i.e. the pauses of a simulated user between Transactions (see
+/// [`think_dist()`]).
+///
+/// Real users don't wait a fixed time between requests, and uniform jitter still produces an
+/// unrealistically regular arrival pattern. Each constructor mirrors a distribution commonly used
+/// to model user behavior.
+#[derive(Debug, Clone, Copy)]
+pub struct ThinkDistribution(Inner);
+
+#[derive(Debug, Clone, Copy)]
+enum Inner {
+    Exponential(Exp<f64>),
+    Normal(Normal<f64>),
+    LogNormal(LogNormal<f64>),
+    Pareto(Pareto<f64>),
+}
+
+impl ThinkDistribution {
+    /// Exponentially distributed think-times with the given mean, i.e. users acting
+    /// independently of each other at a constant rate (a Poisson process).
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `mean` is zero.
+    pub fn exponential(mean: Duration) -> Self {
+        if mean.is_zero() {
+            panic!("Specified exponential mean must be greater than zero.");
+        }
+        Self(Inner::Exponential(
+            Exp::new(1. / mean.as_secs_f64()).expect("Invalid exponential distribution"),
+        ))
+    }
+
+    /// Normally distributed think-times with the given mean and standard deviation. Negative
+    /// samples are treated as no pause.
+    pub fn normal(mean: Duration, std_dev: Duration) -> Self {
+        Self(Inner::Normal(
+            Normal::new(mean.as_secs_f64(), std_dev.as_secs_f64())
+                .expect("Invalid normal distribution"),
+        ))
+    }
+
+    /// Log-normally distributed think-times with the given median, and `sigma` the standard
+    /// deviation of the underlying normal distribution (larger values give a longer right tail).
+    /// A common model of reading time on a page.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `median` is zero, or `sigma` is negative or not finite.
+    pub fn log_normal(median: Duration, sigma: f64) -> Self {
+        if median.is_zero() {
+            panic!("Specified log-normal median must be greater than zero.");
+        }
+        let Ok(dist) = LogNormal::new(median.as_secs_f64().ln(), sigma) else {
+            panic!("Specified log-normal sigma must be non-negative. Value provided was {sigma}.");
+        };
+        Self(Inner::LogNormal(dist))
+    }
+
+    /// Pareto distributed think-times, which are never shorter than `scale`, with a heavy tail
+    /// controlled by `shape` (smaller values give a heavier tail). For `shape > 1`, the mean is
+    /// `scale * shape / (shape - 1)`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `scale` is zero, or `shape` is not positive.
+    pub fn pareto(scale: Duration, shape: f64) -> Self {
+        if scale.is_zero() {
+            panic!("Specified Pareto scale must be greater than zero.");
+        }
+        let Ok(dist) = Pareto::new(scale.as_secs_f64(), shape) else {
+            panic!("Specified Pareto shape must be greater than zero. Value provided was {shape}.");
+        };
+        Self(Inner::Pareto(dist))
+    }
+
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Duration {
+        let secs = match &self.0 {
+            Inner::Exponential(dist) => dist.sample(rng),
+            Inner::Normal(dist) => dist.sample(rng),
+            Inner::LogNormal(dist) => dist.sample(rng),
+            Inner::Pareto(dist) => dist.sample(rng),
+        };
+        // NOTE: Heavy tails can exceed what a `Duration` can hold.
+        Duration::try_from_secs_f64(secs.max(0.)).unwrap_or(Duration::MAX)
+    }
+}
+
+/// Pause for a think-time drawn from the given distribution, to simulate a user between
+/// Transactions.
+///
+/// NOTE: Think-time only spaces out the Transactions of each task. The Scenario's goal TPS is
+/// still reached by running more tasks concurrently.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use balter::ThinkDistribution;
+/// use std::time::Duration;
+///
+/// #[scenario]
+/// async fn browse() {
+///     let think = ThinkDistribution::log_normal(Duration::from_secs(3), 0.8);
+///     loop {
+///         view_page().await;
+///         balter::think_dist(think).await;
+///     }
+/// }
+///
+/// #[transaction]
+/// async fn view_page() -> Result<(), String> {
+///     Ok(())
+/// }
+/// ```
+pub async fn think_dist(dist: ThinkDistribution) {
+    let pause = with_rng(|rng| dist.sample(rng));
+    tokio::time::sleep(pause).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::with_test_rng;
+
+    fn samples(dist: ThinkDistribution) -> Vec<f64> {
+        let mut samples: Vec<_> = with_test_rng(|rng| {
+            (0..100_000)
+                .map(|_| dist.sample(rng).as_secs_f64())
+                .collect()
+        });
+        samples.sort_by(f64::total_cmp);
+        samples
+    }
+
+    fn mean(samples: &[f64]) -> f64 {
+        samples.iter().sum::<f64>() / samples.len() as f64
+    }
+
+    fn quantile(samples: &[f64], quantile: f64) -> f64 {
+        samples[(samples.len() as f64 * quantile) as usize]
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() / expected < 0.05,
+            "Expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn test_exponential() {
+        let samples = samples(ThinkDistribution::exponential(Duration::from_secs(2)));
+        assert_close(mean(&samples), 2.);
+        // NOTE: The median of an exponential distribution is `mean * ln(2)`.
+        assert_close(quantile(&samples, 0.5), 2. * 2f64.ln());
+    }
+
+    #[test]
+    fn test_normal() {
+        let samples = samples(ThinkDistribution::normal(
+            Duration::from_secs(5),
+            Duration::from_secs(1),
+        ));
+        assert_close(mean(&samples), 5.);
+        assert_close(quantile(&samples, 0.8413), 6.);
+    }
+
+    #[test]
+    fn test_normal_never_negative() {
+        let samples = samples(ThinkDistribution::normal(
+            Duration::from_millis(100),
+            Duration::from_secs(1),
+        ));
+        assert_eq!(samples[0], 0.);
+    }
+
+    #[test]
+    fn test_log_normal() {
+        let samples = samples(ThinkDistribution::log_normal(Duration::from_secs(3), 0.5));
+        assert_close(quantile(&samples, 0.5), 3.);
+        assert_close(mean(&samples), 3. * (0.5f64 * 0.5 / 2.).exp());
+    }
+
+    #[test]
+    fn test_pareto() {
+        let samples = samples(ThinkDistribution::pareto(Duration::from_secs(1), 3.));
+        assert!(samples[0] >= 1.);
+        assert_close(mean(&samples), 1.5);
+        // NOTE: The median of a Pareto distribution is `scale * 2^(1/shape)`.
+        assert_close(quantile(&samples, 0.5), 2f64.powf(1. / 3.));
+    }
+
+    #[test]
+    #[should_panic(expected = "Specified Pareto shape must be greater than zero")]
+    fn test_invalid_pareto() {
+        ThinkDistribution::pareto(Duration::from_secs(1), 0.);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_think_dist_pauses() {
+        let start = tokio::time::Instant::now();
+        think_dist(ThinkDistribution::pareto(Duration::from_secs(1), 3.)).await;
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,851 @@
+//! Recording the decisions of the controllers, and replaying them offline.
+use crate::controllers::{CompositeController, Controller, ControllerState};
+use crate::measurement::Measurement;
+use balter_core::{LatencyConfig, OverflowPolicy, ScenarioConfig};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Notify;
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+const COLUMNS: &str = "window,elapsed,success,error,tps,error_rate,latency,rate_limited,\
+    retry_after,stable,state,controller_goal,goal_tps,concurrency";
+const CONFIG_PREFIX: &str = "# config:";
+
+/// Quantile of the recorded `latency` column if the Scenario has no latency goal.
+const DEFAULT_LATENCY_QUANTILE: f64 = 0.99;
+
+/// Number of lines buffered for the writer of a trace, before its `OverflowPolicy` applies.
+const BUFFER_LINES: usize = 1_024;
+
+#[derive(Debug, Error)]
+pub enum TraceError {
+    #[error("Unable to read the controller trace: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid controller trace on line {line}: {message}")]
+    Parse { line: usize, message: String },
+}
+
+/// Writer of the controller trace set by
+/// [`trace_to()`](crate::scenario::ConfigurableScenario::trace_to).
+///
+/// Rows are handed to a background thread through a bounded buffer, so that a slow disk doesn't
+/// stall the sampling loop. Once the buffer is full, the `OverflowPolicy` decides whether to wait
+/// for it (asynchronously, so that the runtime isn't blocked) or to drop rows.
+///
+/// NOTE: Failing to write the trace shouldn't fail the run, so errors are logged and tracing is
+/// stopped instead.
+pub(crate) struct DecisionTrace {
+    sink: Option<TraceSink>,
+    latency_quantile: f64,
+    window: usize,
+}
+
+impl DecisionTrace {
+    pub fn create(path: &Path, overflow: OverflowPolicy) -> Self {
+        let sink = match File::create(path) {
+            Ok(file) => Some(TraceSink::spawn(
+                path,
+                BufWriter::new(file),
+                overflow,
+                BUFFER_LINES,
+            )),
+            Err(err) => {
+                error!(
+                    "Unable to create controller trace {}: {err}",
+                    path.display()
+                );
+                None
+            }
+        };
+        Self::with_sink(sink)
+    }
+
+    fn with_sink(sink: Option<TraceSink>) -> Self {
+        // NOTE: The buffer is still empty, so the header never waits on the `OverflowPolicy`.
+        if let Some(sink) = &sink {
+            let mut state = sink.buffer.lock();
+            state
+                .lines
+                .push_back("# Balter controller trace, see `balter::replay_trace()`".to_string());
+            state.lines.push_back(COLUMNS.to_string());
+        }
+
+        Self {
+            sink,
+            latency_quantile: DEFAULT_LATENCY_QUANTILE,
+            window: 0,
+        }
+    }
+
+    /// Start a new run (or phase) of the Scenario, with controllers built from `config`.
+    pub async fn start(&mut self, phase: Option<&str>, config: &ScenarioConfig) {
+        self.latency_quantile = config
+            .latency
+            .map_or(DEFAULT_LATENCY_QUANTILE, |latency| latency.quantile);
+
+        if let Some(phase) = phase {
+            self.write(format!("# phase: {phase}")).await;
+        }
+        self.write(format!("{CONFIG_PREFIX} {}", format_config(config)))
+            .await;
+    }
+
+    /// Record the probe of `fast_start()` the controllers were calibrated with, as window 0.
+    pub fn calibration(
+        &mut self,
+        probe: &Measurement,
+        controllers: &CompositeController,
+    ) -> impl Future<Output = ()> + Send + '_ {
+        let row = Row::new(0, probe, self.latency_quantile, false, controllers);
+        self.write(row.to_string())
+    }
+
+    /// Record a decision of the controllers, along with the goal TPS and concurrency the Sampler
+    /// ended up with.
+    ///
+    /// NOTE: The row is formatted before the returned future is awaited, so that it doesn't hold
+    /// on to the measurement or the controllers.
+    pub fn decision(
+        &mut self,
+        sample: &Measurement,
+        stable: bool,
+        controllers: &CompositeController,
+        goal_tps: NonZeroU32,
+        concurrency: usize,
+    ) -> impl Future<Output = ()> + Send + '_ {
+        self.window += 1;
+        let mut row = Row::new(
+            self.window,
+            sample,
+            self.latency_quantile,
+            stable,
+            controllers,
+        );
+        row.goal_tps = goal_tps.get();
+        row.concurrency = concurrency;
+        self.write(row.to_string())
+    }
+
+    /// Wait for the buffered rows to be written, so that the trace is complete once the Scenario
+    /// returns.
+    ///
+    /// NOTE: A trace which is dropped instead (e.g. as the Scenario is cancelled) is still
+    /// written in the background, but may be incomplete when the Scenario returns.
+    pub async fn finish(self) {
+        if let Some(sink) = self.sink {
+            sink.finish().await;
+        }
+    }
+
+    async fn write(&mut self, line: String) {
+        if let Some(sink) = &self.sink {
+            sink.send(line).await;
+        }
+    }
+}
+
+/// Background thread writing the lines of a controller trace, see `DecisionTrace`.
+struct TraceSink {
+    path: PathBuf,
+    buffer: Arc<TraceBuffer>,
+    overflow: OverflowPolicy,
+    capacity: usize,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct TraceBuffer {
+    state: Mutex<BufferState>,
+    /// Notifies the writer thread of new lines, or that the buffer was closed.
+    changed: Condvar,
+    /// Notifies blocked senders that a line was taken, or that the buffer was closed.
+    space: Notify,
+}
+
+#[derive(Default)]
+struct BufferState {
+    lines: VecDeque<String>,
+    /// Set once no more lines will be written, either because the trace is finished or because
+    /// writing it failed.
+    closed: bool,
+    dropped: u64,
+}
+
+impl TraceBuffer {
+    fn lock(&self) -> MutexGuard<'_, BufferState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, BufferState>) -> MutexGuard<'a, BufferState> {
+        self.changed
+            .wait(state)
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn push(&self, mut state: MutexGuard<'_, BufferState>, line: String) {
+        state.lines.push_back(line);
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+        self.space.notify_waiters();
+    }
+}
+
+impl TraceSink {
+    fn spawn(
+        path: &Path,
+        writer: impl Write + Send + 'static,
+        overflow: OverflowPolicy,
+        capacity: usize,
+    ) -> Self {
+        let buffer = Arc::new(TraceBuffer::default());
+        let thread = std::thread::spawn({
+            let buffer = buffer.clone();
+            let path = path.to_path_buf();
+            move || write_lines(&path, writer, &buffer)
+        });
+
+        Self {
+            path: path.to_path_buf(),
+            buffer,
+            overflow,
+            capacity,
+            thread: Some(thread),
+        }
+    }
+
+    async fn send(&self, line: String) {
+        loop {
+            // NOTE: Registered before checking the buffer, so that a line taken in between
+            // isn't missed.
+            let mut space = std::pin::pin!(self.buffer.space.notified());
+            space.as_mut().enable();
+
+            {
+                let mut state = self.buffer.lock();
+                if state.closed {
+                    return;
+                }
+                if state.lines.len() < self.capacity {
+                    self.buffer.push(state, line);
+                    return;
+                }
+
+                match self.overflow {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropOldest => {
+                        state.lines.pop_front();
+                        state.dropped += 1;
+                        self.buffer.push(state, line);
+                        return;
+                    }
+                    OverflowPolicy::DropNewest => {
+                        state.dropped += 1;
+                        return;
+                    }
+                }
+            }
+
+            space.await;
+        }
+    }
+
+    /// Close the buffer and wait (off the runtime threads) until its lines have been written.
+    async fn finish(mut self) {
+        self.buffer.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+        }
+    }
+}
+
+// NOTE: Joining the writer thread here would block the runtime thread the Scenario runs on, so
+// the remaining lines are left to it (see `TraceSink::finish()` to wait for them).
+impl Drop for TraceSink {
+    fn drop(&mut self) {
+        self.buffer.close();
+
+        let dropped = self.buffer.lock().dropped;
+        if dropped > 0 {
+            warn!(
+                "Dropped {dropped} lines of controller trace {}, which was written too slowly.",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Write the lines of `buffer` until it is closed and empty.
+fn write_lines(path: &Path, mut writer: impl Write, buffer: &TraceBuffer) {
+    loop {
+        let line = {
+            let mut state = buffer.lock();
+            while state.lines.is_empty() && !state.closed {
+                state = buffer.wait(state);
+            }
+            match state.lines.pop_front() {
+                Some(line) => line,
+                None => return,
+            }
+        };
+        buffer.space.notify_waiters();
+
+        // NOTE: Flushed on every line, so the trace is complete even if the run is killed.
+        if let Err(err) = writeln!(writer, "{line}").and_then(|_| writer.flush()) {
+            error!(
+                "Unable to write controller trace {}, stopping: {err}",
+                path.display()
+            );
+            buffer.close();
+            return;
+        }
+    }
+}
+
+/// Result of re-running the controllers over a recorded trace (see [`replay_trace()`]).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceReplay {
+    /// Number of controller decisions replayed.
+    pub decisions: usize,
+    /// Decisions for which the controllers chose a different goal TPS than recorded.
+    pub mismatches: Vec<TraceMismatch>,
+}
+
+impl TraceReplay {
+    /// Whether the controllers made the same decisions as recorded.
+    pub fn is_deterministic(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceMismatch {
+    pub window: usize,
+    pub recorded: u32,
+    pub replayed: u32,
+}
+
+/// Re-run the controllers over the measurements recorded with
+/// [`trace_to()`](crate::scenario::ConfigurableScenario::trace_to), and compare their decisions
+/// with the recorded ones.
+///
+/// The controllers are deterministic given their inputs, so a replay of an unmodified Balter
+/// should match the trace exactly. After changing a controller, replaying traces of real runs
+/// (e.g. one which misbehaved) shows where its decisions diverge.
+///
+/// NOTE: Only the inputs listed in the trace are replayed. Latency is recorded at the
+/// quantile of the latency goal (or p99 without one), and per-Transaction latency SLOs (see
+/// `#[transaction(latency_slo = ...)]`) are not recorded, so decisions driven by an SLO
+/// show up as mismatches. `Retry-After` holds are timed against the clock, so replaying a trace
+/// holds for longer than the run did. Overrides applied after the controllers (e.g.
+/// `dynamic_goal()`) are recorded in `goal_tps`, but not replayed.
+///
+/// # File Format
+/// Lines starting with `#` are comments, except for `# config:` lines, which list the settings
+/// the controllers are built from (e.g. `# config: error_rate=0.03`). Each starts a new set of
+/// controllers, e.g. for each phase of [`run_phases()`](crate::scenario::ConfigurableScenario::run_phases).
+/// Every other line is a decision, with the columns:
+///
+/// ```text
+/// window,elapsed,success,error,tps,error_rate,latency,rate_limited,retry_after,stable,state,controller_goal,goal_tps,concurrency
+/// ```
+///
+/// Durations are in seconds, and `error_rate` is the effective error rate the controllers acted on
+/// (see [`error_weight()`](crate::scenario::ConfigurableScenario::error_weight)). Window 0 is the probe of `fast_start()`, which the controllers are
+/// calibrated with.
+///
+/// # Example
+/// ```no_run
+/// # fn main() -> Result<(), balter::TraceError> {
+/// let replay = balter::replay_trace("controller-trace.csv")?;
+/// for mismatch in &replay.mismatches {
+///     println!(
+///         "Window {}: recorded {} TPS, replayed {} TPS",
+///         mismatch.window, mismatch.recorded, mismatch.replayed
+///     );
+/// }
+/// assert!(replay.is_deterministic());
+/// # Ok(())
+/// # }
+/// ```
+pub fn replay_trace(path: impl AsRef<Path>) -> Result<TraceReplay, TraceError> {
+    replay(&std::fs::read_to_string(path)?)
+}
+
+fn replay(trace: &str) -> Result<TraceReplay, TraceError> {
+    let mut replay = TraceReplay::default();
+    let mut controllers = None;
+
+    for (idx, line) in trace.lines().enumerate() {
+        let parse_error = |message: &str| TraceError::Parse {
+            line: idx + 1,
+            message: message.to_string(),
+        };
+
+        let line = line.trim();
+        if let Some(config) = line.strip_prefix(CONFIG_PREFIX) {
+            let config = parse_config(config).map_err(|msg| parse_error(&msg))?;
+            controllers = Some(CompositeController::new(&config));
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') || line == COLUMNS {
+            continue;
+        }
+
+        let row = Row::parse(line).map_err(|msg| parse_error(&msg))?;
+        let controllers = controllers
+            .as_mut()
+            .ok_or_else(|| parse_error("decision before any `# config:` line"))?;
+
+        let sample = row.measurement();
+        let replayed = if row.window == 0 {
+            controllers.calibrate(&sample);
+            controllers.initial_tps()
+        } else {
+            controllers.limit(&sample, row.stable)
+        };
+
+        replay.decisions += 1;
+        if replayed.get() != row.controller_goal {
+            replay.mismatches.push(TraceMismatch {
+                window: row.window,
+                recorded: row.controller_goal,
+                replayed: replayed.get(),
+            });
+        }
+    }
+
+    Ok(replay)
+}
+
+/// Settings of the config which the decisions of the trace depend on.
+fn format_config(config: &ScenarioConfig) -> String {
+    let mut settings = vec![];
+    if let Some(max_tps) = config.max_tps {
+        settings.push(format!("max_tps={max_tps}"));
+    }
+    if let Some(error_rate) = config.error_rate {
+        settings.push(format!("error_rate={error_rate}"));
+    }
+    if let Some(LatencyConfig { latency, quantile }) = config.latency {
+        settings.push(format!("latency={}", latency.as_secs_f64()));
+        settings.push(format!("quantile={quantile}"));
+    }
+    if config.rate_limit_backoff {
+        settings.push("rate_limit_backoff=true".to_string());
+    }
+    if let Some(start_tps) = config.descend_from {
+        settings.push(format!("descend_from={start_tps}"));
+    }
+    if let Some(floor) = config.tps_floor {
+        settings.push(format!("tps_floor={floor}"));
+    }
+    if let Some(tolerance) = config.tps_tolerance {
+        settings.push(format!("tps_tolerance={tolerance}"));
+    }
+    settings.join(" ")
+}
+
+fn parse_config(settings: &str) -> Result<ScenarioConfig, String> {
+    let mut config = ScenarioConfig::new("replay");
+    let mut latency = None;
+    let mut quantile = None;
+
+    for setting in settings.split_whitespace() {
+        let (key, value) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("expected `<key>=<value>`, got `{setting}`"))?;
+        let invalid = || format!("invalid value for {key}: `{value}`");
+
+        match key {
+            "max_tps" => config.max_tps = Some(value.parse().map_err(|_| invalid())?),
+            "error_rate" => config.error_rate = Some(value.parse().map_err(|_| invalid())?),
+            "latency" => latency = Some(parse_secs(value).ok_or_else(invalid)?),
+            "quantile" => quantile = Some(value.parse().map_err(|_| invalid())?),
+            "rate_limit_backoff" => {
+                config.rate_limit_backoff = value.parse().map_err(|_| invalid())?
+            }
+            "descend_from" => config.descend_from = Some(value.parse().map_err(|_| invalid())?),
+            "tps_floor" => config.tps_floor = Some(value.parse().map_err(|_| invalid())?),
+            "tps_tolerance" => config.tps_tolerance = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("unknown setting `{key}`")),
+        }
+    }
+
+    config.latency = match (latency, quantile) {
+        (Some(latency), Some(quantile)) => Some(LatencyConfig::new(latency, quantile)),
+        (None, None) => None,
+        _ => return Err("latency and quantile must be set together".to_string()),
+    };
+    Ok(config)
+}
+
+fn parse_secs(value: &str) -> Option<Duration> {
+    Duration::try_from_secs_f64(value.parse().ok()?).ok()
+}
+
+/// A single line of the trace.
+#[derive(Debug, PartialEq)]
+struct Row {
+    window: usize,
+    elapsed: Duration,
+    success: u64,
+    error: u64,
+    tps: f64,
+    error_rate: f64,
+    latency: Duration,
+    rate_limited: u64,
+    retry_after: Option<Duration>,
+    stable: bool,
+    state: ControllerState,
+    controller_goal: u32,
+    goal_tps: u32,
+    concurrency: usize,
+}
+
+impl Row {
+    fn new(
+        window: usize,
+        sample: &Measurement,
+        latency_quantile: f64,
+        stable: bool,
+        controllers: &CompositeController,
+    ) -> Self {
+        Self {
+            window,
+            elapsed: sample.elapsed,
+            success: sample.success_count(),
+            error: sample.error_count(),
+            tps: sample.tps,
+            error_rate: sample.effective_error_rate(),
+            latency: sample.latency(latency_quantile),
+            rate_limited: sample.rate_limited_count(),
+            retry_after: sample.retry_after(),
+            stable,
+            state: controllers.state(),
+            controller_goal: controllers.current_goal().get(),
+            goal_tps: controllers.current_goal().get(),
+            concurrency: 0,
+        }
+    }
+
+    fn parse(line: &str) -> Result<Self, String> {
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let [window, elapsed, success, error, tps, error_rate, latency, rate_limited, retry_after, stable, state, controller_goal, goal_tps, concurrency] =
+            fields[..]
+        else {
+            return Err(format!("expected the columns `{COLUMNS}`"));
+        };
+
+        fn field<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("invalid {name}: `{value}`"))
+        }
+        let secs = |name: &str, value: &str| {
+            parse_secs(value).ok_or_else(|| format!("invalid {name}: `{value}`"))
+        };
+
+        Ok(Self {
+            window: field("window", window)?,
+            elapsed: secs("elapsed", elapsed)?,
+            success: field("success", success)?,
+            error: field("error", error)?,
+            tps: field("tps", tps)?,
+            error_rate: field("error_rate", error_rate)?,
+            latency: secs("latency", latency)?,
+            rate_limited: field("rate_limited", rate_limited)?,
+            retry_after: match retry_after {
+                "" => None,
+                value => Some(secs("retry_after", value)?),
+            },
+            stable: field("stable", stable)?,
+            state: parse_state(state).ok_or_else(|| format!("invalid state: `{state}`"))?,
+            controller_goal: field("controller_goal", controller_goal)?,
+            goal_tps: field("goal_tps", goal_tps)?,
+            concurrency: field("concurrency", concurrency)?,
+        })
+    }
+
+    /// Rebuild the measurement the controllers were given.
+    fn measurement(&self) -> Measurement {
+        let mut sample = Measurement::new(self.success, self.error, self.elapsed)
+            .with_rate_limits(self.rate_limited, self.retry_after)
+            .with_effective_error_rate(self.error_rate);
+        // NOTE: The sampler may have smoothed the TPS, so the recorded value takes precedence.
+        sample.tps = self.tps;
+        if !self.latency.is_zero() {
+            sample.populate_latencies(&[self.latency]);
+        }
+        sample
+    }
+}
+
+impl std::fmt::Display for Row {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.window,
+            self.elapsed.as_secs_f64(),
+            self.success,
+            self.error,
+            self.tps,
+            self.error_rate,
+            self.latency.as_secs_f64(),
+            self.rate_limited,
+            self.retry_after
+                .map(|retry_after| retry_after.as_secs_f64().to_string())
+                .unwrap_or_default(),
+            self.stable,
+            format_state(self.state),
+            self.controller_goal,
+            self.goal_tps,
+            self.concurrency,
+        )
+    }
+}
+
+fn format_state(state: ControllerState) -> &'static str {
+    match state {
+        ControllerState::Searching => "searching",
+        ControllerState::Stable => "stable",
+        ControllerState::Limited => "limited",
+        ControllerState::BackingOff => "backing_off",
+    }
+}
+
+fn parse_state(state: &str) -> Option<ControllerState> {
+    match state {
+        "searching" => Some(ControllerState::Searching),
+        "stable" => Some(ControllerState::Stable),
+        "limited" => Some(ControllerState::Limited),
+        "backing_off" => Some(ControllerState::BackingOff),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Synthetic service which errors on any transaction beyond its capacity.
+    fn measure(goal_tps: NonZeroU32, capacity: u64) -> Measurement {
+        let goal_tps = goal_tps.get() as u64;
+        let success = goal_tps.min(capacity);
+        let error = goal_tps.saturating_sub(capacity);
+        Measurement::new(success, error, Duration::from_millis(1_000))
+    }
+
+    /// Record a run of the controllers against a synthetic service.
+    async fn record(path: &Path, config: &ScenarioConfig) {
+        let mut trace = DecisionTrace::create(path, OverflowPolicy::Block);
+        trace.start(None, config).await;
+
+        let mut controllers = CompositeController::new(config);
+        let mut goal_tps = controllers.initial_tps();
+        for _ in 0..20 {
+            let sample = measure(goal_tps, 3_000);
+            goal_tps = controllers.limit(&sample, true);
+            trace
+                .decision(&sample, true, &controllers, goal_tps, 10)
+                .await;
+        }
+        trace.finish().await;
+    }
+
+    /// Sink which stalls on flushing a line until it is opened, standing in for a slow disk.
+    #[derive(Clone, Default)]
+    struct StalledWriter {
+        open: Arc<(Mutex<bool>, Condvar)>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl StalledWriter {
+        fn open(&self) {
+            let (open, opened) = &*self.open;
+            *open.lock().unwrap() = true;
+            opened.notify_all();
+        }
+
+        fn rows(&self) -> Vec<String> {
+            let written = String::from_utf8(self.written.lock().unwrap().clone()).unwrap();
+            written
+                .lines()
+                .filter(|line| line.starts_with(|c: char| c.is_ascii_digit()))
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    impl Write for StalledWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            let (open, opened) = &*self.open;
+            let mut open = open.lock().unwrap();
+            while !*open {
+                open = opened.wait(open).unwrap();
+            }
+            Ok(())
+        }
+    }
+
+    /// Record 200 decisions to a stalled sink, returning whether recording had to wait for the
+    /// sink and the rows written. Once a decision waits, the sink is opened by another task.
+    async fn record_stalled(overflow: OverflowPolicy) -> (bool, Vec<String>) {
+        let writer = StalledWriter::default();
+        let sink = TraceSink::spawn(Path::new("stalled.csv"), writer.clone(), overflow, 8);
+        let mut trace = DecisionTrace::with_sink(Some(sink));
+
+        let mut config = ScenarioConfig::new("test_slow_sink");
+        config.error_rate = Some(0.03);
+        trace.start(None, &config).await;
+        let controllers = CompositeController::new(&config);
+        let goal_tps = NonZeroU32::new(1_000).unwrap();
+        let sample = measure(goal_tps, 3_000);
+
+        let mut waited = false;
+        for _ in 0..200 {
+            let mut decision =
+                std::pin::pin!(trace.decision(&sample, true, &controllers, goal_tps, 10));
+            if futures_util::poll!(decision.as_mut()).is_pending() {
+                // NOTE: The test runtime has a single thread, so the sink is only opened if
+                // waiting on it yields to the runtime.
+                if !waited {
+                    tokio::spawn({
+                        let writer = writer.clone();
+                        async move { writer.open() }
+                    });
+                }
+                waited = true;
+                decision.await;
+            }
+        }
+
+        writer.open();
+        trace.finish().await;
+        (waited, writer.rows())
+    }
+
+    #[tokio::test]
+    async fn test_slow_sink() {
+        let (waited, rows) = record_stalled(OverflowPolicy::DropNewest).await;
+        assert!(!waited);
+        assert!(rows.len() < 200);
+        assert!(rows[0].starts_with("1,"));
+
+        let (waited, rows) = record_stalled(OverflowPolicy::DropOldest).await;
+        assert!(!waited);
+        assert!(rows.len() < 200);
+        assert!(rows[rows.len() - 1].starts_with("200,"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_sink_yields_to_runtime() {
+        // NOTE: Every row is written, at the cost of stalling the Scenario (but not the runtime).
+        let (waited, rows) = record_stalled(OverflowPolicy::Block).await;
+        assert!(waited);
+        assert_eq!(rows.len(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_drop_stalled_sink() {
+        let writer = StalledWriter::default();
+        let sink = TraceSink::spawn(
+            Path::new("stalled.csv"),
+            writer.clone(),
+            OverflowPolicy::Block,
+            8,
+        );
+        let mut trace = DecisionTrace::with_sink(Some(sink));
+        trace
+            .start(None, &ScenarioConfig::new("test_drop_stalled_sink"))
+            .await;
+
+        // NOTE: Dropping the trace doesn't wait for the stalled writer, which finishes the trace
+        // in the background.
+        drop(trace);
+        writer.open();
+    }
+
+    #[tokio::test]
+    async fn test_replay_is_deterministic() {
+        let path = std::env::temp_dir().join("balter_test_replay_is_deterministic.csv");
+        let mut config = ScenarioConfig::new("test_replay_is_deterministic");
+        config.error_rate = Some(0.03);
+        config.latency = Some(LatencyConfig::new(Duration::from_millis(20), 0.99));
+        record(&path, &config).await;
+
+        let replay = replay_trace(&path).unwrap();
+        assert_eq!(replay.decisions, 20);
+        assert!(replay.is_deterministic(), "{:?}", replay.mismatches);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_detects_changed_decisions() {
+        let path = std::env::temp_dir().join("balter_test_replay_detects_changed_decisions.csv");
+        let mut config = ScenarioConfig::new("test_replay_detects_changed_decisions");
+        config.error_rate = Some(0.03);
+        record(&path, &config).await;
+
+        // NOTE: Stands in for a change to the controllers.
+        let trace = std::fs::read_to_string(&path).unwrap();
+        let trace = trace.replace("error_rate=0.03", "error_rate=0.5");
+        std::fs::remove_file(path).unwrap();
+
+        let replay = replay(&trace).unwrap();
+        assert_eq!(replay.decisions, 20);
+        assert!(!replay.is_deterministic());
+    }
+
+    #[test]
+    fn test_row_round_trip() {
+        let mut config = ScenarioConfig::new("test_row_round_trip");
+        config.rate_limit_backoff = true;
+        config.tps_tolerance = Some(0.01);
+        let controllers = CompositeController::new(&config);
+
+        let mut sample = Measurement::new(900, 100, Duration::from_millis(1_250))
+            .with_rate_limits(80, Some(Duration::from_secs(2)));
+        sample.populate_latencies(&[Duration::from_millis(12)]);
+
+        let row = Row::new(3, &sample, 0.99, true, &controllers);
+        assert_eq!(Row::parse(&row.to_string()).unwrap(), row);
+        let parsed = parse_config(&format_config(&config)).unwrap();
+        assert!(parsed.rate_limit_backoff);
+        assert_eq!(parsed.tps_tolerance, Some(0.01));
+    }
+
+    #[test]
+    fn test_invalid_trace() {
+        let err = replay("1,1,100,0,100,0,0,0,,true,stable,100,100,10").unwrap_err();
+        assert!(matches!(err, TraceError::Parse { line: 1, .. }), "{err}");
+
+        let err = replay("# config: error_rate=0.03\n1,1,100").unwrap_err();
+        assert!(matches!(err, TraceError::Parse { line: 2, .. }), "{err}");
+
+        let err = replay("# config: tps=100").unwrap_err();
+        assert!(matches!(err, TraceError::Parse { line: 1, .. }), "{err}");
+    }
+}
@@ -1,13 +1,26 @@
+use crate::auth::TokenCache;
+use crate::barrier::ScenarioBarrier;
+use crate::hooks::{classify, error_weight, Outcome};
+use crate::measurement::{
+    CustomMeasurements, ErrorCategories, LabeledLatencies, RateLimits, SloLatencies, Variants,
+};
+use crate::sampler::{CountWindow, WindowSlot};
 use arc_swap::ArcSwap;
 use balter_core::TransactionLabels;
+use futures_util::{Stream, StreamExt};
 use governor::DefaultDirectRateLimiter;
 use metrics_util::AtomicBucket;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::cell::RefCell;
 use std::time::{Duration, Instant};
 use std::{
+    any::Any,
+    collections::HashMap,
     future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, Mutex,
     },
 };
 
@@ -15,54 +28,1387 @@ use std::{
 pub async fn transaction_hook<T, R, E>(labels: TransactionLabels, func: T) -> T::Output
 where
     T: Future<Output = Result<R, E>>,
+    R: 'static,
+    E: 'static,
 {
     // TODO: Remove clone
     if let Ok(hook) = TRANSACTION_HOOK.try_with(|v| v.clone()) {
-        {
-            let limiter = hook.limiter.load();
-            limiter.until_ready().await;
-        }
+        let latency = hook.latency.load_full();
+        let slot = until_ready(&hook).await;
 
         let start = Instant::now();
-        let res = func.await;
+        let res = {
+            let _in_flight = InFlight::new(&hook.in_flight);
+            IN_TRANSACTION.scope((), func).await
+        };
         let elapsed = start.elapsed();
 
-        // TODO: Unfortunately we're duplicating all data collection here, which isn't ideal.
-        // It makes more sense to move the metric logging out of the individual
-        // transaction_hooks, and to log it in the sampler.
-        hook.latency.push(elapsed);
-        if cfg!(feature = "metrics") {
-            metrics::histogram!(labels.latency).record(elapsed.as_secs_f64());
+        record_result(&hook, &latency, &labels, elapsed, &res, true, slot);
+        res
+    } else {
+        tracing::error!("No hook available.");
+        func.await
+    }
+}
+
+/// Transaction hook used by the `#[transaction(nested)]` macro. Not intended to be used manually.
+///
+/// When called from within another Transaction, the result is only recorded under the
+/// Transaction's own labels (its metrics and latency SLO): it is not subject to the TPS limit,
+/// and does not count towards the Scenario's TPS, latency or error rate, since the enclosing
+/// Transaction already does. When called directly from a Scenario, it is a regular Transaction.
+pub async fn nested_transaction_hook<T, R, E>(labels: TransactionLabels, func: T) -> T::Output
+where
+    T: Future<Output = Result<R, E>>,
+    R: 'static,
+    E: 'static,
+{
+    if IN_TRANSACTION.try_with(|_| ()).is_err() {
+        return transaction_hook(labels, func).await;
+    }
+
+    let Ok(hook) = TRANSACTION_HOOK.try_with(|v| v.clone()) else {
+        tracing::error!("No hook available.");
+        return func.await;
+    };
+
+    let start = Instant::now();
+    let res = func.await;
+    let latency = hook.latency.load_full();
+    record_result(&hook, &latency, &labels, start.elapsed(), &res, false, None);
+    res
+}
+
+/// Transaction hook used by the `#[transaction(streaming)]` macro. Not intended to be used
+/// manually.
+///
+/// Each item of the returned stream is measured as its own Transaction: it is subject to the TPS
+/// limit (which delays polling the underlying stream), classified as a success or error, and its
+/// latency is the time spent waiting for it. For the first item this includes the call itself.
+pub async fn transaction_stream_hook<T, S, R, E>(
+    labels: TransactionLabels,
+    func: T,
+) -> impl Stream<Item = Result<R, E>>
+where
+    T: Future<Output = S>,
+    S: Stream<Item = Result<R, E>>,
+    R: 'static,
+    E: 'static,
+{
+    let hook = TRANSACTION_HOOK.try_with(|v| v.clone()).ok();
+    if hook.is_none() {
+        tracing::error!("No hook available.");
+    }
+
+    let start = Instant::now();
+    let stream = Box::pin(IN_TRANSACTION.scope((), func).await);
+    let setup = start.elapsed();
+
+    futures_util::stream::unfold(
+        (stream, hook, setup),
+        move |(mut stream, hook, setup)| async move {
+            let Some(hook) = hook else {
+                let res = stream.next().await?;
+                return Some((res, (stream, None, setup)));
+            };
+
+            let latency = hook.latency.load_full();
+            let slot = until_ready(&hook).await;
+
+            let start = Instant::now();
+            let res = {
+                let _in_flight = InFlight::new(&hook.in_flight);
+                IN_TRANSACTION.scope((), stream.next()).await?
+            };
+            let elapsed = setup + start.elapsed();
+
+            record_result(&hook, &latency, &labels, elapsed, &res, true, slot);
+            Some((res, (stream, Some(hook), Duration::ZERO)))
+        },
+    )
+}
+
+/// Count the error of a Transaction under its category, used by the
+/// `#[transaction(categorize = ...)]` macro. Not intended to be used manually.
+///
+/// Only `Err()` results which count as an error (see
+/// [`classify()`](crate::scenario::ConfigurableScenario::classify)) are categorized. Like their
+/// errors, `nested` Transactions run within another Transaction aren't counted.
+pub fn categorize_error<R, E>(
+    nested: bool,
+    res: &Result<R, E>,
+    categorize: impl FnOnce(&E) -> &'static str,
+) where
+    R: 'static,
+    E: 'static,
+{
+    let Err(err) = res else {
+        return;
+    };
+    if nested && IN_TRANSACTION.try_with(|_| ()).is_ok() {
+        return;
+    }
+
+    let _ = TRANSACTION_HOOK.try_with(|hook| {
+        if classify(hook.classifier.as_ref(), res) == Outcome::Error {
+            hook.error_categories.record(categorize(err));
+        }
+    });
+}
+
+/// Retry loop used by the `#[transaction(retries = ...)]` macro. Not intended to be used
+/// manually.
+///
+/// Runs `func` until its result doesn't count as an error (see
+/// [`classify()`](crate::scenario::ConfigurableScenario::classify)), or it has been retried
+/// `retries` times, waiting `backoff_nanos` before the first retry and doubling the wait for each
+/// one after. It runs within the Transaction's hook, so only the final result is recorded, and
+/// the latency covers every attempt along with the waits between them.
+pub async fn retry_hook<T, F, R, E>(retries: u32, backoff_nanos: u64, mut func: T) -> Result<R, E>
+where
+    T: FnMut() -> F,
+    F: Future<Output = Result<R, E>>,
+    R: 'static,
+    E: 'static,
+{
+    let classifier = TRANSACTION_HOOK
+        .try_with(|hook| hook.classifier.clone())
+        .ok()
+        .flatten();
+    let mut backoff = Duration::from_nanos(backoff_nanos);
+
+    let mut attempt = 0;
+    loop {
+        let res = func().await;
+        if attempt >= retries || classify(classifier.as_ref(), &res) != Outcome::Error {
+            return res;
         }
 
-        if res.is_ok() {
+        attempt += 1;
+        tokio::time::sleep(backoff).await;
+        backoff = backoff.saturating_mul(2);
+    }
+}
+
+/// Wait until a Transaction is allowed to run by the TPS limits: the limit of its host (see
+/// [`with_host()`]) if it has one, and the Scenario's.
+///
+/// NOTE: The host's limit is waited on first, so that a Transaction held back by its host doesn't
+/// take up the Scenario's TPS in the meantime. The latency added by the distributed runtime (see
+/// `BalterRuntime::added_latency()`) is then waited out, outside of the measured latency.
+///
+/// With count based sampling windows (see `WindowMode::Count`), the Transaction finally claims a
+/// slot in the current window, waiting for the next window if the current one is full.
+async fn until_ready(hook: &TransactionData) -> Option<WindowSlot> {
+    if let Some(limiter) = HOST
+        .try_with(|host| hook.host_limiters.get(&**host))
+        .ok()
+        .flatten()
+    {
+        limiter.until_ready().await;
+    }
+
+    if let Some(limiter) = &hook.limiter {
+        let limiter = limiter.load();
+        // NOTE: Counts the Transactions held back by the TPS limit (see `rate_limiter_accuracy`).
+        if limiter.check().is_err() {
+            hook.limiter_waits.fetch_add(1, Ordering::Relaxed);
+            limiter.until_ready().await;
+        }
+    }
+
+    if let Some(delay) = hook.added_latency {
+        tokio::time::sleep(delay).await;
+    }
+
+    match &hook.count_window {
+        Some(window) => Some(window.claim().await),
+        None => None,
+    }
+}
+
+/// Record the result of a Transaction under its labels, and unless it is nested within another
+/// Transaction (`aggregate == false`), in the Scenario's measurements. The window `slot` claimed
+/// by the Transaction, if any, is only used up if its result is recorded.
+///
+/// NOTE: `latency` is the Scenario's latency bucket as of the start of the Transaction, so that
+/// the latency of a Transaction still in flight at a phase boundary is dropped along with the
+/// previous phase's bucket (see `TaskAtomics::reset_latencies()`).
+fn record_result<R: 'static, E: 'static>(
+    hook: &TransactionData,
+    latency: &AtomicBucket<Duration>,
+    labels: &TransactionLabels,
+    elapsed: Duration,
+    res: &Result<R, E>,
+    aggregate: bool,
+    slot: Option<WindowSlot>,
+) {
+    let outcome = classify(hook.classifier.as_ref(), res);
+    if outcome == Outcome::ResourceExhausted {
+        hook.resource_exhausted.fetch_add(1, Ordering::Relaxed);
+    }
+    if matches!(outcome, Outcome::Ignore | Outcome::ResourceExhausted) {
+        return;
+    }
+
+    // TODO: Unfortunately we're duplicating all data collection here, which isn't ideal.
+    // It makes more sense to move the metric logging out of the individual
+    // transaction_hooks, and to log it in the sampler.
+    if aggregate && sample_latency(hook.latency_sample_rate) {
+        latency.push(elapsed);
+    }
+    if let Some(slo) = labels.slo {
+        hook.slo_latency.record(slo, elapsed);
+    }
+    if cfg!(feature = "metrics") {
+        metrics::histogram!(labels.latency).record(elapsed.as_secs_f64());
+    }
+
+    if aggregate {
+        if let Ok(variant) = VARIANT.try_with(|variant| *variant) {
+            hook.variants
+                .record(variant, outcome == Outcome::Success, elapsed);
+        }
+    }
+
+    if outcome == Outcome::Success {
+        if aggregate {
             hook.success.fetch_add(1, Ordering::Relaxed);
+        }
 
-            if cfg!(feature = "metrics") {
-                metrics::counter!(labels.success).increment(1);
-            }
-        } else {
+        if cfg!(feature = "metrics") {
+            metrics::counter!(labels.success).increment(1);
+        }
+    } else {
+        if aggregate {
             hook.error.fetch_add(1, Ordering::Relaxed);
-            if cfg!(feature = "metrics") {
-                metrics::counter!(labels.error).increment(1);
-            }
+            let weight = error_weight(hook.error_weight.as_ref(), res);
+            hook.weighted_error.fetch_add(
+                (weight * ERROR_WEIGHT_SCALE).round() as u64,
+                Ordering::Relaxed,
+            );
         }
+        if cfg!(feature = "metrics") {
+            metrics::counter!(labels.error).increment(1);
+        }
+    }
 
-        res
-    } else {
-        tracing::error!("No hook available.");
-        func.await
+    if let Some(slot) = slot {
+        slot.recorded();
     }
 }
 
+/// Whether to record the latency of a Transaction, given the fraction of latencies to record (see
+/// [`latency_sample_rate()`](crate::scenario::ConfigurableScenario::latency_sample_rate)).
+///
+/// NOTE: Sampled with the RNG of the Scenario task, so that it follows the Scenario's seed. Every
+/// latency is recorded outside of a Scenario task, where there is none.
+fn sample_latency(rate: f64) -> bool {
+    rate >= 1.
+        || TASK_RNG
+            .try_with(|rng| rng.borrow_mut().gen_bool(rate))
+            .unwrap_or(true)
+}
+
+/// Run `f` with Balter's RNG: that of the current Scenario task (see
+/// [`seed()`](crate::scenario::ConfigurableScenario::seed)), or outside of a Scenario a
+/// per-thread RNG, which is seeded under the `deterministic` feature.
+pub(crate) fn with_rng<R>(f: impl FnOnce(&mut SmallRng) -> R) -> R {
+    if TASK_RNG.try_with(|_| ()).is_ok() {
+        return TASK_RNG.with(|rng| f(&mut rng.borrow_mut()));
+    }
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+/// Seed of a Scenario's RNGs if none is set via `seed()`: fixed under the `deterministic`
+/// feature, and random otherwise.
+pub(crate) fn default_seed() -> u64 {
+    #[cfg(feature = "deterministic")]
+    return crate::sampler::DETERMINISTIC_SEED;
+
+    #[cfg(not(feature = "deterministic"))]
+    rand::random()
+}
+
+fn new_rng() -> SmallRng {
+    #[cfg(feature = "deterministic")]
+    return SmallRng::seed_from_u64(crate::sampler::DETERMINISTIC_SEED);
+
+    #[cfg(not(feature = "deterministic"))]
+    SmallRng::from_entropy()
+}
+
+thread_local! {
+    // NOTE: Per-thread rather than per-task, so that sampling needs no synchronization.
+    static RNG: RefCell<SmallRng> = RefCell::new(new_rng());
+}
+
+/// Base URL of the currently running Scenario, as set by
+/// [`with_base_url()`](crate::scenario::ConfigurableScenario::with_base_url).
+///
+/// Returns `None` if no base URL was configured, or if called outside of a running Scenario.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+///
+/// #[transaction]
+/// async fn get_users() -> Result<(), String> {
+///     let base_url = balter::base_url().unwrap_or_else(|| "http://localhost:3002".into());
+///     let url = format!("{base_url}/users");
+///     // Some request logic using `url`...
+///     Ok(())
+/// }
+/// ```
+pub fn base_url() -> Option<Arc<str>> {
+    TRANSACTION_HOOK
+        .try_with(|hook| hook.base_url.clone())
+        .ok()
+        .flatten()
+}
+
+/// Shared state of the currently running Scenario, as set by
+/// [`with_state()`](crate::scenario::ConfigurableScenario::with_state).
+///
+/// Every task of the Scenario gets the same `Arc`, so `S` is responsible for its own
+/// synchronization (e.g. a `Mutex` or atomics). Returns `None` if no state was set, if the state
+/// is not of type `S`, or if called outside of a running Scenario.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+///
+/// #[transaction]
+/// async fn create_order() -> Result<(), String> {
+///     let orders = balter::state::<AtomicU64>().ok_or("No state set")?;
+///     let order_id = orders.fetch_add(1, Ordering::Relaxed);
+///     // Some request logic using `order_id`...
+///     Ok(())
+/// }
+/// ```
+pub fn state<S: Send + Sync + 'static>() -> Option<Arc<S>> {
+    let state = TRANSACTION_HOOK
+        .try_with(|hook| hook.state.clone())
+        .ok()
+        .flatten()?;
+    state.downcast().ok()
+}
+
+/// Auth token of the currently running Scenario, from the provider set with
+/// [`with_token_provider()`](crate::scenario::ConfigurableScenario::with_token_provider).
+///
+/// The token is shared by every task of the Scenario and refreshed shortly before it expires,
+/// so it is always valid when returned. Only one task calls the provider per refresh, while the
+/// others wait for its token. Returns `None` if no provider was set, if the provider failed (in
+/// which case the next call tries again), or if called outside of a running Scenario.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+///
+/// #[transaction]
+/// async fn get_profile() -> Result<(), String> {
+///     let token = balter::auth_token().await.ok_or("No auth token")?;
+///     let header = format!("Bearer {token}");
+///     // Some request logic using `header`...
+///     Ok(())
+/// }
+/// ```
+pub async fn auth_token() -> Option<Arc<str>> {
+    let cache = TRANSACTION_HOOK
+        .try_with(|hook| hook.token.clone())
+        .ok()
+        .flatten()?;
+    cache.token().await
+}
+
+/// Record a custom measurement for the currently running Scenario.
+///
+/// Measurements are aggregated over the whole run (or each phase), excluding the warmup of the
+/// Scenario, and reported in [`RunStatistics::measurements`](crate::RunStatistics::measurements).
+/// With the `metrics` feature enabled, each value is also recorded to a histogram of the same
+/// name, so for StatsD the name should only contain letters, digits, `_`, `.` and `-` (see
+/// [`sanitize_metric_name()`](balter_core::sanitize_metric_name)).
+///
+/// Usually called via `#[transaction(record = ...)]`, but can be called directly from any
+/// Transaction or Scenario. Calls made outside of a running Scenario are ignored.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+///
+/// #[transaction]
+/// async fn checkout() -> Result<(), String> {
+///     let items_in_cart = 3;
+///     balter::record("checkout_cart_size", items_in_cart as f64);
+///     Ok(())
+/// }
+/// ```
+pub fn record(name: &'static str, value: f64) {
+    if TRANSACTION_HOOK
+        .try_with(|hook| hook.custom.record(name, value))
+        .is_err()
+    {
+        return;
+    }
+
+    if cfg!(feature = "metrics") {
+        metrics::histogram!(name).record(value);
+    }
+}
+
+/// Record a latency for the currently running Scenario under the given label.
+///
+/// This is useful for breaking down a Transaction, for example measuring time-to-first-byte
+/// separately from the full transfer for streaming or large responses. Latencies recorded under
+/// `"ttfb"` are reported as `ttfb_p50`/`ttfb_p99` in the
+/// [`RunStatistics`](crate::RunStatistics), and every label is available per-window via
+/// [`Measurement::labeled_latency()`](crate::Measurement::labeled_latency). With the `metrics`
+/// feature enabled, each latency is also recorded to a histogram prefixed with the Scenario's
+/// metric names, e.g. `balter_my_scenario_ttfb`.
+///
+/// NOTE: The automatically measured Transaction latency is unaffected, and is still the
+/// wall-clock time of the whole Transaction. Calls made outside of a running Scenario are
+/// ignored.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use std::time::Instant;
+///
+/// #[transaction]
+/// async fn download() -> Result<(), String> {
+///     let start = Instant::now();
+///     // Send the request and wait for the first chunk...
+///     balter::record_latency("ttfb", start.elapsed());
+///     // Stream the rest of the body...
+///     Ok(())
+/// }
+/// ```
+pub fn record_latency(label: &'static str, latency: Duration) {
+    let Ok(base_label) = TRANSACTION_HOOK.try_with(|hook| {
+        hook.labeled_latency.record(label, latency);
+        hook.base_label.clone()
+    }) else {
+        return;
+    };
+
+    if cfg!(feature = "metrics") {
+        metrics::histogram!(format!("{base_label}_{label}")).record(latency.as_secs_f64());
+    }
+}
+
+/// Time a phase of a Transaction, e.g. serializing the request body, for the currently running
+/// Scenario.
+///
+/// This is for debugging the load generator itself: it breaks down where time goes *within* a
+/// Transaction (serialization, waiting on a lock, parsing the response), to tell generator-side
+/// overhead apart from genuine target latency. Timings are aggregated per phase over the whole
+/// run and reported in [`RunStatistics::phases`](crate::RunStatistics::phases) in seconds, and
+/// [`RunStatistics::folded_phases()`](crate::RunStatistics::folded_phases) exports them for a
+/// flamegraph renderer. See [`phase_async()`] for phases which `.await`.
+///
+/// NOTE: Timing is only recorded with the `phase-timing` feature enabled, due to its overhead.
+/// Otherwise `f` is simply called. Nested phases are recorded independently, not as a stack.
+/// Calls made outside of a running Scenario are not recorded.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+///
+/// #[transaction]
+/// async fn create_order() -> Result<(), String> {
+///     let body = balter::phase("serialize", || format!("{{\"items\": {}}}", 3));
+///     // Send the request...
+///     Ok(())
+/// }
+/// ```
+pub fn phase<R>(name: &'static str, f: impl FnOnce() -> R) -> R {
+    if !cfg!(feature = "phase-timing") {
+        return f();
+    }
+
+    let start = Instant::now();
+    let res = f();
+    record_phase(name, start.elapsed());
+    res
+}
+
+/// Time a phase of a Transaction which `.await`s, e.g. waiting on a connection pool. See
+/// [`phase()`].
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use std::time::Duration;
+///
+/// #[transaction]
+/// async fn call_api() -> Result<(), String> {
+///     balter::phase_async("acquire_connection", async {
+///         tokio::time::sleep(Duration::from_millis(1)).await;
+///     })
+///     .await;
+///     Ok(())
+/// }
+/// ```
+pub async fn phase_async<F: Future>(name: &'static str, fut: F) -> F::Output {
+    if !cfg!(feature = "phase-timing") {
+        return fut.await;
+    }
+
+    let start = Instant::now();
+    let res = fut.await;
+    record_phase(name, start.elapsed());
+    res
+}
+
+fn record_phase(name: &'static str, elapsed: Duration) {
+    let _ = TRANSACTION_HOOK.try_with(|hook| hook.phases.record(name, elapsed.as_secs_f64()));
+}
+
+/// Report that a Transaction of the currently running Scenario was rejected by rate limiting
+/// (e.g. an HTTP 429), along with the `Retry-After` if the target provided one.
+///
+/// With [`backoff_on_rate_limit()`](crate::scenario::ConfigurableScenario::backoff_on_rate_limit)
+/// enabled, the goal TPS is reduced in proportion to the fraction of rejected Transactions, and
+/// held there for at least the longest `Retry-After` seen. Otherwise rejections are only counted,
+/// see [`Measurement::rate_limited_count()`](crate::Measurement::rate_limited_count). With the
+/// `reqwest` feature enabled, [`check_rate_limit()`](crate::check_rate_limit) calls this for
+/// responses with a 429 status.
+///
+/// NOTE: This does not change how the Transaction itself is classified, so a rejected
+/// Transaction still counts towards the error rate if it returns an `Err()`. Calls made outside
+/// of a running Scenario are ignored.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use std::time::Duration;
+///
+/// #[transaction]
+/// async fn get_users() -> Result<(), String> {
+///     let status = 429;
+///     if status == 429 {
+///         balter::record_rate_limited(Some(Duration::from_secs(1)));
+///         return Err("Rate limited".into());
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn record_rate_limited(retry_after: Option<Duration>) {
+    let _ = TRANSACTION_HOOK.try_with(|hook| hook.rate_limits.record(retry_after));
+}
+
+/// Run `fut` as the given variant, for comparing variants (e.g. two backends) against each other
+/// within a single Scenario.
+///
+/// Transactions run within `fut` are additionally measured per variant, and reported in
+/// [`RunStatistics::variants`](crate::RunStatistics::variants). The Scenario's own measurements
+/// are unaffected: TPS, latency and error rate still cover all Transactions, and so the
+/// controllers (e.g. `.tps()` or `.error_rate()`) target the combined load of all variants. How
+/// the load is split between variants is up to the Scenario.
+///
+/// NOTE: Variants can't be nested; the innermost variant applies. Nested Transactions (see
+/// `#[transaction(nested)]`) are not measured per variant, as their enclosing Transaction
+/// already is. Outside of a running Scenario, `fut` is run as-is.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+/// use rand::Rng;
+///
+/// #[scenario]
+/// async fn compare_backends() {
+///     loop {
+///         if rand::thread_rng().gen_bool(0.5) {
+///             let _ = balter::variant("A", get_user("http://backend-a")).await;
+///         } else {
+///             let _ = balter::variant("B", get_user("http://backend-b")).await;
+///         }
+///     }
+/// }
+///
+/// #[transaction]
+/// async fn get_user(base_url: &str) -> Result<(), String> {
+///     // Some request logic using `base_url`...
+///     Ok(())
+/// }
+/// ```
+pub async fn variant<F: Future>(name: &'static str, fut: F) -> F::Output {
+    VARIANT.scope(name, fut).await
+}
+
+/// Run `fut` against the given host, subjecting its Transactions to the host's own TPS limit as
+/// set by [`tps_per_host()`](crate::scenario::ConfigurableScenario::tps_per_host).
+///
+/// This allows a Scenario which hits multiple services to limit each independently, e.g. running
+/// 1,000 TPS against one service and only 200 TPS against another within the same user flow.
+/// Transactions run within `fut` wait on the host's limit in addition to the Scenario's.
+///
+/// NOTE: Hosts can't be nested; the innermost host applies. Hosts without a configured limit,
+/// and Transactions run outside of `with_host()`, are only subject to the Scenario's limit.
+/// Outside of a running Scenario, `fut` is run as-is.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+///
+/// #[scenario]
+/// async fn user_flow() {
+///     loop {
+///         let _ = balter::with_host("users.example.com", get_user()).await;
+///         let _ = balter::with_host("billing.example.com", get_invoice()).await;
+///     }
+/// }
+///
+/// #[transaction]
+/// async fn get_user() -> Result<(), String> {
+///     Ok(())
+/// }
+///
+/// #[transaction]
+/// async fn get_invoice() -> Result<(), String> {
+///     Ok(())
+/// }
+/// ```
+pub async fn with_host<F: Future>(host: &str, fut: F) -> F::Output {
+    HOST.scope(Arc::from(host), fut).await
+}
+
+/// Register a cleanup closure for the currently running Scenario, which is run once when the
+/// Scenario terminates.
+///
+/// This runs however the Scenario ends: on completion, when capped by `max_duration()`, when one
+/// of its tasks panics, or when the Scenario is cancelled (dropped before completion, e.g. by
+/// [`try_join_scenarios()`](crate::try_join_scenarios)). Use it to release resources which would
+/// otherwise leave the target in a dirty state, such as test data created by the Scenario.
+///
+/// NOTE: Every registered closure is run, so register cleanup once per resource (e.g. as part of
+/// setup before the Scenario's loop) rather than on every Transaction. Closures are run once the
+/// Scenario's tasks have stopped (for a cancelled Scenario, shortly after it is dropped), and a
+/// panicking closure is logged without affecting the others.
+/// Calls made outside of a running Scenario are ignored.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+///
+/// #[scenario]
+/// async fn my_scenario() {
+///     let user_id = create_test_user().await.unwrap();
+///     balter::on_scenario_end(move || {
+///         // Some cleanup logic for `user_id`...
+///         println!("Deleting test user {user_id}");
+///     });
+///
+///     loop {
+///         let _ = my_transaction().await;
+///     }
+/// }
+///
+/// #[transaction]
+/// async fn create_test_user() -> Result<u32, String> {
+///     Ok(42)
+/// }
+///
+/// #[transaction]
+/// async fn my_transaction() -> Result<(), String> {
+///     Ok(())
+/// }
+/// ```
+pub fn on_scenario_end(cleanup: impl FnOnce() + Send + 'static) {
+    let _ = TRANSACTION_HOOK.try_with(|hook| hook.cleanup.register(Box::new(cleanup)));
+}
+
+/// Whether to inject a failure, for testing how the target (or its error budget) behaves after
+/// a set number of failures: `false` for the first `n` calls of the running Scenario, and `true`
+/// for every call after.
+///
+/// Calls are counted across every task of the Scenario by a shared atomic counter, so exactly
+/// `n` calls return `false` regardless of the concurrency, and which task makes the `n + 1`-th
+/// call is up to the scheduling of the tasks. The count covers the whole run, and isn't reset
+/// between phases. Combined with `max_transactions()`, this injects an exact number of failures.
+///
+/// NOTE: A Scenario has a single counter, so every call of `fail_after()` counts towards it;
+/// call it from a single Transaction. Calls made outside of a running Scenario always return
+/// `false`.
+///
+/// # Example
+/// ```no_run
+/// use balter::prelude::*;
+///
+/// #[transaction]
+/// async fn charge() -> Result<(), String> {
+///     // Everything after the 10,000th charge fails
+///     if balter::fail_after(10_000) {
+///         return Err("Injected failure".into());
+///     }
+///     // Some request logic...
+///     Ok(())
+/// }
+/// ```
+pub fn fail_after(n: u64) -> bool {
+    TRANSACTION_HOOK
+        .try_with(|hook| hook.fail_after.fetch_add(1, Ordering::Relaxed) >= n)
+        .unwrap_or(false)
+}
+
+/// Scale of the fixed-point sum of error weights, see `TransactionData::weighted_error`.
+pub(crate) const ERROR_WEIGHT_SCALE: f64 = 1_000_000.;
+
 #[derive(Clone)]
 pub(crate) struct TransactionData {
-    pub limiter: Arc<ArcSwap<DefaultDirectRateLimiter>>,
+    pub limiter: Option<Arc<ArcSwap<DefaultDirectRateLimiter>>>,
+    pub host_limiters: Arc<HashMap<String, DefaultDirectRateLimiter>>,
     pub success: Arc<AtomicU64>,
     pub error: Arc<AtomicU64>,
-    pub latency: Arc<AtomicBucket<Duration>>,
+    /// Sum of the weights of the errors (see `error_weight()`), scaled by `ERROR_WEIGHT_SCALE`.
+    pub weighted_error: Arc<AtomicU64>,
+    pub resource_exhausted: Arc<AtomicU64>,
+    pub in_flight: Arc<AtomicU64>,
+    pub limiter_waits: Arc<AtomicU64>,
+    /// Swapped out at each phase boundary, see `TaskAtomics::reset_latencies()`.
+    pub latency: Arc<ArcSwap<AtomicBucket<Duration>>>,
+    pub latency_sample_rate: f64,
+    pub added_latency: Option<Duration>,
+    pub labeled_latency: Arc<LabeledLatencies>,
+    pub slo_latency: Arc<SloLatencies>,
+    pub rate_limits: Arc<RateLimits>,
+    pub variants: Arc<Variants>,
+    pub base_url: Option<Arc<str>>,
+    /// Prefix of the Scenario's metric names, see `ScenarioConfig::metrics_base_label()`.
+    pub base_label: Arc<str>,
+    pub custom: Arc<CustomMeasurements>,
+    pub phases: Arc<CustomMeasurements>,
+    pub error_categories: Arc<ErrorCategories>,
+    pub cleanup: Arc<Cleanup>,
+    pub fail_after: Arc<AtomicU64>,
+    pub classifier: Option<Arc<dyn Any + Send + Sync>>,
+    pub error_weight: Option<Arc<dyn Any + Send + Sync>>,
+    pub state: Option<Arc<dyn Any + Send + Sync>>,
+    pub token: Option<Arc<TokenCache>>,
+    pub count_window: Option<Arc<CountWindow>>,
+    pub barrier: Arc<ScenarioBarrier>,
+    #[cfg(feature = "reqwest")]
+    pub connections: Arc<crate::http::ConnectionDiagnostics>,
+}
+
+/// Registry of the closures registered via [`on_scenario_end()`] for a Scenario.
+#[derive(Default)]
+pub(crate) struct Cleanup {
+    inner: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+}
+
+impl Cleanup {
+    fn register(&self, cleanup: Box<dyn FnOnce() + Send>) {
+        let Ok(mut inner) = self.inner.lock() else {
+            tracing::error!("Cleanup registry is poisoned.");
+            return;
+        };
+        inner.push(cleanup);
+    }
+
+    /// Run (and remove) every registered closure.
+    fn run(&self) {
+        let closures = match self.inner.lock() {
+            Ok(mut inner) => std::mem::take(&mut *inner),
+            Err(_) => {
+                tracing::error!("Cleanup registry is poisoned.");
+                return;
+            }
+        };
+
+        for cleanup in closures {
+            if catch_unwind(AssertUnwindSafe(cleanup)).is_err() {
+                tracing::error!("A closure registered with on_scenario_end() panicked.");
+            }
+        }
+    }
+}
+
+// NOTE: Each task of a Scenario holds the registry until it is dropped, so the Scenario has
+// terminated (and none of its tasks are still running) once the last reference is dropped,
+// however it ended.
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        self.run();
+    }
+}
+
+/// Counts a running Transaction, including one which is cancelled or panics.
+struct InFlight<'a>(&'a AtomicU64);
+
+impl<'a> InFlight<'a> {
+    fn new(counter: &'a AtomicU64) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 tokio::task_local! {
     pub(crate) static TRANSACTION_HOOK: TransactionData;
+    /// Set while a Transaction is running, so that `#[transaction(nested)]` Transactions know
+    /// whether they have an enclosing Transaction.
+    static IN_TRANSACTION: ();
+    /// Set within [`variant()`], to measure Transactions per variant.
+    static VARIANT: &'static str;
+    /// Set within [`with_host()`], to apply the host's TPS limit.
+    static HOST: Arc<str>;
+    /// RNG of a Scenario task, seeded from the Scenario's seed, see [`with_rng()`].
+    pub(crate) static TASK_RNG: RefCell<SmallRng>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::Hooks;
+    use crate::measurement::Measurement;
+    use crate::sampler::TaskAtomics;
+    use balter_core::ScenarioConfig;
+    use std::num::NonZeroU32;
+
+    const LABELS: TransactionLabels = TransactionLabels {
+        success: "",
+        error: "",
+        latency: "",
+        slo: None,
+    };
+
+    async fn run_transactions(hooks: Hooks, results: Vec<Result<u32, String>>) -> (u64, u64) {
+        let atomics = TaskAtomics::new(
+            &ScenarioConfig::new(""),
+            &hooks,
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async move {
+                for res in results {
+                    let _ = transaction_hook(LABELS, async move { res }).await;
+                }
+            })
+            .await;
+
+        let measurement = atomics.collect(Duration::from_secs(1));
+        (measurement.success_count(), measurement.error_count())
+    }
+
+    fn classifier(f: fn(&Result<u32, String>) -> Outcome) -> Hooks {
+        let mut hooks = Hooks::default();
+        hooks.set_classifier(Box::new(f));
+        hooks
+    }
+
+    #[tokio::test]
+    async fn test_classify_success() {
+        let hooks = classifier(|res| match res {
+            Err(err) if err == "not found" => Outcome::Success,
+            Err(_) => Outcome::Error,
+            Ok(_) => Outcome::Success,
+        });
+
+        let counts = run_transactions(hooks, vec![Ok(1), Err("not found".into())]).await;
+        assert_eq!(counts, (2, 0));
+    }
+
+    #[tokio::test]
+    async fn test_classify_error() {
+        let hooks = classifier(|res| match res {
+            Ok(status) if *status >= 500 => Outcome::Error,
+            Ok(_) => Outcome::Success,
+            Err(_) => Outcome::Error,
+        });
+
+        let counts = run_transactions(hooks, vec![Ok(200), Ok(503), Err("err".into())]).await;
+        assert_eq!(counts, (1, 2));
+    }
+
+    #[tokio::test]
+    async fn test_classify_ignore() {
+        let hooks = classifier(|res| match res {
+            Ok(0) => Outcome::Ignore,
+            Ok(_) => Outcome::Success,
+            Err(_) => Outcome::Error,
+        });
+
+        let counts = run_transactions(hooks, vec![Ok(0), Ok(0), Ok(1)]).await;
+        assert_eq!(counts, (1, 0));
+    }
+
+    /// Run a Transaction with retries against a target which fails `failures` times before
+    /// succeeding, returning its measurement and the number of attempts.
+    async fn run_flaky(retries: u32, failures: u32) -> (Measurement, u32) {
+        let atomics = TaskAtomics::new(
+            &ScenarioConfig::new(""),
+            &Hooks::default(),
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                let backoff = Duration::from_millis(10).as_nanos() as u64;
+                let _ = transaction_hook(
+                    LABELS,
+                    retry_hook(retries, backoff, || async {
+                        if attempts.fetch_add(1, Ordering::Relaxed) < failures {
+                            Err("connection refused".to_string())
+                        } else {
+                            Ok(())
+                        }
+                    }),
+                )
+                .await;
+            })
+            .await;
+
+        (
+            atomics.collect(Duration::from_secs(1)),
+            attempts.load(Ordering::Relaxed),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_retry_succeeds() {
+        let (measurement, attempts) = run_flaky(3, 2).await;
+
+        assert_eq!(attempts, 3);
+        assert_eq!(measurement.success_count(), 1);
+        assert_eq!(measurement.error_count(), 0);
+        // NOTE: Includes both backoffs, of 10ms and then 20ms.
+        assert!(measurement.latency(0.5) >= Duration::from_millis(30));
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        let (measurement, attempts) = run_flaky(2, 5).await;
+
+        assert_eq!(attempts, 3);
+        assert_eq!(measurement.success_count(), 0);
+        assert_eq!(measurement.error_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_counts_each_item() {
+        let atomics = TaskAtomics::new(
+            &ScenarioConfig::new(""),
+            &Hooks::default(),
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+
+        let received = TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                let items = (0..10).map(|i| if i % 5 == 4 { Err(i) } else { Ok(i) });
+                let stream = transaction_stream_hook(LABELS, async move {
+                    futures_util::stream::iter(items).then(|item| async move {
+                        tokio::time::sleep(Duration::from_millis(2)).await;
+                        item
+                    })
+                })
+                .await;
+                stream.collect::<Vec<_>>().await
+            })
+            .await;
+
+        assert_eq!(received.len(), 10);
+        let measurement = atomics.collect(Duration::from_secs(1));
+        assert_eq!(measurement.success_count(), 8);
+        assert_eq!(measurement.error_count(), 2);
+        assert!(measurement.latency(0.5) >= Duration::from_millis(2));
+    }
+
+    #[tokio::test]
+    async fn test_nested_transactions() {
+        const OUTER: TransactionLabels = TransactionLabels {
+            success: "test_nested_outer_success",
+            error: "test_nested_outer_error",
+            latency: "test_nested_outer_latency",
+            slo: None,
+        };
+        const NESTED: TransactionLabels = TransactionLabels {
+            success: "test_nested_inner_success",
+            error: "test_nested_inner_error",
+            latency: "test_nested_inner_latency",
+            slo: Some(balter_core::LatencySlo {
+                name: "nested",
+                latency: Duration::from_millis(100),
+                quantile: 0.99,
+            }),
+        };
+
+        let atomics = TaskAtomics::new(
+            &ScenarioConfig::new(""),
+            &Hooks::default(),
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                // A composite operation made of two nested Transactions and a regular one.
+                transaction_hook(OUTER, async {
+                    let _ = nested_transaction_hook(NESTED, async { Ok::<_, ()>(()) }).await;
+                    let _ = nested_transaction_hook(NESTED, async { Err::<(), _>(()) }).await;
+                    transaction_hook(LABELS, async { Ok::<_, ()>(()) }).await
+                })
+                .await
+            })
+            .await
+            .unwrap();
+
+        // NOTE: Only the outer and the regular inner Transaction count towards the Scenario, but
+        // the nested Transactions are still recorded under their own labels.
+        let measurement = atomics.collect(Duration::from_secs(1));
+        assert_eq!(measurement.success_count(), 2);
+        assert_eq!(measurement.error_count(), 0);
+        assert!(measurement.slo_statistics().contains_key("nested"));
+
+        // Without an enclosing Transaction, a nested Transaction counts as usual.
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                nested_transaction_hook(NESTED, async { Err::<(), _>(()) }).await
+            })
+            .await
+            .unwrap_err();
+
+        let measurement = atomics.collect(Duration::from_secs(1));
+        assert_eq!(measurement.success_count(), 0);
+        assert_eq!(measurement.error_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_error_categories() {
+        #[derive(Debug)]
+        enum TestError {
+            Timeout,
+            Status(u16),
+            Refused,
+        }
+
+        fn category(err: &TestError) -> &'static str {
+            match err {
+                TestError::Timeout => "timeout",
+                TestError::Status(_) => "server_error",
+                TestError::Refused => "connection_refused",
+            }
+        }
+
+        let mut hooks = Hooks::default();
+        let classifier: fn(&Result<(), TestError>) -> Outcome = |res| match res {
+            Err(TestError::Status(404)) => Outcome::Success,
+            Err(_) => Outcome::Error,
+            Ok(_) => Outcome::Success,
+        };
+        hooks.set_classifier(Box::new(classifier));
+        let atomics = TaskAtomics::new(
+            &ScenarioConfig::new(""),
+            &hooks,
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+
+        let errors = (0..20).map(|i| match i % 10 {
+            0..=5 => TestError::Timeout,
+            6..=8 => TestError::Status(503),
+            _ => TestError::Refused,
+        });
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                for err in errors.chain([TestError::Status(404)]) {
+                    let res = transaction_hook(LABELS, async move { Err::<(), _>(err) }).await;
+                    categorize_error(false, &res, category);
+                }
+                let res = transaction_hook(LABELS, async { Ok(()) }).await;
+                categorize_error(false, &res, category);
+
+                // NOTE: A nested Transaction's error only counts through its enclosing one.
+                let res = transaction_hook(LABELS, async {
+                    let res =
+                        nested_transaction_hook(LABELS, async { Err::<(), _>(TestError::Refused) })
+                            .await;
+                    categorize_error(true, &res, category);
+                    res.map_err(|_| TestError::Timeout)
+                })
+                .await;
+                categorize_error(false, &res, category);
+            })
+            .await;
+
+        let categories = atomics.error_categories();
+        assert_eq!(categories.len(), 3);
+        assert_eq!(categories["timeout"], 13);
+        assert_eq!(categories["server_error"], 6);
+        assert_eq!(categories["connection_refused"], 2);
+    }
+
+    #[cfg(feature = "phase-timing")]
+    #[tokio::test]
+    async fn test_phases() {
+        let atomics = TaskAtomics::new(
+            &ScenarioConfig::new(""),
+            &Hooks::default(),
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                for _ in 0..10 {
+                    let len = phase("serialize", || "{}".len());
+                    assert_eq!(len, 2);
+                    phase_async("wait", tokio::time::sleep(Duration::from_millis(5))).await;
+                }
+            })
+            .await;
+
+        let phases = atomics.phase_timings();
+        assert_eq!(phases.len(), 2);
+        assert_eq!(phases["serialize"].count, 10);
+        assert_eq!(phases["wait"].count, 10);
+        assert!(phases["wait"].mean >= 0.005);
+        assert!(phases["serialize"].mean < phases["wait"].mean);
+    }
+
+    #[tokio::test]
+    async fn test_added_latency() {
+        let mut config = ScenarioConfig::new("");
+        config.added_latency = Some(Duration::from_millis(20));
+        let atomics = TaskAtomics::new(
+            &config,
+            &Hooks::default(),
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+
+        let start = Instant::now();
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                for _ in 0..10 {
+                    let _ = transaction_hook(LABELS, async {
+                        tokio::time::sleep(Duration::from_millis(1)).await;
+                        Ok::<_, ()>(())
+                    })
+                    .await;
+                }
+            })
+            .await;
+
+        // NOTE: The added latency slows each Transaction down, but isn't the target's latency.
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        let measurement = atomics.collect(start.elapsed());
+        assert_eq!(measurement.success_count(), 10);
+        assert!(measurement.latency(0.99) < Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn test_variants() {
+        let atomics = TaskAtomics::new(
+            &ScenarioConfig::new(""),
+            &Hooks::default(),
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                for i in 0..10 {
+                    let _ = variant("A", transaction_hook(LABELS, async { Ok::<_, ()>(()) })).await;
+                    let res = if i % 2 == 0 { Ok(()) } else { Err(()) };
+                    let _ = variant("B", transaction_hook(LABELS, async move { res })).await;
+                }
+                let _ = transaction_hook(LABELS, async { Ok::<_, ()>(()) }).await;
+            })
+            .await;
+
+        // NOTE: The Scenario's measurements include every variant.
+        let measurement = atomics.collect(Duration::from_secs(1));
+        assert_eq!(measurement.success_count(), 16);
+        assert_eq!(measurement.error_count(), 5);
+
+        let variants = measurement.variant_statistics();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants["A"].actual_tps, 10.);
+        assert_eq!(variants["A"].error_rate, 0.);
+        assert_eq!(variants["B"].actual_tps, 5.);
+        assert_eq!(variants["B"].error_rate, 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_tps_per_host() {
+        let mut config = ScenarioConfig::new("");
+        config.tps_per_host = [("a", 50), ("b", 20)]
+            .into_iter()
+            .map(|(host, tps)| (host.to_string(), NonZeroU32::new(tps).unwrap()))
+            .collect();
+        let atomics = TaskAtomics::new(
+            &config,
+            &Hooks::default(),
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+
+        let run = |host: &'static str| async move {
+            let start = Instant::now();
+            let mut count = 0;
+            while start.elapsed() < Duration::from_secs(1) {
+                let tx = transaction_hook(LABELS, async { Ok::<_, ()>(()) });
+                let _ = with_host(host, tx).await;
+                count += 1;
+                // NOTE: Yield so that the hosts are run concurrently.
+                tokio::task::yield_now().await;
+            }
+            count
+        };
+
+        let (a, b, unlimited) = TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                tokio::join!(run("a"), run("b"), run("c"))
+            })
+            .await;
+
+        assert!((40..=52).contains(&a), "host a ran {a} transactions");
+        assert!((15..=22).contains(&b), "host b ran {b} transactions");
+        assert!(unlimited > 1_000, "host c ran {unlimited} transactions");
+    }
+
+    #[tokio::test]
+    async fn test_state() {
+        let hooks = Hooks {
+            state: Some(Arc::new(Mutex::new(vec![1_u32, 2, 3]))),
+            ..Default::default()
+        };
+        let atomics = TaskAtomics::new(
+            &ScenarioConfig::new(""),
+            &hooks,
+            NonZeroU32::new(1_000_000).unwrap(),
+        );
+
+        let task = || {
+            TRANSACTION_HOOK.scope(atomics.clone_to_transaction_data(), async {
+                let ids = state::<Mutex<Vec<u32>>>().unwrap();
+                let mut ids = ids.lock().unwrap();
+                ids.pop()
+            })
+        };
+
+        // NOTE: Each task pulls from the same state.
+        let mut ids = vec![task().await, task().await, task().await, task().await];
+        ids.sort();
+        assert_eq!(ids, vec![None, Some(1), Some(2), Some(3)]);
+
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                assert!(state::<Mutex<Vec<u64>>>().is_none());
+            })
+            .await;
+        assert!(state::<Mutex<Vec<u32>>>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_classify_other_types_use_default() {
+        let mut hooks = Hooks::default();
+        hooks.set_classifier::<(), ()>(Box::new(|_| Outcome::Ignore));
+
+        let counts = run_transactions(hooks, vec![Ok(1), Err("err".into())]).await;
+        assert_eq!(counts, (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_fail_after() {
+        for concurrency in [1, 8, 64] {
+            let atomics = TaskAtomics::new(
+                &ScenarioConfig::new("test_fail_after"),
+                &Hooks::default(),
+                NonZeroU32::new(1_000_000).unwrap(),
+            );
+
+            let tasks: Vec<_> = (0..concurrency)
+                .map(|_| {
+                    tokio::spawn(TRANSACTION_HOOK.scope(
+                        atomics.clone_to_transaction_data(),
+                        async {
+                            for _ in 0..100 {
+                                // NOTE: Yielding interleaves the tasks' Transactions.
+                                let _ = transaction_hook::<_, (), ()>(LABELS, async {
+                                    tokio::task::yield_now().await;
+                                    if fail_after(50) {
+                                        Err(())
+                                    } else {
+                                        Ok(())
+                                    }
+                                })
+                                .await;
+                            }
+                        },
+                    ))
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+
+            let measurement = atomics.collect(Duration::from_secs(1));
+            assert_eq!(measurement.success_count(), 50, "{concurrency} tasks");
+            assert_eq!(
+                measurement.error_count(),
+                100 * concurrency - 50,
+                "{concurrency} tasks"
+            );
+        }
+
+        assert!(!fail_after(0));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn test_record_latency_histogram_name() {
+        use metrics::{
+            Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+        };
+
+        /// Recorder which only keeps the names of the histograms registered.
+        #[derive(Default)]
+        struct HistogramNames(Mutex<Vec<String>>);
+
+        impl Recorder for HistogramNames {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn register_counter(&self, _: &Key, _: &Metadata<'_>) -> Counter {
+                Counter::noop()
+            }
+
+            fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::noop()
+            }
+
+            fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+                self.0.lock().unwrap().push(key.name().to_string());
+                Histogram::noop()
+            }
+        }
+
+        let recorder = HistogramNames::default();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let atomics = TaskAtomics::new(
+            &ScenarioConfig::new("test_record_latency"),
+            &Hooks::default(),
+            NonZeroU32::MAX,
+        );
+        TRANSACTION_HOOK
+            .scope(atomics.clone_to_transaction_data(), async {
+                record_latency("ttfb", Duration::from_millis(5));
+            })
+            .await;
+
+        // NOTE: Prefixed like the Scenario's other metrics, so that the same label recorded by
+        // two Scenarios doesn't end up in one histogram.
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            ["balter_test_record_latency_ttfb"]
+        );
+    }
 }
@@ -0,0 +1,45 @@
+//! Checks that metric names aren't formatted every window.
+//!
+//! NOTE: This is an integration test of its own since it swaps out the global allocator, which
+//! would otherwise apply to every test of the library.
+use balter::MetricsProbe;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::num::NonZeroU32;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Allocator which counts allocations made on the current thread.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[tokio::test]
+async fn test_no_metric_name_allocations() {
+    let mut probe = MetricsProbe::new(
+        "test_no_metric_name_allocations",
+        NonZeroU32::new(1_000).unwrap(),
+    )
+    .await;
+    probe.emit_metrics();
+
+    let before = ALLOCATIONS.with(|count| count.get());
+    probe.emit_metrics();
+    let after = ALLOCATIONS.with(|count| count.get());
+
+    assert_eq!(before, after);
+}
@@ -0,0 +1,62 @@
+use balter::prelude::*;
+use reqwest::Client;
+use std::fmt::Write;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+use tracing_subscriber::FmtSubscriber;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    FmtSubscriber::builder()
+        .with_env_filter("balter=info")
+        .init();
+
+    // Hold 100 tasks and step the TPS up to past the limit of the endpoint.
+    let sweep = scenario_a()
+        .capacity_sweep(
+            100,
+            (1..=10).map(|step| step * 1_000).collect(),
+            Duration::from_secs(30),
+        )
+        .await;
+
+    let mut csv = String::from("goal_tps,actual_tps,latency_p50_ms,latency_p99_ms,error_rate\n");
+    for step in &sweep.steps {
+        writeln!(
+            csv,
+            "{},{:.2},{:.3},{:.3},{:.4}",
+            step.goal_tps,
+            step.actual_tps,
+            step.latency_p50.as_secs_f64() * 1_000.,
+            step.latency_p99.as_secs_f64() * 1_000.,
+            step.error_rate,
+        )?;
+    }
+    std::fs::write("capacity-sweep.csv", csv)?;
+
+    if let Some(step) = sweep.max_sustained(0.05, 0.01) {
+        println!(
+            "Max sustained TPS at {} tasks: {}",
+            sweep.concurrency, step.goal_tps
+        );
+    }
+    Ok(())
+}
+
+#[scenario]
+async fn scenario_a() {
+    let _ = api_a().await;
+}
+
+#[transaction]
+async fn api_a() -> Result<(), reqwest::Error> {
+    let client = CLIENT.get_or_init(Client::new);
+    client
+        .get("http://0.0.0.0:3002/limited/7000/delay/ms/10/server/0")
+        .send()
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Drive Balter's controllers from a custom load generator, rather than a Scenario.
+use balter::{CompositeController, Controller, LatencyConfig, Measurement, ScenarioConfig};
+use reqwest::Client;
+use std::num::NonZeroU32;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use tracing_subscriber::FmtSubscriber;
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    FmtSubscriber::builder()
+        .with_env_filter("balter=info")
+        .init();
+
+    // The same goals as `.tps(10_000).error_rate(0.05).latency(...)` on a Scenario.
+    let mut config = ScenarioConfig::new("external");
+    config.max_tps = NonZeroU32::new(10_000);
+    config.error_rate = Some(0.05);
+    config.latency = Some(LatencyConfig::new(Duration::from_millis(50), 0.99));
+    let mut controllers = CompositeController::new(&config);
+
+    let client = Client::new();
+    let mut goal_tps = controllers.initial_tps();
+    for _ in 0..60 {
+        let start = Instant::now();
+        let (success, error, latencies) = run_window(&client, goal_tps.get()).await;
+        let elapsed = start.elapsed();
+
+        // NOTE: Requests are sent open-loop, so the goal is reached unless the window overran.
+        let stable = elapsed < WINDOW * 2;
+        let sample = Measurement::new(success, error, elapsed).with_latencies(&latencies);
+        goal_tps = controllers.limit(&sample, stable);
+
+        println!(
+            "{:.0} TPS, {:.2}% errors, p99 {:?} -> goal {goal_tps} ({:?})",
+            sample.tps,
+            sample.error_rate * 100.,
+            sample.latency(0.99),
+            controllers.state(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Send `tps` requests spread evenly over a window, returning the number which succeeded and
+/// failed along with the latency of each.
+async fn run_window(client: &Client, tps: u32) -> (u64, u64, Vec<Duration>) {
+    let mut interval = tokio::time::interval(WINDOW / tps);
+    let mut requests = JoinSet::new();
+    for _ in 0..tps {
+        interval.tick().await;
+        let client = client.clone();
+        requests.spawn(async move {
+            let start = Instant::now();
+            let res = client
+                .get("http://0.0.0.0:3002/limited/7000/delay/ms/10/server/0")
+                .send()
+                .await
+                .and_then(|res| res.error_for_status());
+            (res.is_ok(), start.elapsed())
+        });
+    }
+
+    let (mut success, mut error, mut latencies) = (0, 0, vec![]);
+    while let Some(Ok((ok, latency))) = requests.join_next().await {
+        if ok {
+            success += 1;
+        } else {
+            error += 1;
+        }
+        latencies.push(latency);
+    }
+    (success, error, latencies)
+}
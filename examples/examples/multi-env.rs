@@ -0,0 +1,39 @@
+use balter::prelude::*;
+use reqwest::Client;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::info;
+use tracing_subscriber::FmtSubscriber;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+#[tokio::main]
+async fn main() {
+    FmtSubscriber::builder()
+        .with_env_filter("balter=debug")
+        .init();
+
+    // Run the same scenario against two environments, one after the other.
+    for base_url in ["http://0.0.0.0:3002", "http://127.0.0.1:3002"] {
+        let stats = scenario_a()
+            .tps(500)
+            .with_base_url(base_url)
+            .duration(Duration::from_secs(30))
+            .await;
+
+        info!("{base_url}: {stats:?}");
+    }
+}
+
+#[scenario]
+async fn scenario_a() {
+    let _ = api_a().await;
+}
+
+#[transaction]
+async fn api_a() -> Result<(), reqwest::Error> {
+    let client = CLIENT.get_or_init(Client::new);
+    let base_url = balter::base_url().expect("Base URL not set");
+    client.get(format!("{base_url}/delay/ms/10")).send().await?;
+    Ok(())
+}
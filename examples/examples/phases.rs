@@ -0,0 +1,41 @@
+use balter::prelude::*;
+use reqwest::Client;
+use std::time::Duration;
+use tracing::info;
+use tracing_subscriber::FmtSubscriber;
+
+#[tokio::main]
+async fn main() {
+    FmtSubscriber::builder()
+        .with_env_filter("balter=debug")
+        .init();
+
+    // Warm up, sustain, then burst, all on the same client and connection pool.
+    let report = scenario_a()
+        .with_base_url("http://0.0.0.0:3002")
+        .phased()
+        .phase("warmup", |s| s.tps(100).duration(Duration::from_secs(30)))
+        .phase("soak", |s| s.tps(500).duration(Duration::from_secs(120)))
+        .phase("spike", |s| s.tps(2_000).duration(Duration::from_secs(30)))
+        .await;
+
+    for (phase, stats) in report {
+        info!("{phase}: {stats}");
+    }
+}
+
+#[scenario]
+async fn scenario_a() {
+    // NOTE: Created once per task and reused by every phase.
+    let client = Client::new();
+    loop {
+        let _ = api_a(&client).await;
+    }
+}
+
+#[transaction]
+async fn api_a(client: &Client) -> Result<(), reqwest::Error> {
+    let base_url = balter::base_url().expect("Base URL not set");
+    client.get(format!("{base_url}/delay/ms/10")).send().await?;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use balter::prelude::*;
+use reqwest::Client;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+use tracing_subscriber::FmtSubscriber;
+
+type UserId = u64;
+
+#[tokio::main]
+async fn main() {
+    FmtSubscriber::builder()
+        .with_env_filter("balter=info")
+        .init();
+
+    // Pre-created users, shared by every task of the Scenario.
+    let user_ids: Vec<UserId> = (0..1_000).collect();
+
+    scenario_a()
+        .tps(500)
+        .with_state(Mutex::new(user_ids))
+        .duration(Duration::from_secs(60))
+        .await;
+}
+
+#[scenario]
+async fn scenario_a() {
+    let user_ids = balter::state::<Mutex<Vec<UserId>>>().expect("State is set in main()");
+
+    // Check out a user so that no two tasks use the same one at once, and return it after.
+    let user_id = user_ids.lock().unwrap().pop();
+    if let Some(user_id) = user_id {
+        let _ = api_a(user_id).await;
+        user_ids.lock().unwrap().push(user_id);
+    }
+}
+
+#[transaction]
+async fn api_a(user_id: UserId) -> Result<(), reqwest::Error> {
+    let client = CLIENT.get_or_init(Client::new);
+    client
+        .get(format!("http://0.0.0.0:3002/delay/ms/10?user={user_id}"))
+        .send()
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,136 @@
+//! Emit Balter's metrics to a (Dog)StatsD agent listening on `127.0.0.1:8125`.
+//!
+//! Balter emits through the `metrics` facade, so any recorder works. This one is deliberately
+//! minimal to show the mapping; in practice, use an exporter crate built for the same `metrics`
+//! version as Balter, since a recorder for another version never receives its metrics.
+use balter::prelude::*;
+use balter::ScenarioDefaults;
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use reqwest::Client;
+use std::fmt::Display;
+use std::net::UdpSocket;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+use tracing_subscriber::FmtSubscriber;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    FmtSubscriber::builder()
+        .with_env_filter("balter=info")
+        .init();
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect("127.0.0.1:8125")?;
+    metrics::set_global_recorder(StatsdRecorder {
+        socket: Arc::new(socket),
+    })
+    .map_err(|_| anyhow::anyhow!("A metrics recorder is already installed"))?;
+
+    // NOTE: Balter keeps the `.` of the prefix when sanitizing metric names, so these show up
+    // under `loadtest` as `loadtest.api_scenario_a_goal_tps` and so on.
+    balter::set_defaults(ScenarioDefaults::new().sanitize_metric_names());
+    scenario_a()
+        .tps(1_000)
+        .metrics_prefix("loadtest.api")
+        .metrics_interval(Duration::from_secs(1))
+        .duration(Duration::from_secs(60))
+        .await;
+
+    Ok(())
+}
+
+#[scenario]
+async fn scenario_a() {
+    let _ = api_a().await;
+}
+
+#[transaction]
+async fn api_a() -> Result<(), reqwest::Error> {
+    let client = CLIENT.get_or_init(Client::new);
+    client.get("http://0.0.0.0:3002/delay/ms/10").send().await?;
+    Ok(())
+}
+
+struct StatsdRecorder {
+    socket: Arc<UdpSocket>,
+}
+
+impl StatsdRecorder {
+    fn metric(&self, key: &Key) -> Arc<StatsdMetric> {
+        Arc::new(StatsdMetric {
+            name: key.name().to_string(),
+            socket: self.socket.clone(),
+        })
+    }
+}
+
+impl Recorder for StatsdRecorder {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        Counter::from_arc(self.metric(key))
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        Gauge::from_arc(self.metric(key))
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(self.metric(key))
+    }
+}
+
+struct StatsdMetric {
+    name: String,
+    socket: Arc<UdpSocket>,
+}
+
+impl StatsdMetric {
+    /// Send a single line of the statsd protocol, e.g. `api_a_success:1|c`. Metrics are best
+    /// effort, so failures to send are ignored.
+    fn send(&self, value: impl Display, kind: &str) {
+        let _ = self
+            .socket
+            .send(format!("{}:{value}|{kind}", self.name).as_bytes());
+    }
+}
+
+impl CounterFn for StatsdMetric {
+    fn increment(&self, value: u64) {
+        self.send(value, "c");
+    }
+
+    fn absolute(&self, value: u64) {
+        self.send(value, "g");
+    }
+}
+
+impl GaugeFn for StatsdMetric {
+    fn increment(&self, value: f64) {
+        self.send(format!("+{value}"), "g");
+    }
+
+    fn decrement(&self, value: f64) {
+        self.send(format!("-{value}"), "g");
+    }
+
+    fn set(&self, value: f64) {
+        self.send(value, "g");
+    }
+}
+
+impl HistogramFn for StatsdMetric {
+    /// NOTE: Uses the DogStatsD histogram type, since latencies are recorded in seconds rather
+    /// than the milliseconds of a plain statsd timer (`ms`).
+    fn record(&self, value: f64) {
+        self.send(value, "h");
+    }
+}
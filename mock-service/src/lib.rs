@@ -1,7 +1,8 @@
 use axum::{
     debug_handler,
     extract::{Json, Path},
-    http::StatusCode,
+    http::{header::RETRY_AFTER, Method, StatusCode},
+    response::{IntoResponse, Response},
     routing::get,
     Router,
 };
@@ -18,21 +19,33 @@ use std::{
     num::NonZeroU32,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
-        Arc, RwLock as ARwLock,
+        Arc, Mutex, RwLock as ARwLock,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
+use tokio::sync::Semaphore;
 #[allow(unused)]
 use tracing::{debug, error, instrument};
 
 pub mod prelude {
-    pub use super::{Config, LatencyConfig, LatencyKind, TpsConfig, TpsKind};
+    pub use super::{
+        Config, HttpMethod, LatencyConfig, LatencyKind, MethodConfig, TimelinePoint, TpsConfig,
+        TpsKind,
+    };
 }
 
 pub async fn run(addr: SocketAddr) {
     tokio::spawn(tps_updater_task());
+    tokio::spawn(timeline_task());
     let app = Router::new()
-        .route("/", get(mock_route))
+        .route(
+            "/",
+            get(mock_route)
+                .post(mock_route)
+                .put(mock_route)
+                .delete(mock_route),
+        )
+        .route("/timeline/:scenario_name", get(timeline))
         .route("/delay/ms/:delay_ms", get(delay))
         .route(
             "/max/:max_tps/delay/ms/:delay_ms/scenario/:scenario_name",
@@ -41,6 +54,10 @@ pub async fn run(addr: SocketAddr) {
         .route(
             "/limited/:max_tps/delay/ms/:delay_ms/server/:server_id",
             get(limited),
+        )
+        .route(
+            "/connections/:max_connections/delay/ms/:delay_ms/server/:server_id",
+            get(connections),
         );
 
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
@@ -49,11 +66,106 @@ pub async fn run(addr: SocketAddr) {
 
 /** New Handler **/
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Config {
     pub scenario_name: String,
     pub tps: Option<TpsConfig>,
     pub latency: Option<LatencyConfig>,
+    /// Behavior for specific HTTP methods, in place of `tps` and `latency`. Each method is
+    /// limited independently of the others.
+    #[serde(default)]
+    pub methods: HashMap<HttpMethod, MethodConfig>,
+    /// Payload of the request, e.g. the resource to create for a `POST`.
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+    /// Record the TPS offered and served every `TIMELINE_INTERVAL`, see `/timeline`. Only read
+    /// from the first request of a scenario.
+    #[serde(default)]
+    pub record_timeline: bool,
+}
+
+impl Config {
+    /// Behavior for requests with the given method, and the name its state is tracked under.
+    fn behavior(&self, method: HttpMethod) -> (String, Behavior<'_>) {
+        match self.methods.get(&method) {
+            Some(conf) => (
+                format!("{}.{}", self.scenario_name, method.as_str()),
+                Behavior {
+                    tps: conf.tps.as_ref(),
+                    latency: conf.latency.as_ref(),
+                    required_fields: &conf.required_fields,
+                },
+            ),
+            None => (
+                self.scenario_name.clone(),
+                Behavior {
+                    tps: self.tps.as_ref(),
+                    latency: self.latency.as_ref(),
+                    required_fields: &[],
+                },
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HttpMethod {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+impl HttpMethod {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Delete => "DELETE",
+        }
+    }
+}
+
+impl TryFrom<&Method> for HttpMethod {
+    type Error = StatusCode;
+
+    fn try_from(method: &Method) -> Result<Self, Self::Error> {
+        match *method {
+            Method::GET => Ok(HttpMethod::Get),
+            Method::POST => Ok(HttpMethod::Post),
+            Method::PUT => Ok(HttpMethod::Put),
+            Method::DELETE => Ok(HttpMethod::Delete),
+            _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MethodConfig {
+    pub tps: Option<TpsConfig>,
+    pub latency: Option<LatencyConfig>,
+    /// Fields the `body` must be a JSON object with, or the request is rejected with a 422.
+    #[serde(default)]
+    pub required_fields: Vec<String>,
+}
+
+struct Behavior<'a> {
+    tps: Option<&'a TpsConfig>,
+    latency: Option<&'a LatencyConfig>,
+    required_fields: &'a [String],
+}
+
+impl Behavior<'_> {
+    fn validate(&self, body: Option<&serde_json::Value>) -> bool {
+        self.required_fields.is_empty()
+            || body.and_then(|body| body.as_object()).is_some_and(|body| {
+                self.required_fields
+                    .iter()
+                    .all(|field| body.contains_key(field))
+            })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -66,6 +178,17 @@ pub struct TpsConfig {
 pub enum TpsKind {
     CutOff,
     Error,
+    /// Like `Error`, but once the offered load stays above `threshold` TPS for a sustained
+    /// period the capacity collapses (simulating resource exhaustion), and only recovers after
+    /// the load stays below `threshold` for the `recovery` period.
+    Cascading {
+        threshold: NonZeroU32,
+        recovery: Duration,
+    },
+    /// Like `Error`, but responds with a `Retry-After` header (rounded up to whole seconds).
+    RateLimited {
+        retry_after: Duration,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -93,61 +216,233 @@ struct ScenarioState {
     tps_tracker: AtomicU64,
     avg_tps: AtomicU64,
     seen: AtomicBool,
+    cascade: Option<CascadeState>,
+    timeline: Option<Timeline>,
+}
+
+/// How long the offered load must stay above the threshold before capacity collapses.
+const CASCADE_ONSET: Duration = Duration::from_secs(2);
+/// Fraction of the normal capacity left once collapsed.
+const CASCADE_CAPACITY: f64 = 0.25;
+/// Smoothing factor for the offered load, applied every `tps_updater_task` tick (10ms).
+const CASCADE_SMOOTHING: f64 = 0.1;
+
+struct CascadeState {
+    threshold: f64,
+    recovery: Duration,
+    capacity: u32,
+    collapsed_rate_limiter: DefaultDirectRateLimiter,
+    offered_tracker: AtomicU64,
+    collapsed: AtomicBool,
+    inner: Mutex<CascadeInner>,
+}
+
+struct CascadeInner {
+    offered_tps: f64,
+    /// Start of the current run of ticks above (or, when collapsed, below) the threshold.
+    since: Option<Instant>,
+}
+
+impl CascadeState {
+    fn new(capacity: NonZeroU32, threshold: NonZeroU32, recovery: Duration) -> Self {
+        let collapsed_capacity = (capacity.get() as f64 * CASCADE_CAPACITY).max(1.) as u32;
+        Self {
+            threshold: threshold.get() as f64,
+            recovery,
+            capacity: capacity.get(),
+            collapsed_rate_limiter: rate_limiter(collapsed_capacity),
+            offered_tracker: AtomicU64::new(0),
+            collapsed: AtomicBool::new(false),
+            inner: Mutex::new(CascadeInner {
+                offered_tps: 0.,
+                since: None,
+            }),
+        }
+    }
+
+    fn update(&self, name: &str, elapsed: Duration) {
+        let offered = self.offered_tracker.swap(0, Ordering::Relaxed);
+        let tps = offered as f64 / elapsed.as_secs_f64();
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.offered_tps += CASCADE_SMOOTHING * (tps - inner.offered_tps);
+
+        let collapsed = self.collapsed.load(Ordering::Relaxed);
+        // NOTE: When healthy we wait for sustained overload, and when collapsed we wait for
+        // sustained calm.
+        let trending = if collapsed {
+            inner.offered_tps < self.threshold
+        } else {
+            inner.offered_tps > self.threshold
+        };
+
+        if !trending {
+            inner.since = None;
+        } else {
+            let since = *inner.since.get_or_insert_with(Instant::now);
+            let period = if collapsed {
+                self.recovery
+            } else {
+                CASCADE_ONSET
+            };
+
+            if since.elapsed() >= period {
+                self.collapsed.store(!collapsed, Ordering::Relaxed);
+                inner.since = None;
+                if collapsed {
+                    debug!("{name} recovered from cascading failure");
+                } else {
+                    debug!("{name} is in cascading failure");
+                }
+            }
+        }
+
+        let capacity = if self.collapsed.load(Ordering::Relaxed) {
+            self.capacity as f64 * CASCADE_CAPACITY
+        } else {
+            self.capacity as f64
+        };
+        gauge!(format!("mock-server.{}.capacity", name)).set(capacity);
+    }
+}
+
+/// Rejection of a request to [`mock_route()`].
+#[derive(Debug)]
+pub enum MockError {
+    Status(StatusCode),
+    /// A `429 Too Many Requests` with a `Retry-After` header.
+    RateLimited {
+        retry_after: Duration,
+    },
+}
+
+impl From<StatusCode> for MockError {
+    fn from(status: StatusCode) -> Self {
+        MockError::Status(status)
+    }
+}
+
+impl IntoResponse for MockError {
+    fn into_response(self) -> Response {
+        match self {
+            MockError::Status(status) => status.into_response(),
+            MockError::RateLimited { retry_after } => {
+                let retry_after = retry_after.as_secs_f64().ceil().to_string();
+                (StatusCode::TOO_MANY_REQUESTS, [(RETRY_AFTER, retry_after)]).into_response()
+            }
+        }
+    }
 }
 
 #[instrument]
-pub async fn mock_route(Json(config): Json<Config>) -> Result<(), StatusCode> {
-    if config.tps.is_none() && config.latency.is_none() {
+pub async fn mock_route(method: Method, Json(config): Json<Config>) -> Result<(), MockError> {
+    let method = HttpMethod::try_from(&method)?;
+    let (state_name, behavior) = config.behavior(method);
+    let labels = [("method", method.as_str())];
+
+    if behavior.tps.is_none() && behavior.latency.is_none() {
         error!("Garbage configuration for mock server");
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+
+    if !behavior.validate(config.body.as_ref()) {
+        counter!(
+            format!("mock-server.{}.invalid", &config.scenario_name),
+            &labels
+        )
+        .increment(1);
+        return Err(StatusCode::UNPROCESSABLE_ENTITY.into());
     }
 
     let state = {
-        let state = SCENARIO_MAP
-            .read()
-            .unwrap()
-            .get(&config.scenario_name)
-            .cloned();
+        let state = SCENARIO_MAP.read().unwrap().get(&state_name).cloned();
         if let Some(state) = state {
             state
         } else {
             let state = Arc::new(ScenarioState {
-                tps_rate_limiter: config
+                tps_rate_limiter: behavior
                     .tps
-                    .as_ref()
                     .map(|tps_conf| rate_limiter(tps_conf.tps.get())),
                 tps_tracker: AtomicU64::new(0),
                 avg_tps: AtomicU64::new(0),
                 seen: AtomicBool::new(false),
+                cascade: behavior.tps.and_then(|tps_conf| match tps_conf.kind {
+                    TpsKind::Cascading {
+                        threshold,
+                        recovery,
+                    } => Some(CascadeState::new(tps_conf.tps, threshold, recovery)),
+                    _ => None,
+                }),
+                timeline: config.record_timeline.then(Timeline::new),
             });
             {
                 let mut writer = SCENARIO_MAP.write().unwrap();
-                writer.insert(config.scenario_name.clone(), state.clone());
+                writer.insert(state_name, state.clone());
             }
             state
         }
     };
 
-    if let Some(tps_conf) = &config.tps {
+    if let Some(timeline) = &state.timeline {
+        timeline.offered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if let Some(tps_conf) = behavior.tps {
         match tps_conf.kind {
             TpsKind::CutOff => {
                 state.tps_rate_limiter.as_ref().unwrap().until_ready().await;
             }
             TpsKind::Error => {
                 if state.tps_rate_limiter.as_ref().unwrap().check().is_err() {
-                    counter!(format!("mock-server.{}.error", &config.scenario_name)).increment(1);
-                    return Err(StatusCode::TOO_MANY_REQUESTS);
+                    counter!(
+                        format!("mock-server.{}.error", &config.scenario_name),
+                        &labels
+                    )
+                    .increment(1);
+                    return Err(StatusCode::TOO_MANY_REQUESTS.into());
+                }
+            }
+            TpsKind::RateLimited { retry_after } => {
+                if state.tps_rate_limiter.as_ref().unwrap().check().is_err() {
+                    counter!(
+                        format!("mock-server.{}.error", &config.scenario_name),
+                        &labels
+                    )
+                    .increment(1);
+                    return Err(MockError::RateLimited { retry_after });
+                }
+            }
+            TpsKind::Cascading { .. } => {
+                let cascade = state.cascade.as_ref().unwrap();
+                cascade.offered_tracker.fetch_add(1, Ordering::Relaxed);
+
+                let limiter = if cascade.collapsed.load(Ordering::Relaxed) {
+                    &cascade.collapsed_rate_limiter
+                } else {
+                    state.tps_rate_limiter.as_ref().unwrap()
+                };
+
+                if limiter.check().is_err() {
+                    counter!(
+                        format!("mock-server.{}.error", &config.scenario_name),
+                        &labels
+                    )
+                    .increment(1);
+                    return Err(StatusCode::SERVICE_UNAVAILABLE.into());
                 }
             }
         }
     }
 
-    if let Some(latency_conf) = &config.latency {
+    if let Some(latency_conf) = behavior.latency {
         match latency_conf.kind {
             LatencyKind::Delay => {
                 tokio::time::sleep(latency_conf.latency).await;
-                histogram!(format!("mock-server.{}.latency", &config.scenario_name))
-                    .record(latency_conf.latency.as_secs_f64());
+                histogram!(
+                    format!("mock-server.{}.latency", &config.scenario_name),
+                    &labels
+                )
+                .record(latency_conf.latency.as_secs_f64());
             }
             LatencyKind::Noise(std, shape) => {
                 let skew_normal =
@@ -156,8 +451,11 @@ pub async fn mock_route(Json(config): Json<Config>) -> Result<(), StatusCode> {
                 let v: f64 = skew_normal.sample(&mut rand::thread_rng());
 
                 tokio::time::sleep(Duration::from_secs_f64(v)).await;
-                histogram!(format!("mock-server.{}.latency", &config.scenario_name))
-                    .record(latency_conf.latency.as_secs_f64());
+                histogram!(
+                    format!("mock-server.{}.latency", &config.scenario_name),
+                    &labels
+                )
+                .record(latency_conf.latency.as_secs_f64());
             }
             LatencyKind::Linear(latency_tps) => {
                 let avg_tps = state.avg_tps.load(Ordering::Relaxed);
@@ -167,15 +465,25 @@ pub async fn mock_route(Json(config): Json<Config>) -> Result<(), StatusCode> {
                     let wait = ratio * latency_conf.latency.as_secs_f64();
                     let wait = Duration::from_secs_f64(wait);
                     tokio::time::sleep(wait).await;
-                    histogram!(format!("mock-server.{}.latency", &config.scenario_name))
-                        .record(wait.as_secs_f64());
+                    histogram!(
+                        format!("mock-server.{}.latency", &config.scenario_name),
+                        &labels
+                    )
+                    .record(wait.as_secs_f64());
                 }
             }
         }
     }
 
-    counter!(format!("mock-server.{}.success", &config.scenario_name)).increment(1);
+    counter!(
+        format!("mock-server.{}.success", &config.scenario_name),
+        &labels
+    )
+    .increment(1);
     state.tps_tracker.fetch_add(1, Ordering::Relaxed);
+    if let Some(timeline) = &state.timeline {
+        timeline.achieved.fetch_add(1, Ordering::Relaxed);
+    }
 
     Ok(())
 }
@@ -187,10 +495,18 @@ pub async fn mock_route(Json(config): Json<Config>) -> Result<(), StatusCode> {
 /// window needs to be small enough to adjust rapidly, but large enough to not run into issues
 /// with noisy data (currently 10ms by guessing).
 async fn tps_updater_task() {
+    let mut last = Instant::now();
     loop {
         tokio::time::sleep(Duration::from_millis(10)).await;
+        let elapsed = last.elapsed();
+        last = Instant::now();
+
         let scenario_map = SCENARIO_MAP.read().unwrap();
         for (name, state) in scenario_map.iter() {
+            if let Some(cascade) = &state.cascade {
+                cascade.update(name, elapsed);
+            }
+
             let transactions = state.tps_tracker.fetch_min(0, Ordering::Relaxed);
             if !state.seen.fetch_or(true, Ordering::Relaxed) {
                 // We want to skip the first time we measure, since we don't know when the scenario
@@ -206,6 +522,80 @@ async fn tps_updater_task() {
     }
 }
 
+/// Interval at which the TPS of a scenario with `record_timeline` set is recorded.
+const TIMELINE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// TPS a scenario was observed at over time, for comparing the behavior of the controllers
+/// between runs (e.g. how quickly they converge, and whether they oscillate).
+struct Timeline {
+    start: Instant,
+    /// Requests received, whether or not they were served.
+    offered: AtomicU64,
+    /// Requests served successfully.
+    achieved: AtomicU64,
+    points: Mutex<Vec<TimelinePoint>>,
+}
+
+impl Timeline {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            offered: AtomicU64::new(0),
+            achieved: AtomicU64::new(0),
+            points: Mutex::new(vec![]),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let per_sec = |count: u64| count as f64 / elapsed.as_secs_f64();
+        let point = TimelinePoint {
+            time: self.start.elapsed().as_secs_f64(),
+            requested_tps: per_sec(self.offered.swap(0, Ordering::Relaxed)),
+            achieved_tps: per_sec(self.achieved.swap(0, Ordering::Relaxed)),
+        };
+        self.points.lock().unwrap().push(point);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimelinePoint {
+    /// Seconds since the first request of the scenario.
+    pub time: f64,
+    /// TPS of the requests received, including those which errored.
+    pub requested_tps: f64,
+    /// TPS of the requests served successfully.
+    pub achieved_tps: f64,
+}
+
+async fn timeline_task() {
+    let mut last = Instant::now();
+    loop {
+        tokio::time::sleep(TIMELINE_INTERVAL).await;
+        let elapsed = last.elapsed();
+        last = Instant::now();
+
+        let scenario_map = SCENARIO_MAP.read().unwrap();
+        for state in scenario_map.values() {
+            if let Some(timeline) = &state.timeline {
+                timeline.record(elapsed);
+            }
+        }
+    }
+}
+
+/// The timeline recorded for a scenario with `record_timeline` set, as a JSON array of
+/// `TimelinePoint`s. Scenarios with methods configured are recorded as `<scenario>.<METHOD>`.
+#[debug_handler]
+pub async fn timeline(
+    Path(scenario_name): Path<String>,
+) -> Result<Json<Vec<TimelinePoint>>, StatusCode> {
+    let state = SCENARIO_MAP.read().unwrap().get(&scenario_name).cloned();
+    match state.as_ref().and_then(|state| state.timeline.as_ref()) {
+        Some(timeline) => Ok(Json(timeline.points.lock().unwrap().clone())),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
 /** Old Handlers **/
 
 #[debug_handler]
@@ -278,6 +668,39 @@ pub async fn limited(
     Ok(())
 }
 
+lazy_static! {
+    static ref CONNECTIONS_MAP: Arc<ARwLock<HashMap<String, Arc<Semaphore>>>> =
+        Arc::new(ARwLock::new(HashMap::new()));
+}
+
+/// Serve at most `max_connections` requests concurrently, rejecting any beyond that with a 503.
+#[debug_handler]
+pub async fn connections(
+    Path((max_connections, delay_ms, server_id)): Path<(usize, u64, String)>,
+) -> Result<(), StatusCode> {
+    counter!("mock-server.tps").increment(1);
+    TPS_MEASURE.fetch_add(1, Ordering::Relaxed);
+
+    let read = CONNECTIONS_MAP.read().unwrap().get(&server_id).cloned();
+    let connections = if let Some(connections) = read {
+        connections
+    } else {
+        CONNECTIONS_MAP
+            .write()
+            .unwrap()
+            .entry(server_id)
+            .or_insert_with(|| Arc::new(Semaphore::new(max_connections)))
+            .clone()
+    };
+
+    let _permit = connections
+        .try_acquire()
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+
+    Ok(())
+}
+
 /** Utils **/
 
 pub fn rate_limiter(tps: u32) -> DefaultDirectRateLimiter {
@@ -8,6 +8,8 @@ mod tests {
     use balter::prelude::*;
     use mock_service::prelude::*;
     use reqwest::Client;
+    use std::collections::HashMap;
+    use std::num::NonZeroU32;
     use std::sync::OnceLock;
     use std::time::Duration;
 
@@ -49,6 +51,7 @@ mod tests {
                     latency: Duration::from_millis(1),
                     kind: LatencyKind::Delay,
                 }),
+                ..Default::default()
             })
             .send()
             .await?;
@@ -88,6 +91,7 @@ mod tests {
                     latency: Duration::from_millis(400),
                     kind: LatencyKind::Noise(Duration::from_millis(300), 50.),
                 }),
+                ..Default::default()
             })
             .send()
             .await?;
@@ -124,6 +128,315 @@ mod tests {
         assert!(stats.concurrency >= 2);
     }
 
+    #[tokio::test]
+    async fn single_instance_error_rate_timeline() {
+        init().await;
+
+        let stats = scenario_timeline()
+            .error_rate(0.03)
+            .duration(Duration::from_secs(45))
+            .await;
+
+        assert!(dbg!(stats.goal_tps) >= 1_200);
+        let timeline = fetch_timeline("timeline_isolated").await;
+        assert_timeline("error_rate_1300", &timeline, 0.25);
+    }
+
+    #[scenario]
+    async fn scenario_timeline() {
+        let _ = transaction_timeline().await;
+    }
+
+    #[transaction]
+    async fn transaction_timeline() -> anyhow::Result<()> {
+        let client = CLIENT.get_or_init(Client::new);
+        let res = client
+            .get("http://0.0.0.0:3002/")
+            .json(&Config {
+                scenario_name: "timeline_isolated".to_string(),
+                tps: Some(TpsConfig {
+                    tps: NonZeroU32::new(1_300).unwrap(),
+                    kind: TpsKind::Error,
+                }),
+                latency: Some(LatencyConfig {
+                    latency: Duration::from_millis(1),
+                    kind: LatencyKind::Delay,
+                }),
+                record_timeline: true,
+                ..Default::default()
+            })
+            .send()
+            .await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Err"))
+        }
+    }
+
+    #[tokio::test]
+    async fn single_instance_cascading_failure() {
+        init().await;
+
+        let stats = scenario_cascading()
+            .error_rate(0.03)
+            .duration(Duration::from_secs(90))
+            .await;
+
+        // NOTE: The service collapses to 500 TPS of capacity as soon as the search passes the
+        // 900 TPS threshold, and never recovers within the test. Pinning it at the original
+        // capacity would error ~75% of the time. Once backed off the controller still probes up to
+        // 50% above its stable goal, so the last window can be anywhere below the threshold.
+        assert!(dbg!(stats.goal_tps) < 900);
+        assert!(dbg!(stats.error_rate) < 0.4);
+    }
+
+    #[scenario]
+    async fn scenario_cascading() {
+        let client = CLIENT.get_or_init(Client::new);
+        let _ = transaction_cascading(client).await;
+    }
+
+    #[transaction]
+    async fn transaction_cascading(client: &Client) -> anyhow::Result<()> {
+        let res = client
+            .get("http://0.0.0.0:3002/")
+            .json(&Config {
+                scenario_name: "cascading".to_string(),
+                tps: Some(TpsConfig {
+                    tps: NonZeroU32::new(2_000).unwrap(),
+                    kind: TpsKind::Cascading {
+                        threshold: NonZeroU32::new(900).unwrap(),
+                        recovery: Duration::from_secs(600),
+                    },
+                }),
+                latency: Some(LatencyConfig {
+                    latency: Duration::from_millis(1),
+                    kind: LatencyKind::Delay,
+                }),
+                ..Default::default()
+            })
+            .send()
+            .await?;
+
+        if res.status().is_server_error() {
+            Err(anyhow::anyhow!("Err"))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn single_instance_rate_limited() {
+        init().await;
+
+        let stats = scenario_rate_limited()
+            .tps(3_000)
+            .backoff_on_rate_limit()
+            .duration(Duration::from_secs(60))
+            .await;
+
+        // NOTE: The goal recovers in steps of 25% once rejections stop, so it hovers just above
+        // the rate limit.
+        assert!(dbg!(stats.goal_tps) <= 1_300);
+        assert!(dbg!(stats.actual_tps) > 800.);
+    }
+
+    #[scenario]
+    async fn scenario_rate_limited() {
+        let client = CLIENT.get_or_init(Client::new);
+        let _ = transaction_rate_limited(client).await;
+    }
+
+    #[transaction]
+    async fn transaction_rate_limited(client: &Client) -> anyhow::Result<()> {
+        let res = client
+            .get("http://0.0.0.0:3002/")
+            .json(&Config {
+                scenario_name: "rate_limited".to_string(),
+                tps: Some(TpsConfig {
+                    tps: NonZeroU32::new(1_000).unwrap(),
+                    kind: TpsKind::RateLimited {
+                        retry_after: Duration::from_secs(1),
+                    },
+                }),
+                latency: Some(LatencyConfig {
+                    latency: Duration::from_millis(1),
+                    kind: LatencyKind::Delay,
+                }),
+                ..Default::default()
+            })
+            .send()
+            .await?;
+
+        if balter::check_rate_limit(&res) {
+            Err(anyhow::anyhow!("Rate limited"))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn single_instance_record() {
+        init().await;
+
+        let stats = scenario_1ms_record()
+            .tps(500)
+            .duration(Duration::from_secs(10))
+            .await;
+
+        let status = &stats.measurements["transaction_1ms_record_record"];
+        assert!(dbg!(status.count) > 1_000);
+        assert_eq!(status.min, 200.);
+        assert_eq!(status.max, 200.);
+    }
+
+    #[tokio::test]
+    async fn single_instance_write_heavy() {
+        init().await;
+
+        let stats = scenario_write_heavy()
+            .error_rate(0.03)
+            .duration(Duration::from_secs(90))
+            .await;
+
+        // NOTE: Only the POSTs are limited (to 1,000 TPS), and they make up 75% of the
+        // Transactions, so the error rate is reached at around 1,400 TPS.
+        assert!(dbg!(stats.error_rate) < 0.07);
+        assert!(dbg!(stats.goal_tps) <= 1_600);
+        assert!(dbg!(stats.goal_tps) >= 1_200);
+    }
+
+    #[tokio::test]
+    async fn single_instance_invalid_body() {
+        init().await;
+
+        let client = CLIENT.get_or_init(Client::new);
+        let res = client
+            .post("http://0.0.0.0:3002/")
+            .json(&write_heavy_config(Some(
+                serde_json::json!({ "content": "No title" }),
+            )))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[scenario]
+    async fn scenario_write_heavy() {
+        let client = CLIENT.get_or_init(Client::new);
+        for _ in 0..3 {
+            let _ = create_post(client).await;
+        }
+        let _ = get_posts(client).await;
+    }
+
+    #[transaction]
+    async fn create_post(client: &Client) -> anyhow::Result<()> {
+        let body = serde_json::json!({ "title": "Balter", "content": "A load testing framework" });
+        let res = client
+            .post("http://0.0.0.0:3002/")
+            .json(&write_heavy_config(Some(body)))
+            .send()
+            .await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Err"))
+        }
+    }
+
+    #[transaction]
+    async fn get_posts(client: &Client) -> anyhow::Result<()> {
+        let res = client
+            .get("http://0.0.0.0:3002/")
+            .json(&write_heavy_config(None))
+            .send()
+            .await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Err"))
+        }
+    }
+
+    /// Only POSTs are limited, and must have a title and content.
+    fn write_heavy_config(body: Option<serde_json::Value>) -> Config {
+        let latency = || LatencyConfig {
+            latency: Duration::from_millis(1),
+            kind: LatencyKind::Delay,
+        };
+
+        let methods = HashMap::from([
+            (
+                HttpMethod::Post,
+                MethodConfig {
+                    tps: Some(TpsConfig {
+                        tps: NonZeroU32::new(1_000).unwrap(),
+                        kind: TpsKind::Error,
+                    }),
+                    latency: Some(latency()),
+                    required_fields: vec!["title".to_string(), "content".to_string()],
+                },
+            ),
+            (
+                HttpMethod::Get,
+                MethodConfig {
+                    latency: Some(latency()),
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        Config {
+            scenario_name: "write_heavy".to_string(),
+            methods,
+            body,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn single_instance_connection_ramp() {
+        init().await;
+
+        // NOTE: The route only accepts 20 concurrent connections, so errors start above 20.
+        let stats = scenario_10ms_connections_20()
+            .error_rate(0.01)
+            .concurrency_ramp(5, 40, Duration::from_secs(20))
+            .await;
+
+        assert_eq!(stats.concurrency, 40);
+        assert!(dbg!(stats.error_rate) > 0.01);
+        let breaking = dbg!(stats.breaking_concurrency).unwrap();
+        assert!(breaking > 20 && breaking <= 30);
+    }
+
+    #[tokio::test]
+    async fn single_instance_max_tps_at_concurrency() {
+        init().await;
+
+        // NOTE: 10 connections are within the 20 the route accepts, so the TPS is limited by
+        // the 10ms delay rather than by errors.
+        let result = scenario_10ms_connections_20_pinned()
+            .error_rate(0.01)
+            .at_concurrency(10)
+            .duration(Duration::from_secs(30))
+            .find_max_tps()
+            .await;
+
+        assert_eq!(result.stats.concurrency, 10);
+        assert!(result.concurrency_limited);
+        assert!(dbg!(result.max_tps) >= 600);
+        assert!(result.max_tps <= 1_000);
+        assert!(dbg!(result.stats.error_rate) < 0.01);
+    }
+
     /* Scenario Helpers */
 
     static CLIENT: OnceLock<Client> = OnceLock::new();
@@ -143,6 +456,46 @@ mod tests {
         Ok(())
     }
 
+    #[scenario]
+    async fn scenario_10ms_connections_20() {
+        let _ = transaction_10ms_connections_20().await;
+    }
+
+    #[transaction]
+    async fn transaction_10ms_connections_20() -> anyhow::Result<()> {
+        let client = CLIENT.get_or_init(Client::new);
+        let res = client
+            .get("http://0.0.0.0:3002/connections/20/delay/ms/10/server/0")
+            .send()
+            .await?;
+
+        if res.status().is_server_error() {
+            Err(anyhow::anyhow!("Err"))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[scenario]
+    async fn scenario_10ms_connections_20_pinned() {
+        let _ = transaction_10ms_connections_20_pinned().await;
+    }
+
+    #[transaction]
+    async fn transaction_10ms_connections_20_pinned() -> anyhow::Result<()> {
+        let client = CLIENT.get_or_init(Client::new);
+        let res = client
+            .get("http://0.0.0.0:3002/connections/20/delay/ms/10/server/pinned")
+            .send()
+            .await?;
+
+        if res.status().is_server_error() {
+            Err(anyhow::anyhow!("Err"))
+        } else {
+            Ok(())
+        }
+    }
+
     #[scenario]
     async fn scenario_1ms_max_2000() {
         let _ = transaction_1ms_max_2000().await;
@@ -162,4 +515,23 @@ mod tests {
             Ok(())
         }
     }
+
+    #[scenario]
+    async fn scenario_1ms_record() {
+        let _ = transaction_1ms_record().await;
+    }
+
+    fn status_code(status: &u16) -> f64 {
+        *status as f64
+    }
+
+    #[transaction(record = status_code)]
+    async fn transaction_1ms_record() -> Result<u16, reqwest::Error> {
+        let client = CLIENT.get_or_init(Client::new);
+        let res = client
+            .get("http://0.0.0.0:3002/limited/7000/delay/ms/1/server/0")
+            .send()
+            .await?;
+        Ok(res.status().as_u16())
+    }
 }
@@ -44,6 +44,7 @@ mod tests {
                     latency: Duration::from_millis(200),
                     kind: LatencyKind::Linear(NonZeroU32::new(2000).unwrap()),
                 }),
+                ..Default::default()
             })
             .send()
             .await?;
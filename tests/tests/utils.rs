@@ -1,5 +1,7 @@
 use metrics_exporter_prometheus::PrometheusBuilder;
+use mock_service::TimelinePoint;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::OnceLock;
 use std::time::Duration;
 use tracing::{error, Level};
@@ -39,3 +41,123 @@ pub async fn init() {
         tokio::time::sleep(Duration::from_millis(200)).await;
     }
 }
+
+/// Environment variable which, if set, re-records the baselines of `assert_timeline()` from the
+/// current run rather than comparing against them.
+const UPDATE_BASELINES_VAR: &str = "BALTER_UPDATE_BASELINES";
+
+/// Band around the settled TPS within which a timeline counts as converged.
+const SETTLED_BAND: f64 = 0.1;
+
+/// Slack in seconds on the convergence time, since the timeline is only recorded every 500ms.
+const CONVERGENCE_SLACK: f64 = 2.;
+
+/// The timeline the mock service observed for a scenario with `record_timeline` set.
+#[allow(unused)]
+pub async fn fetch_timeline(scenario_name: &str) -> Vec<TimelinePoint> {
+    reqwest::get(format!("http://0.0.0.0:3002/timeline/{scenario_name}"))
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// Compare the timeline of a run against the baseline committed as `baselines/<name>.json`,
+/// to catch regressions in the behavior of the controllers end-to-end.
+///
+/// Fails if the run settled at a different TPS, took longer to converge, or oscillated more
+/// once converged than the baseline, with `tolerance` as a fraction of the baseline (and as an
+/// absolute increase of the coefficient of variation for oscillation). Run with
+/// `BALTER_UPDATE_BASELINES=1` to re-record the baseline after an intended change.
+#[allow(unused)]
+pub fn assert_timeline(name: &str, timeline: &[TimelinePoint], tolerance: f64) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("baselines")
+        .join(format!("{name}.json"));
+
+    if std::env::var_os(UPDATE_BASELINES_VAR).is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, serde_json::to_string_pretty(timeline).unwrap()).unwrap();
+        return;
+    }
+
+    let baseline = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!(
+            "No baseline at {}, record it with {UPDATE_BASELINES_VAR}=1: {err}",
+            path.display()
+        )
+    });
+    let baseline: Vec<TimelinePoint> = serde_json::from_str(&baseline).unwrap();
+
+    let expected = TimelineSummary::new(&baseline);
+    let actual = TimelineSummary::new(timeline);
+
+    assert!(
+        (actual.settled_tps - expected.settled_tps).abs() <= tolerance * expected.settled_tps,
+        "settled at {:.0} TPS rather than {:.0} TPS\n{expected:?}\n{actual:?}",
+        actual.settled_tps,
+        expected.settled_tps
+    );
+    assert!(
+        actual.convergence <= expected.convergence * (1. + tolerance) + CONVERGENCE_SLACK,
+        "converged after {:.1}s rather than {:.1}s\n{expected:?}\n{actual:?}",
+        actual.convergence,
+        expected.convergence
+    );
+    assert!(
+        actual.oscillation <= expected.oscillation + tolerance,
+        "oscillated with a coefficient of variation of {:.3} rather than {:.3}\n{expected:?}\n{actual:?}",
+        actual.oscillation,
+        expected.oscillation
+    );
+}
+
+#[derive(Debug)]
+struct TimelineSummary {
+    /// Mean achieved TPS over the last quarter of the run.
+    settled_tps: f64,
+    /// Seconds until the achieved TPS stayed within `SETTLED_BAND` of `settled_tps`.
+    convergence: f64,
+    /// Coefficient of variation of the achieved TPS once converged.
+    oscillation: f64,
+}
+
+impl TimelineSummary {
+    fn new(timeline: &[TimelinePoint]) -> Self {
+        // NOTE: The last point may only partially cover the end of the run.
+        let timeline = &timeline[..timeline.len().saturating_sub(1)];
+        assert!(timeline.len() >= 4, "timeline too short: {timeline:?}");
+
+        let tail = &timeline[timeline.len() * 3 / 4..];
+        let settled_tps = mean(tail.iter().map(|point| point.achieved_tps));
+
+        let settled = |point: &TimelinePoint| {
+            (point.achieved_tps - settled_tps).abs() <= SETTLED_BAND * settled_tps
+        };
+        let converged_at = timeline
+            .iter()
+            .rposition(|point| !settled(point))
+            .map_or(0, |i| i + 1);
+        let convergence = timeline
+            .get(converged_at)
+            .map_or(f64::INFINITY, |point| point.time - timeline[0].time);
+
+        let converged = timeline[converged_at..].iter().map(|p| p.achieved_tps);
+        let converged_mean = mean(converged.clone());
+        let variance = mean(converged.map(|tps| (tps - converged_mean).powi(2)));
+
+        Self {
+            settled_tps,
+            convergence,
+            oscillation: variance.sqrt() / converged_mean,
+        }
+    }
+}
+
+fn mean(values: impl Iterator<Item = f64> + Clone) -> f64 {
+    let count = values.clone().count();
+    values.sum::<f64>() / count.max(1) as f64
+}